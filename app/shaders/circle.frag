@@ -7,6 +7,7 @@ layout (binding=2) uniform sampler2D tex;
 layout (location=0) in vec2 frag_pos;
 layout (location=1) in vec4 frag_color;
 layout (location=2) in float frag_smooth_factor;
+layout (location=3) in float frag_feather;
 
 
 // Target output color
@@ -15,6 +16,12 @@ layout(location = 0) out vec4 outColor;
 void main() {
     float dist_sq = dot(frag_pos, frag_pos);
     float alpha = smoothstep(1.0, 0.0, dist_sq);
+    if (frag_feather > 0.0) {
+        // edge width in screen pixels, independent of circle size and DPI
+        float dist = sqrt(dist_sq);
+        float edge_width = frag_feather * fwidth(dist);
+        alpha = 1.0 - smoothstep(1.0 - edge_width, 1.0, dist);
+    }
 
     vec4 tex_color = texture(tex, frag_pos * 0.5 + 0.5);
     if (tex_color[0] > 0.7 && tex_color[1] > 0.7 && tex_color[2] > 0.7) {
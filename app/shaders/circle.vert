@@ -4,12 +4,14 @@
 layout (location = 0) in vec4 in_color;
 layout (location = 1) in vec2 in_position;
 layout (location = 2) in int in_trig_time;
+layout (location = 3) in float in_feather;
 
 
 // pass to fragment shader
 layout(location = 0) out vec2 frag_pos;
 layout(location = 1) out vec4 frag_color;
 layout(location = 2) out float frag_smooth_factor;
+layout(location = 3) out float frag_feather;
 
 // substituted uniform_buffers definitions
 layout (std140, binding = 0) uniform Time {
@@ -22,6 +24,11 @@ layout (std140, binding = 1) uniform MapStats {
     float aspect;
 } u_map_stats;
 
+layout (std140, binding = 3) uniform Camera {
+    vec2 pan;
+    float zoom;
+} u_camera;
+
 
 void main() {
     float viewport_aspect = u_map_stats.aspect;
@@ -42,8 +49,9 @@ void main() {
     // Scale vertex position by radius and offset by the circle position
     frag_pos = offsets[vertexID] * vec2(1.0, 1.0 / viewport_aspect);
     frag_color = in_color;
+    frag_feather = in_feather;
     frag_smooth_factor = clamp(float(in_trig_time - u_time.time) / u_map_stats.ar, 0.0, 1.0);
 
     // Set position in screen space
-    gl_Position = vec4(position, 0.0, 1.0);
+    gl_Position = vec4(position * u_camera.zoom + u_camera.pan, 0.0, 1.0);
 }
@@ -0,0 +1,22 @@
+#version 450 core
+
+// Uniform decls
+layout (binding=0) uniform sampler2D glyph_atlas;
+
+// Inputs from the vertex shader
+layout (location=0) in vec2 frag_uv;
+layout (location=1) flat in vec4 frag_color;
+
+
+// Target output color
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    // monochrome atlas, coverage is in every color channel
+    float coverage = texture(glyph_atlas, frag_uv).r;
+    // keep depth free around glyphs
+    if (coverage == 0.0) {
+        discard;
+    }
+    outColor = vec4(frag_color.rgb, frag_color.a * coverage);
+}
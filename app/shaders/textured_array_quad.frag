@@ -0,0 +1,16 @@
+#version 450 core
+
+// Uniform decls
+layout (binding=0) uniform sampler2DArray tex;
+
+// Inputs from the vertex shader
+layout (location=0) in vec2 frag_uv;
+layout (location=1) flat in float frag_layer;
+
+
+// Target output color
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = texture(tex, vec3(frag_uv, frag_layer));
+}
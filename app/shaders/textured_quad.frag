@@ -0,0 +1,15 @@
+#version 450 core
+
+// Uniform decls
+layout (binding=0) uniform sampler2D tex;
+
+// Inputs from the vertex shader
+layout (location=0) in vec2 frag_uv;
+
+
+// Target output color
+layout(location = 0) out vec4 outColor;
+
+void main() {
+    outColor = texture(tex, frag_uv);
+}
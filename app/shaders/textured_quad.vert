@@ -0,0 +1,26 @@
+#version 450 core
+
+// substituted per-instance attributes
+layout (location = 0) in vec2 in_position;
+layout (location = 1) in vec2 in_size;
+layout (location = 2) in vec4 in_uv_rect;
+
+
+// pass to fragment shader
+layout(location = 0) out vec2 frag_uv;
+
+
+void main() {
+    // Triangle strip corners of the unit quad
+    vec2 corners[4] = vec2[](
+        vec2(0.0, 0.0),
+        vec2(1.0, 0.0),
+        vec2(0.0, 1.0),
+        vec2(1.0, 1.0)
+    );
+
+    vec2 corner = corners[gl_VertexIndex % 4];
+    frag_uv = mix(in_uv_rect.xy, in_uv_rect.zw, corner);
+
+    gl_Position = vec4(in_position + corner * in_size, 0.0, 1.0);
+}
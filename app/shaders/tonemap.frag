@@ -0,0 +1,14 @@
+#version 450 core
+
+layout(location = 0) flat in float frag_exposure;
+
+layout(location = 0) out vec4 outColor;
+
+// scene color written by the first subpass
+layout(input_attachment_index = 0, binding = 0) uniform subpassInput scene_color;
+
+void main() {
+    vec3 color = subpassLoad(scene_color).rgb * frag_exposure;
+    // Reinhard
+    outColor = vec4(color / (color + vec3(1.0)), 1.0);
+}
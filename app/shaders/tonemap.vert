@@ -0,0 +1,22 @@
+#version 450 core
+
+// substituted per-instance attributes
+layout (location = 0) in float in_exposure;
+
+
+// pass to fragment shader
+layout(location = 0) flat out float frag_exposure;
+
+
+void main() {
+    // Triangle strip corners covering the whole viewport
+    vec2 corners[4] = vec2[](
+        vec2(-1.0, -1.0),
+        vec2(1.0, -1.0),
+        vec2(-1.0, 1.0),
+        vec2(1.0, 1.0)
+    );
+
+    frag_exposure = in_exposure;
+    gl_Position = vec4(corners[gl_VertexIndex % 4], 0.0, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 450 core
+
+layout(location = 0) flat in vec4 frag_color;
+layout(location = 1) flat in vec4 frag_extra_color;
+
+// swapchain image and the first of `VulkanRenderConfig::extra_color_attachments`
+layout(location = 0) out vec4 out_color;
+layout(location = 1) out vec4 out_extra_color;
+
+void main() {
+    out_color = frag_color;
+    out_extra_color = frag_extra_color;
+}
@@ -0,0 +1,25 @@
+#version 450 core
+
+// substituted per-instance attributes
+layout (location = 0) in vec4 in_color;
+layout (location = 1) in vec4 in_extra_color;
+
+
+// pass to fragment shader
+layout(location = 0) flat out vec4 frag_color;
+layout(location = 1) flat out vec4 frag_extra_color;
+
+
+void main() {
+    // Triangle strip corners covering the whole viewport
+    vec2 corners[4] = vec2[](
+        vec2(-1.0, -1.0),
+        vec2(1.0, -1.0),
+        vec2(-1.0, 1.0),
+        vec2(1.0, 1.0)
+    );
+
+    frag_color = in_color;
+    frag_extra_color = in_extra_color;
+    gl_Position = vec4(corners[gl_VertexIndex % 4], 0.0, 1.0);
+}
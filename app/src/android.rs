@@ -8,9 +8,10 @@ use log::info;
 use sparkles_macro::range_event_start;
 use winit::event_loop::{EventLoop, EventLoopBuilder};
 use winit::platform::android::activity::*;
+use crate::config::RefreshRatePolicy;
 
-pub fn android_main(app: AndroidApp) -> EventLoop<()> {
-    use jni::objects::{JObject, JObjectArray, JValue};
+pub fn android_main(app: AndroidApp, refresh_rate_policy: RefreshRatePolicy) -> EventLoop<()> {
+    use jni::objects::{JObject, JObjectArray};
     use jni::JavaVM;
     use winit::platform::android::EventLoopBuilderExtAndroid;
     use render::android::set_android_context;
@@ -89,16 +90,39 @@ pub fn android_main(app: AndroidApp) -> EventLoop<()> {
         info!("Mode {}: {}x{}@{}", index, width, height, refresh_rate);
     }
 
-    let mut max_framerate_mode = modes
-        .iter()
-        .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
-        .unwrap();
-    info!("Max framerate: {}", max_framerate_mode.1);
+    let preferred_mode = match refresh_rate_policy {
+        RefreshRatePolicy::Max => modes
+            .iter()
+            .max_by(|a, b| a.1.total_cmp(&b.1)),
+        RefreshRatePolicy::Specific(hz) => modes
+            .iter()
+            .min_by(|a, b| (a.1 - hz).abs().total_cmp(&(b.1 - hz).abs())),
+        RefreshRatePolicy::SystemDefault => None,
+    };
+
+    match preferred_mode {
+        Some(&(preferred_id, refresh_rate)) => {
+            info!("Preferred display mode {}: {}Hz", preferred_id, refresh_rate);
+            set_preferred_display_mode(&mut env, &activity, preferred_id);
+        }
+        None => info!("Display mode is chosen by system"),
+    }
 
-    let preferred_id = 1;
+    drop(g);
+
+    *VM.lock().unwrap() = Some(vm);
+    *ACTIVITY.lock().unwrap() = Some(activity);
+    set_android_context(ACTIVITY.clone(), VM.clone());
+    let event_loop = EventLoopBuilder::default().with_android_app(app).build().unwrap();
+    event_loop
+}
+
+/// Set `preferredDisplayModeId` of the activity window
+fn set_preferred_display_mode(env: &mut JNIEnv, activity: &GlobalRef, preferred_id: i32) {
+    use jni::objects::JValue;
 
     let window = env
-        .call_method(&activity, "getWindow", "()Landroid/view/Window;", &[])
+        .call_method(activity, "getWindow", "()Landroid/view/Window;", &[])
         .unwrap()
         .l()
         .unwrap();
@@ -128,7 +152,7 @@ pub fn android_main(app: AndroidApp) -> EventLoop<()> {
         .unwrap();
 
     let window = env
-        .call_method(&activity, "getWindow", "()Landroid/view/Window;", &[])
+        .call_method(activity, "getWindow", "()Landroid/view/Window;", &[])
         .unwrap()
         .l()
         .unwrap();
@@ -139,14 +163,6 @@ pub fn android_main(app: AndroidApp) -> EventLoop<()> {
         &[(&layout_params).into()],
     )
         .unwrap();
-
-    drop(g);
-
-    *VM.lock().unwrap() = Some(vm);
-    *ACTIVITY.lock().unwrap() = Some(activity);
-    set_android_context(ACTIVITY.clone(), VM.clone());
-    let event_loop = EventLoopBuilder::default().with_android_app(app).build().unwrap();
-    event_loop
 }
 
 lazy_static!{
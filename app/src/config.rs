@@ -0,0 +1,215 @@
+use std::time::Duration;
+use crate::input_map::InputMap;
+use crate::render_thread::RenderThreadConfig;
+use web_time::Instant;
+#[cfg(feature = "vulkan")]
+use render::vulkan_backend::config::{DepthFormat, DepthMode, DevicePreference, InFlightFrames, OutputColorSpace, VulkanRenderConfig};
+
+/// Which display mode to request on Android
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RefreshRatePolicy {
+    /// Mode with the highest refresh rate
+    #[default]
+    Max,
+    /// Mode with refresh rate closest to the given one, in Hz
+    Specific(f32),
+    /// Do not request any mode
+    SystemDefault,
+}
+
+/// How scene time advances between rendered frames
+#[derive(Clone, Copy, Debug, Default)]
+pub enum TimeSource {
+    /// Real time since start
+    #[default]
+    Wall,
+    /// Fixed number of milliseconds per rendered frame, for reproducible captures
+    FixedStep(u32),
+}
+
+/// How `InputAction::ToggleFullscreen` enters fullscreen
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FullscreenMode {
+    /// Window covers the current monitor, display mode is not changed
+    #[default]
+    Borderless,
+    /// Video mode of the current monitor with the highest resolution and refresh rate
+    Exclusive,
+}
+
+/// Scene time, advanced once per rendered frame according to `TimeSource`
+pub struct SceneClock {
+    source: TimeSource,
+    start: Instant,
+    last_frame: Instant,
+    time_ms: u64,
+    frame_dt: f32,
+}
+
+impl SceneClock {
+    pub fn new(source: TimeSource) -> Self {
+        let now = Instant::now();
+        Self {
+            source,
+            start: now,
+            last_frame: now,
+            time_ms: 0,
+            frame_dt: 0.0,
+        }
+    }
+
+    /// Advance time to the new frame
+    pub fn tick(&mut self) {
+        let now = Instant::now();
+        match self.source {
+            TimeSource::Wall => {
+                self.time_ms = (now - self.start).as_millis() as u64;
+                self.frame_dt = (now - self.last_frame).as_secs_f32();
+            }
+            TimeSource::FixedStep(step_ms) => {
+                self.time_ms += step_ms as u64;
+                self.frame_dt = step_ms as f32 / 1000.0;
+            }
+        }
+        self.last_frame = now;
+    }
+
+    /// Scene time of the current frame in milliseconds
+    pub fn time_ms(&self) -> u64 {
+        self.time_ms
+    }
+
+    /// Scene time between previous and current frame in seconds
+    pub fn frame_dt(&self) -> f32 {
+        self.frame_dt
+    }
+}
+
+/// Window icon as 8-bit RGBA pixels, row by row
+#[derive(Clone, Debug)]
+pub struct WindowIcon {
+    pub rgba: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Application-level settings, applied when window and renderer are created
+#[derive(Clone)]
+pub struct AppConfig {
+    /// Initial window title, can be changed later with `AppState::set_title`
+    pub title: String,
+    /// Desktop only, invalid icon is ignored with an error
+    pub icon: Option<WindowIcon>,
+    /// Width and height in logical pixels. `None` lets the platform choose
+    pub initial_size: Option<(u32, u32)>,
+    /// Logical pixels
+    pub min_size: Option<(u32, u32)>,
+    /// Logical pixels
+    pub max_size: Option<(u32, u32)>,
+    pub resizable: bool,
+    /// Title bar and borders
+    pub decorations: bool,
+    /// Limit redraw rate to this value. `None` redraws as fast as presentation allows
+    pub target_fps: Option<f32>,
+    /// Android only
+    pub refresh_rate: RefreshRatePolicy,
+    /// Drives `Time` uniform, trail and background animation
+    pub time_source: TimeSource,
+    pub fullscreen_mode: FullscreenMode,
+    /// Initial key and mouse button bindings
+    pub input_map: InputMap,
+    /// Name, priority and affinity of the thread running the event loop and rendering
+    pub render_thread: RenderThreadConfig,
+    /// Renderer settings, e.g. `VulkanRenderConfig::low_latency_preset()`. `vsync` is the initial state of
+    /// `InputAction::ToggleVsync`
+    #[cfg(feature = "vulkan")]
+    pub render: VulkanRenderConfig,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            title: "shades of pink".to_string(),
+            icon: None,
+            initial_size: None,
+            min_size: None,
+            max_size: None,
+            resizable: true,
+            decorations: true,
+            target_fps: None,
+            refresh_rate: RefreshRatePolicy::default(),
+            time_source: TimeSource::default(),
+            fullscreen_mode: FullscreenMode::default(),
+            input_map: InputMap::default(),
+            render_thread: RenderThreadConfig::default(),
+            #[cfg(feature = "vulkan")]
+            render: VulkanRenderConfig {
+                msaa_samples: None,
+                vsync: false,
+                present_modes: Vec::new(),
+                swapchain_image_count: None,
+                device_preference: DevicePreference::HighPerformance,
+                in_flight_frames: InFlightFrames::One,
+                depth_format: DepthFormat::D16,
+                depth_mode: DepthMode::Standard,
+                extra_color_attachments: Vec::new(),
+                post_subpass: false,
+                pipeline_cache_path: Some(std::env::temp_dir().join("winit-vulkan-pipeline-cache.bin")),
+                post_process_shader: None,
+                output_color_space: OutputColorSpace::Srgb,
+                frame_timeout: Some(Duration::from_secs(5)),
+                timeline_semaphores: true,
+                max_api_version: None,
+                dynamic_rendering: false,
+                group_draws_by_pipeline: true,
+                indirect_batches: true,
+                bindless_textures: false,
+            },
+        }
+    }
+}
+
+/// Paces redraws to a fixed rate.
+///
+/// Next frame is scheduled from the previous deadline (not from the end of rendering), so time spent
+/// rendering is compensated. If frame is late by more than one period, schedule is restarted from now.
+pub struct FramePacer {
+    frame_duration: Duration,
+    next_frame: Instant,
+    /// Difference between actual and scheduled frame start, in milliseconds. Positive means late.
+    pacing_error: f32,
+}
+
+impl FramePacer {
+    pub fn new(target_fps: f32) -> Self {
+        Self {
+            frame_duration: Duration::from_secs_f32(1.0 / target_fps.max(1.0)),
+            next_frame: Instant::now(),
+            pacing_error: 0.0,
+        }
+    }
+
+    /// Measure how far from the schedule current frame has started
+    pub fn begin_frame(&mut self) {
+        let now = Instant::now();
+        self.pacing_error = if now >= self.next_frame {
+            (now - self.next_frame).as_secs_f32() * 1000.0
+        } else {
+            -(self.next_frame - now).as_secs_f32() * 1000.0
+        };
+    }
+
+    /// Returns the moment when the next frame should start
+    pub fn schedule_next(&mut self) -> Instant {
+        let now = Instant::now();
+        self.next_frame += self.frame_duration;
+        if self.next_frame + self.frame_duration < now {
+            self.next_frame = now;
+        }
+        self.next_frame
+    }
+
+    pub fn pacing_error(&self) -> f32 {
+        self.pacing_error
+    }
+}
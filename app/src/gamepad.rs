@@ -0,0 +1,75 @@
+use std::time::Instant;
+use gilrs::{Axis, Button, EventType, Gilrs};
+use log::{info, warn};
+
+/// Lamp offset for a single d-pad press, same as for arrow keys
+const DPAD_STEP: f32 = 0.1;
+/// Lamp speed with stick fully tilted, in NDC per second
+const STICK_SPEED: f32 = 1.5;
+const STICK_DEADZONE: f32 = 0.15;
+
+/// Scene mutation requested by gamepad input
+pub enum GamepadAction {
+    /// Move touch position by the given offset in NDC
+    Move([f32; 2]),
+    /// Return to the center, same as mouse click
+    Reset,
+}
+
+pub struct GamepadInput {
+    gilrs: Gilrs,
+    /// Left stick position, y points down
+    stick: [f32; 2],
+    last_poll: Instant,
+}
+
+impl GamepadInput {
+    /// `None` if gamepads are not supported on this platform
+    pub fn new() -> Option<Self> {
+        let gilrs = Gilrs::new()
+            .map_err(|e| warn!("Gamepad input is not available: {}", e))
+            .ok()?;
+        for (_, gamepad) in gilrs.gamepads() {
+            info!("Gamepad found: {}", gamepad.name());
+        }
+        Some(Self {
+            gilrs,
+            stick: [0.0, 0.0],
+            last_poll: Instant::now(),
+        })
+    }
+
+    /// Process pending gamepad events. Stick movement is scaled by time since the previous poll.
+    pub fn poll(&mut self) -> Vec<GamepadAction> {
+        let mut actions = Vec::new();
+        while let Some(event) = self.gilrs.next_event() {
+            match event.event {
+                EventType::ButtonPressed(Button::DPadLeft, _) => actions.push(GamepadAction::Move([-DPAD_STEP, 0.0])),
+                EventType::ButtonPressed(Button::DPadRight, _) => actions.push(GamepadAction::Move([DPAD_STEP, 0.0])),
+                EventType::ButtonPressed(Button::DPadUp, _) => actions.push(GamepadAction::Move([0.0, -DPAD_STEP])),
+                EventType::ButtonPressed(Button::DPadDown, _) => actions.push(GamepadAction::Move([0.0, DPAD_STEP])),
+                EventType::ButtonPressed(Button::South, _) => actions.push(GamepadAction::Reset),
+                EventType::AxisChanged(Axis::LeftStickX, value, _) => self.stick[0] = value,
+                // gilrs reports y up
+                EventType::AxisChanged(Axis::LeftStickY, value, _) => self.stick[1] = -value,
+                EventType::Connected => info!("Gamepad connected: {}", self.gilrs.gamepad(event.id).name()),
+                EventType::Disconnected => {
+                    info!("Gamepad disconnected");
+                    self.stick = [0.0, 0.0];
+                }
+                _ => {}
+            }
+        }
+
+        let now = Instant::now();
+        let dt = (now - self.last_poll).as_secs_f32();
+        self.last_poll = now;
+        if self.stick[0].hypot(self.stick[1]) > STICK_DEADZONE {
+            actions.push(GamepadAction::Move([
+                self.stick[0] * STICK_SPEED * dt,
+                self.stick[1] * STICK_SPEED * dt,
+            ]));
+        }
+        actions
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+use winit::event::{ElementState, MouseButton, WindowEvent};
+use winit::keyboard::{Key, NamedKey};
+
+/// Behavior triggered by keyboard or mouse input through `InputMap`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum InputAction {
+    Exit,
+    ToggleFullscreen,
+    ToggleVsync,
+    ToggleStatsOverlay,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    /// Return to the center
+    ResetPosition,
+    /// Move camera with cursor while held
+    Pan,
+}
+
+/// Key or mouse button which can be bound to an action
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum InputBinding {
+    Key(NamedKey),
+    /// Lowercase character produced by the key, e.g. "v"
+    Character(String),
+    Mouse(MouseButton),
+}
+
+/// Binding table from keys and mouse buttons to actions.
+///
+/// Each binding triggers at most one action, one action can have several bindings.
+/// Key repeats are ignored.
+#[derive(Clone, Debug)]
+pub struct InputMap {
+    bindings: HashMap<InputBinding, InputAction>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        let mut map = Self::empty();
+        map.bind(InputBinding::Key(NamedKey::GoBack), InputAction::Exit);
+        map.bind(InputBinding::Key(NamedKey::BrowserBack), InputAction::Exit);
+        map.bind(InputBinding::Key(NamedKey::F11), InputAction::ToggleFullscreen);
+        map.bind(InputBinding::Character("v".to_string()), InputAction::ToggleVsync);
+        map.bind(InputBinding::Key(NamedKey::F3), InputAction::ToggleStatsOverlay);
+        map.bind(InputBinding::Key(NamedKey::ArrowUp), InputAction::MoveUp);
+        map.bind(InputBinding::Key(NamedKey::ArrowDown), InputAction::MoveDown);
+        map.bind(InputBinding::Key(NamedKey::ArrowLeft), InputAction::MoveLeft);
+        map.bind(InputBinding::Key(NamedKey::ArrowRight), InputAction::MoveRight);
+        map.bind(InputBinding::Mouse(MouseButton::Left), InputAction::ResetPosition);
+        map.bind(InputBinding::Mouse(MouseButton::Right), InputAction::Pan);
+        map
+    }
+}
+
+impl InputMap {
+    /// Map without any bindings
+    pub fn empty() -> Self {
+        Self {
+            bindings: HashMap::new(),
+        }
+    }
+
+    /// Replaces action previously bound to `binding`
+    pub fn bind(&mut self, binding: InputBinding, action: InputAction) {
+        self.bindings.insert(binding, action);
+    }
+
+    pub fn unbind(&mut self, binding: &InputBinding) {
+        self.bindings.remove(binding);
+    }
+
+    /// Remove all bindings of the action
+    pub fn unbind_action(&mut self, action: InputAction) {
+        self.bindings.retain(|_, a| *a != action);
+    }
+
+    pub fn bindings_of(&self, action: InputAction) -> impl Iterator<Item=&InputBinding> {
+        self.bindings.iter().filter(move |(_, a)| **a == action).map(|(b, _)| b)
+    }
+
+    /// Action bound to the key or mouse button of the event, together with press/release state
+    pub fn action_for(&self, event: &WindowEvent) -> Option<(InputAction, ElementState)> {
+        let (binding, state) = match event {
+            WindowEvent::KeyboardInput { event, .. } if !event.repeat => {
+                let binding = match &event.logical_key {
+                    Key::Named(key) => InputBinding::Key(*key),
+                    Key::Character(c) => InputBinding::Character(c.to_lowercase()),
+                    _ => return None,
+                };
+                (binding, event.state)
+            }
+            WindowEvent::MouseInput { state, button, .. } => (InputBinding::Mouse(*button), *state),
+            _ => return None,
+        };
+        self.bindings.get(&binding).map(|action| (*action, state))
+    }
+}
@@ -1,12 +1,27 @@
 #[cfg(target_os = "android")]
 pub mod android;
 
+#[cfg(feature = "vulkan")]
 pub mod winit;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
 pub mod scene;
+pub mod config;
+pub mod render_messages;
+pub mod triple_buffer;
+pub mod input_map;
+pub mod touch;
+pub mod render_thread;
+#[cfg(feature = "vulkan")]
+pub mod monitor;
+pub mod scene_runner;
+pub mod stats_overlay;
+#[cfg(feature = "gamepad")]
+pub mod gamepad;
 
 #[cfg(target_os = "android")]
 #[no_mangle]
 fn android_main(app: crate::winit::AndroidApp) {
     use crate::winit::run_android;
-    run_android(app);
+    run_android::<crate::scene::Scene>(app);
 }
\ No newline at end of file
@@ -1,7 +1,24 @@
-use log::LevelFilter;
-use simple_logger::SimpleLogger;
-
+#[cfg(all(not(target_arch = "wasm32"), feature = "vulkan"))]
 fn main() {
+    use app::config::AppConfig;
+    use app::scene::Scene;
+    use log::LevelFilter;
+    use simple_logger::SimpleLogger;
+
     SimpleLogger::new().with_utc_timestamps().with_colors(true).with_level(LevelFilter::Info).init().unwrap();
-    app::winit::run();
-}
\ No newline at end of file
+    app::winit::run::<Scene>(AppConfig::default());
+}
+
+#[cfg(all(not(target_arch = "wasm32"), not(feature = "vulkan")))]
+fn main() {
+    eprintln!("app was built without the `vulkan` feature, there is nothing to render with");
+    std::process::exit(1);
+}
+
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    use app::config::AppConfig;
+    use app::scene::Scene;
+
+    app::web::run::<Scene>(AppConfig::default());
+}
@@ -0,0 +1,38 @@
+use winit::monitor::MonitorHandle;
+
+/// Monitor as reported by `AppState::available_monitors`
+#[derive(Clone, Debug, PartialEq)]
+pub struct MonitorInfo {
+    /// Index to be used with `AppState::set_fullscreen_on`
+    pub index: usize,
+    pub name: Option<String>,
+    /// Physical pixels
+    pub size: (u32, u32),
+    /// Top left corner on the virtual desktop, physical pixels
+    pub position: (i32, i32),
+    pub scale_factor: f64,
+    /// Distinct refresh rates of the video modes, ascending, in millihertz
+    pub refresh_rates_millihertz: Vec<u32>,
+    pub is_primary: bool,
+}
+
+impl MonitorInfo {
+    pub(crate) fn new(index: usize, monitor: &MonitorHandle, primary: Option<&MonitorHandle>) -> Self {
+        let mut refresh_rates_millihertz: Vec<u32> = monitor.video_modes()
+            .map(|mode| mode.refresh_rate_millihertz())
+            .collect();
+        refresh_rates_millihertz.sort_unstable();
+        refresh_rates_millihertz.dedup();
+        let size = monitor.size();
+        let position = monitor.position();
+        MonitorInfo {
+            index,
+            name: monitor.name(),
+            size: (size.width, size.height),
+            position: (position.x, position.y),
+            scale_factor: monitor.scale_factor(),
+            refresh_rates_millihertz,
+            is_primary: primary == Some(monitor),
+        }
+    }
+}
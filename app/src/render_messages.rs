@@ -0,0 +1,132 @@
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use crate::triple_buffer::{triple_buffer, TripleBufferReader, TripleBufferWriter};
+
+/// Commands which can be sent to the app from any thread, without access to the `Scene`.
+///
+/// Messages are applied in the order they were sent, all pending messages are applied before the next
+/// frame is rendered. Several messages of the same kind sent between frames are coalesced: only the last
+/// one has visible effect.
+#[derive(Debug, Clone)]
+pub enum RenderMessage {
+    /// Same as `AppState::set_clear_color`. `None` returns to the color provided by the scene
+    SetClearColor(Option<[f32; 3]>),
+    /// Camera pan in NDC and zoom factor
+    SetCamera {
+        pan: [f32; 2],
+        zoom: f32,
+    },
+    SetVsync(bool),
+}
+
+/// Whether the app still renders, shared with threads sending messages.
+///
+/// Rendering stops for good after a panic in the renderer. The message channel is closed at the same
+/// time, so `Sender::send` fails instead of queueing messages nobody will apply
+#[derive(Clone, Default)]
+pub struct RenderStatus {
+    inner: Arc<RenderStatusInner>,
+}
+
+#[derive(Default)]
+struct RenderStatusInner {
+    stopped: AtomicBool,
+    panic_message: Mutex<Option<String>>,
+}
+
+impl RenderStatus {
+    pub fn is_stopped(&self) -> bool {
+        self.inner.stopped.load(Ordering::Acquire)
+    }
+
+    /// Message of the panic which stopped rendering
+    pub fn panic_message(&self) -> Option<String> {
+        self.inner.panic_message.lock().unwrap().clone()
+    }
+
+    fn set_panicked(&self, message: String) {
+        *self.inner.panic_message.lock().unwrap() = Some(message);
+        self.inner.stopped.store(true, Ordering::Release);
+    }
+}
+
+/// Receiving end of the render message channel, owned by the app
+pub struct RenderMessages {
+    sender: Sender<RenderMessage>,
+    receiver: Receiver<RenderMessage>,
+    status: RenderStatus,
+    latest: TripleBufferReader<RenderMessage>,
+    /// Taken by `take_latest_writer`
+    latest_writer: Option<TripleBufferWriter<RenderMessage>>,
+}
+
+impl RenderMessages {
+    pub fn new() -> Self {
+        let (sender, receiver) = channel();
+        let (latest_writer, latest) = triple_buffer();
+        Self {
+            sender,
+            receiver,
+            status: RenderStatus::default(),
+            latest,
+            latest_writer: Some(latest_writer),
+        }
+    }
+
+    pub fn sender(&self) -> Sender<RenderMessage> {
+        self.sender.clone()
+    }
+
+    pub fn status(&self) -> RenderStatus {
+        self.status.clone()
+    }
+
+    /// Report the panic to other threads and close the channel: pending messages are dropped and all senders,
+    /// including ones created later, fail to send
+    pub fn close_after_panic(&mut self, message: String) {
+        self.status.set_panicked(message);
+        let (_, receiver) = channel();
+        self.receiver = receiver;
+    }
+
+    /// Wait-free alternative to `sender` for a single thread publishing messages at a high rate, e.g. camera
+    /// following a sensor. Only the newest message published between two frames is applied, older ones are
+    /// dropped instead of being queued. There is one writer, `None` after the first call
+    pub fn take_latest_writer(&mut self) -> Option<TripleBufferWriter<RenderMessage>> {
+        self.latest_writer.take()
+    }
+
+    /// All messages sent so far in order, then the latest published one
+    pub fn drain(&mut self) -> impl Iterator<Item=RenderMessage> + '_ {
+        let latest = self.latest.take();
+        self.receiver.try_iter().chain(latest)
+    }
+}
+
+impl Default for RenderMessages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_is_closed_after_panic() {
+        let mut messages = RenderMessages::new();
+        let sender = messages.sender();
+        let status = messages.status();
+        sender.send(RenderMessage::SetVsync(true)).unwrap();
+        assert!(!status.is_stopped());
+
+        messages.close_after_panic("boom".to_string());
+        assert!(status.is_stopped());
+        assert_eq!(status.panic_message().as_deref(), Some("boom"));
+        assert!(sender.send(RenderMessage::SetVsync(false)).is_err());
+        assert!(messages.sender().send(RenderMessage::SetVsync(false)).is_err());
+        assert_eq!(messages.drain().count(), 0);
+    }
+}
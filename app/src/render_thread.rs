@@ -0,0 +1,129 @@
+use log::warn;
+
+/// Scheduling priority of the render thread
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ThreadPriority {
+    /// Nice value -10
+    High,
+    /// Nice value 10, e.g. for background rendering which should not slow down other apps
+    Low,
+    /// Unix nice value, from -20 (highest) to 19 (lowest)
+    Nice(i32),
+}
+
+impl ThreadPriority {
+    #[cfg_attr(not(all(feature = "thread_scheduling", any(target_os = "linux", target_os = "android"))), allow(dead_code))]
+    fn nice(self) -> i32 {
+        match self {
+            ThreadPriority::High => -10,
+            ThreadPriority::Low => 10,
+            ThreadPriority::Nice(nice) => nice.clamp(-20, 19),
+        }
+    }
+}
+
+/// Name, priority and CPU affinity of the thread which handles window events and renders frames.
+///
+/// Rendering runs on the event loop thread, so settings are applied to the thread calling `winit::run`
+/// (the main thread on Android). Name is always shown in sparkles traces. Priority and affinity require
+/// the `thread_scheduling` feature and are applied on Linux and Android only: raising priority above
+/// normal needs `CAP_SYS_NICE` or a raised `RLIMIT_NICE`, and Android may ignore affinity for apps
+/// in background. Failures are logged and rendering continues with defaults.
+///
+/// OS settings are applied with libc directly instead of the `thread-priority` crate: it has no affinity
+/// API, and maps priorities to its own scale instead of nice values.
+#[derive(Clone, Debug)]
+pub struct RenderThreadConfig {
+    /// Also set as OS thread name on Linux and Android, truncated to 15 bytes. Not set on the main thread
+    /// of the process (e.g. with `winit::run` on desktop Linux), as its name is the process name shown by
+    /// `ps` and used by `killall`
+    pub name: String,
+    /// `None` keeps the inherited priority
+    pub priority: Option<ThreadPriority>,
+    /// Indices of CPU cores the thread may run on. `None` or empty keeps the inherited mask
+    pub affinity: Option<Vec<usize>>,
+}
+
+impl Default for RenderThreadConfig {
+    fn default() -> Self {
+        Self {
+            name: "render".to_string(),
+            priority: None,
+            affinity: None,
+        }
+    }
+}
+
+impl RenderThreadConfig {
+    /// Apply settings to the current thread. Sparkles must be initialized before
+    pub(crate) fn apply_to_current_thread(&self) {
+        sparkles::set_cur_thread_name(self.name.clone());
+        self.apply_os_settings();
+    }
+
+    #[cfg(all(feature = "thread_scheduling", any(target_os = "linux", target_os = "android")))]
+    fn apply_os_settings(&self) {
+        use std::ffi::CString;
+        use log::{debug, info};
+
+        let main_thread = unsafe { libc::gettid() == libc::getpid() };
+        let name: Vec<u8> = self.name.bytes().filter(|b| *b != 0).take(15).collect();
+        if main_thread {
+            debug!("Render thread is the main thread, process name is kept");
+        } else if let Ok(name) = CString::new(name) {
+            let res = unsafe { libc::prctl(libc::PR_SET_NAME, name.as_ptr() as libc::c_ulong, 0, 0, 0) };
+            if res != 0 {
+                warn!("Failed to set render thread name: {}", std::io::Error::last_os_error());
+            }
+        }
+
+        if let Some(priority) = self.priority {
+            // on Linux nice value is per thread when set by thread id
+            let res = unsafe {
+                let tid = libc::gettid();
+                libc::setpriority(libc::PRIO_PROCESS, tid as libc::id_t, priority.nice())
+            };
+            if res != 0 {
+                warn!("Failed to set render thread priority {:?}: {}", priority, std::io::Error::last_os_error());
+            } else {
+                info!("Render thread priority set to {:?}", priority);
+            }
+        }
+
+        if let Some(cores) = self.affinity.as_ref().filter(|cores| !cores.is_empty()) {
+            // CPU_SET panics on indices which don't fit into cpu_set_t
+            let (cores, invalid): (Vec<usize>, Vec<usize>) = cores.iter()
+                .partition(|&&core| core < libc::CPU_SETSIZE as usize);
+            if !invalid.is_empty() {
+                warn!("Render thread affinity: cores {:?} are out of range, at most {} cores are supported",
+                      invalid, libc::CPU_SETSIZE);
+            }
+            if cores.is_empty() {
+                return;
+            }
+            let res = unsafe {
+                let mut set: libc::cpu_set_t = std::mem::zeroed();
+                for &core in &cores {
+                    libc::CPU_SET(core, &mut set);
+                }
+                libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set)
+            };
+            if res != 0 {
+                warn!("Failed to set render thread affinity {:?}: {}", cores, std::io::Error::last_os_error());
+            } else {
+                info!("Render thread affinity set to cores {:?}", cores);
+            }
+        }
+    }
+
+    #[cfg(not(all(feature = "thread_scheduling", any(target_os = "linux", target_os = "android"))))]
+    fn apply_os_settings(&self) {
+        if self.priority.is_some() || self.affinity.as_ref().is_some_and(|cores| !cores.is_empty()) {
+            if cfg!(feature = "thread_scheduling") {
+                warn!("Render thread priority and affinity are supported on Linux and Android only, ignored");
+            } else {
+                warn!("Render thread priority and affinity require `thread_scheduling` feature, ignored");
+            }
+        }
+    }
+}
@@ -7,13 +7,15 @@ use render_core::pipeline::{PipelineDesc, UniformBindingType, UniformBindingsDes
 use render_core::state::StateUpdatesBytes;
 use render_core::state::uniform::{UniformBufferState, UniformImageState};
 use render_core::use_shader;
-use crate::scene::uniforms::{MapStats, Time};
+use crate::scene::uniforms::{Camera, MapStats, Time};
 
 define_layout! {
     pub struct CircleAttributes {
         pub color: vec4<0>,
         pub pos: vec2<0>,
         pub trig_time: int<0>,
+        /// Width of the anti-aliased edge in screen pixels. 0 keeps radial falloff over the whole circle
+        pub feather: float<0>,
     }
 }
 
@@ -22,19 +24,24 @@ pub struct CirclePipleine;
 
 impl PipelineDesc for CirclePipleine {
     type PerInsAttrib = CircleAttributes;
-    type Uniforms<'a> = (&'a UniformBufferState<Time>, &'a UniformBufferState<MapStats>, &'a UniformImageState);
+    type Uniforms<'a> = (&'a UniformBufferState<Time>, &'a UniformBufferState<MapStats>, &'a UniformImageState,
+                         &'a UniformBufferState<Camera>);
     const SHADERS: (&'static [u8], &'static [u8]) = use_shader!("circle");
     fn get_uniform_ids(uniforms: Self::Uniforms<'_>) -> UniformBindingsDesc {
-        let (time, map_stats, image) = uniforms;
+        let (time, map_stats, image, camera) = uniforms;
         UniformBindingsDesc {
             image_bindings: smallvec![(2, image.id())],
-            buffer_bindings: smallvec![(0, time.id()), (1, map_stats.id())],
+            buffer_bindings: smallvec![(0, time.id()), (1, map_stats.id()), (3, camera.id())],
+            dynamic_offsets: smallvec![],
+            vertex_buffers: smallvec![],
+            bindless_texture: None,
         }
     }
     fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]> {
         smallvec![(0, UniformBindingType::UniformBuffer),
             (1, UniformBindingType::UniformBuffer),
-            (2, UniformBindingType::CombinedImageSampler)]
+            (2, UniformBindingType::CombinedImageSampler),
+            (3, UniformBindingType::UniformBuffer)]
     }
     const VERTEX_ASSEMBLY: VertexAssembly = VertexAssembly::TriangleStrip;
     const VERTICES_PER_INSTANCE: usize = 4;
@@ -46,6 +53,114 @@ impl Default for CircleAttributes {
             color: [1.0, 1.0, 1.0, 0.0].into(),
             pos: [0.0, 0.0].into(),
             trig_time: 0.into(),
+            feather: 0.0.into(),
         }
     }
-}
\ No newline at end of file
+}
+#[cfg(all(test, feature = "vulkan"))]
+mod tests {
+    use std::path::Path;
+    use std::time::{Duration, Instant};
+    use render::CollectDrawStateUpdates;
+    use render::util::golden::check_golden;
+    use render::vulkan_backend::VulkanBackend;
+    use render::vulkan_backend::config::VulkanRenderConfig;
+    use render::vulkan_backend::render_pass::ClearBehavior;
+    use render::vulkan_backend::surface_context::SurfaceId;
+    use render_core::collect_state::single_object::SingleObject;
+    use crate::config::{SceneClock, TimeSource};
+    use super::*;
+
+    const SIZE: (u32, u32) = (64, 64);
+    const STEP_MS: u32 = 100;
+    /// Rasterization, texture filtering and derivatives differ slightly between GPUs
+    const TOLERANCE: u8 = 12;
+
+    #[derive(CollectDrawStateUpdates)]
+    struct CircleScene {
+        time: UniformBufferState<Time>,
+        map_stats: UniformBufferState<MapStats>,
+        camera: UniformBufferState<Camera>,
+        image: UniformImageState,
+        circle: SingleObject<CirclePipleine>,
+    }
+
+    impl CircleScene {
+        fn new(r: f32, camera: Camera, circle: CircleAttributes) -> Self {
+            let time = Time { time: 0.into() }.to_new_uniform();
+            let map_stats = MapStats {
+                r: r.into(),
+                ar: 1_500.0.into(),
+                aspect: 1.0.into(),
+            }.to_new_uniform();
+            let camera = camera.to_new_uniform();
+            // 2x2 texels, none of which is discarded as white
+            let image = UniformImageState::new("golden_checker.png".to_string());
+            let circle = SingleObject::new(circle, (&time, &map_stats, &image, &camera));
+            Self {
+                time,
+                map_stats,
+                camera,
+                image,
+                circle,
+            }
+        }
+    }
+
+    /// Render `frames` frames of the scene with `FixedStep` time to a headless surface and compare the last one
+    /// with `golden/<name>.png`, see `check_golden`
+    fn check_scene(name: &str, mut scene: CircleScene, frames: u32) -> anyhow::Result<()> {
+        let mut backend = VulkanBackend::new_headless(SIZE, VulkanRenderConfig::default())?;
+        let clear = ClearBehavior::Clear([0.0; 3]);
+        // texture is requested by the first frame and loaded by another thread
+        let start = Instant::now();
+        loop {
+            backend.render(SurfaceId::MAIN, &mut scene, clear)?;
+            if backend.loading_image_count(SurfaceId::MAIN) == Some(0) {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(10), "Texture is not loaded in time");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        let mut clock = SceneClock::new(TimeSource::FixedStep(STEP_MS));
+        for _ in 0..frames {
+            clock.tick();
+            scene.time.set(Time { time: (clock.time_ms() as i32).into() });
+            backend.render(SurfaceId::MAIN, &mut scene, clear)?;
+        }
+        let (rgba, extent) = backend.read_frame(SurfaceId::MAIN)?;
+        let golden_path = Path::new(env!("CARGO_MANIFEST_DIR")).join("golden").join(format!("{}.png", name));
+        check_golden(&rgba, extent, &golden_path, TOLERANCE)
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn radial_falloff() {
+        let scene = CircleScene::new(1.6, Camera {
+            pan: [0.0, 0.0].into(),
+            zoom: 1.0.into(),
+        }, CircleAttributes {
+            color: [1.0, 1.0, 1.0, 1.0].into(),
+            trig_time: i32::MAX.into(),
+            ..Default::default()
+        });
+        check_scene("circle_radial", scene, 1).unwrap();
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn feathered_edge_fading_with_camera() {
+        // fades over `ar` ms before `trig_time`: 0.47 of the color after 3 frames
+        let scene = CircleScene::new(1.0, Camera {
+            pan: [-0.1, 0.05].into(),
+            zoom: 1.25.into(),
+        }, CircleAttributes {
+            color: [1.0, 0.8, 0.6, 1.0].into(),
+            pos: [0.2, -0.1].into(),
+            trig_time: 1_000.into(),
+            feather: 1.5.into(),
+        });
+        check_scene("circle_feathered", scene, 3).unwrap();
+    }
+}
@@ -1,29 +1,169 @@
+use web_time::Instant;
+use log::info;
+use winit::event::{ElementState, MouseScrollDelta, WindowEvent};
+use winit::window::Window;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
 use render::CollectDrawStateUpdates;
 use render_core::collect_state::ordered_object_pool::OrderedObjectPool;
 use render_core::collect_state::single_object::SingleObject;
 use render_core::layout::LayoutInfo;
 use render_core::state::uniform::{UniformBufferState, UniformImageState};
-use crate::scene::circle::{CircleAttributes, CirclePipleine};
-use crate::scene::uniforms::{MapStats, Time};
+use crate::config::SceneClock;
+use crate::input_map::InputAction;
+use crate::render_messages::RenderMessage;
+use crate::scene::circle::{CircleAttributes, CircleAttributesExt, CirclePipleine};
+use crate::scene::text::TextLayer;
+use crate::scene::textured_quad::{TexturedQuadAttributes, TexturedQuadPipeline};
+use crate::scene::uniforms::{Camera, CameraExt, MapStats, Time};
+use crate::touch::TouchTracker;
+#[cfg(feature = "gamepad")]
+use crate::gamepad::GamepadAction;
 
 pub mod uniforms;
 pub mod circle;
+pub mod textured_quad;
+pub mod textured_array_quad;
+pub mod text;
+pub mod tonemap;
+pub mod two_targets;
+
+/// Scene driven by `AppState`: receives window events, is updated once per frame and then rendered.
+///
+/// Window-level events (close, fullscreen, vsync toggle) are handled by the app, all events are still
+/// passed to the scene.
+pub trait SceneTrait: render_core::collect_state::CollectDrawStateUpdates {
+    fn new(aspect: f32) -> Self;
+    fn handle_event(&mut self, event: &WindowEvent, window: &Window);
+    /// Called before rendering each frame
+    fn update(&mut self, clock: &SceneClock);
+
+    /// Used when clear color is not set with `RenderMessage::SetClearColor`
+    fn clear_color(&self) -> [f32; 3] {
+        [0.0, 0.0, 0.0]
+    }
+
+    /// Messages not handled by the app itself, such as `RenderMessage::SetCamera`
+    fn handle_render_message(&mut self, _message: RenderMessage) {}
+
+    /// Actions bound in `InputMap`, both press and release. Raw event is passed to `handle_event` as well
+    fn handle_action(&mut self, _action: InputAction, _state: ElementState) {}
+
+    #[cfg(feature = "gamepad")]
+    fn handle_gamepad(&mut self, _action: GamepadAction) {}
+}
+
+const MIN_ZOOM: f32 = 0.25;
+const MAX_ZOOM: f32 = 8.0;
+/// Zoom factor for a single scroll line
+const ZOOM_STEP: f32 = 1.1;
+/// Touchpads report scroll in physical pixels, this is in logical ones
+const PIXELS_PER_SCROLL_LINE: f32 = 40.0;
 
 #[derive(CollectDrawStateUpdates)]
 pub struct Scene {
     // uniforms
     pub time: UniformBufferState<Time>,
     pub map_stats: UniformBufferState<MapStats>,
+    pub camera: UniformBufferState<Camera>,
     pub image: UniformImageState,
 
 
     // objects
     pub mirror_lamp: SingleObject<CirclePipleine>,
     pub trail: OrderedObjectPool<CirclePipleine, u64>,
+    pub image_preview: SingleObject<TexturedQuadPipeline>,
+    pub text: TextLayer,
+
+    // input state
+    #[collect(skip)]
+    bg_color: [f32; 3],
+    #[collect(skip)]
+    last_touch_pos: [f32; 2],
+    #[collect(skip)]
+    prev_touch_event_time: Instant,
+    /// Lamp follows a single finger, two fingers zoom the camera
+    #[collect(skip)]
+    touches: TouchTracker,
+    /// Right mouse button is held
+    #[collect(skip)]
+    panning: bool,
+    #[collect(skip)]
+    last_cursor_pos: Option<PhysicalPosition<f64>>,
+    /// Scene time of the last trail point, ms
+    #[collect(skip)]
+    trail_last_update: u64,
+    /// Physical size from the last `Resized` event, positions of input events are in the same pixels
+    #[collect(skip)]
+    window_size: Option<PhysicalSize<u32>>,
+    /// From the last `ScaleFactorChanged` event
+    #[collect(skip)]
+    scale_factor: Option<f64>,
 }
 
 impl Scene {
-    pub fn new(aspect: f32) -> Scene {
+    /// White ASCII text with the top-left corner at `pos` in NDC. Must be called every frame from `update`
+    pub fn draw_text(&mut self, pos: [f32; 2], text: &str) {
+        self.text.draw_text(pos, text, [1.0, 1.0, 1.0, 1.0]);
+    }
+
+    /// Move touch position, mirror lamp moves in the opposite direction
+    fn move_touch_pos(&mut self, delta: [f32; 2]) {
+        self.mirror_lamp.modify_pos(|mut pos| {
+            pos[0] -= delta[0];
+            pos[1] -= delta[1];
+            pos
+        });
+        self.last_touch_pos[0] += delta[0];
+        self.last_touch_pos[1] += delta[1];
+    }
+
+    fn reset_touch_pos(&mut self) {
+        self.mirror_lamp.set_pos([0.0, 0.0]);
+        self.last_touch_pos = [0.0, 0.0];
+    }
+
+    /// Multiply camera zoom, result is clamped to `MIN_ZOOM..=MAX_ZOOM`
+    fn zoom_by(&mut self, factor: f32) {
+        self.camera.modify_zoom(|zoom| (zoom * factor).clamp(MIN_ZOOM, MAX_ZOOM));
+    }
+
+    /// Window may already report a new size before its `Resized` event is delivered
+    fn window_size(&self, window: &Window) -> PhysicalSize<u32> {
+        self.window_size.unwrap_or_else(|| window.inner_size())
+    }
+
+    /// Position in physical pixels to NDC
+    fn to_ndc(&self, position: PhysicalPosition<f64>, window: &Window) -> [f32; 2] {
+        let size = self.window_size(window);
+        [
+            (position.x as f32 / size.width as f32) * 2.0 - 1.0,
+            (position.y as f32 / size.height as f32) * 2.0 - 1.0,
+        ]
+    }
+
+    fn set_aspect(&mut self, size: PhysicalSize<u32>) {
+        if size.width == 0 || size.height == 0 {
+            return;
+        }
+        let aspect = size.width as f32 / size.height as f32;
+        self.map_stats.modify(|stats| {
+            stats.aspect = aspect.into();
+        });
+        self.text.set_aspect(aspect);
+    }
+
+    /// Move camera by the offset in NDC
+    fn pan_by(&mut self, delta: [f32; 2]) {
+        self.camera.modify_pan(|mut pan| {
+            pan[0] += delta[0];
+            pan[1] += delta[1];
+            pan
+        });
+    }
+}
+
+impl SceneTrait for Scene {
+    fn new(aspect: f32) -> Scene {
         let time = Time {
             time: 0.into()
         }.to_new_uniform();
@@ -34,22 +174,212 @@ impl Scene {
             ar: 1_500.0.into()
         }.to_new_uniform();
 
+        let camera = Camera {
+            pan: [0.0, 0.0].into(),
+            zoom: 1.0.into(),
+        }.to_new_uniform();
+
         let image = UniformImageState::new("bulb.jpg".to_string());
 
         let lamp2 = SingleObject::new(CircleAttributes {
             color: [0.6, 0.1, 0.8, 1.0].into(),
             pos: [0.0, 0.0].into(),
             trig_time: i32::MAX.into(),
-        }, (&time, &map_stats, &image));
+            feather: 1.5.into(),
+        }, (&time, &map_stats, &image, &camera));
         
-        let trail = OrderedObjectPool::new((&time, &map_stats, &image));
+        // trail keeps 2 seconds of points, added every 200ms
+        let trail = OrderedObjectPool::with_capacity((&time, &map_stats, &image, &camera), 16);
+
+        // small preview of the texture in the bottom-left corner
+        let image_preview = SingleObject::new(TexturedQuadAttributes {
+            pos: [-0.95, 0.65].into(),
+            size: [0.3, 0.3].into(),
+            ..Default::default()
+        }, &image).with_z_order(1);
+
+        let text = TextLayer::new(0.06, aspect, 32);
         
         Self {
             time,
             map_stats,
+            camera,
             mirror_lamp: lamp2,
             image,
-            trail
+            trail,
+            image_preview,
+            text,
+
+            bg_color: [0.0, 0.0, 0.0],
+            last_touch_pos: [0.0, 0.0],
+            prev_touch_event_time: Instant::now(),
+            touches: TouchTracker::new(),
+            panning: false,
+            last_cursor_pos: None,
+            trail_last_update: 0,
+            window_size: None,
+            scale_factor: None,
+        }
+    }
+
+    fn handle_event(&mut self, event: &WindowEvent, window: &Window) {
+        match event {
+            WindowEvent::Touch(t) => {
+                info!("Touch event: {:?}", t);
+                let now = Instant::now();
+                let elapsed = now.duration_since(self.prev_touch_event_time);
+                self.prev_touch_event_time = now;
+                info!("Elapsed: {:?}", elapsed);
+
+                let pos = self.to_ndc(t.location, window);
+                if let Some(pinch) = self.touches.handle_touch(t, pos) {
+                    self.zoom_by(pinch);
+                }
+                if self.touches.count() <= 1 {
+                    self.last_touch_pos = pos;
+                    self.mirror_lamp.set_pos([-pos[0], -pos[1]])
+                }
+            }
+
+            // end events of fingers are not delivered to unfocused window
+            WindowEvent::Focused(false) => self.touches.clear(),
+
+            // button release is not delivered when cursor is outside of the window
+            WindowEvent::CursorLeft { .. } => {
+                self.panning = false;
+                self.last_cursor_pos = None;
+            }
+
+            WindowEvent::CursorMoved { position, .. } => {
+                if let (true, Some(last)) = (self.panning, self.last_cursor_pos) {
+                    let size = self.window_size(window);
+                    let delta = [
+                        (position.x - last.x) as f32 / size.width as f32 * 2.0,
+                        (position.y - last.y) as f32 / size.height as f32 * 2.0,
+                    ];
+                    self.pan_by(delta);
+                }
+                self.last_cursor_pos = Some(*position);
+            }
+
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(pos) => {
+                        let scale_factor = self.scale_factor.unwrap_or_else(|| window.scale_factor());
+                        (pos.y / scale_factor) as f32 / PIXELS_PER_SCROLL_LINE
+                    }
+                };
+                self.zoom_by(ZOOM_STEP.powf(lines));
+            }
+
+            WindowEvent::Resized(size) => {
+                self.window_size = Some(*size);
+                self.set_aspect(*size);
+            }
+
+            // physical size changes together with the scale, `Resized` follows if it does
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                info!("Scale factor changed to {}", scale_factor);
+                self.scale_factor = Some(*scale_factor);
+                self.window_size = None;
+                self.set_aspect(window.inner_size());
+            }
+            _ => {}
+        }
+    }
+
+    fn update(&mut self, clock: &SceneClock) {
+        let time_ms = clock.time_ms();
+        let now = time_ms as f32;
+
+        //recalculate bg
+        let normalized_touch_pos = [
+            (self.last_touch_pos[0] + 1.0) / 2.0,
+            (self.last_touch_pos[1] + 1.0) / 2.0,
+        ];
+
+        let new_color = [
+            normalized_touch_pos[0] * 0.6 + normalized_touch_pos[1] * 0.3 + (now / 600.0).sin() * 0.05,
+            normalized_touch_pos[0] * 0.3 + normalized_touch_pos[1] * 0.3 + (now / 600.0 + 1.0).sin() * 0.05,
+            normalized_touch_pos[1] * 0.6 + normalized_touch_pos[0] * 0.3 + (now / 600.0 + 2.0).sin() * 0.05,
+        ];
+
+        // adjust new_color, depending on color distance
+        let color_dir = [
+            new_color[0] - self.bg_color[0],
+            new_color[1] - self.bg_color[1],
+            new_color[2] - self.bg_color[2],
+        ];
+
+        let elapsed = clock.frame_dt();
+        let color_dist = (color_dir[0].powi(2) + color_dir[1].powi(2) + color_dir[2].powi(2)).sqrt();
+        let color_dist = (color_dist + 0.5) * elapsed * 20.0;
+        let color_change = [
+            color_dir[0] * color_dist,
+            color_dir[1] * color_dist,
+            color_dir[2] * color_dist,
+        ];
+
+        self.bg_color[0] += color_change[0];
+        self.bg_color[1] += color_change[1];
+        self.bg_color[2] += color_change[2];
+
+        // update trail
+        self.time.set(Time{time: (time_ms as i32).into()});
+        if time_ms - self.trail_last_update > 200 {
+            let trail_id = self.trail_last_update;
+
+            self.trail.create(trail_id, CircleAttributes {
+                pos: [self.last_touch_pos[0], self.last_touch_pos[1]].into(),
+                color: [1.0, 0.7, 1.0, 1.0].into(),
+                trig_time: (trail_id as i32 + 1_500).into(),
+                ..Default::default()
+            });
+
+            self.trail.auto_remove(trail_id.saturating_sub(2_000));
+
+            self.trail_last_update = time_ms;
+        }
+
+        self.text.begin();
+        let fps = if elapsed > 0.0 { 1.0 / elapsed } else { 0.0 };
+        self.draw_text([-0.98, -0.98], &format!("FPS: {:.0}", fps));
+        self.text.finish();
+    }
+
+    fn clear_color(&self) -> [f32; 3] {
+        self.bg_color
+    }
+
+    fn handle_render_message(&mut self, message: RenderMessage) {
+        if let RenderMessage::SetCamera { pan, zoom } = message {
+            self.camera.set(Camera {
+                pan: pan.into(),
+                zoom: zoom.clamp(MIN_ZOOM, MAX_ZOOM).into(),
+            });
+        }
+    }
+
+    fn handle_action(&mut self, action: InputAction, state: ElementState) {
+        let pressed = state == ElementState::Pressed;
+        match action {
+            // lamp moves when the key is released
+            InputAction::MoveLeft if !pressed => self.move_touch_pos([-0.1, 0.0]),
+            InputAction::MoveRight if !pressed => self.move_touch_pos([0.1, 0.0]),
+            InputAction::MoveUp if !pressed => self.move_touch_pos([0.0, -0.1]),
+            InputAction::MoveDown if !pressed => self.move_touch_pos([0.0, 0.1]),
+            InputAction::ResetPosition if pressed => self.reset_touch_pos(),
+            InputAction::Pan => self.panning = pressed,
+            _ => {}
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn handle_gamepad(&mut self, action: GamepadAction) {
+        match action {
+            GamepadAction::Move(delta) => self.move_touch_pos(delta),
+            GamepadAction::Reset => self.reset_touch_pos(),
         }
     }
 }
@@ -0,0 +1,181 @@
+use std::mem::offset_of;
+use smallvec::{smallvec, SmallVec};
+use render::{define_layout, CollectDrawStateUpdates};
+use render_core::collect_state::ordered_object_pool::OrderedObjectPool;
+use render_core::layout::{LayoutInfo, MemberMeta};
+use render_core::layout::types::*;
+use render_core::pipeline::{PipelineDesc, UniformBindingType, UniformBindingsDesc, VertexAssembly};
+use render_core::state::StateUpdatesBytes;
+use render_core::state::uniform::UniformImageState;
+use render_core::use_shader;
+
+/// Monochrome atlas of printable ASCII, 16 glyphs per row starting from space
+pub const GLYPH_ATLAS: &str = "font_atlas.png";
+const ATLAS_COLUMNS: u32 = 16;
+const ATLAS_ROWS: u32 = 6;
+const ATLAS_SIZE: [f32; 2] = [256.0, 192.0];
+/// Width to height ratio of a glyph cell
+const GLYPH_ASPECT: f32 = 0.5;
+
+define_layout! {
+    pub struct GlyphAttributes {
+        /// Top-left corner in NDC, y points down
+        pub pos: vec2<0>,
+        /// Width and height in NDC
+        pub size: vec2<0>,
+        /// Atlas region: u0, v0, u1, v1
+        pub uv_rect: vec4<0>,
+        pub color: vec4<0>,
+    }
+}
+
+/// Single glyph quad sampling the glyph atlas, alpha blended with the given color
+#[derive(Default)]
+pub struct TextPipeline;
+
+impl PipelineDesc for TextPipeline {
+    type PerInsAttrib = GlyphAttributes;
+    type Uniforms<'a> = &'a UniformImageState;
+    const SHADERS: (&'static [u8], &'static [u8]) = use_shader!("text");
+    fn get_uniform_ids(atlas: Self::Uniforms<'_>) -> UniformBindingsDesc {
+        UniformBindingsDesc {
+            image_bindings: smallvec![(0, atlas.id())],
+            buffer_bindings: smallvec![],
+            dynamic_offsets: smallvec![],
+            vertex_buffers: smallvec![],
+            bindless_texture: None,
+        }
+    }
+    fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]> {
+        smallvec![(0, UniformBindingType::CombinedImageSampler)]
+    }
+    const VERTEX_ASSEMBLY: VertexAssembly = VertexAssembly::TriangleStrip;
+    const VERTICES_PER_INSTANCE: usize = 4;
+}
+
+impl Default for GlyphAttributes {
+    fn default() -> Self {
+        Self {
+            pos: [0.0, 0.0].into(),
+            size: [0.0, 0.0].into(),
+            uv_rect: [0.0, 0.0, 0.0, 0.0].into(),
+            color: [1.0, 1.0, 1.0, 1.0].into(),
+        }
+    }
+}
+
+/// Atlas region of ASCII character, other characters are shown as '?'
+fn glyph_uv_rect(c: char) -> [f32; 4] {
+    let c = if (' '..='~').contains(&c) { c } else { '?' };
+    let index = c as u32 - ' ' as u32;
+    let (column, row) = (index % ATLAS_COLUMNS, index / ATLAS_COLUMNS);
+    let cell = [1.0 / ATLAS_COLUMNS as f32, 1.0 / ATLAS_ROWS as f32];
+    // inset by half a texel, so linear filtering does not pick up neighbour glyphs
+    let inset = [0.5 / ATLAS_SIZE[0], 0.5 / ATLAS_SIZE[1]];
+    [
+        column as f32 * cell[0] + inset[0],
+        row as f32 * cell[1] + inset[1],
+        (column + 1) as f32 * cell[0] - inset[0],
+        (row + 1) as f32 * cell[1] - inset[1],
+    ]
+}
+
+/// What glyph object currently shows, to skip uploads of unchanged glyphs
+#[derive(Clone, Copy, PartialEq)]
+struct DrawnGlyph {
+    c: char,
+    pos: [f32; 2],
+    size: [f32; 2],
+    color: [f32; 4],
+}
+
+/// Immediate mode text drawn on top of the scene.
+///
+/// Text is drawn again every frame between `begin` and `finish`. Glyph objects are kept in renderer
+/// and reused, so only changed glyphs are uploaded.
+#[derive(CollectDrawStateUpdates)]
+pub struct TextLayer {
+    pub atlas: UniformImageState,
+    glyphs: OrderedObjectPool<TextPipeline, u32>,
+
+    /// Glyph height in NDC
+    #[collect(skip)]
+    glyph_height: f32,
+    #[collect(skip)]
+    aspect: f32,
+    /// Indexed by glyph key
+    #[collect(skip)]
+    drawn: Vec<DrawnGlyph>,
+    /// Glyphs drawn in the current frame
+    #[collect(skip)]
+    glyph_count: usize,
+}
+
+impl TextLayer {
+    /// `capacity` glyphs are kept in renderer for reuse
+    pub fn new(glyph_height: f32, aspect: f32, capacity: usize) -> Self {
+        let atlas = UniformImageState::new(GLYPH_ATLAS.to_string());
+        let glyphs = OrderedObjectPool::with_capacity(&atlas, capacity).with_z_order(2);
+        Self {
+            atlas,
+            glyphs,
+            glyph_height,
+            aspect,
+            drawn: Vec::new(),
+            glyph_count: 0,
+        }
+    }
+
+    /// Keeps glyphs square in screen space after resize
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.aspect = aspect;
+    }
+
+    /// Width in NDC of the longest line of `text`
+    pub fn text_width(&self, text: &str) -> f32 {
+        let glyph_width = self.glyph_height * GLYPH_ASPECT / self.aspect;
+        text.lines().map(|line| line.chars().count()).max().unwrap_or(0) as f32 * glyph_width
+    }
+
+    pub fn begin(&mut self) {
+        self.glyph_count = 0;
+    }
+
+    /// Lay out ASCII text in a single line per '\n', starting from the top-left corner `pos` in NDC
+    pub fn draw_text(&mut self, pos: [f32; 2], text: &str, color: [f32; 4]) {
+        let size = [self.glyph_height * GLYPH_ASPECT / self.aspect, self.glyph_height];
+        let mut cursor = pos;
+        for c in text.chars() {
+            if c == '\n' {
+                cursor = [pos[0], cursor[1] + size[1]];
+                continue;
+            }
+            if c != ' ' {
+                let drawn = DrawnGlyph { c, pos: cursor, size, color };
+                if self.drawn.get(self.glyph_count) != Some(&drawn) {
+                    self.glyphs.entry(self.glyph_count as u32).set(GlyphAttributes {
+                        pos: cursor.into(),
+                        size: size.into(),
+                        uv_rect: glyph_uv_rect(c).into(),
+                        color: color.into(),
+                    });
+                    if self.glyph_count < self.drawn.len() {
+                        self.drawn[self.glyph_count] = drawn;
+                    } else {
+                        self.drawn.push(drawn);
+                    }
+                }
+                self.glyph_count += 1;
+            }
+            cursor[0] += size[0];
+        }
+    }
+
+    /// Remove glyphs which were not drawn again in this frame
+    pub fn finish(&mut self) {
+        for key in self.glyph_count..self.drawn.len() {
+            self.glyphs.remove(&(key as u32));
+        }
+        self.drawn.truncate(self.glyph_count);
+    }
+}
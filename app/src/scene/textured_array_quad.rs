@@ -0,0 +1,58 @@
+use std::mem::offset_of;
+use smallvec::{smallvec, SmallVec};
+use render::define_layout;
+use render_core::layout::{LayoutInfo, MemberMeta};
+use render_core::layout::types::*;
+use render_core::pipeline::{PipelineDesc, UniformBindingType, UniformBindingsDesc, VertexAssembly};
+use render_core::state::StateUpdatesBytes;
+use render_core::state::uniform::UniformImageArrayState;
+use render_core::use_shader;
+
+define_layout! {
+    pub struct TexturedArrayQuadAttributes {
+        /// Top-left corner in NDC, y points down
+        pub pos: vec2<0>,
+        /// Width and height in NDC
+        pub size: vec2<0>,
+        /// Sampled texture region: u0, v0, u1, v1
+        pub uv_rect: vec4<0>,
+        /// Index of the texture array layer
+        pub layer: float<0>,
+    }
+}
+
+/// Same as `TexturedQuadPipeline`, but each instance samples its own layer of a texture array, so
+/// many sprites share one image binding
+#[derive(Default)]
+pub struct TexturedArrayQuadPipeline;
+
+impl PipelineDesc for TexturedArrayQuadPipeline {
+    type PerInsAttrib = TexturedArrayQuadAttributes;
+    type Uniforms<'a> = &'a UniformImageArrayState;
+    const SHADERS: (&'static [u8], &'static [u8]) = use_shader!("textured_array_quad");
+    fn get_uniform_ids(image: Self::Uniforms<'_>) -> UniformBindingsDesc {
+        UniformBindingsDesc {
+            image_bindings: smallvec![(0, image.id())],
+            buffer_bindings: smallvec![],
+            dynamic_offsets: smallvec![],
+            vertex_buffers: smallvec![],
+            bindless_texture: None,
+        }
+    }
+    fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]> {
+        smallvec![(0, UniformBindingType::CombinedImageSampler)]
+    }
+    const VERTEX_ASSEMBLY: VertexAssembly = VertexAssembly::TriangleStrip;
+    const VERTICES_PER_INSTANCE: usize = 4;
+}
+
+impl Default for TexturedArrayQuadAttributes {
+    fn default() -> Self {
+        Self {
+            pos: [0.0, 0.0].into(),
+            size: [1.0, 1.0].into(),
+            uv_rect: [0.0, 0.0, 1.0, 1.0].into(),
+            layer: 0.0.into(),
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::mem::offset_of;
+use smallvec::{smallvec, SmallVec};
+use render::define_layout;
+use render_core::layout::{LayoutInfo, MemberMeta};
+use render_core::layout::types::*;
+use render_core::pipeline::{PipelineDesc, UniformBindingType, UniformBindingsDesc, VertexAssembly};
+use render_core::state::StateUpdatesBytes;
+use render_core::state::uniform::UniformImageState;
+use render_core::use_shader;
+
+define_layout! {
+    pub struct TexturedQuadAttributes {
+        /// Top-left corner in NDC, y points down
+        pub pos: vec2<0>,
+        /// Width and height in NDC
+        pub size: vec2<0>,
+        /// Sampled texture region: u0, v0, u1, v1
+        pub uv_rect: vec4<0>,
+    }
+}
+
+/// Axis-aligned quad sampling a single image, for sprites and UI
+#[derive(Default)]
+pub struct TexturedQuadPipeline;
+
+impl PipelineDesc for TexturedQuadPipeline {
+    type PerInsAttrib = TexturedQuadAttributes;
+    type Uniforms<'a> = &'a UniformImageState;
+    const SHADERS: (&'static [u8], &'static [u8]) = use_shader!("textured_quad");
+    fn get_uniform_ids(image: Self::Uniforms<'_>) -> UniformBindingsDesc {
+        UniformBindingsDesc {
+            image_bindings: smallvec![(0, image.id())],
+            buffer_bindings: smallvec![],
+            dynamic_offsets: smallvec![],
+            vertex_buffers: smallvec![],
+            bindless_texture: None,
+        }
+    }
+    fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]> {
+        smallvec![(0, UniformBindingType::CombinedImageSampler)]
+    }
+    const VERTEX_ASSEMBLY: VertexAssembly = VertexAssembly::TriangleStrip;
+    const VERTICES_PER_INSTANCE: usize = 4;
+}
+
+impl Default for TexturedQuadAttributes {
+    fn default() -> Self {
+        Self {
+            pos: [0.0, 0.0].into(),
+            size: [1.0, 1.0].into(),
+            uv_rect: [0.0, 0.0, 1.0, 1.0].into(),
+        }
+    }
+}
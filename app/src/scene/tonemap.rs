@@ -0,0 +1,51 @@
+use std::mem::offset_of;
+use smallvec::{smallvec, SmallVec};
+use render::define_layout;
+use render_core::layout::{LayoutInfo, MemberMeta};
+use render_core::layout::types::*;
+use render_core::pipeline::{PipelineDesc, UniformBindingType, UniformBindingsDesc, VertexAssembly};
+use render_core::state::StateUpdatesBytes;
+use render_core::use_shader;
+
+define_layout! {
+    pub struct TonemapAttributes {
+        /// Scene color multiplier before tone mapping
+        pub exposure: float<0>,
+    }
+}
+
+/// Fullscreen pass of the post subpass, reads scene color as input attachment.
+///
+/// Requires `VulkanRenderConfig::post_subpass`. Without a post subpass object the swapchain image is left undefined.
+#[derive(Default)]
+pub struct TonemapPipeline;
+
+impl PipelineDesc for TonemapPipeline {
+    type PerInsAttrib = TonemapAttributes;
+    type Uniforms<'a> = ();
+    const SHADERS: (&'static [u8], &'static [u8]) = use_shader!("tonemap");
+    fn get_uniform_ids(_: Self::Uniforms<'_>) -> UniformBindingsDesc {
+        // input attachment is bound by renderer
+        UniformBindingsDesc {
+            image_bindings: smallvec![],
+            buffer_bindings: smallvec![],
+            dynamic_offsets: smallvec![],
+            vertex_buffers: smallvec![],
+            bindless_texture: None,
+        }
+    }
+    fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]> {
+        smallvec![(0, UniformBindingType::InputAttachment)]
+    }
+    const VERTEX_ASSEMBLY: VertexAssembly = VertexAssembly::TriangleStrip;
+    const VERTICES_PER_INSTANCE: usize = 4;
+    const SUBPASS: u32 = 1;
+}
+
+impl Default for TonemapAttributes {
+    fn default() -> Self {
+        Self {
+            exposure: 1.0.into(),
+        }
+    }
+}
@@ -0,0 +1,177 @@
+use std::mem::offset_of;
+use smallvec::{smallvec, SmallVec};
+use render::define_layout;
+use render_core::layout::{LayoutInfo, MemberMeta};
+use render_core::layout::types::*;
+use render_core::pipeline::{PipelineDesc, UniformBindingType, UniformBindingsDesc, VertexAssembly};
+use render_core::state::StateUpdatesBytes;
+use render_core::use_shader;
+
+define_layout! {
+    pub struct TwoTargetsAttributes {
+        pub color: vec4<0>,
+        /// Written to the first extra color attachment
+        pub extra_color: vec4<0>,
+    }
+}
+
+/// Fills the viewport with a color and the first extra color attachment with another one.
+///
+/// Requires one entry in `VulkanRenderConfig::extra_color_attachments`.
+#[derive(Default)]
+pub struct TwoTargetsPipeline;
+
+impl PipelineDesc for TwoTargetsPipeline {
+    type PerInsAttrib = TwoTargetsAttributes;
+    type Uniforms<'a> = ();
+    const SHADERS: (&'static [u8], &'static [u8]) = use_shader!("two_targets");
+    fn get_uniform_ids(_: Self::Uniforms<'_>) -> UniformBindingsDesc {
+        UniformBindingsDesc {
+            image_bindings: smallvec![],
+            buffer_bindings: smallvec![],
+            dynamic_offsets: smallvec![],
+            vertex_buffers: smallvec![],
+            bindless_texture: None,
+        }
+    }
+    fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]> {
+        smallvec![]
+    }
+    const VERTEX_ASSEMBLY: VertexAssembly = VertexAssembly::TriangleStrip;
+    const VERTICES_PER_INSTANCE: usize = 4;
+}
+
+impl Default for TwoTargetsAttributes {
+    fn default() -> Self {
+        Self {
+            color: [0.0, 0.0, 0.0, 1.0].into(),
+            extra_color: [0.0, 0.0, 0.0, 0.0].into(),
+        }
+    }
+}
+
+#[cfg(all(test, feature = "vulkan"))]
+mod tests {
+    use ash::vk;
+    use render::CollectDrawStateUpdates;
+    use render::vulkan_backend::VulkanBackend;
+    use render::vulkan_backend::config::{InFlightFrames, VulkanRenderConfig};
+    use render::vulkan_backend::render_pass::ClearBehavior;
+    use render::vulkan_backend::surface_context::SurfaceId;
+    use render_core::collect_state::ordered_object_pool::OrderedObjectPool;
+    use render_core::collect_state::single_object::SingleObject;
+    use super::*;
+
+    #[derive(CollectDrawStateUpdates)]
+    struct TwoTargetsScene {
+        quad: SingleObject<TwoTargetsPipeline>,
+    }
+
+    /// Quad recolored every frame, covered by another quad for a few frames
+    #[derive(CollectDrawStateUpdates)]
+    struct InFlightScene {
+        quad: SingleObject<TwoTargetsPipeline>,
+        covers: OrderedObjectPool<TwoTargetsPipeline, u32>,
+    }
+
+    fn to_unorm(color: [f32; 4]) -> [u8; 4] {
+        color.map(|c| (c * 255.0).round() as u8)
+    }
+
+    /// Every texel equals `expected` up to rounding of the UNORM conversion
+    fn assert_filled(rgba: &[u8], expected: [u8; 4]) {
+        assert!(!rgba.is_empty());
+        for (i, texel) in rgba.chunks_exact(4).enumerate() {
+            let close = texel.iter().zip(expected).all(|(a, e)| a.abs_diff(e) <= 1);
+            assert!(close, "Texel {} is {:?}, expected {:?}", i, texel, expected);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn both_targets_are_written() {
+        let config = VulkanRenderConfig {
+            extra_color_attachments: vec![vk::Format::R8G8B8A8_UNORM],
+            ..Default::default()
+        };
+        let mut backend = VulkanBackend::new_headless((16, 8), config).unwrap();
+        let mut scene = TwoTargetsScene {
+            quad: SingleObject::new(TwoTargetsAttributes {
+                color: [0.2, 0.6, 1.0, 1.0].into(),
+                extra_color: [1.0, 0.0, 0.4, 0.8].into(),
+            }, ()),
+        };
+        backend.render(SurfaceId::MAIN, &mut scene, ClearBehavior::Clear([0.0; 3])).unwrap();
+
+        let (main, extent) = backend.read_frame(SurfaceId::MAIN).unwrap();
+        assert_eq!((extent.width, extent.height), (16, 8));
+        assert_eq!(main.len(), 16 * 8 * 4);
+        assert_filled(&main, [51, 153, 255, 255]);
+
+        let (extra, extent) = backend.read_extra_color_attachment(SurfaceId::MAIN, 0).unwrap();
+        assert_eq!((extent.width, extent.height), (16, 8));
+        assert_filled(&extra, [255, 0, 102, 204]);
+
+        assert!(backend.read_extra_color_attachment(SurfaceId::MAIN, 1).is_err());
+    }
+
+    /// Frames are submitted without waiting for each other, except where the test reads them back
+    fn render_frames_in_flight(in_flight_frames: InFlightFrames) {
+        let config = VulkanRenderConfig {
+            in_flight_frames,
+            extra_color_attachments: vec![vk::Format::R8G8B8A8_UNORM],
+            ..Default::default()
+        };
+        let mut backend = VulkanBackend::new_headless((16, 8), config).unwrap();
+        let frame_color = |frame: u32| [frame as f32 / 16.0, 0.5, 1.0 - frame as f32 / 16.0, 1.0];
+        let cover_color = [0.0, 1.0, 0.0, 1.0];
+        let mut scene = InFlightScene {
+            quad: SingleObject::new(TwoTargetsAttributes {
+                color: frame_color(0).into(),
+                extra_color: [0.0; 4].into(),
+            }, ()),
+            covers: OrderedObjectPool::new(()).with_z_order(1),
+        };
+
+        for frame in 0..12 {
+            scene.quad.modify(|attributes| {
+                attributes.color = frame_color(frame).into();
+                attributes.extra_color = [0.0, 0.0, frame as f32 / 16.0, 1.0].into();
+            });
+            match frame {
+                4 => scene.covers.create(0, TwoTargetsAttributes {
+                    color: cover_color.into(),
+                    extra_color: [1.0; 4].into(),
+                }),
+                8 => assert!(scene.covers.remove(&0)),
+                _ => {}
+            }
+            backend.render(SurfaceId::MAIN, &mut scene, ClearBehavior::Clear([0.0; 3])).unwrap();
+
+            if matches!(frame, 3 | 4 | 7 | 8 | 11) {
+                let covered = (4..8).contains(&frame);
+                let (main, _) = backend.read_frame(SurfaceId::MAIN).unwrap();
+                let expected = if covered { cover_color } else { frame_color(frame) };
+                assert_filled(&main, to_unorm(expected));
+                let (extra, _) = backend.read_extra_color_attachment(SurfaceId::MAIN, 0).unwrap();
+                let expected = if covered { [1.0; 4] } else { [0.0, 0.0, frame as f32 / 16.0, 1.0] };
+                assert_filled(&extra, to_unorm(expected));
+            }
+        }
+        assert_eq!(backend.submitted_frames(SurfaceId::MAIN), Some(12));
+        // validation runs in the API calls, errors of all frames are reported by now
+        assert_eq!(backend.validation_error_count(), 0);
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn two_frames_in_flight() {
+        render_frames_in_flight(InFlightFrames::Two);
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn three_frames_in_flight() {
+        render_frames_in_flight(InFlightFrames::Three);
+    }
+}
@@ -1,7 +1,8 @@
 use std::mem::offset_of;
 use render::define_layout;
 use render_core::layout::{LayoutInfo, MemberMeta};
-use render_core::layout::types::{float, int, GlslTypeVariant};
+use render_core::layout::types::{float, int, mat4, vec2, GlslTypeVariant};
+use render_core::projection;
 use render_core::state::StateUpdatesBytes;
 
 define_layout! {
@@ -12,6 +13,30 @@ define_layout! {
     }
 }
 
+define_layout! {
+    /// Applied to scene objects in vertex shader: `pos * zoom + pan`
+    pub struct Camera {
+        pub pan: vec2<0>,
+        pub zoom: float<0>
+    }
+}
+
+define_layout! {
+    /// World to clip space transform for vertex shaders: `gl_Position = u_projection.matrix * vec4(pos, 0.0, 1.0)`
+    pub struct Projection {
+        pub matrix: mat4<0>
+    }
+}
+
+impl Projection {
+    /// Keeps proportions for viewport `aspect` (width / height), see `render_core::projection::orthographic`
+    pub fn orthographic(aspect: f32, zoom: f32, pan: [f32; 2]) -> Self {
+        Self {
+            matrix: projection::orthographic(aspect, zoom, pan).into(),
+        }
+    }
+}
+
 define_layout! {
     pub struct Time {
         pub time: int<0>
@@ -0,0 +1,333 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::Sender;
+use log::error;
+use render::backend::{ClearBehavior, RenderBackend, RenderResult, SurfaceId};
+use render_core::collect_state::CollectDrawStateUpdates;
+use crate::config::{SceneClock, TimeSource};
+use crate::render_messages::{RenderMessage, RenderMessages, RenderStatus};
+use crate::scene::SceneTrait;
+use crate::stats_overlay::{PerfStats, StatsOverlay, WithOverlay};
+use crate::triple_buffer::TripleBufferWriter;
+
+/// Frame logic of `AppState` which does not need the window: render messages, scene clock, updates of the scene
+/// and the stats overlay, and drawing them to the main surface of the backend.
+///
+/// Generic over the backend, so scenes can be run with `NullBackend` in tests without a GPU.
+pub struct SceneRunner<S: SceneTrait, B: RenderBackend> {
+    /// `None` while renderer is being reinitialized, or after it panicked
+    backend: Option<B>,
+    scene: S,
+    stats_overlay: StatsOverlay,
+    /// Set by `RenderMessage::SetClearColor`, replaces `SceneTrait::clear_color`
+    clear_color_override: Option<[f32; 3]>,
+    render_messages: RenderMessages,
+    clock: SceneClock,
+    /// Message of a panic caught during rendering
+    render_panic: Option<String>,
+}
+
+impl<S: SceneTrait, B: RenderBackend> SceneRunner<S, B> {
+    pub fn new(backend: B, aspect: f32, time_source: TimeSource) -> Self {
+        Self {
+            backend: Some(backend),
+            scene: S::new(aspect),
+            stats_overlay: StatsOverlay::new(aspect),
+            clear_color_override: None,
+            render_messages: RenderMessages::new(),
+            clock: SceneClock::new(time_source),
+            render_panic: None,
+        }
+    }
+
+    pub fn scene(&self) -> &S {
+        &self.scene
+    }
+
+    pub fn scene_mut(&mut self) -> &mut S {
+        &mut self.scene
+    }
+
+    pub fn backend(&self) -> Option<&B> {
+        self.backend.as_ref()
+    }
+
+    pub fn backend_mut(&mut self) -> Option<&mut B> {
+        self.backend.as_mut()
+    }
+
+    /// Nothing is rendered until `set_backend` is called
+    pub fn take_backend(&mut self) -> Option<B> {
+        self.backend.take()
+    }
+
+    /// Replace the backend with a new one, which has none of the resources of the scene yet
+    pub fn set_backend(&mut self, backend: B) {
+        self.backend = Some(backend);
+        self.reset_updates();
+    }
+
+    /// Upload all resources of the scene and the overlay again on the next frame, e.g. after the backend
+    /// lost them together with the surface
+    pub fn reset_updates(&mut self) {
+        self.scene.reset_updates();
+        self.stats_overlay.reset_updates();
+    }
+
+    pub fn stats_overlay(&self) -> &StatsOverlay {
+        &self.stats_overlay
+    }
+
+    pub fn stats_overlay_mut(&mut self) -> &mut StatsOverlay {
+        &mut self.stats_overlay
+    }
+
+    /// Message of the panic which stopped rendering, if any
+    pub fn render_panic(&self) -> Option<&str> {
+        self.render_panic.as_deref()
+    }
+
+    /// Fixed clear color, replaces `SceneTrait::clear_color` until `reset_clear_color` is called
+    pub fn set_clear_color(&mut self, color: [f32; 3]) {
+        self.clear_color_override = Some(color);
+    }
+
+    /// Return to the clear color provided by the scene, black by default
+    pub fn reset_clear_color(&mut self) {
+        self.clear_color_override = None;
+    }
+
+    /// Sender for commands from other threads, they are applied before the next frame
+    pub fn render_message_sender(&self) -> Sender<RenderMessage> {
+        self.render_messages.sender()
+    }
+
+    /// Lets other threads see that rendering stopped after a renderer panic
+    pub fn render_status(&self) -> RenderStatus {
+        self.render_messages.status()
+    }
+
+    /// See `RenderMessages::take_latest_writer`
+    pub fn take_latest_render_message_writer(&mut self) -> Option<TripleBufferWriter<RenderMessage>> {
+        self.render_messages.take_latest_writer()
+    }
+
+    /// Apply pending render messages and advance the scene clock.
+    ///
+    /// Messages which configure the window or the backend, like `RenderMessage::SetVsync`, are returned to the app
+    pub fn begin_frame(&mut self) -> Vec<RenderMessage> {
+        let messages: Vec<_> = self.render_messages.drain().collect();
+        let mut app_messages = Vec::new();
+        for message in messages {
+            match message {
+                RenderMessage::SetClearColor(Some(color)) => self.set_clear_color(color),
+                RenderMessage::SetClearColor(None) => self.reset_clear_color(),
+                message @ RenderMessage::SetVsync(_) => app_messages.push(message),
+                message => self.scene.handle_render_message(message),
+            }
+        }
+        self.clock.tick();
+        app_messages
+    }
+
+    /// Update the scene for the frame started with `begin_frame`, and the overlay with `stats`
+    pub fn update(&mut self, stats: &PerfStats) {
+        self.scene.update(&self.clock);
+        self.stats_overlay.update(stats);
+    }
+
+    /// Draw the scene with the overlay on top to the main surface. Idle without a backend.
+    ///
+    /// A panic of the backend is returned as an error, after which the backend is dropped and rendering stops
+    /// for good, see `render_panic`
+    pub fn render(&mut self) -> anyhow::Result<RenderResult> {
+        let Some(backend) = self.backend.as_mut() else {
+            return Ok(RenderResult::Idle);
+        };
+        let clear_color = self.clear_color_override.unwrap_or(self.scene.clear_color());
+        let mut draw_state = WithOverlay {
+            scene: &mut self.scene,
+            overlay: &mut self.stats_overlay,
+        };
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            backend.render(SurfaceId::MAIN, &mut draw_state, ClearBehavior::Clear(clear_color))
+        }));
+        match res {
+            Ok(res) => res,
+            Err(payload) => Err(self.handle_render_panic(payload)),
+        }
+    }
+
+    /// Renderer state can't be trusted after a panic, it is released right away
+    fn handle_render_panic(&mut self, payload: Box<dyn Any + Send>) -> anyhow::Error {
+        let msg = payload.downcast_ref::<&str>().map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic payload".to_string());
+        error!("Renderer panicked: {}", msg);
+        self.render_panic = Some(msg.clone());
+        self.render_messages.close_after_panic(msg.clone());
+
+        // a second panic during cleanup is only logged
+        if let Some(backend) = self.backend.take() {
+            if panic::catch_unwind(AssertUnwindSafe(|| drop(backend))).is_err() {
+                error!("Renderer panicked again while being destroyed");
+            }
+        }
+        anyhow::anyhow!("Renderer panicked: {}", msg)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use winit::event::WindowEvent;
+    use winit::window::Window;
+    use render::CollectDrawStateUpdates;
+    use render::null_backend::{NullBackend, UpdateCounts};
+    use render_core::collect_state::ordered_object_pool::OrderedObjectPool;
+    use crate::scene::tonemap::{TonemapAttributes, TonemapPipeline};
+    use super::*;
+
+    #[derive(CollectDrawStateUpdates)]
+    struct PoolScene {
+        pool: OrderedObjectPool<TonemapPipeline, u32>,
+        #[collect(skip)]
+        updates: u32,
+    }
+
+    impl SceneTrait for PoolScene {
+        fn new(_aspect: f32) -> Self {
+            Self {
+                pool: OrderedObjectPool::new(()),
+                updates: 0,
+            }
+        }
+        fn handle_event(&mut self, _event: &WindowEvent, _window: &Window) {}
+        fn update(&mut self, _clock: &SceneClock) {
+            self.updates += 1;
+        }
+        fn clear_color(&self) -> [f32; 3] {
+            [0.1, 0.2, 0.3]
+        }
+    }
+
+    /// Backend which panics on the first frame
+    struct PanickingBackend;
+
+    impl RenderBackend for PanickingBackend {
+        fn render(&mut self, _id: SurfaceId, _draw_state_diff: &mut impl CollectDrawStateUpdates,
+                  _clear_behavior: ClearBehavior) -> anyhow::Result<RenderResult> {
+            panic!("device exploded");
+        }
+    }
+
+    /// First frame uploads resources of the stats overlay, following frames only carry scene changes
+    fn runner_after_first_frame() -> SceneRunner<PoolScene, NullBackend> {
+        let mut runner = SceneRunner::new(NullBackend::new(), 1.0, TimeSource::FixedStep(16));
+        frame(&mut runner);
+        runner
+    }
+
+    fn frame(runner: &mut SceneRunner<PoolScene, NullBackend>) -> UpdateCounts {
+        runner.begin_frame();
+        runner.update(&PerfStats::default());
+        let res = runner.render().unwrap();
+        assert!(matches!(res, RenderResult::Rendered));
+        runner.backend().unwrap().last_frame_updates()
+    }
+
+    #[test]
+    fn unchanged_scene_has_no_updates() {
+        let mut runner = runner_after_first_frame();
+        assert_eq!(frame(&mut runner), UpdateCounts::default());
+        assert_eq!(runner.scene().updates, 2);
+        assert_eq!(runner.backend().unwrap().rendered_frames(), 2);
+    }
+
+    #[test]
+    fn created_objects_are_uploaded_in_one_batch() {
+        let mut runner = runner_after_first_frame();
+        for i in 0..3 {
+            runner.scene_mut().pool.create(i, TonemapAttributes::default());
+        }
+        assert_eq!(frame(&mut runner), UpdateCounts { object_batches: 1, ..Default::default() });
+        assert_eq!(frame(&mut runner), UpdateCounts::default());
+    }
+
+    #[test]
+    fn removed_objects_are_destroyed() {
+        let mut runner = runner_after_first_frame();
+        for i in 0..3 {
+            runner.scene_mut().pool.create(i, TonemapAttributes::default());
+        }
+        frame(&mut runner);
+
+        assert!(runner.scene_mut().pool.remove(&0));
+        assert!(runner.scene_mut().pool.remove(&2));
+        assert_eq!(frame(&mut runner), UpdateCounts { objects: 2, ..Default::default() });
+    }
+
+    #[test]
+    fn new_backend_receives_all_objects_again() {
+        let mut runner = SceneRunner::new(NullBackend::new(), 1.0, TimeSource::FixedStep(16));
+        let overlay = frame(&mut runner);
+        runner.scene_mut().pool.create(0, TonemapAttributes::default());
+        runner.scene_mut().pool.create(1, TonemapAttributes::default());
+        frame(&mut runner);
+
+        assert!(runner.take_backend().is_some());
+        runner.set_backend(NullBackend::new());
+        let after_reset = frame(&mut runner);
+        assert_eq!(after_reset.images, overlay.images);
+        assert_eq!(after_reset.total() - overlay.total(), 1);
+    }
+
+    #[test]
+    fn clear_color_message_overrides_scene() {
+        let mut runner = runner_after_first_frame();
+        assert_eq!(runner.backend().unwrap().last_clear_behavior(), Some(ClearBehavior::Clear([0.1, 0.2, 0.3])));
+
+        let sender = runner.render_message_sender();
+        sender.send(RenderMessage::SetClearColor(Some([1.0, 0.0, 0.0]))).unwrap();
+        frame(&mut runner);
+        assert_eq!(runner.backend().unwrap().last_clear_behavior(), Some(ClearBehavior::Clear([1.0, 0.0, 0.0])));
+
+        sender.send(RenderMessage::SetClearColor(None)).unwrap();
+        let app_messages = runner.begin_frame();
+        assert!(app_messages.is_empty());
+        runner.render().unwrap();
+        assert_eq!(runner.backend().unwrap().last_clear_behavior(), Some(ClearBehavior::Clear([0.1, 0.2, 0.3])));
+    }
+
+    #[test]
+    fn vsync_message_is_returned_to_app() {
+        let mut runner = runner_after_first_frame();
+        runner.render_message_sender().send(RenderMessage::SetVsync(false)).unwrap();
+        let app_messages = runner.begin_frame();
+        assert!(matches!(app_messages.as_slice(), [RenderMessage::SetVsync(false)]));
+    }
+
+    #[test]
+    fn paused_backend_keeps_updates_for_later() {
+        let mut runner = runner_after_first_frame();
+        runner.backend_mut().unwrap().set_paused(true);
+        runner.scene_mut().pool.create(0, TonemapAttributes::default());
+        runner.update(&PerfStats::default());
+        assert!(matches!(runner.render().unwrap(), RenderResult::Idle));
+
+        runner.backend_mut().unwrap().set_paused(false);
+        assert_eq!(frame(&mut runner), UpdateCounts { objects: 1, ..Default::default() });
+    }
+
+    #[test]
+    fn backend_panic_stops_rendering() {
+        let mut runner: SceneRunner<PoolScene, _> = SceneRunner::new(PanickingBackend, 1.0, TimeSource::FixedStep(16));
+        let status = runner.render_status();
+        let err = runner.render().unwrap_err();
+        assert!(err.to_string().contains("device exploded"));
+        assert_eq!(runner.render_panic(), Some("device exploded"));
+        assert!(runner.backend().is_none());
+        assert!(runner.render_message_sender().send(RenderMessage::SetVsync(true)).is_err());
+        assert!(status.is_stopped());
+        assert!(matches!(runner.render().unwrap(), RenderResult::Idle));
+    }
+}
@@ -0,0 +1,83 @@
+use render::CollectDrawStateUpdates;
+use render_core::collect_state::{CollectDrawStateUpdates, GraphicsUpdateCmd};
+use crate::scene::text::TextLayer;
+
+/// Values shown by `StatsOverlay`
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PerfStats {
+    /// Frames rendered during the last second
+    pub fps: u32,
+    /// FPS corresponding to the 99th percentile of recent frame times
+    pub low_1_fps: Option<f32>,
+    /// Average GPU time of recent frames in milliseconds, if timestamps are supported
+    pub gpu_frame_ms: Option<f32>,
+    pub object_count: usize,
+    /// Pipeline binds of the last recorded frame
+    pub pipeline_binds: u32,
+}
+
+const OVERLAY_COLOR: [f32; 4] = [1.0, 1.0, 0.4, 1.0];
+const MARGIN: f32 = 0.02;
+
+/// Performance HUD in the top-right corner, drawn on top of any scene. Toggled with F3 by the app
+#[derive(CollectDrawStateUpdates)]
+pub struct StatsOverlay {
+    text: TextLayer,
+    #[collect(skip)]
+    visible: bool,
+}
+
+impl StatsOverlay {
+    pub fn new(aspect: f32) -> Self {
+        Self {
+            text: TextLayer::new(0.05, aspect, 96),
+            visible: false,
+        }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn set_aspect(&mut self, aspect: f32) {
+        self.text.set_aspect(aspect);
+    }
+
+    /// Must be called every frame, hidden overlay removes its glyphs
+    pub fn update(&mut self, stats: &PerfStats) {
+        self.text.begin();
+        if self.visible {
+            let low_1_fps = stats.low_1_fps.map_or("-".to_string(), |fps| format!("{:.0}", fps));
+            let gpu_frame_ms = stats.gpu_frame_ms.map_or("-".to_string(), |ms| format!("{:.2}ms", ms));
+            let text = format!("FPS: {}\n1% low: {}\nGPU: {}\nObjects: {}\nPipeline binds: {}",
+                               stats.fps, low_1_fps, gpu_frame_ms, stats.object_count, stats.pipeline_binds);
+            let x = 1.0 - MARGIN - self.text.text_width(&text);
+            self.text.draw_text([x, -1.0 + MARGIN], &text, OVERLAY_COLOR);
+        }
+        self.text.finish();
+    }
+}
+
+/// Scene together with the overlay, collected as a single draw state
+pub struct WithOverlay<'a, S> {
+    pub scene: &'a mut S,
+    pub overlay: &'a mut StatsOverlay,
+}
+
+impl<S: CollectDrawStateUpdates> CollectDrawStateUpdates for WithOverlay<'_, S> {
+    fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd<'_>> {
+        self.scene.collect_updates().chain(self.overlay.collect_updates())
+    }
+    fn clear_updates(&mut self) {
+        self.scene.clear_updates();
+        self.overlay.clear_updates();
+    }
+    fn reset_updates(&mut self) {
+        self.scene.reset_updates();
+        self.overlay.reset_updates();
+    }
+}
@@ -0,0 +1,69 @@
+use std::collections::HashMap;
+use winit::event::{Touch, TouchPhase};
+
+/// Positions of active touches by finger id, with pinch tracking for exactly two fingers
+#[derive(Default, Debug)]
+pub struct TouchTracker {
+    touches: HashMap<u64, [f32; 2]>,
+    /// Distance between the two fingers at the previous pinch update
+    pinch_distance: Option<f32>,
+}
+
+impl TouchTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Update the touch with its position already converted by the caller, e.g. to NDC.
+    ///
+    /// Returns the ratio of the new pinch distance to the previous one when two fingers moved
+    pub fn handle_touch(&mut self, touch: &Touch, pos: [f32; 2]) -> Option<f32> {
+        match touch.phase {
+            TouchPhase::Started => {
+                self.touches.insert(touch.id, pos);
+                self.pinch_distance = self.current_distance();
+                None
+            }
+            TouchPhase::Moved => {
+                self.touches.insert(touch.id, pos);
+                let distance = self.current_distance()?;
+                let prev = self.pinch_distance.replace(distance)?;
+                (prev > f32::EPSILON).then(|| distance / prev)
+            }
+            TouchPhase::Ended | TouchPhase::Cancelled => {
+                self.touches.remove(&touch.id);
+                self.pinch_distance = self.current_distance();
+                None
+            }
+        }
+    }
+
+    pub fn touches(&self) -> &HashMap<u64, [f32; 2]> {
+        &self.touches
+    }
+
+    /// Number of fingers on the screen
+    pub fn count(&self) -> usize {
+        self.touches.len()
+    }
+
+    /// Distance between two fingers, `None` with any other number of touches
+    pub fn pinch_distance(&self) -> Option<f32> {
+        self.pinch_distance
+    }
+
+    /// Forget all touches, e.g. when window loses focus and end events may not arrive
+    pub fn clear(&mut self) {
+        self.touches.clear();
+        self.pinch_distance = None;
+    }
+
+    fn current_distance(&self) -> Option<f32> {
+        if self.touches.len() != 2 {
+            return None;
+        }
+        let mut positions = self.touches.values();
+        let (a, b) = (positions.next()?, positions.next()?);
+        Some(((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2)).sqrt())
+    }
+}
@@ -0,0 +1,119 @@
+use std::cell::UnsafeCell;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Set in `back` when the back buffer holds a value not seen by the reader yet
+const NEW: u8 = 0b100;
+const INDEX_MASK: u8 = 0b011;
+
+struct Shared<T> {
+    buffers: [UnsafeCell<Option<T>>; 3],
+    /// Index of the buffer owned by neither side, with `NEW` flag
+    back: AtomicU8,
+}
+
+// each buffer is accessed only by the side which currently owns its index
+unsafe impl<T: Send> Sync for Shared<T> {}
+
+/// Wait-free hand-off of the latest value from one thread to another.
+///
+/// Writer and reader each own one of three buffers and swap it with the shared back buffer, so neither
+/// side ever blocks or allocates. Values published between two reads are dropped, only the newest is read.
+pub fn triple_buffer<T: Send>() -> (TripleBufferWriter<T>, TripleBufferReader<T>) {
+    let shared = Arc::new(Shared {
+        buffers: [UnsafeCell::new(None), UnsafeCell::new(None), UnsafeCell::new(None)],
+        back: AtomicU8::new(2),
+    });
+    (
+        TripleBufferWriter { shared: shared.clone(), index: 0 },
+        TripleBufferReader { shared, index: 1 },
+    )
+}
+
+pub struct TripleBufferWriter<T> {
+    shared: Arc<Shared<T>>,
+    index: u8,
+}
+
+impl<T: Send> TripleBufferWriter<T> {
+    /// Replace the previously published value, if it was not read yet
+    pub fn publish(&mut self, value: T) {
+        unsafe {
+            *self.shared.buffers[self.index as usize].get() = Some(value);
+        }
+        // release: value is visible to the reader which acquires this index
+        let prev = self.shared.back.swap(self.index | NEW, Ordering::AcqRel);
+        self.index = prev & INDEX_MASK;
+    }
+}
+
+pub struct TripleBufferReader<T> {
+    shared: Arc<Shared<T>>,
+    index: u8,
+}
+
+impl<T: Send> TripleBufferReader<T> {
+    /// Latest value published since the previous call
+    pub fn take(&mut self) -> Option<T> {
+        if self.shared.back.load(Ordering::Relaxed) & NEW == 0 {
+            return None;
+        }
+        let prev = self.shared.back.swap(self.index, Ordering::AcqRel);
+        self.index = prev & INDEX_MASK;
+        unsafe { (*self.shared.buffers[self.index as usize].get()).take() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::triple_buffer;
+
+    #[test]
+    fn take_returns_each_published_value_once() {
+        let (mut writer, mut reader) = triple_buffer();
+        assert_eq!(reader.take(), None);
+        writer.publish(1);
+        assert_eq!(reader.take(), Some(1));
+        assert_eq!(reader.take(), None);
+    }
+
+    #[test]
+    fn take_returns_newest_value() {
+        let (mut writer, mut reader) = triple_buffer();
+        writer.publish(1);
+        writer.publish(2);
+        assert_eq!(reader.take(), Some(2));
+        assert_eq!(reader.take(), None);
+
+        writer.publish(3);
+        writer.publish(4);
+        writer.publish(5);
+        assert_eq!(reader.take(), Some(5));
+    }
+
+    #[test]
+    fn reader_on_other_thread_sees_values_in_order() {
+        // Miri is slow, but still finds data races with a few hundred values
+        const COUNT: u32 = if cfg!(miri) { 200 } else { 100_000 };
+        let (mut writer, mut reader) = triple_buffer();
+
+        let reader_thread = thread::spawn(move || {
+            let mut last = None;
+            while last != Some(COUNT - 1) {
+                match reader.take() {
+                    Some(value) => {
+                        assert!(last.is_none_or(|last| value > last), "{} read after {:?}", value, last);
+                        last = Some(value);
+                    }
+                    None => thread::yield_now(),
+                }
+            }
+            assert_eq!(reader.take(), None);
+        });
+        for value in 0..COUNT {
+            writer.publish(value);
+        }
+        reader_thread.join().unwrap();
+    }
+}
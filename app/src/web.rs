@@ -0,0 +1,79 @@
+use log::{error, info};
+use winit::application::ApplicationHandler;
+use winit::event::WindowEvent;
+use winit::event_loop::{ActiveEventLoop, EventLoop};
+use winit::platform::web::{EventLoopExtWebSys, WindowAttributesExtWebSys};
+use winit::window::{Window, WindowAttributes, WindowId};
+use crate::config::{AppConfig, SceneClock};
+use crate::scene::SceneTrait;
+
+/// Run the scene in a canvas appended to the document body.
+///
+/// There is no renderer on the web yet: scene receives events and is updated every frame, but its
+/// draw state updates are discarded.
+pub fn run<S: SceneTrait + 'static>(config: AppConfig) {
+    let event_loop = EventLoop::new().unwrap();
+    event_loop.spawn_app(WebApp::<S> {
+        config,
+        state: None,
+    });
+}
+
+struct WebAppState<S: SceneTrait> {
+    window: Window,
+    scene: S,
+    clock: SceneClock,
+}
+
+struct WebApp<S: SceneTrait> {
+    config: AppConfig,
+    state: Option<WebAppState<S>>,
+}
+
+impl<S: SceneTrait> ApplicationHandler for WebApp<S> {
+    fn resumed(&mut self, event_loop: &ActiveEventLoop) {
+        if self.state.is_some() {
+            return;
+        }
+        let window = match event_loop.create_window(WindowAttributes::default()
+            .with_title(self.config.title.as_str())
+            .with_append(true)) {
+            Ok(window) => window,
+            Err(e) => {
+                error!("Failed to create canvas: {}", e);
+                return;
+            }
+        };
+        let size = window.inner_size();
+        // canvas may have zero size until it is laid out
+        let aspect = if size.height == 0 { 1.0 } else { size.width as f32 / size.height as f32 };
+        window.request_redraw();
+        info!("Canvas created, rendering is not supported on the web");
+
+        self.state = Some(WebAppState {
+            window,
+            scene: S::new(aspect),
+            clock: SceneClock::new(self.config.time_source),
+        });
+    }
+
+    fn window_event(&mut self, event_loop: &ActiveEventLoop, _window_id: WindowId, event: WindowEvent) {
+        let Some(state) = self.state.as_mut() else {
+            return;
+        };
+        state.scene.handle_event(&event, &state.window);
+        if let Some((action, element_state)) = self.config.input_map.action_for(&event) {
+            state.scene.handle_action(action, element_state);
+        }
+        match event {
+            WindowEvent::CloseRequested => event_loop.exit(),
+            WindowEvent::RedrawRequested => {
+                state.clock.tick();
+                state.scene.update(&state.clock);
+                state.scene.clear_updates();
+                state.window.request_redraw();
+            }
+            _ => {}
+        }
+    }
+}
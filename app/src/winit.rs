@@ -1,65 +1,134 @@
 use std::fs;
-use log::{error, info, warn};
+use log::{debug, error, info, warn};
 use sparkles_macro::{instant_event, range_event_start};
 use std::time::Instant;
+use std::sync::mpsc::Sender;
 use sparkles::FinalizeGuard;
 use winit::application::ApplicationHandler;
-use winit::event_loop::{ActiveEventLoop, EventLoopBuilder};
-use winit::keyboard::NamedKey;
-use winit::window::{Fullscreen, Window, WindowAttributes, WindowId};
-use winit::{event::WindowEvent, event_loop::EventLoop, keyboard};
-use winit::event::{ElementState, MouseButton};
+use winit::event_loop::{ActiveEventLoop, ControlFlow, EventLoopBuilder};
+use winit::dpi::LogicalSize;
+use winit::monitor::MonitorHandle;
+use winit::window::{Fullscreen, Icon, Window, WindowAttributes, WindowId};
+use winit::{event::WindowEvent, event_loop::EventLoop};
+use winit::event::{ElementState, StartCause};
 #[cfg(target_os = "android")]
 pub use winit::platform::android::activity::AndroidApp;
 use winit::raw_window_handle::{HasRawDisplayHandle, HasRawWindowHandle};
 use render::vulkan_backend::VulkanBackend;
-
-use render::vulkan_backend::config::VulkanRenderConfig;
-use crate::scene::circle::{CircleAttributes, CircleAttributesExt};
-use crate::scene::Scene;
-use crate::scene::uniforms::Time;
+use render::vulkan_backend::error::VulkanError;
+
+use render::vulkan_backend::surface_context::SurfaceId;
+use crate::config::{AppConfig, FramePacer, FullscreenMode};
+use crate::monitor::MonitorInfo;
+use crate::scene::SceneTrait;
+use crate::scene_runner::SceneRunner;
+use crate::render_messages::{RenderMessage, RenderStatus};
+use crate::triple_buffer::TripleBufferWriter;
+use crate::stats_overlay::PerfStats;
+use crate::input_map::{InputAction, InputMap};
+#[cfg(feature = "gamepad")]
+use crate::gamepad::GamepadInput;
 
 #[cfg(target_os = "android")]
-pub fn run_android(app: AndroidApp) {
+pub fn run_android<S: SceneTrait>(app: AndroidApp) {
     use crate::android::android_main;
-    let event_loop = android_main(app);
-    let mut winit_app: WinitApp = WinitApp::new();
+    let config = AppConfig::default();
+    let event_loop = android_main(app, config.refresh_rate);
+    let mut winit_app: WinitApp<S> = WinitApp::new(config);
+    winit_app.config.render_thread.apply_to_current_thread();
     event_loop.run_app(&mut winit_app).unwrap();
 }
 
 #[cfg(not(target_os = "android"))]
-pub fn run() {
+pub fn run<S: SceneTrait>(config: AppConfig) {
     let event_loop = EventLoop::new().unwrap();
-    let mut winit_app: WinitApp = WinitApp::new();
+    let mut winit_app: WinitApp<S> = WinitApp::new(config);
+    winit_app.config.render_thread.apply_to_current_thread();
     event_loop.run_app(&mut winit_app).unwrap();
 }
 
-struct WinitApp {
-    app_state: Option<AppState>,
+/// Title, icon, size limits and decorations from config
+fn window_attributes(config: &AppConfig) -> WindowAttributes {
+    let icon = config.icon.as_ref().and_then(|icon| {
+        Icon::from_rgba(icon.rgba.clone(), icon.width, icon.height)
+            .inspect_err(|e| error!("Invalid window icon: {}", e))
+            .ok()
+    });
+    let mut attributes = WindowAttributes::default()
+        .with_title(config.title.as_str())
+        .with_window_icon(icon)
+        .with_resizable(config.resizable)
+        .with_decorations(config.decorations);
+    if let Some((width, height)) = config.initial_size {
+        attributes = attributes.with_inner_size(LogicalSize::new(width, height));
+    }
+    if let Some((width, height)) = config.min_size {
+        attributes = attributes.with_min_inner_size(LogicalSize::new(width, height));
+    }
+    if let Some((width, height)) = config.max_size {
+        attributes = attributes.with_max_inner_size(LogicalSize::new(width, height));
+    }
+    attributes
+}
+
+struct WinitApp<S: SceneTrait> {
+    config: AppConfig,
+    app_state: Option<AppState<S>>,
     g: FinalizeGuard,
 }
 
-impl WinitApp {
-    fn new() -> Self {
+impl<S: SceneTrait> WinitApp<S> {
+    fn new(config: AppConfig) -> Self {
         let g = sparkles::init_default();
-        Self { app_state: None, g }
+        Self { config, app_state: None, g }
     }
 }
 
-impl ApplicationHandler for WinitApp {
+impl<S: SceneTrait> ApplicationHandler for WinitApp<S> {
     fn resumed(&mut self, event_loop: &ActiveEventLoop) {
         let g = range_event_start!("[WINIT] resumed");
         info!("\t\t*** APP RESUMED ***");
+        if let Some(app_state) = self.app_state.as_mut() {
+            // window is kept, but native surface may be new (Android)
+            if let Err(e) = app_state.resume() {
+                error!("Failed to resume rendering: {:?}", e);
+            }
+            return;
+        }
         let window = event_loop
-            .create_window(WindowAttributes::default().with_title("shades of pink"))
+            .create_window(window_attributes(&self.config))
             .unwrap();
 
         window.request_redraw();
 
-        let app_state = AppState::new_winit(window);
+        let app_state = AppState::new_winit(window, &self.config);
         self.app_state = Some(app_state);
     }
 
+    fn suspended(&mut self, _event_loop: &ActiveEventLoop) {
+        let g = range_event_start!("[WINIT] suspended");
+        info!("\t\t*** APP SUSPENDED ***");
+        if let Some(app_state) = self.app_state.as_mut() {
+            if let Err(e) = app_state.suspend() {
+                error!("Failed to pause rendering: {:?}", e);
+            }
+        }
+    }
+
+    fn new_events(&mut self, event_loop: &ActiveEventLoop, cause: StartCause) {
+        // paced redraw deadline reached
+        if let StartCause::ResumeTimeReached { .. } = cause {
+            if let Some(app_state) = self.app_state.as_ref() {
+                if app_state.rendering_active {
+                    app_state.window.request_redraw();
+                } else {
+                    // deadline is not moved while rendering is stopped, waking up on it would spin
+                    event_loop.set_control_flow(ControlFlow::Wait);
+                }
+            }
+        }
+    }
+
     fn window_event(
         &mut self,
         event_loop: &ActiveEventLoop,
@@ -67,23 +136,31 @@ impl ApplicationHandler for WinitApp {
         event: WindowEvent,
     ) {
         let g = range_event_start!("[WINIT] window event");
-        if self.app_state.as_mut().unwrap().is_finished() {
+        let app_state = self.app_state.as_mut().unwrap();
+        if let Err(e) = app_state.handle_event(event_loop, event) {
+            error!("Error handling event: {:?}", e);
+        }
+        // checked after handling, no more redraws are requested once app is finished
+        if app_state.is_finished() {
             info!("Exit requested!");
             event_loop.exit();
         }
-        if let Err(e) = self.app_state.as_mut().unwrap().handle_event(event_loop, event) {
-            error!("Error handling event: {:?}", e);
-        }
     }
 
     fn exiting(&mut self, _event_loop: &ActiveEventLoop) {
         let g = range_event_start!("[WINIT] Exiting");
         info!("\t\t*** APP EXITING ***");
+        if let Some(msg) = self.app_state.as_ref().and_then(|s| s.render_panic()) {
+            error!("Exiting after renderer panic: {}", msg);
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn about_to_wait(&mut self, _event_loop: &ActiveEventLoop) {
+        if let Some(app_state) = self.app_state.as_mut() {
+            app_state.poll_gamepad();
+        }
     }
-    //
-    // fn about_to_wait(&mut self, event_loop: &ActiveEventLoop) {
-    //     info!("\t\t*** APP ABOUT TO WAIT ***");
-    // }
 
     fn memory_warning(&mut self, event_loop: &ActiveEventLoop) {
         let g = range_event_start!("[WINIT] Memory warning");
@@ -91,25 +168,31 @@ impl ApplicationHandler for WinitApp {
     }
 }
 
-pub struct AppState {
+pub struct AppState<S: SceneTrait> {
     app_finished: bool,
-    prev_touch_event_time: Instant,
-    start_time: Instant,
 
-    vulkan_backend: VulkanBackend,
+    /// Scene, stats overlay and the renderer, app is finished after a panic in it
+    runner: SceneRunner<S, VulkanBackend>,
     window: Window,
 
     frame_cnt: i32,
     last_sec: Instant,
+    /// Frames rendered during the last full second
+    last_fps: u32,
+    input_map: InputMap,
+    fullscreen_mode: FullscreenMode,
+    /// Monitor chosen with `set_fullscreen_on`, current monitor of the window if `None`
+    fullscreen_monitor: Option<MonitorHandle>,
 
     rendering_active: bool,
+    vsync: bool,
+    /// Native window may not exist, window surface is released
+    suspended: bool,
 
-    scene: Scene,
-    bg_color: [f32; 3],
-    last_touch_pos: [f32; 2],
-    last_frame_time: Instant,
-    
-    trail_last_update: Instant,
+    frame_pacer: Option<FramePacer>,
+
+    #[cfg(feature = "gamepad")]
+    gamepad: Option<GamepadInput>,
 }
 
 pub enum AppResult {
@@ -117,186 +200,316 @@ pub enum AppResult {
     Exit,
 }
 
-impl AppState {
-    pub fn new_winit(window: Window) -> AppState {
+impl<S: SceneTrait> AppState<S> {
+    pub fn new_winit(window: Window, app_config: &AppConfig) -> AppState<S> {
 
         let raw_window_handle = window.raw_window_handle().unwrap();
         let raw_display_handle = window.raw_display_handle().unwrap();
         let inner_size = window.inner_size();
-        let config = VulkanRenderConfig {
-            msaa_samples: None,
-        };
-        let vulkan_backend = VulkanBackend::new_for_window(raw_window_handle, raw_display_handle, (inner_size.width, inner_size.height), config).unwrap();
+        let vulkan_backend = VulkanBackend::new_for_window(raw_window_handle, raw_display_handle, (inner_size.width, inner_size.height), app_config.render.clone()).unwrap();
 
         let aspect = inner_size.width as f32 / inner_size.height as f32;
-        let object_group = Scene::new(aspect);
         Self {
-            scene: object_group,
             app_finished: false,
-            prev_touch_event_time: Instant::now(),
 
-            vulkan_backend,
+            runner: SceneRunner::new(vulkan_backend, aspect, app_config.time_source),
             window,
 
             last_sec: Instant::now(),
             frame_cnt: 0,
+            last_fps: 0,
+            input_map: app_config.input_map.clone(),
+            fullscreen_mode: app_config.fullscreen_mode,
+            fullscreen_monitor: None,
 
             rendering_active: true,
-            start_time: Instant::now(),
-            bg_color: [0.0, 0.0, 0.0],
-            last_touch_pos: [0.0, 0.0],
+            vsync: app_config.render.vsync,
+            suspended: false,
 
-            last_frame_time: Instant::now(),
-            trail_last_update: Instant::now(),
+            frame_pacer: app_config.target_fps.map(FramePacer::new),
+
+            #[cfg(feature = "gamepad")]
+            gamepad: GamepadInput::new(),
         }
     }
     
-    fn calculate_aspect(&self) -> f32 {
-        let inner_size = self.window.inner_size();
-        inner_size.width as f32 / inner_size.height as f32
-    }
-
     pub fn is_finished(&self) -> bool {
         self.app_finished
     }
 
-    pub fn handle_event(
-        &mut self,
-        _event_loop: &ActiveEventLoop,
-        evt: WindowEvent,
-    ) -> anyhow::Result<()> {
-        match &evt {
-            WindowEvent::CloseRequested
-            | WindowEvent::KeyboardInput {
-                event:
-                winit::event::KeyEvent {
-                    logical_key: keyboard::Key::Named(NamedKey::GoBack | NamedKey::BrowserBack),
-                    state: ElementState::Pressed,
-                    ..
-                },
-                ..
-            } => {
-                let g = range_event_start!("[APP] Close requested");
-                info!("Close requested...");
+    /// Message of the panic which stopped rendering, if any
+    pub fn render_panic(&self) -> Option<&str> {
+        self.runner.render_panic()
+    }
+
+    pub fn title(&self) -> String {
+        self.window.title()
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.window.set_title(title);
+    }
+
+    /// Difference between actual and scheduled start of the last frame in milliseconds.
+    /// `None` if frame rate is not limited
+    pub fn pacing_error(&self) -> Option<f32> {
+        self.frame_pacer.as_ref().map(|p| p.pacing_error())
+    }
+
+    /// Stop rendering and release window surface, native window can be destroyed after this call
+    fn suspend(&mut self) -> anyhow::Result<()> {
+        self.rendering_active = false;
+        self.suspended = true;
+        if let Some(vulkan_backend) = self.runner.backend_mut() {
+            vulkan_backend.pause(true)?;
+        }
+        Ok(())
+    }
+
+    fn resume(&mut self) -> anyhow::Result<()> {
+        let Some(vulkan_backend) = self.runner.backend_mut() else {
+            return Ok(());
+        };
+        if vulkan_backend.is_paused() {
+            let inner_size = self.window.inner_size();
+            vulkan_backend.set_released_window_handle(SurfaceId::MAIN, self.window.raw_window_handle()?,
+                                                      self.window.raw_display_handle()?, (inner_size.width, inner_size.height))?;
+            vulkan_backend.resume()?;
+            // objects were destroyed together with the surface
+            self.runner.reset_updates();
+        }
+        self.suspended = false;
+        self.rendering_active = true;
+        self.window.request_redraw();
+        Ok(())
+    }
+
+    /// Fixed clear color, replaces `SceneTrait::clear_color` until `reset_clear_color` is called
+    pub fn set_clear_color(&mut self, color: [f32; 3]) {
+        self.runner.set_clear_color(color);
+    }
+
+    /// Return to the clear color provided by the scene, black by default
+    pub fn reset_clear_color(&mut self) {
+        self.runner.reset_clear_color();
+    }
+
+    /// Sender for commands from other threads, they are applied before the next frame
+    pub fn render_message_sender(&self) -> Sender<RenderMessage> {
+        self.runner.render_message_sender()
+    }
+
+    /// Lets other threads see that rendering stopped after a renderer panic
+    pub fn render_status(&self) -> RenderStatus {
+        self.runner.render_status()
+    }
+
+    /// See `RenderMessages::take_latest_writer`
+    pub fn take_latest_render_message_writer(&mut self) -> Option<TripleBufferWriter<RenderMessage>> {
+        self.runner.take_latest_render_message_writer()
+    }
+
+    /// Apply render messages, app handles those which the runner returns
+    fn begin_frame(&mut self) {
+        for message in self.runner.begin_frame() {
+            if let RenderMessage::SetVsync(vsync) = message {
+                self.set_vsync(vsync);
+            }
+        }
+    }
+
+    fn set_vsync(&mut self, vsync: bool) {
+        self.vsync = vsync;
+        if let Some(vulkan_backend) = self.runner.backend_mut() {
+            if let Err(e) = vulkan_backend.set_vsync(vsync) {
+                error!("Failed to switch vsync: {:?}", e);
+            }
+        }
+    }
+
+    #[cfg(feature = "gamepad")]
+    fn poll_gamepad(&mut self) {
+        let Some(gamepad) = self.gamepad.as_mut() else {
+            return;
+        };
+        for action in gamepad.poll() {
+            self.runner.scene_mut().handle_gamepad(action);
+        }
+    }
+
+    /// Show or hide performance overlay
+    pub fn toggle_stats_overlay(&mut self) {
+        self.runner.stats_overlay_mut().toggle();
+    }
+
+    /// Values for the stats overlay, queried from the renderer only while it is visible
+    fn perf_stats(&self) -> PerfStats {
+        let mut stats = PerfStats {
+            fps: self.last_fps,
+            ..Default::default()
+        };
+        if self.runner.stats_overlay().is_visible() {
+            if let Some(vulkan_backend) = self.runner.backend() {
+                if let Some(history) = vulkan_backend.frame_history(SurfaceId::MAIN) {
+                    stats.low_1_fps = history.cpu_percentile(99.0).filter(|ms| *ms > 0.0).map(|ms| 1000.0 / ms);
+                    stats.gpu_frame_ms = history.gpu_stats().map(|s| s.avg);
+                }
+                stats.object_count = vulkan_backend.object_count(SurfaceId::MAIN).unwrap_or(0);
+                stats.pipeline_binds = vulkan_backend.draw_stats(SurfaceId::MAIN).map_or(0, |s| s.pipeline_binds);
+            }
+        }
+        stats
+    }
+
+    /// Render the scene, reinitializing renderer if vulkan device was lost
+    fn render_scene(&mut self) -> anyhow::Result<()> {
+        let res = self.runner.render();
+        if self.runner.render_panic().is_some() {
+            // renderer is released, nothing would be rendered anymore
+            self.app_finished = true;
+        }
+        let Err(e) = res else {
+            // idle result is expected while suspended
+            return Ok(());
+        };
+        let Some(err @ (VulkanError::DeviceLost | VulkanError::Timeout)) = e.downcast_ref::<VulkanError>() else {
+            return Err(e);
+        };
+
+        let g = range_event_start!("[APP] Recover from device lost");
+        error!("{}! Reinitializing renderer...", err);
+        let vulkan_backend = self.runner.take_backend().unwrap();
+        let vulkan_backend = match vulkan_backend.reinitialize() {
+            Ok(vulkan_backend) => vulkan_backend,
+            Err(e) => {
+                // nothing would be rendered anymore
+                error!("Failed to reinitialize renderer, exiting: {:?}", e);
                 self.app_finished = true;
+                return Err(e.context("Failed to reinitialize renderer"));
             }
+        };
+        // scene state is preserved, but all its resources must be uploaded again
+        self.runner.set_backend(vulkan_backend);
+        Ok(())
+    }
 
-            WindowEvent::KeyboardInput {
-                event:
-                winit::event::KeyEvent {
-                    logical_key: keyboard::Key::Named(NamedKey::F11),
-                    state: ElementState::Pressed,
-                    ..
-                },
-                ..
-            } => {
+    /// App-level actions, all actions are also passed to the scene
+    fn handle_action(&mut self, action: InputAction, state: ElementState) {
+        if state != ElementState::Pressed {
+            return;
+        }
+        match action {
+            InputAction::Exit => self.request_exit(),
+            InputAction::ToggleFullscreen => {
                 if self.window.fullscreen().is_none() {
                     let g = range_event_start!("[APP] Enable fullscreen");
-                    let monitor = self.window.current_monitor().unwrap();
-                    // find max by width and refresh rate
-                    let mode = monitor
-                        .video_modes()
-                        .map(|m| (m.size().width, m.refresh_rate_millihertz(), m))
-                        .max_by_key(|(w, hz, m)| w * 5000 + * hz)
-                        .map(|(_, _, m)| m)
-                        .unwrap();
-                    info!("Entering fullscreen mode {:?}, refresh rate: {}", mode.size(), mode.refresh_rate_millihertz() as f32 / 1000.0);
-                    self.window
-                        .set_fullscreen(Some(Fullscreen::Exclusive(mode)));
+                    self.window.set_fullscreen(Some(self.fullscreen()));
                 } else {
                     let g = range_event_start!("[APP] Exit fullscreen mode");
                     self.window.set_fullscreen(None);
                 }
             }
-            WindowEvent::KeyboardInput {
-                event: winit::event::KeyEvent {
-                    logical_key: keyboard::Key::Named(NamedKey::ArrowLeft),
-                    state: ElementState::Released,
-                    ..
-                },
-                ..
-            } => {
-                self.scene.mirror_lamp.modify_pos(|mut pos| {
-                    pos[0] += 0.1;
-                    pos
-                });
-                self.last_touch_pos[0] -= 0.1;
-            }
+            InputAction::ToggleVsync => self.set_vsync(!self.vsync),
+            InputAction::ToggleStatsOverlay => self.toggle_stats_overlay(),
+            _ => {}
+        }
+    }
 
-            WindowEvent::KeyboardInput {
-                event: winit::event::KeyEvent {
-                    logical_key: keyboard::Key::Named(NamedKey::ArrowRight),
-                    state: ElementState::Released,
-                    ..
-                },
-                ..
-            } => {
-                self.scene.mirror_lamp.modify_pos(|mut pos| {
-                    pos[0] -= 0.1;
-                    pos
-                });
-                self.last_touch_pos[0] += 0.1;
+    /// Fullscreen according to `fullscreen_mode`, borderless if no video mode is available
+    fn fullscreen(&self) -> Fullscreen {
+        let monitor = self.fullscreen_target_monitor();
+        if self.fullscreen_mode == FullscreenMode::Exclusive {
+            // find max by width and refresh rate
+            let mode = monitor.clone().and_then(|monitor| monitor
+                .video_modes()
+                .map(|m| (m.size().width, m.refresh_rate_millihertz(), m))
+                .max_by_key(|(w, hz, m)| w * 5000 + * hz)
+                .map(|(_, _, m)| m));
+            if let Some(mode) = mode {
+                info!("Entering fullscreen mode {:?}, refresh rate: {}", mode.size(), mode.refresh_rate_millihertz() as f32 / 1000.0);
+                return Fullscreen::Exclusive(mode);
             }
+            warn!("No video mode available for exclusive fullscreen, using borderless");
+        }
+        info!("Entering borderless fullscreen");
+        Fullscreen::Borderless(monitor)
+    }
 
-            WindowEvent::KeyboardInput {
-                event: winit::event::KeyEvent {
-                    logical_key: keyboard::Key::Named(NamedKey::ArrowUp),
-                    state: ElementState::Released,
-                    ..
-                },
-                ..
-            } => {
-                self.scene.mirror_lamp.modify_pos(|mut pos| {
-                    pos[1] += 0.1;
-                    pos
-                });
-                self.last_touch_pos[1] -= 0.1;
-            }
+    /// Chosen monitor if it is still connected, otherwise the primary one, otherwise the current one
+    fn fullscreen_target_monitor(&self) -> Option<MonitorHandle> {
+        let Some(chosen) = &self.fullscreen_monitor else {
+            return self.window.current_monitor();
+        };
+        if self.window.available_monitors().any(|m| m == *chosen) {
+            return Some(chosen.clone());
+        }
+        warn!("Fullscreen monitor {:?} is not available anymore, using primary", chosen.name());
+        self.window.primary_monitor().or_else(|| self.window.current_monitor())
+    }
 
-            WindowEvent::KeyboardInput {
-                event: winit::event::KeyEvent {
-                    logical_key: keyboard::Key::Named(NamedKey::ArrowDown),
-                    state: ElementState::Released,
-                    ..
-                },
-                ..
-            } => {
-                self.scene.mirror_lamp.modify_pos(|mut pos| {
-                    pos[1] -= 0.1;
-                    pos
-                });
-                self.last_touch_pos[1] += 0.1;
-            }
+    /// Used the next time fullscreen is entered
+    pub fn set_fullscreen_mode(&mut self, mode: FullscreenMode) {
+        self.fullscreen_mode = mode;
+    }
 
-            WindowEvent::Touch(t) => {
-                let g = range_event_start!("[APP] Touch event");
-                info!("Touch event: {:?}", t);
-                let now = Instant::now();
-                let prev = self.prev_touch_event_time;
-                let elapsed = now.duration_since(prev);
-                self.prev_touch_event_time = now;
-                info!("Elapsed: {:?}", elapsed);
-
-                let pos = [
-                    (t.location.x as f32 / self.window.inner_size().width as f32) * 2.0 - 1.0,
-                    (t.location.y as f32 / self.window.inner_size().height as f32) * 2.0 - 1.0,
-                ];
-                self.last_touch_pos = pos;
-                self.scene.mirror_lamp.set_pos([-pos[0], -pos[1]])
-            }
+    /// Connected monitors, indexed in the order reported by the platform
+    pub fn available_monitors(&self) -> Vec<MonitorInfo> {
+        let primary = self.window.primary_monitor();
+        self.window.available_monitors()
+            .enumerate()
+            .map(|(i, monitor)| MonitorInfo::new(i, &monitor, primary.as_ref()))
+            .collect()
+    }
 
-            WindowEvent::MouseInput {
-                state: ElementState::Pressed,
-                button: MouseButton::Left,
-                ..
-            } => {
-                info!("Mouse left button pressed!");
-                self.scene.mirror_lamp.set_pos([0.0, 0.0]);
-                self.last_touch_pos = [0.0, 0.0];
-            }
+    /// Enter fullscreen on the monitor with index from `available_monitors`. The monitor is kept for later
+    /// toggles, if it is unplugged the primary one is used instead
+    pub fn set_fullscreen_on(&mut self, monitor_index: usize, mode: FullscreenMode) -> anyhow::Result<()> {
+        let monitor = self.window.available_monitors().nth(monitor_index)
+            .ok_or_else(|| anyhow::anyhow!("Monitor {} does not exist", monitor_index))?;
+        let g = range_event_start!("[APP] Enable fullscreen");
+        info!("Fullscreen on monitor {}: {:?}", monitor_index, monitor.name());
+        self.fullscreen_mode = mode;
+        self.fullscreen_monitor = Some(monitor);
+        self.window.set_fullscreen(Some(self.fullscreen()));
+        Ok(())
+    }
+
+    /// Move fullscreen window to another monitor when the chosen one was unplugged
+    fn check_fullscreen_monitor(&mut self) {
+        let Some(chosen) = &self.fullscreen_monitor else {
+            return;
+        };
+        if self.window.fullscreen().is_none() || self.window.available_monitors().any(|m| m == *chosen) {
+            return;
+        }
+        self.window.set_fullscreen(Some(self.fullscreen()));
+        self.fullscreen_monitor = None;
+    }
+
+    fn request_exit(&mut self) {
+        let g = range_event_start!("[APP] Close requested");
+        info!("Close requested...");
+        self.app_finished = true;
+    }
 
+    /// Bindings can be changed at runtime, initial ones are taken from `AppConfig::input_map`
+    pub fn input_map_mut(&mut self) -> &mut InputMap {
+        &mut self.input_map
+    }
+
+    pub fn handle_event(
+        &mut self,
+        event_loop: &ActiveEventLoop,
+        evt: WindowEvent,
+    ) -> anyhow::Result<()> {
+        self.runner.scene_mut().handle_event(&evt, &self.window);
+        if let Some((action, state)) = self.input_map.action_for(&evt) {
+            self.handle_action(action, state);
+            self.runner.scene_mut().handle_action(action, state);
+        }
+        match &evt {
+            WindowEvent::CloseRequested => self.request_exit(),
             WindowEvent::RedrawRequested => {
-                let now = self.start_time.elapsed().as_millis() as f32;
                 // self.object_group.time.update(Time {
                 //     time: now,
                 // });
@@ -307,73 +520,53 @@ impl AppState {
                 let g = range_event_start!("[APP] Redraw requested");
                 if !self.app_finished && self.rendering_active {
                     // info!("Begin rendering ...");
-                    //recalculate bg
-                    let normalized_touch_pos = [
-                        (self.last_touch_pos[0] + 1.0) / 2.0,
-                        (self.last_touch_pos[1] + 1.0) / 2.0,
-                    ];
-
-                    let new_color = [
-                        normalized_touch_pos[0] * 0.6 + normalized_touch_pos[1] * 0.3 + (now / 600.0).sin() * 0.05,
-                        normalized_touch_pos[0] * 0.3 + normalized_touch_pos[1] * 0.3 + (now / 600.0 + 1.0).sin() * 0.05,
-                        normalized_touch_pos[1] * 0.6 + normalized_touch_pos[0] * 0.3 + (now / 600.0 + 2.0).sin() * 0.05,
-                    ];
-
-                    // adjust new_color, depending on color distance
-                    let color_dir = [
-                        new_color[0] - self.bg_color[0],
-                        new_color[1] - self.bg_color[1],
-                        new_color[2] - self.bg_color[2],
-                    ];
-
-                    let elapsed = self.last_frame_time.elapsed().as_secs_f32();
-                    let color_dist = (color_dir[0].powi(2) + color_dir[1].powi(2) + color_dir[2].powi(2)).sqrt();
-                    let color_dist = (color_dist + 0.5) * elapsed * 20.0;
-                    let color_change = [
-                        color_dir[0] * color_dist,
-                        color_dir[1] * color_dist,
-                        color_dir[2] * color_dist,
-                    ];
-
-                    self.bg_color[0] += color_change[0];
-                    self.bg_color[1] += color_change[1];
-                    self.bg_color[2] += color_change[2];
-
-                    // update trail
-                    self.scene.time.set(Time{time: (self.start_time.elapsed().as_millis() as i32).into()});
-                    if self.trail_last_update.elapsed().as_secs_f32() > 0.2 {
-                        let trail_id = self.trail_last_update.duration_since(self.start_time).as_millis() as u64;
-
-                        let cur_entry = self.scene.trail.create(trail_id, CircleAttributes {
-                            pos: [self.last_touch_pos[0], self.last_touch_pos[1]].into(),
-                            color: [1.0, 0.7, 1.0, 1.0].into(),
-                            trig_time: (trail_id as i32 + 1_500).into(),
-                        });
-
-                        self.scene.trail.auto_remove(trail_id - 2_000);
-                        
-                        self.trail_last_update = Instant::now();
+                    self.begin_frame();
+                    if let Some(pacer) = self.frame_pacer.as_mut() {
+                        instant_event!("[APP] Paced frame start");
+                        pacer.begin_frame();
                     }
+                    let stats = self.perf_stats();
+                    self.runner.update(&stats);
 
-                    self.vulkan_backend.render(&mut self.scene, self.bg_color)?;
+                    self.render_scene()?;
 
                     self.frame_cnt += 1;
                     if self.last_sec.elapsed().as_secs() >= 1 {
                         instant_event!("[APP] New sec!");
                         sparkles::flush_thread_local();
 
-                        info!("FPS: {}", self.frame_cnt);
+                        match self.pacing_error() {
+                            Some(err) => info!("FPS: {}, pacing error: {:.2}ms", self.frame_cnt, err),
+                            None => info!("FPS: {}", self.frame_cnt),
+                        }
+                        if let Some(history) = self.runner.backend().and_then(|b| b.frame_history(SurfaceId::MAIN)) {
+                            if let (Some(stats), Some(p99)) = (history.cpu_stats(), history.cpu_percentile(99.0)) {
+                                debug!("Frame time: avg {:.2}ms, max {:.2}ms, p99 {:.2}ms", stats.avg, stats.max, p99);
+                            }
+                        }
+                        self.last_fps = self.frame_cnt as u32;
                         self.frame_cnt = 0;
                         self.last_sec = Instant::now();
                     }
-                    let g = range_event_start!("[APP] window.request_redraw call");
-                    self.window.request_redraw();
+                    if let Some(pacer) = self.frame_pacer.as_mut() {
+                        // redraw will be requested in `new_events` when deadline is reached
+                        event_loop.set_control_flow(ControlFlow::WaitUntil(pacer.schedule_next()));
+                    }
+                    else {
+                        let g = range_event_start!("[APP] window.request_redraw call");
+                        self.window.request_redraw();
+                    }
                     // info!("Finish rendering");
                 }
-                self.last_frame_time = Instant::now();
+                else {
+                    // rendering is restarted with a redraw request, which schedules the next deadline
+                    event_loop.set_control_flow(ControlFlow::Wait);
+                }
             }
             WindowEvent::Resized(size) => {
                 info!("Resized to {}x{}", size.width, size.height);
+                // platforms resize fullscreen window when its monitor is gone
+                self.check_fullscreen_monitor();
                 if size.width == 0 || size.height == 0 {
                     warn!("One of dimensions is 0! Suspending rendering...");
                     self.rendering_active = false;
@@ -381,14 +574,25 @@ impl AppState {
                     if !self.rendering_active {
                         info!("Continue rendering...");
                     }
-                    else {
-                        let aspect = self.calculate_aspect();
-                        self.scene.map_stats.modify(|stats| {
-                            stats.aspect = aspect.into();
-                        })
+                    self.runner.stats_overlay_mut().set_aspect(size.width as f32 / size.height as f32);
+                    if let Some(vulkan_backend) = self.runner.backend_mut() {
+                        if let Err(e) = vulkan_backend.recreate_resize(SurfaceId::MAIN, (size.width, size.height)) {
+                            error!("Failed to recreate swapchain: {}", e);
+                        }
+                    }
+                    // surface-less renderer only remembers the size until resume
+                    self.rendering_active = !self.suspended;
+                    if self.rendering_active {
+                        // paced loop may be waiting without a deadline
+                        self.window.request_redraw();
                     }
-                    self.vulkan_backend.recreate_resize((size.width, size.height));
-                    self.rendering_active = true;
+                }
+            }
+            WindowEvent::ScaleFactorChanged { scale_factor, .. } => {
+                info!("Scale factor changed to {}", scale_factor);
+                let size = self.window.inner_size();
+                if size.width != 0 && size.height != 0 {
+                    self.runner.stats_overlay_mut().set_aspect(size.width as f32 / size.height as f32);
                 }
             }
             // _ => info!("new window event: {:?}", evt),
@@ -0,0 +1,271 @@
+//! Shader compilation for build scripts.
+//!
+//! Compiles `.vert` and `.frag` sources of a directory into `<dir>/compiled/<name>_<stage>.spv`, the files
+//! included by `use_shader!`. `#include "..."` is resolved relative to the including file, then in include
+//! directories, `#include <...>` only in include directories. Uses `glslc` from the Vulkan SDK, or
+//! libshaderc with `shaderc` feature.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Context};
+
+type SkipFn = Box<dyn Fn(&str) -> bool>;
+
+pub struct ShaderBuild {
+    source_dir: PathBuf,
+    include_dirs: Vec<PathBuf>,
+    skip: Option<SkipFn>,
+}
+
+impl ShaderBuild {
+    /// Source directory is also the first include directory
+    pub fn new(source_dir: impl Into<PathBuf>) -> Self {
+        let source_dir = source_dir.into();
+        Self {
+            include_dirs: vec![source_dir.clone()],
+            source_dir,
+            skip: None,
+        }
+    }
+
+    /// Directory with shared headers, e.g. uniform block definitions. Searched in order of adding
+    pub fn include_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.include_dirs.push(dir.into());
+        self
+    }
+
+    /// Skip sources with file name for which `skip` returns true
+    pub fn skip(mut self, skip: impl Fn(&str) -> bool + 'static) -> Self {
+        self.skip = Some(Box::new(skip));
+        self
+    }
+
+    /// Compile all sources and tell cargo to rerun the build script when sources or includes change
+    pub fn compile(self) -> anyhow::Result<()> {
+        println!("cargo:rerun-if-changed={}", self.source_dir.display());
+        for dir in self.include_dirs.iter().filter(|dir| !dir.starts_with(&self.source_dir)) {
+            println!("cargo:rerun-if-changed={}", dir.display());
+        }
+
+        let output_dir = self.source_dir.join("compiled");
+        fs::create_dir_all(&output_dir).context("Failed to create output directory")?;
+
+        let mut compiler = Compiler::new()?;
+        for (path, name) in self.sources()? {
+            let output_path = output_dir.join(name.replace('.', "_") + ".spv");
+            compiler.compile(&path, &output_path, &self.include_dirs)
+                .with_context(|| format!("Failed to compile {}", path.display()))?;
+        }
+        Ok(())
+    }
+
+    /// Paths and file names of shader sources which are not skipped
+    fn sources(&self) -> anyhow::Result<Vec<(PathBuf, String)>> {
+        let entries = fs::read_dir(&self.source_dir)
+            .with_context(|| format!("Failed to read shader directory {}", self.source_dir.display()))?;
+        let mut sources = Vec::new();
+        for entry in entries {
+            let path = entry.context("Failed to read entry")?.path();
+            let (Some(name), Some(ext)) = (path.file_name().and_then(|name| name.to_str()), path.extension()) else {
+                continue;
+            };
+            if ext != "vert" && ext != "frag" {
+                continue;
+            }
+            if self.skip.as_ref().is_some_and(|skip| skip(name)) {
+                continue;
+            }
+            let name = name.to_string();
+            sources.push((path, name));
+        }
+        Ok(sources)
+    }
+}
+
+#[cfg(not(feature = "shaderc"))]
+struct Compiler;
+
+#[cfg(not(feature = "shaderc"))]
+impl Compiler {
+    fn new() -> anyhow::Result<Self> {
+        Ok(Compiler)
+    }
+
+    fn compile(&mut self, source: &Path, output: &Path, include_dirs: &[PathBuf]) -> anyhow::Result<()> {
+        let mut command = std::process::Command::new("glslc");
+        command.arg(source).arg("-o").arg(output);
+        for dir in include_dirs {
+            command.arg("-I").arg(dir);
+        }
+        // errors are printed by glslc
+        let status = command.status()
+            .map_err(|e| anyhow!("glslc is required, install Vulkan SDK or enable `shaderc` feature: {}", e))?;
+        if !status.success() {
+            anyhow::bail!("glslc exited with {}", status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "shaderc")]
+struct Compiler(shaderc::Compiler);
+
+#[cfg(feature = "shaderc")]
+impl Compiler {
+    fn new() -> anyhow::Result<Self> {
+        shaderc::Compiler::new().map(Compiler).ok_or_else(|| anyhow!("Failed to create shaderc compiler"))
+    }
+
+    fn compile(&mut self, source: &Path, output: &Path, include_dirs: &[PathBuf]) -> anyhow::Result<()> {
+        let kind = match source.extension().and_then(|ext| ext.to_str()) {
+            Some("vert") => shaderc::ShaderKind::Vertex,
+            _ => shaderc::ShaderKind::Fragment,
+        };
+        let text = fs::read_to_string(source)?;
+        let mut options = shaderc::CompileOptions::new().ok_or_else(|| anyhow!("Failed to create shaderc options"))?;
+        options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_0 as u32);
+        options.set_include_callback(|requested, include_type, requesting, _depth| {
+            resolve_include(requested, include_type, Path::new(requesting), include_dirs)
+        });
+
+        let artifact = self.0.compile_into_spirv(&text, kind, &source.to_string_lossy(), "main", Some(&options))
+            .map_err(|e| anyhow!("{}", e))?;
+        if artifact.get_num_warnings() > 0 {
+            for line in artifact.get_warning_messages().lines() {
+                println!("cargo:warning={}", line);
+            }
+        }
+        fs::write(output, artifact.as_binary_u8())?;
+        Ok(())
+    }
+}
+
+/// Includes outside of the source directory are not watched by cargo otherwise
+#[cfg(feature = "shaderc")]
+fn resolve_include(requested: &str, include_type: shaderc::IncludeType, requesting: &Path,
+                   include_dirs: &[PathBuf]) -> shaderc::IncludeCallbackResult {
+    let relative = matches!(include_type, shaderc::IncludeType::Relative);
+    let path = find_include(requested, relative, requesting, include_dirs)?;
+    let content = fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    println!("cargo:rerun-if-changed={}", path.display());
+    Ok(shaderc::ResolvedInclude {
+        resolved_name: path.to_string_lossy().into_owned(),
+        content,
+    })
+}
+
+/// `#include "..."` is searched next to the including file, `#include <...>` in include directories
+#[cfg(any(feature = "shaderc", test))]
+fn find_include(requested: &str, relative: bool, requesting: &Path, include_dirs: &[PathBuf]) -> Result<PathBuf, String> {
+    // a failed relative include is retried by shaderc as a standard one
+    let dirs: Vec<&Path> = if relative {
+        requesting.parent().into_iter().collect()
+    } else {
+        include_dirs.iter().map(PathBuf::as_path).collect()
+    };
+    dirs.iter()
+        .map(|dir| dir.join(requested))
+        .find(|path| path.is_file())
+        .ok_or_else(|| format!("{} not found", requested))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use super::{find_include, ShaderBuild};
+
+    /// Empty directory for the files of one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("render-build-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn source_names(build: &ShaderBuild) -> Vec<String> {
+        let mut names: Vec<String> = build.sources().unwrap().into_iter().map(|(_, name)| name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn only_shader_stages_are_compiled() {
+        let dir = test_dir("stages");
+        for name in ["a.vert", "a.frag", "common.glsl", "notes.txt", "noext"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        assert_eq!(source_names(&ShaderBuild::new(&dir)), ["a.frag", "a.vert"]);
+    }
+
+    #[test]
+    fn skipped_sources_are_not_compiled() {
+        let dir = test_dir("skip");
+        for name in ["egui.vert", "egui.frag", "solid.vert", "solid.frag"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let build = ShaderBuild::new(&dir).skip(|name| name.starts_with("egui"));
+        assert_eq!(source_names(&build), ["solid.frag", "solid.vert"]);
+    }
+
+    #[test]
+    fn relative_include_is_found_next_to_including_file() {
+        let dir = test_dir("relative");
+        fs::create_dir_all(dir.join("common")).unwrap();
+        fs::write(dir.join("common/color.glsl"), "").unwrap();
+        fs::write(dir.join("color.glsl"), "").unwrap();
+
+        let found = find_include("color.glsl", true, &dir.join("common/a.frag"), &[dir.clone()]).unwrap();
+        assert_eq!(found, dir.join("common/color.glsl"));
+        // not searched in include directories
+        assert!(find_include("color.glsl", true, &dir.join("other/a.frag"), &[dir.clone()]).is_err());
+    }
+
+    #[test]
+    fn standard_include_is_found_in_first_include_dir_having_it() {
+        let dir = test_dir("standard");
+        for sub in ["first", "second", "shaders"] {
+            fs::create_dir_all(dir.join(sub)).unwrap();
+        }
+        fs::write(dir.join("second/uniforms.glsl"), "").unwrap();
+        fs::write(dir.join("shaders/uniforms.glsl"), "").unwrap();
+        let include_dirs = [dir.join("first"), dir.join("second")];
+
+        let found = find_include("uniforms.glsl", false, &dir.join("shaders/a.frag"), &include_dirs).unwrap();
+        assert_eq!(found, dir.join("second/uniforms.glsl"));
+    }
+
+    #[test]
+    fn missing_include_is_reported() {
+        let dir = test_dir("missing_include");
+        let err = find_include("missing.glsl", false, &dir.join("a.frag"), &[dir.clone()]).unwrap_err();
+        assert_eq!(err, "missing.glsl not found");
+    }
+
+    #[cfg(feature = "shaderc")]
+    #[test]
+    fn resolved_include_has_path_and_content() {
+        let dir = test_dir("resolve");
+        fs::write(dir.join("color.glsl"), "vec3 color();").unwrap();
+        let include = super::resolve_include("color.glsl", shaderc::IncludeType::Standard, &dir.join("a.frag"),
+                                             &[dir.clone()]).unwrap();
+        assert_eq!(include.resolved_name, dir.join("color.glsl").to_string_lossy());
+        assert_eq!(include.content, "vec3 color();");
+    }
+
+    #[test]
+    fn missing_source_dir_is_reported() {
+        let dir = test_dir("missing_dir").join("shaders");
+        let err = ShaderBuild::new(&dir).sources().unwrap_err();
+        assert_eq!(err.to_string(), format!("Failed to read shader directory {}", dir.display()));
+    }
+
+    #[test]
+    fn failed_compilation_names_the_source() {
+        let dir = test_dir("invalid");
+        let source = dir.join("broken.frag");
+        fs::write(&source, "#version 450\nvoid main() { not glsl }\n").unwrap();
+        let err = ShaderBuild::new(&dir).compile().unwrap_err();
+        assert!(err.to_string().starts_with(&format!("Failed to compile {}", source.display())), "{:#}", err);
+    }
+}
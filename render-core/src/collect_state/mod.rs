@@ -6,15 +6,21 @@ pub mod ordered_object_pool;
 
 use crate::object_handles::{ObjectId, UniformResourceId};
 use crate::{ObjectUpdate2DCmd, UniformBufferCmd};
+use crate::collect_state::object_updates::ObjectBatchCreate;
 use crate::collect_state::uniform_updates::ImageCmd;
 
 pub trait CollectDrawStateUpdates {
     fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd>;
     fn clear_updates(&mut self);
+    /// Mark the whole state as not uploaded, so the next `collect_updates` creates every resource again.
+    ///
+    /// Used when renderer was reinitialized and lost all resources.
+    fn reset_updates(&mut self);
 }
 
 pub enum GraphicsUpdateCmd<'a> {
     Object2D(ObjectId, ObjectUpdate2DCmd<'a>),
+    Object2DBatch(ObjectBatchCreate),
     UniformBuffer(UniformResourceId, UniformBufferCmd<'a>),
     Image(UniformResourceId, ImageCmd),
 }
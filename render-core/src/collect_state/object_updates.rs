@@ -1,13 +1,40 @@
+use ash::vk;
 use crate::BufferUpdateCmd;
+use crate::object_handles::{ObjectId, UniformResourceId};
 use crate::collect_state::buffer_updates::BufferUpdateData;
 use crate::pipeline::{PipelineDescWrapper, UniformBindingsDesc};
 
 pub enum ObjectUpdate2DCmd<'a> {
     Create {
         pipeline_desc: fn() -> PipelineDescWrapper,
-        uniform_bindings_desc: UniformBindingsDesc,
-        initial_state: BufferUpdateData<'a>
+        /// Boxed to keep update commands small
+        uniform_bindings_desc: Box<UniformBindingsDesc>,
+        initial_state: BufferUpdateData<'a>,
+        /// Objects with lower value are drawn first
+        z_order: i32,
+        /// Draw into the render target with this id instead of the window
+        render_target: Option<UniformResourceId>,
+        /// Other than 1 only for `PipelineDesc::INSTANCED` pipelines
+        instance_count: u32,
     },
     AttribUpdate(BufferUpdateCmd<'a>),
+    /// Hidden objects keep their resources but are not drawn
+    SetVisible(bool),
+    /// Number of instances drawn, for `PipelineDesc::INSTANCED` pipelines
+    SetInstanceCount(u32),
+    /// Clip drawing to this rect in framebuffer pixels, intersected with the render area.
+    /// `None` draws over the whole render area
+    SetScissor(Option<vk::Rect2D>),
     Destroy
+}
+
+/// Creation of several objects with the same pipeline and uniforms, uploaded as one attribute buffer
+pub struct ObjectBatchCreate {
+    pub ids: Vec<ObjectId>,
+    pub pipeline_desc: fn() -> PipelineDescWrapper,
+    pub uniform_bindings_desc: Box<UniformBindingsDesc>,
+    /// Attributes of all objects, one after another in `ids` order
+    pub initial_state: Vec<u8>,
+    pub z_order: i32,
+    pub render_target: Option<UniformResourceId>,
 }
\ No newline at end of file
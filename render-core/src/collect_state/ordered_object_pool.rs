@@ -1,18 +1,46 @@
+use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
 use std::fmt::Display;
 use std::mem;
 use crate::collect_state::CollectDrawStateUpdates;
+use crate::collect_state::object_updates::ObjectBatchCreate;
 use crate::{BufferUpdateCmd, GraphicsUpdateCmd, ObjectUpdate2DCmd};
-use crate::object_handles::{get_new_object_id, ObjectId};
+use crate::object_handles::{get_new_object_id, ObjectId, UniformResourceId};
+use crate::state::uniform::RenderTargetState;
 use crate::pipeline::{PipelineDesc, PipelineDescWrapper, UniformBindingsDesc};
 use crate::state::StateUpdatesBytes;
+use crate::layout::LayoutInfo;
+
+/// What has to be sent to renderer for the object on the next collect
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PendingCmd {
+    /// Object does not exist in renderer yet
+    Create,
+    /// Hidden slot is reused: make it visible and upload the whole state
+    Show,
+    /// Upload modified bytes only
+    Update,
+}
 
 pub struct OrderedObjectPool<P: PipelineDesc, K: Ord> {
     pipeline_desc: P, 
     
-    objects_per_ins_attrib: BTreeMap<K, (ObjectId, StateUpdatesBytes<P::PerInsAttrib>, bool)>,
+    objects_per_ins_attrib: BTreeMap<K, (ObjectId, StateUpdatesBytes<P::PerInsAttrib>, PendingCmd)>,
     uniform_bindings: UniformBindingsDesc,
+    /// Shared by all objects of the pool
+    z_order: i32,
+    render_target: Option<UniformResourceId>,
+
+    /// Max number of objects kept in renderer, including hidden ones
+    capacity: usize,
+    /// Hidden objects existing in renderer, ready for reuse
+    free_slots: Vec<ObjectId>,
+    /// Reserved slots which are not created in renderer yet
+    new_slots: Vec<ObjectId>,
+    /// Initial state for reserved slots
+    default_state: StateUpdatesBytes<P::PerInsAttrib>,
     
+    hidden_ids: Vec<ObjectId>,
     removed_ids: Vec<ObjectId>,
 }
 
@@ -20,22 +48,114 @@ impl<P: PipelineDesc, K: Ord> OrderedObjectPool<P, K>
     where P::PerInsAttrib: Default {
     /// Create new empty object pool
     pub fn new(uniforms: P::Uniforms<'_>) -> Self {
+        Self::with_capacity(uniforms, 0)
+    }
+
+    /// Create new object pool, which keeps up to `capacity` objects in renderer.
+    ///
+    /// Slots are created upfront, and removed objects are hidden instead of destroyed, so following
+    /// `create` calls reuse renderer resources instead of allocating new ones.
+    /// Reused slots do not keep creation order in the draw order.
+    pub fn with_capacity(uniforms: P::Uniforms<'_>, capacity: usize) -> Self {
         Self {
             pipeline_desc: P::default(),
             uniform_bindings: P::get_uniform_ids(uniforms),
             objects_per_ins_attrib: BTreeMap::new(),
+            z_order: 0,
+            render_target: None,
+
+            capacity,
+            free_slots: Vec::with_capacity(capacity),
+            new_slots: (0..capacity).map(|_| get_new_object_id()).collect(),
+            default_state: StateUpdatesBytes::new(P::PerInsAttrib::default()),
+
+            hidden_ids: Vec::new(),
             removed_ids: Vec::new(),
         }
     }
+
+    /// Set draw layer for all objects of the pool, lower value is drawn first. Default is 0.
+    ///
+    /// Takes effect only for objects created in renderer after the call.
+    pub fn with_z_order(mut self, z_order: i32) -> Self {
+        self.z_order = z_order;
+        self
+    }
+
+    /// Draw all objects of the pool into the offscreen render target instead of the window.
+    /// Pipeline must use subpass 0.
+    ///
+    /// Takes effect only for objects created in renderer after the call.
+    pub fn with_render_target(mut self, render_target: &RenderTargetState) -> Self {
+        self.render_target = Some(render_target.id());
+        self
+    }
+
+    /// Number of objects in the pool
+    pub fn len(&self) -> usize {
+        self.objects_per_ins_attrib.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects_per_ins_attrib.is_empty()
+    }
+
+    /// Number of objects kept in renderer for reuse
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    fn acquire_slot(free_slots: &mut Vec<ObjectId>, new_slots: &mut Vec<ObjectId>) -> (ObjectId, PendingCmd) {
+        if let Some(id) = free_slots.pop() {
+            (id, PendingCmd::Show)
+        }
+        else {
+            (new_slots.pop().unwrap_or_else(get_new_object_id), PendingCmd::Create)
+        }
+    }
+
+    /// Hide object for reuse if capacity allows, destroy otherwise
+    fn release_slot(&mut self, id: ObjectId, pending: PendingCmd) {
+        let retained = self.objects_per_ins_attrib.len() + self.free_slots.len() + self.new_slots.len();
+        let keep = retained < self.capacity;
+        match pending {
+            PendingCmd::Create => {
+                if keep {
+                    self.new_slots.push(id);
+                }
+            }
+            // slot is still hidden in renderer
+            PendingCmd::Show => {
+                if keep {
+                    self.free_slots.push(id);
+                }
+                else {
+                    self.removed_ids.push(id);
+                }
+            }
+            PendingCmd::Update => {
+                if keep {
+                    self.hidden_ids.push(id);
+                    self.free_slots.push(id);
+                }
+                else {
+                    self.removed_ids.push(id);
+                }
+            }
+        }
+    }
     
     /// Get per instance attributes for object with given key
     /// 
     /// If object with given key does not exist, it will be created with default attributes
     pub fn entry(&mut self, key: K) -> &mut StateUpdatesBytes<P::PerInsAttrib> {
-        &mut self.objects_per_ins_attrib.entry(key).or_insert_with(|| {
-            let object_id = get_new_object_id();
-            (object_id, StateUpdatesBytes::default(), true)
-        }).1
+        match self.objects_per_ins_attrib.entry(key) {
+            Entry::Occupied(entry) => &mut entry.into_mut().1,
+            Entry::Vacant(entry) => {
+                let (object_id, pending) = Self::acquire_slot(&mut self.free_slots, &mut self.new_slots);
+                &mut entry.insert((object_id, StateUpdatesBytes::new(P::PerInsAttrib::default()), pending)).1
+            }
+        }
     }
     
     /// Create new object with given key and attributes
@@ -45,14 +165,24 @@ impl<P: PipelineDesc, K: Ord> OrderedObjectPool<P, K>
         if self.objects_per_ins_attrib.contains_key(&key) {
             return;
         }
-        let object_id = get_new_object_id();
-        self.objects_per_ins_attrib.insert(key, (object_id, StateUpdatesBytes::new(attrib), true));
+        let (object_id, pending) = Self::acquire_slot(&mut self.free_slots, &mut self.new_slots);
+        self.objects_per_ins_attrib.insert(key, (object_id, StateUpdatesBytes::new(attrib), pending));
     }
     
+    /// Create several objects at once.
+    ///
+    /// Objects which are not created in renderer yet are uploaded as a single batch on the next collect,
+    /// same as for several `create` calls between collects. Existing keys are not modified.
+    pub fn create_many(&mut self, objects: impl IntoIterator<Item=(K, P::PerInsAttrib)>) {
+        for (key, attrib) in objects {
+            self.create(key, attrib);
+        }
+    }
+
     /// Remove object with given key
     pub fn remove(&mut self, key: &K) -> bool {
-        if let Some(removed) = self.objects_per_ins_attrib.remove(key) {
-            self.removed_ids.push(removed.0);
+        if let Some((id, _, pending)) = self.objects_per_ins_attrib.remove(key) {
+            self.release_slot(id, pending);
             true
         }
         else {
@@ -65,18 +195,27 @@ impl<P: PipelineDesc, K: Ord> OrderedObjectPool<P, K>
     pub fn auto_remove(&mut self, key_threshold: K) where K: Display {
         if self.objects_per_ins_attrib.iter().any(|(key, _)| key < &key_threshold) {
             let retained = self.objects_per_ins_attrib.split_off(&key_threshold);
-            for (_, (id, _, is_new)) in mem::take(&mut self.objects_per_ins_attrib) {
-                if !is_new {
-                    self.removed_ids.push(id);
-                }
+            let removed = mem::replace(&mut self.objects_per_ins_attrib, retained);
+            for (_, (id, _, pending)) in removed {
+                self.release_slot(id, pending);
             }
-            self.objects_per_ins_attrib = retained;
         }
     }
 
     pub fn get_pipeline_info(&self) -> fn() -> PipelineDescWrapper {
         P::collect
     }
+
+    fn create_cmd<'a>(&self, id: ObjectId, attrib: &'a StateUpdatesBytes<P::PerInsAttrib>) -> GraphicsUpdateCmd<'a> {
+        GraphicsUpdateCmd::object_update_2d(id, ObjectUpdate2DCmd::Create {
+            pipeline_desc: self.get_pipeline_info(),
+            uniform_bindings_desc: Box::new(self.uniform_bindings.clone()),
+            initial_state: attrib.modified_bytes().unwrap(),
+            z_order: self.z_order,
+            render_target: self.render_target,
+            instance_count: 1,
+        })
+    }
 }
 
 // updates
@@ -84,31 +223,206 @@ impl<K: Ord, P: PipelineDesc> CollectDrawStateUpdates for OrderedObjectPool<P, K
     where P::PerInsAttrib: Default {
     fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd> {
         let removed = self.removed_ids.iter().map(|id| GraphicsUpdateCmd::object_update_2d(*id, ObjectUpdate2DCmd::Destroy));
+        let hidden = self.hidden_ids.iter().map(|id| GraphicsUpdateCmd::object_update_2d(*id, ObjectUpdate2DCmd::SetVisible(false)));
+
+        // reserved slots are created hidden
+        let reserved = self.new_slots.iter().flat_map(|id| [
+            self.create_cmd(*id, &self.default_state),
+            GraphicsUpdateCmd::object_update_2d(*id, ObjectUpdate2DCmd::SetVisible(false)),
+        ]);
         
-        let updated = self.objects_per_ins_attrib.iter().filter_map(|(_, (id, attrib, is_new))| {
-            if *is_new {
-                let pipeline_info = self.get_pipeline_info();
-                let s = attrib.modified_bytes().unwrap();
-                Some(GraphicsUpdateCmd::object_update_2d(*id, ObjectUpdate2DCmd::Create {
-                    pipeline_desc: pipeline_info,
-                    uniform_bindings_desc: self.uniform_bindings.clone(),
-                    initial_state: s
-                }))
-            }
-            else {
-                attrib.modified_bytes().map(|s| 
-                    GraphicsUpdateCmd::object_update_2d(*id, ObjectUpdate2DCmd::AttribUpdate(BufferUpdateCmd::Update(s))))
-            }
+        // all new objects share one attributes buffer
+        let created: Vec<_> = self.objects_per_ins_attrib.values()
+            .filter(|(_, _, pending)| *pending == PendingCmd::Create)
+            .collect();
+        let batch = (created.len() > 1).then(|| GraphicsUpdateCmd::Object2DBatch(ObjectBatchCreate {
+            ids: created.iter().map(|(id, _, _)| *id).collect(),
+            pipeline_desc: self.get_pipeline_info(),
+            uniform_bindings_desc: Box::new(self.uniform_bindings.clone()),
+            initial_state: created.iter()
+                .flat_map(|(_, attrib, _)| {
+                    let bytes = attrib.modified_bytes().unwrap().modified_bytes;
+                    // objects are found in the buffer by their index in `ids`
+                    debug_assert_eq!(bytes.len(), P::PerInsAttrib::SIZE);
+                    bytes
+                })
+                .copied()
+                .collect(),
+            z_order: self.z_order,
+            render_target: self.render_target,
+        }));
+        let batched = batch.is_some();
+        
+        let updated = self.objects_per_ins_attrib.values().flat_map(move |(id, attrib, pending)| {
+            let (first_cmd, with_attrib_updates) = match pending {
+                PendingCmd::Create if batched => (None, false),
+                PendingCmd::Create => (Some(self.create_cmd(*id, attrib)), false),
+                PendingCmd::Show => (Some(GraphicsUpdateCmd::object_update_2d(*id, ObjectUpdate2DCmd::SetVisible(true))), true),
+                PendingCmd::Update => (None, true),
+            };
+            let attrib_updates = attrib.modified_ranges()
+                .filter(move |_| with_attrib_updates)
+                .map(move |s| GraphicsUpdateCmd::object_update_2d(*id, ObjectUpdate2DCmd::AttribUpdate(BufferUpdateCmd::Update(s))));
+            first_cmd.into_iter().chain(attrib_updates)
         });
         
-        removed.chain(updated)
+        removed.chain(hidden).chain(reserved).chain(batch).chain(updated)
     }
 
     fn clear_updates(&mut self) {
-        for (_, (_, attrib, is_new)) in self.objects_per_ins_attrib.iter_mut() {
+        for (_, (_, attrib, pending)) in self.objects_per_ins_attrib.iter_mut() {
             attrib.clear_modified();
-            *is_new = false;
+            *pending = PendingCmd::Update;
         }
+        self.free_slots.append(&mut self.new_slots);
+        self.hidden_ids.clear();
         self.removed_ids.clear();
     }
-}
\ No newline at end of file
+
+    fn reset_updates(&mut self) {
+        for (_, (_, attrib, pending)) in self.objects_per_ins_attrib.iter_mut() {
+            attrib.mark_modified();
+            *pending = PendingCmd::Create;
+        }
+        self.new_slots.append(&mut self.free_slots);
+        // removed objects do not exist in the new renderer anyway
+        self.hidden_ids.clear();
+        self.removed_ids.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use smallvec::{smallvec, SmallVec};
+    use crate::collect_state::{CollectDrawStateUpdates, GraphicsUpdateCmd};
+    use crate::layout::{LayoutInfo, MemberMeta};
+    use crate::layout::types::GlslTypeVariant;
+    use crate::object_handles::ObjectId;
+    use crate::pipeline::{PipelineDesc, UniformBindingType, UniformBindingsDesc, VertexAssembly};
+    use crate::{BufferUpdateCmd, ObjectUpdate2DCmd};
+    use super::OrderedObjectPool;
+
+    #[derive(Clone, Copy, Default)]
+    #[repr(C)]
+    struct Attrib {
+        value: f32,
+    }
+
+    impl LayoutInfo for Attrib {
+        const MEMBERS_META: &'static [MemberMeta] = &[
+            MemberMeta { name: "value", range: 0..4, ty: GlslTypeVariant::Float },
+        ];
+    }
+
+    #[derive(Default)]
+    struct TestPipeline;
+
+    impl PipelineDesc for TestPipeline {
+        type PerInsAttrib = Attrib;
+        type Uniforms<'a> = ();
+        const SHADERS: (&'static [u8], &'static [u8]) = (&[], &[]);
+        fn get_uniform_ids(_: Self::Uniforms<'_>) -> UniformBindingsDesc {
+            UniformBindingsDesc {
+                buffer_bindings: smallvec![],
+                image_bindings: smallvec![],
+                dynamic_offsets: smallvec![],
+                vertex_buffers: smallvec![],
+                bindless_texture: None,
+            }
+        }
+        fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]> {
+            smallvec![]
+        }
+        const VERTEX_ASSEMBLY: VertexAssembly = VertexAssembly::TriangleStrip;
+        const VERTICES_PER_INSTANCE: usize = 4;
+    }
+
+    type Pool = OrderedObjectPool<TestPipeline, u32>;
+
+    /// Update command without its data
+    #[derive(Debug, PartialEq)]
+    enum Cmd {
+        Create(ObjectId),
+        Batch(Vec<ObjectId>),
+        Visible(ObjectId, bool),
+        Attrib(ObjectId),
+        Destroy(ObjectId),
+    }
+
+    fn collect(pool: &mut Pool) -> Vec<Cmd> {
+        let cmds = pool.collect_updates().map(|cmd| match cmd {
+            GraphicsUpdateCmd::Object2D(id, ObjectUpdate2DCmd::Create { .. }) => Cmd::Create(id),
+            GraphicsUpdateCmd::Object2D(id, ObjectUpdate2DCmd::SetVisible(visible)) => Cmd::Visible(id, visible),
+            GraphicsUpdateCmd::Object2D(id, ObjectUpdate2DCmd::AttribUpdate(BufferUpdateCmd::Update(_))) => Cmd::Attrib(id),
+            GraphicsUpdateCmd::Object2D(id, ObjectUpdate2DCmd::Destroy) => Cmd::Destroy(id),
+            GraphicsUpdateCmd::Object2DBatch(batch) => Cmd::Batch(batch.ids),
+            _ => panic!("unexpected update command"),
+        }).collect();
+        pool.clear_updates();
+        cmds
+    }
+
+    fn id(pool: &Pool, key: u32) -> ObjectId {
+        pool.objects_per_ins_attrib[&key].0
+    }
+
+    #[test]
+    fn create_many_is_one_batch_in_key_order() {
+        let mut pool = Pool::new(());
+        pool.create_many([(3, Attrib { value: 3.0 }), (1, Attrib { value: 1.0 }), (2, Attrib { value: 2.0 })]);
+
+        let batches: Vec<_> = pool.collect_updates().map(|cmd| match cmd {
+            GraphicsUpdateCmd::Object2DBatch(batch) => batch,
+            _ => panic!("objects are not batched"),
+        }).collect();
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].ids, [id(&pool, 1), id(&pool, 2), id(&pool, 3)]);
+        let values: Vec<_> = [1.0f32, 2.0, 3.0].iter().flat_map(|v| v.to_ne_bytes()).collect();
+        assert_eq!(batches[0].initial_state, values);
+    }
+
+    #[test]
+    fn removed_slot_is_reused_in_same_frame() {
+        let mut pool = Pool::with_capacity((), 1);
+        pool.create(1, Attrib { value: 1.0 });
+        let slot = id(&pool, 1);
+        assert_eq!(collect(&mut pool), [Cmd::Create(slot)]);
+
+        pool.remove(&1);
+        pool.create(2, Attrib { value: 2.0 });
+        assert_eq!(id(&pool, 2), slot);
+        assert_eq!(collect(&mut pool), [Cmd::Visible(slot, false), Cmd::Visible(slot, true), Cmd::Attrib(slot)]);
+    }
+
+    #[test]
+    fn removed_beyond_capacity_is_destroyed() {
+        let mut pool = Pool::with_capacity((), 1);
+        pool.create(1, Attrib::default());
+        pool.create(2, Attrib::default());
+        let (first, second) = (id(&pool, 1), id(&pool, 2));
+        assert_eq!(collect(&mut pool), [Cmd::Batch(vec![first, second])]);
+
+        pool.remove(&1);
+        pool.remove(&2);
+        assert_eq!(collect(&mut pool), [Cmd::Destroy(first), Cmd::Visible(second, false)]);
+        assert!(collect(&mut pool).is_empty());
+    }
+
+    #[test]
+    fn reset_creates_free_slots_hidden_again() {
+        let mut pool = Pool::with_capacity((), 2);
+        pool.create(1, Attrib::default());
+        let first = id(&pool, 1);
+        let reserved = collect(&mut pool);
+        let Cmd::Create(spare) = reserved[0] else { panic!("reserved slot is not created first") };
+        assert_eq!(reserved, [Cmd::Create(spare), Cmd::Visible(spare, false), Cmd::Create(first)]);
+
+        pool.create(2, Attrib::default());
+        pool.remove(&1);
+        assert_eq!(id(&pool, 2), spare);
+        assert_eq!(collect(&mut pool), [Cmd::Visible(first, false), Cmd::Visible(spare, true), Cmd::Attrib(spare)]);
+
+        pool.reset_updates();
+        assert_eq!(collect(&mut pool), [Cmd::Create(first), Cmd::Visible(first, false), Cmd::Create(spare)]);
+    }
+}
@@ -1,7 +1,9 @@
 use std::ops::{Deref, DerefMut};
+use ash::vk;
 use crate::collect_state::{CollectDrawStateUpdates, GraphicsUpdateCmd};
 use crate::layout::LayoutInfo;
-use crate::object_handles::{get_new_object_id, ObjectId};
+use crate::object_handles::{get_new_object_id, ObjectId, UniformResourceId};
+use crate::state::uniform::RenderTargetState;
 use crate::{BufferUpdateCmd, ObjectUpdate2DCmd};
 use crate::pipeline::{PipelineDesc, PipelineDescWrapper, UniformBindingsDesc};
 use crate::state::StateUpdatesBytes;
@@ -12,6 +14,12 @@ pub struct SingleObject<P: PipelineDesc> {
     per_ins_attrib: StateUpdatesBytes<P::PerInsAttrib>,
     uniform_bindings: UniformBindingsDesc,
     object_id: ObjectId,
+    z_order: i32,
+    render_target: Option<UniformResourceId>,
+    instance_count: u32,
+    instance_count_changed: bool,
+    scissor: Option<vk::Rect2D>,
+    scissor_changed: bool,
 
     is_first: bool
 }
@@ -24,11 +32,80 @@ impl<P: PipelineDesc> SingleObject<P> {
             per_ins_attrib: attributes.to_state(),
             uniform_bindings,
             object_id,
+            z_order: 0,
+            render_target: None,
+            instance_count: 1,
+            instance_count_changed: false,
+            scissor: None,
+            scissor_changed: false,
 
             is_first: true
         }
     }
 
+    /// Set draw layer, objects with lower value are drawn first. Default is 0.
+    ///
+    /// Takes effect only before the object is created in renderer.
+    pub fn with_z_order(mut self, z_order: i32) -> Self {
+        self.z_order = z_order;
+        self
+    }
+
+    /// Draw into the offscreen render target instead of the window. Pipeline must use subpass 0.
+    ///
+    /// Takes effect only before the object is created in renderer.
+    pub fn with_render_target(mut self, render_target: &RenderTargetState) -> Self {
+        self.render_target = Some(render_target.id());
+        self
+    }
+
+    /// Offsets of `UniformBufferDynamic` bindings for this object, see `UniformBindingsDesc::dynamic_offsets`.
+    ///
+    /// Takes effect only before the object is created in renderer.
+    pub fn with_dynamic_offsets(mut self, offsets: &[u32]) -> Self {
+        self.uniform_bindings.dynamic_offsets = offsets.into();
+        self
+    }
+
+    /// Number of instances to draw, e.g. particles with per-instance data in `PipelineDesc::VERTEX_BINDINGS`.
+    /// Pipeline must be `PipelineDesc::INSTANCED`. Default is 1
+    pub fn with_instance_count(mut self, instance_count: u32) -> Self {
+        self.set_instance_count(instance_count);
+        self
+    }
+
+    /// Change the number of drawn instances, see `with_instance_count`
+    pub fn set_instance_count(&mut self, instance_count: u32) {
+        assert!(instance_count == 1 || P::INSTANCED, "Pipeline {} is not instanced", std::any::type_name::<P>());
+        if self.instance_count != instance_count {
+            self.instance_count = instance_count;
+            self.instance_count_changed = true;
+        }
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instance_count
+    }
+
+    /// Clip the object to a rect in framebuffer pixels, e.g. bounds of a parent widget. Default is `None`,
+    /// the whole render area
+    pub fn with_scissor(mut self, scissor: vk::Rect2D) -> Self {
+        self.set_scissor(Some(scissor));
+        self
+    }
+
+    /// Change or remove the clip rect, see `with_scissor`
+    pub fn set_scissor(&mut self, scissor: Option<vk::Rect2D>) {
+        if self.scissor != scissor {
+            self.scissor = scissor;
+            self.scissor_changed = true;
+        }
+    }
+
+    pub fn scissor(&self) -> Option<vk::Rect2D> {
+        self.scissor
+    }
+
     pub fn id(&self) -> ObjectId {
         self.object_id
     }
@@ -58,23 +135,38 @@ impl<P: PipelineDesc> CollectDrawStateUpdates for SingleObject<P> {
     fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd> {
         let id = self.id();
 
-        if self.is_first {
+        let create = self.is_first.then(|| {
             let pipeline_info = self.get_pipeline_info();
             let s = self.per_ins_attrib.modified_bytes().unwrap();
-            Some(GraphicsUpdateCmd::object_update_2d(id, ObjectUpdate2DCmd::Create {
+            GraphicsUpdateCmd::object_update_2d(id, ObjectUpdate2DCmd::Create {
                 pipeline_desc: pipeline_info,
-                uniform_bindings_desc: self.uniform_bindings.clone(),
-                initial_state: s
-            })).into_iter()
-        }
-        else {
-            self.per_ins_attrib.modified_bytes().map(|s|
-                GraphicsUpdateCmd::object_update_2d(id, ObjectUpdate2DCmd::AttribUpdate(BufferUpdateCmd::Update(s)))
-            ).into_iter()
-        }
+                uniform_bindings_desc: Box::new(self.uniform_bindings.clone()),
+                initial_state: s,
+                z_order: self.z_order,
+                render_target: self.render_target,
+                instance_count: self.instance_count,
+            })
+        });
+        let instance_count = (!self.is_first && self.instance_count_changed).then(||
+            GraphicsUpdateCmd::object_update_2d(id, ObjectUpdate2DCmd::SetInstanceCount(self.instance_count))
+        );
+        // created without scissor
+        let scissor = (self.scissor_changed || self.is_first && self.scissor.is_some()).then(||
+            GraphicsUpdateCmd::object_update_2d(id, ObjectUpdate2DCmd::SetScissor(self.scissor))
+        );
+        let updates = (!self.is_first).then(|| self.per_ins_attrib.modified_ranges().map(move |s|
+            GraphicsUpdateCmd::object_update_2d(id, ObjectUpdate2DCmd::AttribUpdate(BufferUpdateCmd::Update(s)))
+        ));
+        create.into_iter().chain(instance_count).chain(scissor).chain(updates.into_iter().flatten())
     }
     fn clear_updates(&mut self) {
         self.clear_modified();
+        self.instance_count_changed = false;
+        self.scissor_changed = false;
         self.is_first = false;
     }
+    fn reset_updates(&mut self) {
+        self.mark_modified();
+        self.is_first = true;
+    }
 }
\ No newline at end of file
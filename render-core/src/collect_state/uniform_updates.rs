@@ -7,6 +7,19 @@ pub enum UniformBufferCmd<'a> {
 }
 
 pub enum ImageCmd {
+    /// Path relative to `resources`. Images are decoded to RGBA8, `.ktx2` textures keep their compressed
+    /// format and mip levels, with fallback to variants supported by the device
+    ///
+    /// Loaded on a background thread, objects sample a transparent placeholder until the image is ready,
+    /// and the checker of `MISSING_TEXTURE_ID` if it fails to load
     Create(String),
+    /// Array image with a layer from each path, in order. Images are decoded to RGBA8 and must have the
+    /// same size. Sampled as `sampler2DArray`, loaded like `Create`
+    CreateArray(Vec<String>),
+    /// Offscreen color image, rendered every frame before the main render pass
+    CreateRenderTarget {
+        width: u32,
+        height: u32,
+    },
     Destroy
 }
\ No newline at end of file
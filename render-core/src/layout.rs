@@ -67,6 +67,24 @@ pub mod types {
         }
     }
 
+    #[derive(Copy, Clone)]
+    #[repr(C, align(16))]
+    pub struct mat4<const P: usize>([[f32; 4]; 4], MaybeUninit<[u32; P]>);
+    impl<const P: usize> GlslType for mat4<P> {
+        const T: GlslTypeVariant = GlslTypeVariant::Mat4;
+        type Inner = [[f32; 4]; 4];
+    }
+    impl<const P: usize> From<[[f32; 4]; 4]> for mat4<P> {
+        fn from(data: [[f32; 4]; 4]) -> Self {
+            mat4(data, MaybeUninit::uninit())
+        }
+    }
+    impl<const P: usize> From<mat4<P>> for [[f32; 4]; 4] {
+        fn from(data: mat4<P>) -> [[f32; 4]; 4] {
+            data.0
+        }
+    }
+
 
     #[derive(Copy, Clone)]
     #[repr(C)]
@@ -123,24 +141,127 @@ pub mod types {
         }
     }
 
+    /// Compact vertex attribute type of 4 bytes, with components converted by the vertex input.
+    /// In a uniform buffer the member is read as a `uint` and unpacked by the shader, e.g. with `unpackUnorm4x8`
+    macro_rules! packed_type {
+        ($(#[$doc:meta])* $name:ident, $variant:ident, $inner:ty) => {
+            $(#[$doc])*
+            #[derive(Copy, Clone)]
+            #[repr(C)]
+            pub struct $name<const P: usize>($inner, MaybeUninit<[u32; P]>);
+            impl<const P: usize> GlslType for $name<P> {
+                const T: GlslTypeVariant = GlslTypeVariant::$variant;
+                type Inner = $inner;
+            }
+            impl<const P: usize> From<$inner> for $name<P> {
+                fn from(data: $inner) -> Self {
+                    $name(data, MaybeUninit::uninit())
+                }
+            }
+            impl<const P: usize> From<$name<P>> for $inner {
+                fn from(data: $name<P>) -> $inner {
+                    data.0
+                }
+            }
+        };
+    }
+
+    packed_type!(
+        /// `vec4` in shader with components in 0..=1, e.g. colors
+        unorm8x4, Unorm8x4, [u8; 4]
+    );
+    packed_type!(
+        /// `vec4` in shader with components in -1..=1, e.g. normals
+        snorm8x4, Snorm8x4, [i8; 4]
+    );
+    packed_type!(
+        /// `uvec4` in shader
+        u8x4, Uint8x4, [u8; 4]
+    );
+    packed_type!(
+        /// `ivec2` in shader, e.g. positions in pixels
+        i16x2, Int16x2, [i16; 2]
+    );
+    packed_type!(
+        /// `vec2` in shader with components in 0..=1, e.g. texture coordinates
+        unorm16x2, Unorm16x2, [u16; 2]
+    );
+    packed_type!(
+        /// `vec2` in shader with components in -1..=1
+        snorm16x2, Snorm16x2, [i16; 2]
+    );
+    packed_type!(
+        /// `vec4` in shader from 10-bit red, green, blue and 2-bit alpha, red in the lowest bits
+        unorm10_10_10_2, Unorm10_10_10_2, u32
+    );
+
     #[derive(Debug, Copy, Clone)]
     pub enum GlslTypeVariant {
         Vec2,
         Vec3,
         Vec4,
+        /// Column-major, each column is a vec4
+        Mat4,
         Float,
         Uint,
         Int,
+        Unorm8x4,
+        Snorm8x4,
+        Uint8x4,
+        Int16x2,
+        Unorm16x2,
+        Snorm16x2,
+        Unorm10_10_10_2,
     }
     impl GlslTypeVariant {
+
         pub fn format(&self) -> Format {
             match self {
                 GlslTypeVariant::Vec2 => Format::R32G32_SFLOAT,
                 GlslTypeVariant::Vec3 => Format::R32G32B32_SFLOAT,
                 GlslTypeVariant::Vec4 => Format::R32G32B32A32_SFLOAT,
+                // format of a single column
+                GlslTypeVariant::Mat4 => Format::R32G32B32A32_SFLOAT,
                 GlslTypeVariant::Float => Format::R32_SFLOAT,
                 GlslTypeVariant::Uint => Format::R32_UINT,
                 GlslTypeVariant::Int => Format::R32_SINT,
+                GlslTypeVariant::Unorm8x4 => Format::R8G8B8A8_UNORM,
+                GlslTypeVariant::Snorm8x4 => Format::R8G8B8A8_SNORM,
+                GlslTypeVariant::Uint8x4 => Format::R8G8B8A8_UINT,
+                GlslTypeVariant::Int16x2 => Format::R16G16_SINT,
+                GlslTypeVariant::Unorm16x2 => Format::R16G16_UNORM,
+                GlslTypeVariant::Snorm16x2 => Format::R16G16_SNORM,
+                GlslTypeVariant::Unorm10_10_10_2 => Format::A2B10G10R10_UNORM_PACK32,
+            }
+        }
+
+        /// Number of vertex attribute locations consumed by the type
+        pub fn location_count(&self) -> u32 {
+            match self {
+                GlslTypeVariant::Mat4 => 4,
+                _ => 1,
+            }
+        }
+
+        pub const fn std140_align(&self) -> usize {
+            match self {
+                GlslTypeVariant::Vec2 => 8,
+                GlslTypeVariant::Vec3 | GlslTypeVariant::Vec4 | GlslTypeVariant::Mat4 => 16,
+                GlslTypeVariant::Float | GlslTypeVariant::Uint | GlslTypeVariant::Int => 4,
+                // packed types, same as `uint`
+                _ => 4,
+            }
+        }
+
+        pub const fn std140_size(&self) -> usize {
+            match self {
+                GlslTypeVariant::Vec2 => 8,
+                GlslTypeVariant::Vec3 => 12,
+                GlslTypeVariant::Vec4 => 16,
+                GlslTypeVariant::Mat4 => 64,
+                GlslTypeVariant::Float | GlslTypeVariant::Uint | GlslTypeVariant::Int => 4,
+                // packed types, same as `uint`
+                _ => 4,
             }
         }
     }
@@ -171,9 +292,57 @@ pub trait LayoutInfo : Sized {
     }
 }
 
+/// Offset of a member following `prev_end` in std140 layout.
+///
+/// Used by `define_layout!` to check at compile time that Rust layout matches std140.
+pub const fn std140_offset(prev_end: usize, ty: GlslTypeVariant) -> usize {
+    let align = ty.std140_align();
+    prev_end.div_ceil(align) * align
+}
+
 pub struct MemberMeta {
     pub name: &'static str,
     pub range: Range<usize>,
     pub ty: GlslTypeVariant,
     // r#type: TypeId,
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::std140_offset;
+    use super::types::GlslTypeVariant::{self, *};
+
+    /// Offsets of members placed one after another, and the end of the last one
+    fn std140_offsets(members: &[GlslTypeVariant]) -> (Vec<usize>, usize) {
+        let mut end = 0;
+        let offsets = members.iter().map(|ty| {
+            let offset = std140_offset(end, *ty);
+            end = offset + ty.std140_size();
+            offset
+        }).collect();
+        (offsets, end)
+    }
+
+    #[test]
+    fn scalar_after_vec3_fills_its_tail() {
+        // uniform U { float a; vec2 b; vec3 c; float d; vec4 e; mat4 f; }
+        let (offsets, end) = std140_offsets(&[Float, Vec2, Vec3, Float, Vec4, Mat4]);
+        assert_eq!(offsets, [0, 8, 16, 28, 32, 48]);
+        assert_eq!(end, 112);
+    }
+
+    #[test]
+    fn vectors_are_aligned_to_their_size() {
+        // uniform U { float a; vec2 b; float c; vec3 d; vec2 e; }
+        let (offsets, end) = std140_offsets(&[Float, Vec2, Float, Vec3, Vec2]);
+        assert_eq!(offsets, [0, 8, 16, 32, 48]);
+        assert_eq!(end, 56);
+    }
+
+    #[test]
+    fn mat4_is_aligned_as_vec4() {
+        // uniform U { vec2 a; mat4 b; float c; vec4 d; }
+        let (offsets, end) = std140_offsets(&[Vec2, Mat4, Float, Vec4]);
+        assert_eq!(offsets, [0, 16, 80, 96]);
+        assert_eq!(end, 112);
+    }
+}
@@ -2,6 +2,7 @@ pub mod collect_state;
 pub mod object_handles;
 pub mod layout;
 pub mod pipeline;
+pub mod projection;
 pub mod state;
 
 pub use layout::types::GlslType;
@@ -9,6 +9,10 @@ pub fn get_new_object_id() -> ObjectId {
 
 pub type UniformResourceId = usize;
 
+/// Built-in 2x2 magenta and black checker image, bound in place of images which failed to load.
+/// Can be bound explicitly, e.g. while the real texture is not made yet
+pub const MISSING_TEXTURE_ID: UniformResourceId = UniformResourceId::MAX;
+
 
 static LAST_UNIFORM_RESOURCE_ID: AtomicUsize = AtomicUsize::new(0);
 pub fn get_new_uniform_id() -> UniformResourceId {
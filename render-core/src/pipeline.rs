@@ -1,7 +1,7 @@
 use std::any::TypeId;
 use ash::vk;
 use ash::vk::{Format, PipelineVertexInputStateCreateInfo, VertexInputAttributeDescription, VertexInputBindingDescription};
-use smallvec::{smallvec, SmallVec};
+use smallvec::SmallVec;
 use crate::layout::{LayoutInfo, MemberMeta};
 use crate::layout::types::GlslTypeVariant;
 use crate::object_handles::UniformResourceId;
@@ -12,6 +12,45 @@ pub enum VertexAssembly {
     TriangleList,
 }
 
+/// Value of a shader specialization constant, declared in GLSL with `layout(constant_id = N) const`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecConstValue {
+    Bool(bool),
+    Int(i32),
+    Uint(u32),
+    Float(f32),
+}
+
+impl SpecConstValue {
+    /// 4 bytes as read by the shader, bool is a `VkBool32`
+    pub fn to_bytes(self) -> [u8; 4] {
+        match self {
+            SpecConstValue::Bool(v) => (v as u32).to_ne_bytes(),
+            SpecConstValue::Int(v) => v.to_ne_bytes(),
+            SpecConstValue::Uint(v) => v.to_ne_bytes(),
+            SpecConstValue::Float(v) => v.to_ne_bytes(),
+        }
+    }
+}
+
+/// Stencil test, same for front and back faces.
+///
+/// Requires depth format with stencil, ignored otherwise. Stencil is cleared to 0 at the start of the frame.
+#[derive(Debug, Clone, Copy)]
+pub struct StencilState {
+    pub compare_op: vk::CompareOp,
+    /// Both stencil and depth tests passed
+    pub pass_op: vk::StencilOp,
+    pub fail_op: vk::StencilOp,
+    pub depth_fail_op: vk::StencilOp,
+    pub reference: u32,
+    pub compare_mask: u32,
+    pub write_mask: u32,
+}
+
+/// `constant_id` of the texture array size in pipelines with `PipelineDesc::BINDLESS_TEXTURES`
+pub const BINDLESS_TEXTURE_COUNT_ID: u32 = 1000;
+
 pub trait PipelineDesc: Default + 'static {
     type PerInsAttrib: LayoutInfo;
     type Uniforms<'a>;
@@ -20,11 +59,39 @@ pub trait PipelineDesc: Default + 'static {
     fn get_uniform_bindings() -> SmallVec<[(u32, UniformBindingType); 5]>;
     const VERTEX_ASSEMBLY: VertexAssembly;
     const VERTICES_PER_INSTANCE: usize;
+    const STENCIL: Option<StencilState> = None;
+    /// 1 draws in the post subpass, after all objects of subpass 0, and can read their color
+    /// through `UniformBindingType::InputAttachment`. Requires post subpass enabled in render config.
+    const SUBPASS: u32 = 0;
+    /// Vertex buffer bindings after `PerInsAttrib` at binding 0, numbered from 1. Their locations follow
+    /// the ones of `PerInsAttrib`. Buffers are given for each object by `UniformBindingsDesc::vertex_buffers`
+    const VERTEX_BINDINGS: &'static [VertexBinding] = &[];
+    /// Objects are drawn with several instances (see `SingleObject::with_instance_count`), which read the same
+    /// `PerInsAttrib` and differ by `gl_InstanceIndex` and per-instance `VERTEX_BINDINGS`. Objects of
+    /// instanced pipelines are never drawn with indirect batch draws
+    const INSTANCED: bool = false;
+    /// Values of specialization constants by `constant_id`, applied to both shader stages. Pipeline variants
+    /// of one shader are separate `PipelineDesc` types, e.g. generic over a const parameter
+    const SPECIALIZATION_CONSTANTS: &'static [(u32, SpecConstValue)] = &[];
+    /// Objects sample the texture given by `UniformBindingsDesc::bindless_texture` from an array at set 1,
+    /// with index in a push constant:
+    /// ```glsl
+    /// layout(constant_id = 1000) const uint TEXTURE_COUNT = 1;
+    /// layout(set = 1, binding = 0) uniform sampler2D textures[TEXTURE_COUNT];
+    /// layout(push_constant) uniform TextureIndex { uint texture_index; };
+    /// ```
+    /// With descriptor indexing the array holds all bindless textures and is bound once, otherwise each
+    /// object has its own set with a single texture and index 0
+    const BINDLESS_TEXTURES: bool = false;
 
     fn get_id() -> TypeId {
         TypeId::of::<Self>()
     }
     fn collect() -> PipelineDescWrapper {
+        let mut attributes = Self::PerInsAttrib::get_attributes_configuration().with_bindings(Self::VERTEX_BINDINGS);
+        if Self::INSTANCED {
+            attributes = attributes.with_shared_attributes();
+        }
         PipelineDescWrapper {
             id: Self::get_id(),
             name: std::any::type_name::<Self>(),
@@ -33,8 +100,13 @@ pub trait PipelineDesc: Default + 'static {
             vertex_shader: Self::SHADERS.0,
             fragment_shader: Self::SHADERS.1,
 
-            attributes: Self::PerInsAttrib::get_attributes_configuration(),
+            attributes,
+            instanced: Self::INSTANCED,
+            specialization_constants: Self::SPECIALIZATION_CONSTANTS,
+            bindless_textures: Self::BINDLESS_TEXTURES,
             uniform_bindings: Self::get_uniform_bindings(),
+            stencil: Self::STENCIL,
+            subpass: Self::SUBPASS,
         }
     }
 }
@@ -42,7 +114,12 @@ pub trait PipelineDesc: Default + 'static {
 #[derive(Debug, Clone)]
 pub enum UniformBindingType {
     UniformBuffer,
+    /// Uniform buffer shared by many objects, each object reads `range` bytes starting at its own offset
+    /// from `UniformBindingsDesc::dynamic_offsets`
+    UniformBufferDynamic { range: u64 },
     CombinedImageSampler,
+    /// Color written by subpass 0, bound by renderer. Only for pipelines of the post subpass
+    InputAttachment,
 }
 
 #[derive(Debug, Clone)]
@@ -55,13 +132,58 @@ pub struct PipelineDescWrapper {
     pub fragment_shader: &'static [u8],
 
     pub attributes: VertexInputDesc,
+    pub instanced: bool,
+    pub specialization_constants: &'static [(u32, SpecConstValue)],
+    pub bindless_textures: bool,
     pub uniform_bindings: SmallVec<[(u32, UniformBindingType); 5]>,
+    pub stencil: Option<StencilState>,
+    pub subpass: u32,
 }
 
 #[derive(Clone, Debug)]
 pub struct UniformBindingsDesc {
+    /// Both `UniformBuffer` and `UniformBufferDynamic` bindings
     pub buffer_bindings: SmallVec<[(u32, UniformResourceId); 5]>,
     pub image_bindings: SmallVec<[(u32, UniformResourceId); 5]>,
+    /// One offset in bytes per `UniformBufferDynamic` binding, in ascending binding order.
+    /// Must be a multiple of `minUniformBufferOffsetAlignment` of the device
+    pub dynamic_offsets: SmallVec<[u32; 2]>,
+    /// Uniform buffer bound to each of `PipelineDesc::VERTEX_BINDINGS`, by vertex binding number
+    pub vertex_buffers: SmallVec<[(u32, UniformResourceId); 2]>,
+    /// 2d image sampled through the texture array of `PipelineDesc::BINDLESS_TEXTURES` pipelines
+    pub bindless_texture: Option<UniformResourceId>,
+}
+
+/// Vertex buffer binding with elements described by a layout, e.g. positions shared by many objects
+/// or colors updated every frame.
+///
+/// Bound buffer is created with `UniformBufferState`, its layout holds consecutive elements, each
+/// `stride` bytes long
+#[derive(Clone, Copy)]
+pub struct VertexBinding {
+    pub members: &'static [MemberMeta],
+    pub stride: usize,
+    pub input_rate: vk::VertexInputRate,
+}
+
+impl VertexBinding {
+    /// Element `i` is read by vertex `i` of each instance
+    pub const fn per_vertex<L: LayoutInfo>() -> Self {
+        Self {
+            members: L::MEMBERS_META,
+            stride: L::SIZE,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }
+    }
+
+    /// Element `i` is read by all vertices of instance `i`
+    pub const fn per_instance<L: LayoutInfo>() -> Self {
+        Self {
+            members: L::MEMBERS_META,
+            stride: L::SIZE,
+            input_rate: vk::VertexInputRate::INSTANCE,
+        }
+    }
 }
 
 
@@ -73,26 +195,61 @@ pub struct VertexInputDesc {
     binding_desc: SmallVec<[VertexInputBindingDescription; 1]>,
 }
 
-/// Use single binding for now
+/// Per-instance attributes at binding 0, optionally followed by `VertexBinding`s
 impl VertexInputDesc {
     pub fn new(members_meta: &'static [MemberMeta], size: usize) -> Self {
+        let mut desc = Self {
+            attrib_desc: SmallVec::new(),
+            binding_desc: SmallVec::new(),
+        };
+        desc.push_binding(members_meta, size, vk::VertexInputRate::INSTANCE);
+        desc
+    }
 
-        let binding_desc = smallvec![VertexInputBindingDescription::default()
-                .binding(0)
-                .input_rate(vk::VertexInputRate::INSTANCE)
-                .stride(size as u32)];
-
-        let attrib_desc = members_meta.iter().enumerate().map(|(i, member)| {
-            VertexInputAttributeDescription::default()
-                .binding(0)
-                .format(member.ty.format())
-                .offset(member.range.start as u32)
-                .location(i as u32)
-        }).collect::<SmallVec<_>>();
-        Self {
-            attrib_desc,
-            binding_desc,
+    /// Add bindings numbered after the existing ones, with locations following the existing ones
+    pub fn with_bindings(mut self, bindings: &[VertexBinding]) -> Self {
+        for binding in bindings {
+            self.push_binding(binding.members, binding.stride, binding.input_rate);
         }
+        self
+    }
+
+    fn push_binding(&mut self, members_meta: &'static [MemberMeta], stride: usize, input_rate: vk::VertexInputRate) {
+        let binding = self.binding_desc.len() as u32;
+        self.binding_desc.push(VertexInputBindingDescription::default()
+            .binding(binding)
+            .input_rate(input_rate)
+            .stride(stride as u32));
+
+        // matrices take a location per column
+        let mut location = self.attrib_desc.len() as u32;
+        self.attrib_desc.extend(members_meta.iter().flat_map(|member| {
+            let first_location = location;
+            location += member.ty.location_count();
+            (0..member.ty.location_count()).map(move |column| {
+                VertexInputAttributeDescription::default()
+                    .binding(binding)
+                    .format(member.ty.format())
+                    .offset(member.range.start as u32 + column * 16)
+                    .location(first_location + column)
+            })
+        }));
+    }
+
+    /// Attributes of binding 0 are read by all instances, with zero stride
+    pub fn with_shared_attributes(mut self) -> Self {
+        self.binding_desc[0].stride = 0;
+        self
+    }
+
+    /// Shader input locations of all attributes
+    pub fn locations(&self) -> impl Iterator<Item=u32> + '_ {
+        self.attrib_desc.iter().map(|attrib| attrib.location)
+    }
+
+    /// Including binding 0 of per-instance attributes
+    pub fn binding_count(&self) -> u32 {
+        self.binding_desc.len() as u32
     }
 
     pub fn get_input_state_create_info(&mut self) -> PipelineVertexInputStateCreateInfo {
@@ -103,12 +260,28 @@ impl VertexInputDesc {
 }
 
 
+pub const SPIRV_MAGIC: u32 = 0x0723_0203;
+/// Magic number, version, generator, bound and schema words
+pub const SPIRV_HEADER_SIZE: usize = 20;
+
+/// Whether `code` is a whole number of words starting with a little-endian SPIR-V header.
+/// Used by `use_shader!` to reject broken shaders at compile time
+pub const fn is_spirv(code: &[u8]) -> bool {
+    code.len() >= SPIRV_HEADER_SIZE
+        && code.len().is_multiple_of(4)
+        && u32::from_le_bytes([code[0], code[1], code[2], code[3]]) == SPIRV_MAGIC
+}
+
+/// Vertex and fragment shaders compiled by build script, checked to be SPIR-V modules at compile time
 #[macro_export]
 macro_rules! use_shader {
-    ($name:expr) => {
-        (
-            include_bytes!(concat!("../../shaders/compiled/", $name, "_vert.spv")),
-            include_bytes!(concat!("../../shaders/compiled/", $name, "_frag.spv"))
-        )
-    };
+    ($name:expr) => {{
+        const VERTEX: &[u8] = include_bytes!(concat!("../../shaders/compiled/", $name, "_vert.spv"));
+        const FRAGMENT: &[u8] = include_bytes!(concat!("../../shaders/compiled/", $name, "_frag.spv"));
+        const _: () = assert!($crate::pipeline::is_spirv(VERTEX),
+            concat!("shaders/compiled/", $name, "_vert.spv is not a SPIR-V module (bad magic number or size not a multiple of 4)"));
+        const _: () = assert!($crate::pipeline::is_spirv(FRAGMENT),
+            concat!("shaders/compiled/", $name, "_frag.spv is not a SPIR-V module (bad magic number or size not a multiple of 4)"));
+        (VERTEX, FRAGMENT)
+    }};
 }
\ No newline at end of file
@@ -0,0 +1,41 @@
+/// Column-major matrix, as stored by `mat4` in std140 uniform buffers
+pub type Mat4 = [[f32; 4]; 4];
+
+pub const IDENTITY: Mat4 = [
+    [1.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0],
+];
+
+/// 2d orthographic projection to clip space: `pos * scale * zoom + pan`, where `scale` is `(1, aspect)`.
+///
+/// World unit is half of the viewport width on both axes, so shapes keep proportions for any `aspect`
+/// (width / height). `pan` is in NDC, the same as `Camera` uniforms of scenes. Depth is passed unchanged
+pub fn orthographic(aspect: f32, zoom: f32, pan: [f32; 2]) -> Mat4 {
+    [
+        [zoom, 0.0, 0.0, 0.0],
+        [0.0, zoom * aspect, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [pan[0], pan[1], 0.0, 1.0],
+    ]
+}
+
+/// Inverse of `orthographic`, e.g. to map cursor position in NDC to world coordinates
+pub fn orthographic_inverse(aspect: f32, zoom: f32, pan: [f32; 2]) -> Mat4 {
+    let (sx, sy) = (1.0 / zoom, 1.0 / (zoom * aspect));
+    [
+        [sx, 0.0, 0.0, 0.0],
+        [0.0, sy, 0.0, 0.0],
+        [0.0, 0.0, 1.0, 0.0],
+        [-pan[0] * sx, -pan[1] * sy, 0.0, 1.0],
+    ]
+}
+
+/// Transform a 2d point with z = 0 and w = 1
+pub fn transform_point(m: &Mat4, p: [f32; 2]) -> [f32; 2] {
+    [
+        m[0][0] * p[0] + m[1][0] * p[1] + m[3][0],
+        m[0][1] * p[0] + m[1][1] * p[1] + m[3][1],
+    ]
+}
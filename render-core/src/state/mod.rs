@@ -1,68 +1,196 @@
 pub mod uniform;
 
 use std::ops::Range;
+use smallvec::{smallvec, SmallVec};
 use crate::BufferUpdateData;
 use crate::layout::LayoutInfo;
 
+/// Max number of disjoint modified ranges, closest ranges are merged above this limit
+pub const MAX_MODIFIED_RANGES: usize = 4;
+
+type ModifiedRanges = SmallVec<[Range<usize>; MAX_MODIFIED_RANGES]>;
+
 #[derive(Default)]
 pub struct StateUpdatesBytes<T: LayoutInfo> {
     inner: T,
-    modified: Option<Range<usize>>
+    /// Sorted, disjoint and non-adjacent
+    modified: ModifiedRanges,
 }
 impl<T: LayoutInfo> StateUpdatesBytes<T> {
     pub fn new(v: T) -> Self {
         Self {
             inner: v,
-            modified: Some(0..T::SIZE)
+            modified: smallvec![0..T::SIZE]
         }
     }
+    /// Only members which differ from the current value are marked modified
     pub fn set(&mut self, v: T) {
-        self.inner = v;
-        self.modified = Some(0..T::SIZE);
+        self.modify(|inner| *inner = v);
     }
+
+    /// Only members whose bytes were changed by `f` are marked modified, so changing a single field
+    /// of a large uniform uploads just that field
     pub fn modify<F>(&mut self, f: F)
     where F: FnOnce(&mut T) {
+        if T::MEMBERS_META.is_empty() {
+            f(&mut self.inner);
+            self.mark_modified();
+            return;
+        }
+        let old: SmallVec<[u8; 256]> = SmallVec::from_slice(self.inner.as_bytes());
         f(&mut self.inner);
-        self.modified = Some(0..T::SIZE);
+        let new = self.inner.as_bytes();
+        for member in T::MEMBERS_META {
+            let range = member.range.clone();
+            if old[range.clone()] != new[range.clone()] {
+                insert_range(&mut self.modified, range);
+            }
+        }
     }
 
 
     /// safety: Not intended to use from user code!
+    ///
+    /// Returned range must lie within `0..T::SIZE`, this is checked in debug builds only.
     pub unsafe fn modify_field<F>(&mut self, f: F)
         where F: FnOnce(&mut T) -> Range<usize> {
         let range = f(&mut self.inner);
+        debug_assert!(range.start <= range.end && range.end <= T::SIZE,
+                      "Modified range {:?} is out of layout bounds 0..{}", range, T::SIZE);
+        debug_assert!(self.inner.as_bytes().len() >= T::SIZE,
+                      "Layout bytes are shorter ({}) than layout size {}", self.inner.as_bytes().len(), T::SIZE);
         self.merge_range(range);
     }
 
     fn merge_range(&mut self, r: Range<usize>) {
-        self.modified = merge_ranges(self.modified.clone(), r);
+        insert_range(&mut self.modified, r);
     }
 
+    /// Single span covering all modified bytes, including unmodified gaps between them
     pub fn modified_bytes(&self) -> Option<BufferUpdateData> {
-        self.modified.as_ref()
-            .map(|r| {
-                let bytes = &self.inner.as_bytes()[r.clone()];
-                let offset = r.start;
-                BufferUpdateData {
-                    modified_bytes: bytes,
-                    buffer_offset: offset
-                }
-            })
+        let start = self.modified.first()?.start;
+        let end = self.modified.last()?.end;
+        Some(self.update_data(start..end))
+    }
+
+    /// Each disjoint modified range separately, in ascending order
+    pub fn modified_ranges(&self) -> impl Iterator<Item=BufferUpdateData<'_>> {
+        self.modified.iter().map(|r| self.update_data(r.clone()))
+    }
+
+    fn update_data(&self, r: Range<usize>) -> BufferUpdateData<'_> {
+        BufferUpdateData {
+            modified_bytes: &self.inner.as_bytes()[r.clone()],
+            buffer_offset: r.start
+        }
     }
 
     pub fn clear_modified(&mut self) {
-        self.modified = None;
+        self.modified.clear();
+    }
+
+    pub fn mark_modified(&mut self) {
+        self.modified = smallvec![0..T::SIZE];
+    }
+}
+
+/// Insert range into sorted disjoint ranges, merging overlapping and adjacent ones.
+///
+/// If there are more than `MAX_MODIFIED_RANGES` ranges after insertion, two ranges with the smallest gap
+/// between them are merged.
+fn insert_range(ranges: &mut ModifiedRanges, r: Range<usize>) {
+    if r.is_empty() {
+        return;
+    }
+    // ranges touching `r` are merged into it
+    let first = ranges.partition_point(|existing| existing.end < r.start);
+    let last = ranges.partition_point(|existing| existing.start <= r.end);
+    let merged = if first < last {
+        ranges[first].start.min(r.start)..ranges[last - 1].end.max(r.end)
+    } else {
+        r
+    };
+    ranges.drain(first..last);
+    ranges.insert(first, merged);
+
+    if ranges.len() > MAX_MODIFIED_RANGES {
+        let i = (0..ranges.len() - 1)
+            .min_by_key(|&i| ranges[i + 1].start - ranges[i].end)
+            .unwrap();
+        let next = ranges.remove(i + 1);
+        ranges[i].end = next.end;
     }
 }
 
 
-pub fn merge_ranges(r1: Option<Range<usize>>, r2: Range<usize>) -> Option<Range<usize>> {
-    match r1 {
-        Some(r) => {
-            let start = r.start.min(r2.start);
-            let end = r.end.max(r2.end);
-            Some(start..end)
-        },
-        None => Some(r2)
+#[cfg(test)]
+mod tests {
+    use std::mem::offset_of;
+    use crate::layout::{LayoutInfo, MemberMeta};
+    use crate::layout::types::GlslTypeVariant;
+    use super::*;
+
+    /// Same layout as `define_layout!` would produce for three vec4 members and a mat4
+    #[derive(Clone, Copy, Default)]
+    #[repr(C, align(16))]
+    struct Large {
+        color: [f32; 4],
+        offset: [f32; 4],
+        scale: [f32; 4],
+        transform: [f32; 16],
+    }
+
+    impl LayoutInfo for Large {
+        const MEMBERS_META: &'static [MemberMeta] = &[
+            MemberMeta { name: "color", range: offset_of!(Large, color)..offset_of!(Large, color) + 16, ty: GlslTypeVariant::Vec4 },
+            MemberMeta { name: "offset", range: offset_of!(Large, offset)..offset_of!(Large, offset) + 16, ty: GlslTypeVariant::Vec4 },
+            MemberMeta { name: "scale", range: offset_of!(Large, scale)..offset_of!(Large, scale) + 16, ty: GlslTypeVariant::Vec4 },
+            MemberMeta { name: "transform", range: offset_of!(Large, transform)..offset_of!(Large, transform) + 64, ty: GlslTypeVariant::Mat4 },
+        ];
+    }
+
+    fn ranges(state: &StateUpdatesBytes<Large>) -> Vec<(usize, usize)> {
+        state.modified_ranges().map(|r| (r.buffer_offset, r.modified_bytes.len())).collect()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn new_state_is_fully_modified() {
+        let state = Large::default().to_state();
+        assert_eq!(ranges(&state), [(0, Large::SIZE)]);
+    }
+
+    #[test]
+    fn modify_marks_only_changed_member() {
+        let mut state = Large::default().to_state();
+        state.clear_modified();
+
+        state.modify(|v| v.offset = [1.0, 2.0, 3.0, 4.0]);
+        assert_eq!(ranges(&state), [(16, 16)]);
+        let modified = state.modified_bytes().unwrap();
+        assert_eq!(modified.buffer_offset, 16);
+        assert_eq!(*modified.modified_bytes, Large { offset: [1.0, 2.0, 3.0, 4.0], ..Default::default() }.as_bytes()[16..32]);
+    }
+
+    #[test]
+    fn set_with_equal_members_marks_nothing() {
+        let value = Large { scale: [2.0; 4], ..Default::default() };
+        let mut state = value.to_state();
+        state.clear_modified();
+
+        state.set(value);
+        assert!(ranges(&state).is_empty());
+        assert!(state.modified_bytes().is_none());
+    }
+
+    #[test]
+    fn separate_members_stay_separate_ranges() {
+        let mut state = Large::default().to_state();
+        state.clear_modified();
+
+        state.set(Large { color: [1.0; 4], transform: [1.0; 16], ..Default::default() });
+        assert_eq!(ranges(&state), [(0, 16), (48, 64)]);
+        // single span covers the unmodified gap
+        let span = state.modified_bytes().unwrap();
+        assert_eq!((span.buffer_offset, span.modified_bytes.len()), (0, Large::SIZE));
+    }
+}
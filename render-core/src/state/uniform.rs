@@ -46,6 +46,7 @@ impl<L: LayoutInfo> UniformBufferState<L> {
 pub struct UniformImageState {
     pub id: UniformResourceId,
     pub new_image_path: Option<String>,
+    path: String,
     is_first: bool,
 }
 
@@ -55,7 +56,8 @@ impl UniformImageState {
         let uniform_resource_id = get_new_uniform_id();
         Self {
             id: uniform_resource_id,
-            new_image_path: Some(path),
+            new_image_path: Some(path.clone()),
+            path,
             is_first: true
         }
     }
@@ -64,27 +66,86 @@ impl UniformImageState {
     }
 }
 
+/// Texture array with a layer per image, e.g. sprites of the same size sharing a single binding.
+///
+/// Bound as `UniformBindingType::CombinedImageSampler`, shader declares `sampler2DArray` and selects
+/// the layer with the third texture coordinate, usually from a per-instance attribute
+pub struct UniformImageArrayState {
+    id: UniformResourceId,
+    paths: Vec<String>,
+    is_first: bool,
+}
+
+impl UniformImageArrayState {
+    /// Paths are relative to `resources`
+    pub fn new(paths: Vec<String>) -> Self {
+        assert!(!paths.is_empty(), "Texture array must have at least one layer");
+        Self {
+            id: get_new_uniform_id(),
+            paths,
+            is_first: true,
+        }
+    }
+
+    pub fn id(&self) -> UniformResourceId {
+        self.id
+    }
+
+    pub fn layer_count(&self) -> u32 {
+        self.paths.len() as u32
+    }
+}
+
+/// Offscreen image which objects created with `with_render_target` are drawn into.
+///
+/// Its id can be bound as `UniformBindingType::CombinedImageSampler` in pipelines drawn later in the frame.
+pub struct RenderTargetState {
+    id: UniformResourceId,
+    width: u32,
+    height: u32,
+    is_first: bool,
+}
+
+impl RenderTargetState {
+    pub fn new(width: u32, height: u32) -> Self {
+        Self {
+            id: get_new_uniform_id(),
+            width,
+            height,
+            is_first: true,
+        }
+    }
+
+    pub fn id(&self) -> UniformResourceId {
+        self.id
+    }
+}
+
 // updates
 
 impl<L: LayoutInfo> CollectDrawStateUpdates for UniformBufferState<L> {
     fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd> {
-        if self.is_first {
+        let create = self.is_first.then(|| {
             let r = self.modified_bytes().unwrap();
-            Some(GraphicsUpdateCmd::uniform_buffer_update(self.id, UniformBufferCmd::Create(r))).into_iter()
-        }
-        else {
-            self.modified_bytes().map(|r| {
-                GraphicsUpdateCmd::uniform_buffer_update(self.id, UniformBufferCmd::Update(
-                    BufferUpdateCmd::Update(r)
-                ))
-            }).into_iter()
-        }
+            GraphicsUpdateCmd::uniform_buffer_update(self.id, UniformBufferCmd::Create(r))
+        });
+        let updates = (!self.is_first).then(|| self.modified_ranges().map(|r| {
+            GraphicsUpdateCmd::uniform_buffer_update(self.id, UniformBufferCmd::Update(
+                BufferUpdateCmd::Update(r)
+            ))
+        }));
+        create.into_iter().chain(updates.into_iter().flatten())
     }
 
     fn clear_updates(&mut self) {
         self.clear_modified();
         self.is_first = false;
     }
+
+    fn reset_updates(&mut self) {
+        self.mark_modified();
+        self.is_first = true;
+    }
 }
 
 impl CollectDrawStateUpdates for UniformImageState {
@@ -102,4 +163,39 @@ impl CollectDrawStateUpdates for UniformImageState {
         self.new_image_path = None;
         self.is_first = false;
     }
-}
\ No newline at end of file
+
+    fn reset_updates(&mut self) {
+        self.new_image_path = Some(self.path.clone());
+        self.is_first = true;
+    }
+}
+impl CollectDrawStateUpdates for UniformImageArrayState {
+    fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd<'_>> {
+        self.is_first.then(|| GraphicsUpdateCmd::Image(self.id, ImageCmd::CreateArray(self.paths.clone()))).into_iter()
+    }
+
+    fn clear_updates(&mut self) {
+        self.is_first = false;
+    }
+
+    fn reset_updates(&mut self) {
+        self.is_first = true;
+    }
+}
+
+impl CollectDrawStateUpdates for RenderTargetState {
+    fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd<'_>> {
+        self.is_first.then(|| GraphicsUpdateCmd::Image(self.id, ImageCmd::CreateRenderTarget {
+            width: self.width,
+            height: self.height,
+        })).into_iter()
+    }
+
+    fn clear_updates(&mut self) {
+        self.is_first = false;
+    }
+
+    fn reset_updates(&mut self) {
+        self.is_first = true;
+    }
+}
@@ -20,18 +20,35 @@ pub fn define_layout(input: TokenStream) -> TokenStream {
     let mut member_meta_entries = Vec::new();
     let mut trait_methods = Vec::new();
     let mut trait_methods_defs = Vec::new();
+    let mut std140_checks = Vec::new();
 
     for (i, field) in fields.iter().enumerate() {
         let field_name = field.ident.as_ref().unwrap();
         let field_type = &field.ty;
         let glsl_type = match quote!(#field_type).to_string().as_str() {
+            "mat4 < 0 >" => quote! { GlslTypeVariant::Mat4 },
             "vec4 < 0 >" => quote! { GlslTypeVariant::Vec4 },
+            "vec3 < 0 >" => quote! { GlslTypeVariant::Vec3 },
             "vec2 < 0 >" => quote! { GlslTypeVariant::Vec2 },
             "uint < 0 >" => quote! { GlslTypeVariant::Uint },
             "float < 0 >" => quote! { GlslTypeVariant::Float },
             "int < 0 >" => quote! { GlslTypeVariant::Int },
+            "unorm8x4 < 0 >" => quote! { GlslTypeVariant::Unorm8x4 },
+            "snorm8x4 < 0 >" => quote! { GlslTypeVariant::Snorm8x4 },
+            "u8x4 < 0 >" => quote! { GlslTypeVariant::Uint8x4 },
+            "i16x2 < 0 >" => quote! { GlslTypeVariant::Int16x2 },
+            "unorm16x2 < 0 >" => quote! { GlslTypeVariant::Unorm16x2 },
+            "snorm16x2 < 0 >" => quote! { GlslTypeVariant::Snorm16x2 },
+            "unorm10_10_10_2 < 0 >" => quote! { GlslTypeVariant::Unorm10_10_10_2 },
             t => panic!("Unsupported type in define_layout: {}", t),
         };
+        let std140_message = format!("Member `{}` of `{}` is not placed according to std140 layout. \
+            Scalar members can't follow vec3, reorder the members", field_name, struct_name);
+        std140_checks.push(quote! {
+            let offset = render_core::layout::std140_offset(end, #glsl_type);
+            assert!(offset == offset_of!(#struct_name, #field_name), #std140_message);
+            end = offset + #glsl_type.std140_size();
+        });
         member_meta_entries.push(quote! {
             MemberMeta {
                 name: stringify!(#field_name),
@@ -96,6 +113,13 @@ pub fn define_layout(input: TokenStream) -> TokenStream {
             #(#pub_fields),*
         }
 
+        // Rust layout must match std140, because the same bytes are uploaded to uniform buffers
+        const _: () = {
+            let mut end = 0usize;
+            #(#std140_checks)*
+            let _ = end;
+        };
+
         impl LayoutInfo for #struct_name {
             const MEMBERS_META: &'static [MemberMeta] = &[
                 #(#member_meta_entries),*
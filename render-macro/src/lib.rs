@@ -1,99 +1,259 @@
 mod define_layout;
 
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput, Data, Fields};
+use proc_macro2::TokenTree;
+use quote::{format_ident, quote, quote_spanned};
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, parse_quote, DeriveInput, Data, Fields, Ident, Index, Member, Type};
 
-#[proc_macro_derive(CollectDrawStateUpdates)]
+/// Fields marked with `#[collect(skip)]` are ignored, all other fields must implement `CollectDrawStateUpdates`.
+///
+/// For enums, only fields of the current variant are collected.
+///
+/// ```
+/// #[derive(render_macro::CollectDrawStateUpdates)]
+/// struct Layer;
+///
+/// #[derive(render_macro::CollectDrawStateUpdates)]
+/// struct Scene {
+///     layer: Layer,
+///     #[collect(skip)]
+///     time: f32,
+/// }
+/// ```
+///
+/// `skip` is the only supported attribute
+///
+/// ```compile_fail
+/// #[derive(render_macro::CollectDrawStateUpdates)]
+/// struct Layer;
+///
+/// #[derive(render_macro::CollectDrawStateUpdates)]
+/// struct Scene {
+///     layer: Layer,
+///     #[collect(foo)]
+///     time: f32,
+/// }
+/// ```
+///
+/// Fields without draw state must be skipped
+///
+/// ```compile_fail
+/// #[derive(render_macro::CollectDrawStateUpdates)]
+/// struct Layer;
+///
+/// #[derive(render_macro::CollectDrawStateUpdates)]
+/// struct Scene {
+///     layer: Layer,
+///     time: f32,
+/// }
+/// ```
+#[proc_macro_derive(CollectDrawStateUpdates, attributes(collect))]
 pub fn derive_collect_draw_state_updates(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
+    match derive_collect_impl(&input) {
+        Ok(expanded) => TokenStream::from(expanded),
+        Err(e) => TokenStream::from(e.to_compile_error()),
+    }
+}
+
+fn derive_collect_impl(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
     let name = &input.ident;
+    let trait_path = quote! { render_core::collect_state::CollectDrawStateUpdates };
 
-    let (updates, clear_updates) =
-        if let Data::Struct(data) = &input.data {
-            match &data.fields {
-                Fields::Named(fields) => {
-                    let update_calls: Vec<_> = fields.named.iter().map(|f| {
-                        let field_name = &f.ident;
-                        quote! {
-                            self.#field_name.collect_updates()
-                        }
-                    }).collect();
-                    let first_update_call = update_calls.first();
-                    let rest_update_calls = update_calls.iter().skip(1);
-
-                    let clear_calls: Vec<_> = fields.named.iter().map(|f| {
-                        let field_name = &f.ident;
-                        quote! {
-                            self.#field_name.clear_updates();
-                        }
-                    }).collect();
-
-                    (
-                        quote! {
-                            #first_update_call
-                            #(.chain(#rest_update_calls))*
-                        },
-                        quote! {
-                            #(#clear_calls)*
-                        },
-                    )
-                }
-                Fields::Unnamed(fields) => {
-                    let mut update_calls = fields.unnamed.iter().enumerate().map(|(i, _)| {
-                        let index = syn::Index::from(i);
-                        quote! {
-                            self.#index.collect_updates()
-                        }
-                    });
-                    let first_update_call = update_calls.next();
-
-                    let clear_calls = fields.unnamed.iter().enumerate().map(|(i, _)| {
-                        let index = syn::Index::from(i);
-                        quote! {
-                            self.#index.clear_updates();
-                        }
-                    });
-
-                    (
-                        quote! {
-                            #first_update_call
-                            #(.chain(#update_calls))*
-                        },
-                        quote! {
-                            #(#clear_calls)*
-                        },
-                    )
-                }
-                Fields::Unit => (
-                    quote! { iter::empty() },
-                    quote! {},
-                ),
+    let mut bounded_types = Vec::new();
+    let (updates, clear_updates, reset_updates) = match &input.data {
+        Data::Struct(data) => {
+            let fields = state_fields(&data.fields)?;
+            bounded_types.extend(fields.iter().map(|(_, ty)| *ty));
+
+            // calls are spanned to the field type, so missing trait implementation is reported at the field
+            let update_calls = fields.iter().map(|(member, ty)| quote_spanned! {ty.span()=>
+                #trait_path::collect_updates(&self.#member)
+            });
+            let clear_calls = fields.iter().map(|(member, ty)| quote_spanned! {ty.span()=>
+                #trait_path::clear_updates(&mut self.#member);
+            });
+            let reset_calls = fields.iter().map(|(member, ty)| quote_spanned! {ty.span()=>
+                #trait_path::reset_updates(&mut self.#member);
+            });
+
+            (
+                quote! {
+                    std::iter::empty()
+                    #(.chain(#update_calls))*
+                },
+                quote! { #(#clear_calls)* },
+                quote! { #(#reset_calls)* },
+            )
+        }
+        Data::Enum(data) if !data.variants.is_empty() => {
+            let mut update_arms = Vec::new();
+            let mut clear_arms = Vec::new();
+            let mut reset_arms = Vec::new();
+            for variant in &data.variants {
+                let fields = state_fields(&variant.fields)?;
+                bounded_types.extend(fields.iter().map(|(_, ty)| *ty));
+
+                let variant_name = &variant.ident;
+                let bindings: Vec<_> = fields.iter()
+                    .map(|(member, _)| match member {
+                        Member::Named(ident) => format_ident!("__{}", ident),
+                        Member::Unnamed(index) => format_ident!("__{}", index.index),
+                    })
+                    .collect();
+                let pattern = match &variant.fields {
+                    Fields::Named(_) => {
+                        let members = fields.iter().map(|(member, _)| member);
+                        quote! { Self::#variant_name { #(#members: #bindings,)* .. } }
+                    }
+                    Fields::Unnamed(unnamed) => {
+                        // skipped fields are ignored with `_`
+                        let elements = (0..unnamed.unnamed.len()).map(|i| {
+                            match fields.iter().position(|(member, _)| *member == Member::Unnamed(Index::from(i))) {
+                                Some(pos) => {
+                                    let binding = &bindings[pos];
+                                    quote! { #binding }
+                                }
+                                None => quote! { _ },
+                            }
+                        });
+                        quote! { Self::#variant_name(#(#elements),*) }
+                    }
+                    Fields::Unit => quote! { Self::#variant_name },
+                };
+
+                let update_calls = fields.iter().zip(&bindings).map(|((_, ty), binding)| quote_spanned! {ty.span()=>
+                    #trait_path::collect_updates(#binding)
+                });
+                let clear_calls = fields.iter().zip(&bindings).map(|((_, ty), binding)| quote_spanned! {ty.span()=>
+                    #trait_path::clear_updates(#binding);
+                });
+                let reset_calls = fields.iter().zip(&bindings).map(|((_, ty), binding)| quote_spanned! {ty.span()=>
+                    #trait_path::reset_updates(#binding);
+                });
+
+                // each variant produces its own iterator type
+                update_arms.push(quote! {
+                    #pattern => Box::new(std::iter::empty() #(.chain(#update_calls))*)
+                        as Box<dyn Iterator<Item=render_core::GraphicsUpdateCmd<'_>> + '_>,
+                });
+                clear_arms.push(quote! { #pattern => { #(#clear_calls)* } });
+                reset_arms.push(quote! { #pattern => { #(#reset_calls)* } });
             }
-        } else {
+
             (
-                quote! { iter::empty() },
-                quote! {},
+                quote! { match self { #(#update_arms)* } },
+                quote! { match self { #(#clear_arms)* } },
+                quote! { match self { #(#reset_arms)* } },
             )
-        };
+        }
+        _ => (
+            quote! { std::iter::empty() },
+            quote! {},
+            quote! {},
+        ),
+    };
+
+    // field types depending on type parameters can't be checked without a bound
+    let type_params: Vec<_> = input.generics.type_params().map(|p| p.ident.clone()).collect();
+    let mut generics = input.generics.clone();
+    let where_clause = generics.make_where_clause();
+    for ty in bounded_types {
+        if uses_type_params(quote!(#ty), &type_params) {
+            where_clause.predicates.push(parse_quote! { #ty: #trait_path });
+        }
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
-    let expanded = quote! {
-        impl render_core::collect_state::CollectDrawStateUpdates for #name {
+    Ok(quote! {
+        impl #impl_generics #trait_path for #name #ty_generics #where_clause {
             fn collect_updates(&self) -> impl Iterator<Item=render_core::GraphicsUpdateCmd> + '_ {
                 #updates
             }
 
-            fn clear_updates(&mut self) { 
+            fn clear_updates(&mut self) {
                 #clear_updates
             }
+
+            fn reset_updates(&mut self) {
+                #reset_updates
+            }
         }
-    };
+    })
+}
 
-    TokenStream::from(expanded)
+fn uses_type_params(tokens: proc_macro2::TokenStream, type_params: &[Ident]) -> bool {
+    tokens.into_iter().any(|token| match token {
+        TokenTree::Ident(ident) => type_params.contains(&ident),
+        TokenTree::Group(group) => uses_type_params(group.stream(), type_params),
+        _ => false,
+    })
+}
+
+/// Fields which are not marked with `#[collect(skip)]`
+fn state_fields(fields: &Fields) -> syn::Result<Vec<(Member, &Type)>> {
+    let mut res = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let mut skip = false;
+        for attr in field.attrs.iter().filter(|a| a.path().is_ident("collect")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    skip = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported collect attribute, expected `skip`"))
+                }
+            })?;
+        }
+        if skip {
+            continue;
+        }
+
+        let member = match &field.ident {
+            Some(ident) => Member::Named(ident.clone()),
+            None => Member::Unnamed(Index::from(i)),
+        };
+        res.push((member, &field.ty));
+    }
+    Ok(res)
 }
 
 
 
+/// Struct with GLSL-typed members, laid out as std140 for uniform buffers and attribute upload.
+///
+/// Rust layout is checked against std140 at compile time. Members must be ordered so both agree:
+///
+/// ```
+/// use std::mem::offset_of;
+/// use render_core::layout::{LayoutInfo, MemberMeta};
+/// use render_core::layout::types::{float, vec3, GlslTypeVariant};
+/// use render_core::state::StateUpdatesBytes;
+///
+/// render_macro::define_layout! {
+///     pub struct Light {
+///         pub intensity: float<0>,
+///         pub dir: vec3<0>
+///     }
+/// }
+/// ```
+///
+/// A scalar can't follow vec3: std140 places it into the tail of the vec3, but in Rust vec3 is 16 bytes
+///
+/// ```compile_fail
+/// use std::mem::offset_of;
+/// use render_core::layout::{LayoutInfo, MemberMeta};
+/// use render_core::layout::types::{float, vec3, GlslTypeVariant};
+/// use render_core::state::StateUpdatesBytes;
+///
+/// render_macro::define_layout! {
+///     pub struct Light {
+///         pub dir: vec3<0>,
+///         pub intensity: float<0>
+///     }
+/// }
+/// ```
 #[proc_macro]
 pub fn define_layout(input: TokenStream) -> TokenStream {
     define_layout::define_layout(input)
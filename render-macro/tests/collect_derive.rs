@@ -0,0 +1,123 @@
+use render_core::collect_state::{CollectDrawStateUpdates, GraphicsUpdateCmd};
+use render_core::object_handles::ObjectId;
+use render_core::ObjectUpdate2DCmd;
+
+/// Emits one command with its id until updates are cleared
+struct Leaf {
+    id: ObjectId,
+    pending: bool,
+    resets: u32,
+}
+
+impl Leaf {
+    fn new(id: ObjectId) -> Self {
+        Self { id, pending: true, resets: 0 }
+    }
+}
+
+impl CollectDrawStateUpdates for Leaf {
+    fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd<'_>> {
+        self.pending.then(|| GraphicsUpdateCmd::object_update_2d(self.id, ObjectUpdate2DCmd::SetVisible(true)))
+            .into_iter()
+    }
+
+    fn clear_updates(&mut self) {
+        self.pending = false;
+    }
+
+    fn reset_updates(&mut self) {
+        self.pending = true;
+        self.resets += 1;
+    }
+}
+
+fn collected_ids(state: &impl CollectDrawStateUpdates) -> Vec<ObjectId> {
+    state.collect_updates().map(|cmd| match cmd {
+        GraphicsUpdateCmd::Object2D(id, _) => id,
+        _ => panic!("unexpected update command"),
+    }).collect()
+}
+
+#[derive(render_macro::CollectDrawStateUpdates)]
+enum Variants<T> {
+    A {
+        x: Leaf,
+        #[collect(skip)]
+        y: u32,
+    },
+    B(#[collect(skip)] u8, T),
+    C,
+}
+
+#[derive(render_macro::CollectDrawStateUpdates)]
+struct Pair(#[collect(skip)] u32, Leaf, #[collect(skip)] &'static str, Leaf);
+
+#[derive(render_macro::CollectDrawStateUpdates)]
+struct Layer<P> {
+    objects: P,
+    #[collect(skip)]
+    name: &'static str,
+}
+
+#[derive(render_macro::CollectDrawStateUpdates)]
+struct Wrapper<T>(T, #[collect(skip)] u8);
+
+#[derive(render_macro::CollectDrawStateUpdates)]
+enum Empty {}
+
+#[test]
+fn enum_collects_only_current_variant() {
+    let mut a: Variants<Leaf> = Variants::A { x: Leaf::new(1), y: 0 };
+    assert_eq!(collected_ids(&a), [1]);
+    a.clear_updates();
+    assert!(collected_ids(&a).is_empty());
+    a.reset_updates();
+    assert_eq!(collected_ids(&a), [1]);
+    let Variants::A { x, y } = &a else { unreachable!() };
+    assert_eq!((x.resets, *y), (1, 0));
+
+    let mut b: Variants<Leaf> = Variants::B(7, Leaf::new(2));
+    assert_eq!(collected_ids(&b), [2]);
+    b.clear_updates();
+    assert!(collected_ids(&b).is_empty());
+    let Variants::B(skipped, _) = &b else { unreachable!() };
+    assert_eq!(*skipped, 7);
+
+    let mut c: Variants<Leaf> = Variants::C;
+    assert!(collected_ids(&c).is_empty());
+    c.clear_updates();
+    c.reset_updates();
+}
+
+#[test]
+fn skipped_tuple_fields_are_not_collected() {
+    let mut pair = Pair(5, Leaf::new(1), "skipped", Leaf::new(2));
+    assert_eq!(collected_ids(&pair), [1, 2]);
+    pair.clear_updates();
+    assert!(collected_ids(&pair).is_empty());
+    pair.reset_updates();
+    assert_eq!((pair.1.resets, pair.3.resets), (1, 1));
+    assert_eq!((pair.0, pair.2), (5, "skipped"));
+}
+
+#[test]
+fn generic_fields_are_collected() {
+    let mut layer = Layer { objects: Leaf::new(3), name: "layer" };
+    assert_eq!(collected_ids(&layer), [3]);
+    layer.clear_updates();
+    assert!(collected_ids(&layer).is_empty());
+    assert_eq!(layer.name, "layer");
+
+    // nested generic types get their bounds from the field types
+    let mut nested = Wrapper(Layer { objects: Wrapper(Leaf::new(4), 0), name: "nested" }, 1);
+    assert_eq!(collected_ids(&nested), [4]);
+    nested.reset_updates();
+    assert_eq!(nested.0.objects.0.resets, 1);
+    assert_eq!((nested.1, nested.0.objects.1), (1, 0));
+}
+
+#[test]
+fn empty_enum_implements_trait() {
+    fn implements<T: CollectDrawStateUpdates>() {}
+    implements::<Empty>();
+}
@@ -0,0 +1,50 @@
+use std::mem::offset_of;
+use render_core::layout::{LayoutInfo, MemberMeta};
+use render_core::layout::types::{float, mat4, vec2, vec3, vec4, GlslTypeVariant};
+use render_core::state::StateUpdatesBytes;
+use render_macro::define_layout;
+
+define_layout! {
+    pub struct Mixed {
+        pub a: float<0>,
+        pub b: vec2<0>,
+        pub c: vec3<0>,
+        pub d: vec4<0>,
+        pub e: mat4<0>
+    }
+}
+
+define_layout! {
+    pub struct Packed {
+        pub a: vec2<0>,
+        pub b: float<0>,
+        pub c: float<0>,
+        pub d: mat4<0>,
+        pub e: vec3<0>
+    }
+}
+
+fn member_offsets<T: LayoutInfo>() -> Vec<(&'static str, usize)> {
+    T::MEMBERS_META.iter().map(|m| (m.name, m.range.start)).collect()
+}
+
+#[test]
+fn mixed_layout_matches_std140() {
+    // uniform Mixed { float a; vec2 b; vec3 c; vec4 d; mat4 e; }
+    assert_eq!(member_offsets::<Mixed>(), [("a", 0), ("b", 8), ("c", 16), ("d", 32), ("e", 48)]);
+    assert_eq!(Mixed::SIZE, 112);
+}
+
+#[test]
+fn scalars_after_vec2_are_packed() {
+    // uniform Packed { vec2 a; float b; float c; mat4 d; vec3 e; }
+    assert_eq!(member_offsets::<Packed>(), [("a", 0), ("b", 8), ("c", 12), ("d", 16), ("e", 80)]);
+    assert_eq!(Packed::SIZE, 96);
+}
+
+#[test]
+fn member_ranges_cover_rust_sizes() {
+    let ranges: Vec<_> = Mixed::MEMBERS_META.iter().map(|m| m.range.len()).collect();
+    // vec3 takes 16 bytes in Rust, tail padding is uploaded too
+    assert_eq!(ranges, [4, 8, 16, 16, 64]);
+}
@@ -0,0 +1,18 @@
+/// Built-in shaders are checked in to `shaders/compiled`. With `build_shaders` feature they are compiled from
+/// sources instead, egui ones only with `egui` feature
+#[cfg(feature = "build_shaders")]
+fn main() {
+    let egui = std::env::var_os("CARGO_FEATURE_EGUI").is_some();
+    let result = render_build::ShaderBuild::new("shaders")
+        .skip(move |name| !egui && name.starts_with("egui"))
+        .compile();
+    if let Err(e) = result {
+        eprintln!("Error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "build_shaders"))]
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+}
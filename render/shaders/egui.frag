@@ -0,0 +1,13 @@
+#version 450 core
+
+layout (binding = 0) uniform sampler2D tex;
+
+layout (location = 0) in vec2 frag_uv;
+layout (location = 1) in vec4 frag_color;
+
+layout(location = 0) out vec4 out_color;
+
+void main() {
+    // surface format is UNORM, so blending happens in gamma space, as egui expects
+    out_color = frag_color * texture(tex, frag_uv);
+}
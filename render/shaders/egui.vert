@@ -0,0 +1,21 @@
+#version 450 core
+
+layout(push_constant) uniform PushConstants {
+    // screen size in egui points
+    vec2 screen_size;
+} pc;
+
+layout (location = 0) in vec2 in_pos;
+layout (location = 1) in vec2 in_uv;
+// premultiplied, gamma space
+layout (location = 2) in vec4 in_color;
+
+layout(location = 0) out vec2 frag_uv;
+layout(location = 1) out vec4 frag_color;
+
+void main() {
+    frag_uv = in_uv;
+    frag_color = in_color;
+    // egui y axis points down, same as Vulkan NDC
+    gl_Position = vec4(in_pos / pc.screen_size * 2.0 - 1.0, 0.0, 1.0);
+}
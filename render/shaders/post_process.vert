@@ -0,0 +1,10 @@
+#version 450 core
+
+// uv of the sampled scene, (0, 0) is the top left corner
+layout(location = 0) out vec2 frag_uv;
+
+// single triangle covering the screen, without vertex buffers
+void main() {
+    frag_uv = vec2((gl_VertexIndex << 1) & 2, gl_VertexIndex & 2);
+    gl_Position = vec4(frag_uv * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,18 @@
+#version 450 core
+
+// linear scene color, stored in UNORM image
+layout(set = 0, binding = 0) uniform sampler2D scene;
+
+layout(location = 0) in vec2 frag_uv;
+layout(location = 0) out vec4 out_color;
+
+vec3 srgb_encode(vec3 linear) {
+    vec3 low = linear * 12.92;
+    vec3 high = 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055;
+    return mix(high, low, lessThanEqual(linear, vec3(0.0031308)));
+}
+
+void main() {
+    vec4 color = texture(scene, frag_uv);
+    out_color = vec4(srgb_encode(clamp(color.rgb, 0.0, 1.0)), color.a);
+}
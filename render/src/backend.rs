@@ -0,0 +1,47 @@
+use render_core::collect_state::CollectDrawStateUpdates;
+
+/// Identifies a window surface inside a backend
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SurfaceId(pub(crate) usize);
+
+impl SurfaceId {
+    /// Surface of the window passed to `VulkanBackend::new_for_window`
+    pub const MAIN: SurfaceId = SurfaceId(0);
+}
+
+/// How color attachment is initialized at the start of the frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ClearBehavior {
+    /// RGB in the same color space as shader outputs, see `VulkanBackend::color_space`
+    Clear([f32; 3]),
+    /// Keep contents of the previous frame rendered to the same swapchain image.
+    ///
+    /// Each swapchain image keeps its own contents. When image has not been rendered yet (first frames,
+    /// after swapchain recreation), there is nothing to load and it is cleared with black instead.
+    Load,
+}
+
+impl Default for ClearBehavior {
+    /// Clear with black
+    fn default() -> Self {
+        ClearBehavior::Clear([0.0; 3])
+    }
+}
+
+/// Outcome of `RenderBackend::render`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenderResult {
+    Rendered,
+    /// Renderer is paused, nothing was drawn
+    Idle,
+}
+
+/// Drawing of the scene state, implemented by `VulkanBackend` and by `NullBackend` for tests without a GPU.
+///
+/// Covers only per-frame rendering, creation and window management are specific to each backend.
+pub trait RenderBackend {
+    /// Apply updates of `draw_state_diff` and draw a frame to the surface. Updates are cleared unless
+    /// `RenderResult::Idle` is returned
+    fn render(&mut self, id: SurfaceId, draw_state_diff: &mut impl CollectDrawStateUpdates,
+              clear_behavior: ClearBehavior) -> anyhow::Result<RenderResult>;
+}
@@ -1,4 +1,10 @@
+#[cfg(all(feature = "vulkan", target_arch = "wasm32"))]
+compile_error!("Vulkan backend is not available on wasm32, disable default features of `render`");
+
+#[cfg(feature = "vulkan")]
 pub mod vulkan_backend;
+pub mod backend;
+pub mod null_backend;
 pub mod util;
 #[cfg(target_os = "android")]
 pub mod android;
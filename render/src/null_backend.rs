@@ -0,0 +1,93 @@
+use render_core::collect_state::{CollectDrawStateUpdates, GraphicsUpdateCmd};
+use crate::backend::{ClearBehavior, RenderBackend, RenderResult, SurfaceId};
+
+/// Number of update commands of each kind
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UpdateCounts {
+    pub objects: usize,
+    pub object_batches: usize,
+    pub uniform_buffers: usize,
+    pub images: usize,
+}
+
+impl UpdateCounts {
+    pub fn total(&self) -> usize {
+        self.objects + self.object_batches + self.uniform_buffers + self.images
+    }
+
+    fn add(&mut self, other: UpdateCounts) {
+        self.objects += other.objects;
+        self.object_batches += other.object_batches;
+        self.uniform_buffers += other.uniform_buffers;
+        self.images += other.images;
+    }
+}
+
+/// Backend which draws nothing: `render` drains update commands of the draw state and counts them.
+///
+/// For testing scene logic headlessly, e.g. that an input produces the expected updates. Surface ids are
+/// not checked, all surfaces share the counters.
+#[derive(Default, Debug)]
+pub struct NullBackend {
+    rendered_frames: u64,
+    last_frame: UpdateCounts,
+    total: UpdateCounts,
+    clear_behavior: Option<ClearBehavior>,
+    paused: bool,
+}
+
+impl NullBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Frames rendered while not paused
+    pub fn rendered_frames(&self) -> u64 {
+        self.rendered_frames
+    }
+
+    /// Updates drained by the last rendered frame
+    pub fn last_frame_updates(&self) -> UpdateCounts {
+        self.last_frame
+    }
+
+    /// Updates drained by all rendered frames
+    pub fn total_updates(&self) -> UpdateCounts {
+        self.total
+    }
+
+    /// Clear behavior passed to the last rendered frame
+    pub fn last_clear_behavior(&self) -> Option<ClearBehavior> {
+        self.clear_behavior
+    }
+
+    /// Like `VulkanBackend::pause`, `render` returns `RenderResult::Idle` without touching the state
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+}
+
+impl RenderBackend for NullBackend {
+    fn render(&mut self, _id: SurfaceId, draw_state_diff: &mut impl CollectDrawStateUpdates,
+              clear_behavior: ClearBehavior) -> anyhow::Result<RenderResult> {
+        if self.paused {
+            return Ok(RenderResult::Idle);
+        }
+        let mut counts = UpdateCounts::default();
+        for cmd in draw_state_diff.collect_updates() {
+            match cmd {
+                GraphicsUpdateCmd::Object2D(..) => counts.objects += 1,
+                GraphicsUpdateCmd::Object2DBatch(..) => counts.object_batches += 1,
+                GraphicsUpdateCmd::UniformBuffer(..) => counts.uniform_buffers += 1,
+                GraphicsUpdateCmd::Image(..) => counts.images += 1,
+            }
+        }
+        draw_state_diff.clear_updates();
+
+        self.rendered_frames += 1;
+        self.last_frame = counts;
+        self.total.add(counts);
+        self.clear_behavior = Some(clear_behavior);
+        Ok(RenderResult::Rendered)
+    }
+}
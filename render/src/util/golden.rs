@@ -0,0 +1,172 @@
+use std::path::{Path, PathBuf};
+use ash::vk::Extent2D;
+use image::{Rgba, RgbaImage};
+
+/// Environment variable which makes `check_golden` overwrite golden images with the captured ones
+pub const UPDATE_GOLDEN_ENV: &str = "UPDATE_GOLDEN";
+
+/// Pixels of two same-sized images which differ by more than the tolerance in any channel
+#[derive(Debug)]
+pub struct ImageDiff {
+    pub mismatched_pixels: usize,
+    pub max_channel_difference: u8,
+    /// Mismatched pixels in red over a dimmed grayscale of the expected image
+    pub diff_image: RgbaImage,
+}
+
+/// Compare images channel by channel, `None` if every channel differs by at most `tolerance`
+pub fn compare_images(actual: &RgbaImage, expected: &RgbaImage, tolerance: u8) -> anyhow::Result<Option<ImageDiff>> {
+    if actual.dimensions() != expected.dimensions() {
+        anyhow::bail!("Image size {:?} differs from expected {:?}", actual.dimensions(), expected.dimensions());
+    }
+    let mut mismatched_pixels = 0;
+    let mut max_channel_difference = 0;
+    let diff_image = RgbaImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let difference = a.0.iter().zip(e.0).map(|(a, e)| a.abs_diff(e)).max().unwrap_or(0);
+        max_channel_difference = max_channel_difference.max(difference);
+        if difference > tolerance {
+            mismatched_pixels += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            let luma = (e[0] as u32 * 3 + e[1] as u32 * 6 + e[2] as u32) / 10;
+            let dimmed = (luma / 3) as u8;
+            Rgba([dimmed, dimmed, dimmed, 255])
+        }
+    });
+    Ok((mismatched_pixels > 0).then_some(ImageDiff {
+        mismatched_pixels,
+        max_channel_difference,
+        diff_image,
+    }))
+}
+
+/// Compare a captured RGBA8 frame with the golden PNG at `golden_path`.
+///
+/// On mismatch, `<name>.actual.png` and `<name>.diff.png` are written next to the golden image and an error
+/// is returned. Missing golden image is an error too, unless `UPDATE_GOLDEN` environment variable is set,
+/// in which case the golden image is (re)written from `rgba` and the check passes.
+pub fn check_golden(rgba: &[u8], extent: Extent2D, golden_path: &Path, tolerance: u8) -> anyhow::Result<()> {
+    let update = std::env::var_os(UPDATE_GOLDEN_ENV).is_some();
+    check_golden_with(rgba, extent, golden_path, tolerance, update)
+}
+
+/// `check_golden` with `UPDATE_GOLDEN` passed explicitly
+fn check_golden_with(rgba: &[u8], extent: Extent2D, golden_path: &Path, tolerance: u8, update: bool) -> anyhow::Result<()> {
+    let actual = RgbaImage::from_raw(extent.width, extent.height, rgba.to_vec())
+        .ok_or_else(|| anyhow::anyhow!("Captured frame is smaller than {}x{} RGBA8", extent.width, extent.height))?;
+
+    if update {
+        actual.save(golden_path)?;
+        return Ok(());
+    }
+    if !golden_path.exists() {
+        anyhow::bail!("Golden image {:?} does not exist, set {} to create it", golden_path, UPDATE_GOLDEN_ENV);
+    }
+    let expected = image::open(golden_path)?.to_rgba8();
+
+    let Some(diff) = compare_images(&actual, &expected, tolerance)? else {
+        return Ok(());
+    };
+    let actual_path = sibling_path(golden_path, "actual");
+    let diff_path = sibling_path(golden_path, "diff");
+    actual.save(&actual_path)?;
+    diff.diff_image.save(&diff_path)?;
+    anyhow::bail!("{} pixels differ from {:?} by up to {} (tolerance {}), see {:?}",
+                  diff.mismatched_pixels, golden_path, diff.max_channel_difference, tolerance, diff_path)
+}
+
+/// `dir/name.png` -> `dir/name.<suffix>.png`
+fn sibling_path(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!("{}.{}.png", stem, suffix))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use ash::vk::Extent2D;
+    use image::{Rgba, RgbaImage};
+    use super::{check_golden_with, compare_images};
+
+    const TOLERANCE: u8 = 12;
+
+    fn gray(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    /// Empty directory for the files of one test
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("golden-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = gray(4, 4, 100);
+        assert!(compare_images(&image, &image, 0).unwrap().is_none());
+    }
+
+    #[test]
+    fn difference_within_tolerance_matches() {
+        let expected = gray(4, 4, 100);
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 2, Rgba([100, 100 + TOLERANCE, 100, 255]));
+        assert!(compare_images(&actual, &expected, TOLERANCE).unwrap().is_none());
+    }
+
+    #[test]
+    fn difference_just_over_tolerance_is_reported() {
+        let expected = gray(4, 4, 100);
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 2, Rgba([100, 100, 100 - TOLERANCE - 1, 255]));
+        let diff = compare_images(&actual, &expected, TOLERANCE).unwrap().unwrap();
+        assert_eq!(diff.mismatched_pixels, 1);
+        assert_eq!(diff.max_channel_difference, TOLERANCE + 1);
+        assert_eq!(*diff.diff_image.get_pixel(1, 2), Rgba([255, 0, 0, 255]));
+        assert_ne!(*diff.diff_image.get_pixel(0, 0), Rgba([255, 0, 0, 255]));
+    }
+
+    #[test]
+    fn size_mismatch_is_error() {
+        let err = compare_images(&gray(4, 4, 0), &gray(4, 2, 0), TOLERANCE).unwrap_err();
+        assert!(err.to_string().contains("differs from expected"));
+    }
+
+    #[test]
+    fn mismatch_writes_actual_and_diff_images() {
+        let dir = test_dir("mismatch");
+        let golden_path = dir.join("scene.png");
+        gray(2, 2, 0).save(&golden_path).unwrap();
+        let actual = gray(2, 2, 200);
+
+        let err = check_golden_with(actual.as_raw(), Extent2D { width: 2, height: 2 }, &golden_path, TOLERANCE, false)
+            .unwrap_err();
+        assert!(err.to_string().contains("4 pixels differ"));
+        let written_actual = image::open(dir.join("scene.actual.png")).unwrap().to_rgba8();
+        assert_eq!(written_actual, actual);
+        let written_diff = image::open(dir.join("scene.diff.png")).unwrap().to_rgba8();
+        assert!(written_diff.pixels().all(|p| *p == Rgba([255, 0, 0, 255])));
+        // golden image is left as it was
+        assert_eq!(image::open(&golden_path).unwrap().to_rgba8(), gray(2, 2, 0));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_golden_is_written_only_when_updating() {
+        let dir = test_dir("missing");
+        let golden_path = dir.join("scene.png");
+        let actual = gray(2, 2, 50);
+        let extent = Extent2D { width: 2, height: 2 };
+
+        assert!(check_golden_with(actual.as_raw(), extent, &golden_path, TOLERANCE, false).is_err());
+        assert!(!golden_path.exists());
+        check_golden_with(actual.as_raw(), extent, &golden_path, TOLERANCE, true).unwrap();
+        check_golden_with(actual.as_raw(), extent, &golden_path, TOLERANCE, false).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
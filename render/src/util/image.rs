@@ -8,6 +8,10 @@ pub enum ReadImageError {
     ImageError(#[from] image::ImageError),
     #[error("Image has zero size")]
     ZeroSize,
+    #[error("Invalid KTX2 file: {0}")]
+    InvalidKtx2(&'static str),
+    #[error("Unsupported KTX2 file: {0}")]
+    UnsupportedKtx2(String),
 }
 pub type ReadImageResult<T> = Result<T, ReadImageError>;
 pub fn read_image_from_bytes(image_bytes: Vec<u8>) -> ReadImageResult<(Vec<u8>, Extent2D)> {
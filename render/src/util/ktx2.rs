@@ -0,0 +1,108 @@
+use ash::vk::{self, Extent2D};
+use crate::util::image::{ReadImageError, ReadImageResult};
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+const HEADER_LEN: usize = 80;
+const LEVEL_INDEX_ENTRY_LEN: usize = 24;
+
+/// Block-compressed texture family, used to choose a file variant supported by the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompressedFamily {
+    /// BC1-BC7, desktop GPUs
+    Bc,
+    /// ETC2 and EAC, required by OpenGL ES 3 class mobile GPUs
+    Etc2,
+    /// Most modern mobile GPUs
+    Astc,
+}
+
+impl CompressedFamily {
+    /// Suffix of file variants, e.g. `ground.astc.ktx2` for `ground.ktx2`
+    pub fn suffix(self) -> &'static str {
+        match self {
+            CompressedFamily::Bc => "bc7",
+            CompressedFamily::Etc2 => "etc2",
+            CompressedFamily::Astc => "astc",
+        }
+    }
+
+    pub fn of_format(format: vk::Format) -> Option<Self> {
+        let raw = format.as_raw();
+        if (vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::BC7_SRGB_BLOCK.as_raw()).contains(&raw) {
+            Some(CompressedFamily::Bc)
+        } else if (vk::Format::ETC2_R8G8B8_UNORM_BLOCK.as_raw()..=vk::Format::EAC_R11G11_SNORM_BLOCK.as_raw()).contains(&raw) {
+            Some(CompressedFamily::Etc2)
+        } else if (vk::Format::ASTC_4X4_UNORM_BLOCK.as_raw()..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw()).contains(&raw) {
+            Some(CompressedFamily::Astc)
+        } else {
+            None
+        }
+    }
+
+    /// Families in order of preference for the target platform
+    pub fn platform_preference() -> [Self; 3] {
+        if cfg!(any(target_os = "android", target_os = "ios")) {
+            [CompressedFamily::Astc, CompressedFamily::Etc2, CompressedFamily::Bc]
+        } else {
+            [CompressedFamily::Bc, CompressedFamily::Etc2, CompressedFamily::Astc]
+        }
+    }
+}
+
+/// Single 2d texture from a KTX2 file, with data of every mip level as stored in the file
+pub struct Ktx2Texture {
+    pub format: vk::Format,
+    pub extent: Extent2D,
+    /// Level 0 is the full size image
+    pub levels: Vec<Vec<u8>>,
+}
+
+/// Parse KTX2 file without supercompression, with a single layer and face.
+///
+/// Basis Universal (`VK_FORMAT_UNDEFINED`) and zstd supercompressed files are rejected, they must be
+/// transcoded offline to a format supported by the device
+pub fn read_ktx2_from_bytes(bytes: &[u8]) -> ReadImageResult<Ktx2Texture> {
+    if bytes.len() < HEADER_LEN || bytes[..12] != KTX2_IDENTIFIER {
+        return Err(ReadImageError::InvalidKtx2("missing KTX2 identifier"));
+    }
+    let u32_at = |offset: usize| u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap());
+    let u64_at = |offset: usize| u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+
+    let format = vk::Format::from_raw(u32_at(12) as i32);
+    let (width, height, depth) = (u32_at(20), u32_at(24), u32_at(28));
+    let (layer_count, face_count, level_count) = (u32_at(32), u32_at(36), u32_at(40));
+    let supercompression = u32_at(44);
+
+    if format == vk::Format::UNDEFINED {
+        return Err(ReadImageError::UnsupportedKtx2("Basis Universal textures must be transcoded".to_string()));
+    }
+    if supercompression != 0 {
+        return Err(ReadImageError::UnsupportedKtx2(format!("supercompression scheme {}", supercompression)));
+    }
+    if depth > 0 || layer_count > 1 || face_count != 1 {
+        return Err(ReadImageError::UnsupportedKtx2(format!(
+            "only 2d textures are supported, got depth {}, {} layers, {} faces", depth, layer_count, face_count)));
+    }
+    if width == 0 || height == 0 {
+        return Err(ReadImageError::ZeroSize);
+    }
+
+    // 0 means mips should be generated at load time, only the base level is stored
+    let level_count = level_count.max(1) as usize;
+    if level_count > 32 || bytes.len() < HEADER_LEN + level_count * LEVEL_INDEX_ENTRY_LEN {
+        return Err(ReadImageError::InvalidKtx2("level index is truncated"));
+    }
+    let levels = (0..level_count).map(|level| {
+        let entry = HEADER_LEN + level * LEVEL_INDEX_ENTRY_LEN;
+        let (offset, len) = (u64_at(entry) as usize, u64_at(entry + 8) as usize);
+        bytes.get(offset..offset.saturating_add(len))
+            .map(|data| data.to_vec())
+            .ok_or(ReadImageError::InvalidKtx2("level data is out of file bounds"))
+    }).collect::<ReadImageResult<_>>()?;
+
+    Ok(Ktx2Texture {
+        format,
+        extent: Extent2D { width, height },
+        levels,
+    })
+}
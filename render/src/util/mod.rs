@@ -1,7 +1,9 @@
 use std::fs;
 use std::path::PathBuf;
 
+pub mod golden;
 pub mod image;
+pub mod ktx2;
 
 #[cfg(not(target_os = "android"))]
 pub fn get_resource(path: PathBuf) -> anyhow::Result<Vec<u8>> {
@@ -17,7 +19,8 @@ pub fn get_resource(path: PathBuf) -> anyhow::Result<Vec<u8>> {
 
     let mut vm_lock = VM.get().unwrap().lock().unwrap();
     let vm = vm_lock.as_mut().unwrap();
-    let mut env = vm.get_env().unwrap();
+    // also called from the texture loader thread
+    let mut env = vm.attach_current_thread_permanently()?;
 
     let mut activity_lock = ACTIVITY.get().unwrap().lock().unwrap();
     let activity = activity_lock.as_mut().unwrap();
@@ -34,7 +37,9 @@ pub fn get_resource(path: PathBuf) -> anyhow::Result<Vec<u8>> {
     let asset_manager_ptr = unsafe { AAssetManager_fromJava(env.get_native_interface(), asset_manager.into_raw()) };
     let asset_manager = unsafe { ndk::asset::AssetManager::from_ptr(NonNull::new(asset_manager_ptr).unwrap()) };
     let filename_cstr = CString::new(path.to_str().unwrap())?;
-    let mut asset = asset_manager.open(&filename_cstr).unwrap();
+    // missing asset is an error, texture loading probes for optional variants
+    let mut asset = asset_manager.open(&filename_cstr)
+        .ok_or_else(|| anyhow::anyhow!("Asset not found: {:?}", path))?;
     let mut buffer = Vec::new();
     use std::io::Read;
     asset.read_to_end(&mut buffer)?;
@@ -0,0 +1,127 @@
+use std::collections::BTreeMap;
+use ash::vk;
+use ash::vk::{DescriptorBindingFlags, DescriptorSet, DescriptorSetLayout, DescriptorType, ShaderStageFlags};
+use render_core::object_handles::UniformResourceId;
+use crate::vulkan_backend::object_resource_pool::UniformImage;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+
+/// Length of the texture array with descriptor indexing
+pub const BINDLESS_TEXTURE_COUNT: u32 = 1024;
+
+/// Length of the texture array seen by shaders, the value of `BINDLESS_TEXTURE_COUNT_ID`.
+/// Without descriptor indexing it holds only the texture of the object
+pub fn texture_count(device: &VkDeviceRef) -> u32 {
+    if device.descriptor_indexing_enabled() {
+        BINDLESS_TEXTURE_COUNT
+    } else {
+        1
+    }
+}
+
+/// Layout of set 1 of `PipelineDesc::BINDLESS_TEXTURES` pipelines. Pipelines and `TextureTable` create their
+/// own, sets are compatible with any layout defined the same way
+pub fn texture_set_layout(device: &VkDeviceRef) -> DescriptorSetLayout {
+    let bindings = [vk::DescriptorSetLayoutBinding::default()
+        .binding(0)
+        .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+        .descriptor_count(texture_count(device))
+        .stage_flags(ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT)];
+    let binding_flags = [DescriptorBindingFlags::PARTIALLY_BOUND | DescriptorBindingFlags::UPDATE_AFTER_BIND];
+    let mut binding_flags_info = vk::DescriptorSetLayoutBindingFlagsCreateInfo::default()
+        .binding_flags(&binding_flags);
+    let mut create_info = vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings);
+    if device.descriptor_indexing_enabled() {
+        create_info = create_info
+            .flags(vk::DescriptorSetLayoutCreateFlags::UPDATE_AFTER_BIND_POOL)
+            .push_next(&mut binding_flags_info);
+    }
+    unsafe { device.create_descriptor_set_layout(&create_info, None) }.unwrap()
+}
+
+/// All bindless textures in a single descriptor set, used when descriptor indexing is enabled.
+///
+/// Images get a slot on first use by an object and keep it, since images are never destroyed. Unused
+/// slots are left unwritten. Slots are written while no frames are in flight, and update-after-bind keeps
+/// recorded command buffers valid
+pub struct TextureTable {
+    device: VkDeviceRef,
+    layout: DescriptorSetLayout,
+    pool: vk::DescriptorPool,
+    set: DescriptorSet,
+    slots: BTreeMap<UniformResourceId, u32>,
+}
+
+impl TextureTable {
+    pub fn new(device: VkDeviceRef) -> Self {
+        let layout = texture_set_layout(&device);
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .ty(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(BINDLESS_TEXTURE_COUNT)];
+        let pool_info = vk::DescriptorPoolCreateInfo::default()
+            .max_sets(1)
+            .pool_sizes(&pool_sizes)
+            .flags(vk::DescriptorPoolCreateFlags::UPDATE_AFTER_BIND);
+        let pool = unsafe { device.create_descriptor_pool(&pool_info, None) }.unwrap();
+        let set_layouts = [layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(pool)
+            .set_layouts(&set_layouts);
+        let set = unsafe { device.allocate_descriptor_sets(&alloc_info) }.unwrap()[0];
+        device.set_object_name(set, "Bindless textures");
+
+        Self {
+            device,
+            layout,
+            pool,
+            set,
+            slots: BTreeMap::new(),
+        }
+    }
+
+    pub fn descriptor_set(&self) -> DescriptorSet {
+        self.set
+    }
+
+    /// Slot of the image. A new slot is written with `image`, e.g. a placeholder while the image is loading
+    pub fn slot(&mut self, id: UniformResourceId, image: &UniformImage) -> u32 {
+        if let Some(slot) = self.slots.get(&id) {
+            return *slot;
+        }
+        let slot = self.slots.len() as u32;
+        assert!(slot < BINDLESS_TEXTURE_COUNT, "Bindless texture array is full, {} textures are used", slot);
+        self.slots.insert(id, slot);
+        self.write_slot(slot, image);
+        slot
+    }
+
+    /// Replace the image in its slot, if it has one
+    pub fn update(&self, id: UniformResourceId, image: &UniformImage) {
+        if let Some(slot) = self.slots.get(&id) {
+            self.write_slot(*slot, image);
+        }
+    }
+
+    fn write_slot(&self, slot: u32, image: &UniformImage) {
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image.image_view)
+            .sampler(image.sampler)];
+        let descriptor_write = vk::WriteDescriptorSet::default()
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .dst_set(self.set)
+            .dst_binding(0)
+            .dst_array_element(slot)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[descriptor_write], &[]) }
+    }
+}
+
+impl Drop for TextureTable {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_descriptor_pool(self.pool, None);
+            self.device.destroy_descriptor_set_layout(self.layout, None);
+        }
+    }
+}
@@ -1,10 +1,222 @@
+use std::path::PathBuf;
+use std::time::Duration;
 use ash::vk;
+use crate::vulkan_backend::swapchain_wrapper::PresentConfig;
 
+/// Number of frames CPU can record and submit before waiting for GPU
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InFlightFrames {
+    #[default]
+    One,
+    Two,
+    Three,
+}
+
+impl InFlightFrames {
+    pub fn count(self) -> usize {
+        match self {
+            InFlightFrames::One => 1,
+            InFlightFrames::Two => 2,
+            InFlightFrames::Three => 3,
+        }
+    }
+}
+
+/// Which physical device to use when several are available
+#[derive(Default, Clone, Debug)]
+pub enum DevicePreference {
+    /// Discrete GPU, then integrated, then CPU
+    #[default]
+    HighPerformance,
+    /// Integrated GPU, then discrete, then CPU
+    LowPower,
+    /// First device with name containing this string, case insensitive
+    ByName(String),
+    /// Index in the list returned by `VulkanBackend::enumerate_devices`
+    ByIndex(usize),
+}
+
+/// Format of the depth attachment
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthFormat {
+    /// Supported everywhere
+    #[default]
+    D16,
+    D32,
+    /// With 8-bit stencil, required for pipelines with stencil test
+    D24S8,
+    /// With 8-bit stencil, required for pipelines with stencil test
+    D32S8,
+}
+
+impl DepthFormat {
+    pub fn format(self) -> vk::Format {
+        match self {
+            DepthFormat::D16 => vk::Format::D16_UNORM,
+            DepthFormat::D32 => vk::Format::D32_SFLOAT,
+            DepthFormat::D24S8 => vk::Format::D24_UNORM_S8_UINT,
+            DepthFormat::D32S8 => vk::Format::D32_SFLOAT_S8_UINT,
+        }
+    }
+
+    pub fn has_stencil(self) -> bool {
+        matches!(self, DepthFormat::D24S8 | DepthFormat::D32S8)
+    }
+
+    /// Floating point formats, which benefit from `DepthMode::ReverseZ`
+    pub fn is_float(self) -> bool {
+        matches!(self, DepthFormat::D32 | DepthFormat::D32S8)
+    }
+}
+
+/// Color space of values written by shaders and of clear colors
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputColorSpace {
+    /// Values are stored as is in a UNORM swapchain image, shaders must apply sRGB encoding themselves
+    #[default]
+    Srgb,
+    /// Linear values, encoded on write by an sRGB swapchain format. If the surface supports only UNORM
+    /// formats, the scene is encoded by a full-screen pass, see `VulkanBackend::set_post_process`
+    Linear,
+}
+
+/// Mapping of distance to depth values
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    /// Depth is cleared to 1.0, fragments with smaller depth pass
+    #[default]
+    Standard,
+    /// Depth is cleared to 0.0, fragments with greater depth pass. Projection must map the near plane to 1
+    /// and the far plane to 0, e.g. by swapping near and far in a perspective matrix, and objects at depth 0
+    /// are never drawn. Gives much better precision with a float depth format (`DepthFormat::D32`), little
+    /// benefit with fixed point ones
+    ReverseZ,
+}
+
+impl DepthMode {
+    pub fn clear_value(self) -> f32 {
+        match self {
+            DepthMode::Standard => 1.0,
+            DepthMode::ReverseZ => 0.0,
+        }
+    }
+
+    pub fn compare_op(self) -> vk::CompareOp {
+        match self {
+            DepthMode::Standard => vk::CompareOp::LESS,
+            DepthMode::ReverseZ => vk::CompareOp::GREATER,
+        }
+    }
+}
+
+#[derive(Default, Clone)]
 pub struct VulkanRenderConfig {
     pub msaa_samples: Option<u32>,
+    /// Present with FIFO mode. Otherwise `present_modes` are tried
+    pub vsync: bool,
+    /// Present modes to try in order when vsync is off, FIFO is used if none is supported.
+    /// Empty means MAILBOX, then IMMEDIATE
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    /// Requested number of swapchain images, clamped to surface limits. `None` is one more than the minimum
+    pub swapchain_image_count: Option<u32>,
+    /// Falls back to `DevicePreference::HighPerformance` if preferred device is not found
+    pub device_preference: DevicePreference,
+    /// Buffer updates are copied on the queue after reads of the frames in flight, so a frame waits only for the
+    /// one which used its slot before, and host visible device memory is written through a staging buffer too.
+    /// Destroying objects, loaded images and egui updates still wait for all submitted frames
+    pub in_flight_frames: InFlightFrames,
+    /// Unsupported format is replaced with a supported one, keeping stencil if possible
+    pub depth_format: DepthFormat,
+    pub depth_mode: DepthMode,
+    /// Color attachments written by fragment shader outputs at locations 1.., in addition to the swapchain
+    /// image at location 0. At most 3 are used. They are cleared with zeros every frame and are not presented.
+    pub extra_color_attachments: Vec<vk::Format>,
+    /// Add a second subpass, which reads color of the first one as input attachment and writes the swapchain
+    /// image, e.g. for tone mapping. Pipelines choose subpass with `PipelineDesc::SUBPASS`. Disables MSAA
+    pub post_subpass: bool,
+    /// File used to seed pipeline cache on init and persist it on shutdown
+    pub pipeline_cache_path: Option<PathBuf>,
+    /// SPIR-V fragment shader of the full-screen post-processing pass, see `VulkanBackend::set_post_process`
+    pub post_process_shader: Option<Vec<u8>>,
+    /// Preferred color space of shader outputs, selects sRGB or UNORM surface format.
+    /// Actual one is reported by `VulkanBackend::color_space`
+    pub output_color_space: OutputColorSpace,
+    /// Longest wait for a previous frame or swapchain image in `VulkanBackend::render`, after which it fails
+    /// with `VulkanError::Timeout` instead of blocking forever on a hung driver. `None` waits without limit.
+    /// Must be well above the longest expected frame time
+    pub frame_timeout: Option<Duration>,
+    /// Track frame completion with a single timeline semaphore instead of a fence per frame in flight.
+    /// Requires Vulkan 1.2 or `VK_KHR_timeline_semaphore`, falls back to fences otherwise.
+    /// Not used with `VulkanBackend::from_existing`
+    pub timeline_semaphores: bool,
+    /// Highest Vulkan API version to request, `None` is `physical_device::MAX_API_VERSION`. The highest one
+    /// supported by the loader up to this is used, but never lower than 1.0. Features promoted to core in the
+    /// negotiated version are enabled without their extensions
+    pub max_api_version: Option<u32>,
+    /// Record the main pass with `vkCmdBeginRendering` instead of render pass and framebuffer objects.
+    /// Requires Vulkan 1.3 or `VK_KHR_dynamic_rendering` on 1.2, falls back to render passes otherwise and
+    /// with `post_subpass`. Offscreen render targets and the post-process pass always use render passes.
+    /// Not used with `VulkanBackend::from_existing`
+    pub dynamic_rendering: bool,
+    /// Draw objects with equal z_order grouped by pipeline instead of by id, so each pipeline is bound once
+    /// per group. Changes which of overlapping objects with equal z_order is drawn on top
+    pub group_draws_by_pipeline: bool,
+    /// Draw each batch of objects created together (`GraphicsUpdateCmd::Object2DBatch`) with indirect draws
+    /// from a device-local args buffer, which is rewritten only when objects of the batch are hidden, shown
+    /// or destroyed. Requires `drawIndirectFirstInstance`, a single draw per batch also `multiDrawIndirect`.
+    /// Batches are drawn per object if unsupported. Not used with `VulkanBackend::from_existing`
+    pub indirect_batches: bool,
+    /// Keep textures of `PipelineDesc::BINDLESS_TEXTURES` pipelines in a single array of
+    /// `bindless::BINDLESS_TEXTURE_COUNT` descriptors, bound once and indexed per object with a push constant.
+    /// Requires Vulkan 1.2 or `VK_EXT_descriptor_indexing` with partially bound and update-after-bind sampled
+    /// images, each object gets a descriptor set with its texture otherwise. Not used with
+    /// `VulkanBackend::from_existing`
+    pub bindless_textures: bool,
 }
 
 impl VulkanRenderConfig {
+    /// Low latency without tearing for interactive apps.
+    ///
+    /// MAILBOX with 3 swapchain images: presentation is synced to the monitor refresh, but rendering is
+    /// not blocked by it and the newest frame replaces a queued one. GPU keeps rendering frames which are
+    /// never shown, so power usage is as high as with vsync off. Falls back to FIFO_RELAXED (tears only when
+    /// a frame is late), then FIFO (adds up to a refresh interval of latency).
+    ///
+    /// Two frames in flight let CPU record the next frame while GPU renders, at the cost of one more
+    /// frame of input latency when GPU bound.
+    pub fn low_latency_preset() -> Self {
+        Self {
+            vsync: false,
+            present_modes: vec![vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO_RELAXED, vk::PresentModeKHR::FIFO],
+            swapchain_image_count: Some(3),
+            in_flight_frames: InFlightFrames::Two,
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn present_config(&self) -> PresentConfig {
+        PresentConfig {
+            vsync: self.vsync,
+            present_modes: self.present_modes.clone(),
+            image_count: self.swapchain_image_count,
+            srgb_format: self.output_color_space == OutputColorSpace::Linear,
+        }
+    }
+
+    /// Format of offscreen images of headless surfaces, chosen as the swapchain one with all formats supported
+    pub(crate) fn headless_format(&self) -> vk::Format {
+        if self.present_config().srgb_format {
+            vk::Format::B8G8R8A8_SRGB
+        } else {
+            vk::Format::B8G8R8A8_UNORM
+        }
+    }
+
+    /// Timeout in nanoseconds for fence waits and image acquire
+    pub(crate) fn frame_timeout_ns(&self) -> u64 {
+        self.frame_timeout.map_or(u64::MAX, |timeout| timeout.as_nanos().min(u64::MAX as u128) as u64)
+    }
+
     pub fn get_msaa_samples(&self) -> Option<vk::SampleCountFlags> {
         self.msaa_samples.map(|msaa_samples|
             match msaa_samples {
@@ -19,4 +231,4 @@ impl VulkanRenderConfig {
             }
         )
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,8 @@
+use std::collections::BTreeMap;
 use crate::vulkan_backend::resource_manager::{BufferResource, ResourceManager};
 use crate::vulkan_backend::wrappers::device::VkDeviceRef;
 use ash::vk;
-use ash::vk::{BufferUsageFlags, CommandBuffer, DescriptorBufferInfo, DescriptorPool, DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorType, Extent2D, ImageTiling, PipelineBindPoint, PipelineLayout, SampleCountFlags, ShaderStageFlags, WriteDescriptorSet, WHOLE_SIZE};
+use ash::vk::{BufferUsageFlags, CommandBuffer, DescriptorBufferInfo, DeviceSize, DescriptorPool, DescriptorPoolSize, DescriptorSet, DescriptorSetAllocateInfo, DescriptorSetLayout, DescriptorSetLayoutBinding, DescriptorType, Extent2D, ImageTiling, PipelineBindPoint, PipelineLayout, SampleCountFlags, ShaderStageFlags, WriteDescriptorSet, WHOLE_SIZE};
 use log::info;
 use smallvec::SmallVec;
 use sparkles_macro::range_event_start;
@@ -10,90 +11,154 @@ use crate::util::image::read_image_from_bytes;
 use crate::vulkan_backend::object_resource_pool::UniformImage;
 use crate::vulkan_backend::wrappers::image::imageview_info_for_image;
 
-pub struct DescriptorSetPool {
-    device: VkDeviceRef,
+/// Size of a single pool block, in sets and descriptors of each type
+const BLOCK_SETS: u32 = 50;
+const BLOCK_UNIFORM_BUFFERS: u32 = 50;
+const BLOCK_DYNAMIC_UNIFORM_BUFFERS: u32 = 50;
+const BLOCK_IMAGE_SAMPLERS: u32 = 50;
+const BLOCK_INPUT_ATTACHMENTS: u32 = 10;
 
-    descriptor_pool: DescriptorPool,
+/// Descriptor set usage of a `DescriptorSetPool`
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DescriptorPoolStats {
+    pub current_sets: u32,
+    /// Highest `current_sets` since creation
+    pub peak_sets: u32,
+    /// Number of vulkan descriptor pools
+    pub blocks: usize,
+    /// Released sets kept for reuse
+    pub cached_sets: u32,
+    /// Total sets allocated from vulkan pools
+    pub allocations: u64,
+    /// Total sets taken from the cache instead of allocation
+    pub reuses: u64,
+}
 
-    allocated_sets: u32,
-    capacity_sets: u32,
+/// Descriptor pool growing by fixed-size blocks: when all blocks are exhausted, a new one is created.
+///
+/// Released sets are not freed, but cached per layout and reused with new bindings.
+pub struct DescriptorSetPool {
+    device: VkDeviceRef,
 
-    allocated_uniform_buffers: u32,
-    capacity_uniform_buffers: u32,
+    blocks: Vec<DescriptorPool>,
+    /// Released sets with their block index
+    free_sets: BTreeMap<DescriptorSetLayout, Vec<(DescriptorSet, usize)>>,
 
-    allocated_image_samplers: u32,
-    capacity_image_samplers: u32,
+    current_sets: u32,
+    peak_sets: u32,
+    allocations: u64,
+    reuses: u64,
 }
 
 impl DescriptorSetPool {
     pub fn new(device: VkDeviceRef) -> Self {
-        let capacity_sets = 50;
-        let capacity_uniform_buffers = 50;
-        let capacity_image_samplers = 50;
+        let first_block = Self::create_block(&device);
+
+        DescriptorSetPool {
+            device,
+            blocks: vec![first_block],
+            free_sets: BTreeMap::new(),
+
+            current_sets: 0,
+            peak_sets: 0,
+            allocations: 0,
+            reuses: 0,
+        }
+    }
 
+    fn create_block(device: &VkDeviceRef) -> DescriptorPool {
         let pool_sizes = [
             DescriptorPoolSize::default()
-                .descriptor_count(capacity_uniform_buffers)
+                .descriptor_count(BLOCK_UNIFORM_BUFFERS)
                 .ty(DescriptorType::UNIFORM_BUFFER),
             DescriptorPoolSize::default()
-                .descriptor_count(capacity_image_samplers)
-                .ty(DescriptorType::COMBINED_IMAGE_SAMPLER)];
+                .descriptor_count(BLOCK_DYNAMIC_UNIFORM_BUFFERS)
+                .ty(DescriptorType::UNIFORM_BUFFER_DYNAMIC),
+            DescriptorPoolSize::default()
+                .descriptor_count(BLOCK_IMAGE_SAMPLERS)
+                .ty(DescriptorType::COMBINED_IMAGE_SAMPLER),
+            DescriptorPoolSize::default()
+                .descriptor_count(BLOCK_INPUT_ATTACHMENTS)
+                .ty(DescriptorType::INPUT_ATTACHMENT)];
         let desc_pool_info = vk::DescriptorPoolCreateInfo::default()
-            .max_sets(capacity_sets)
+            .max_sets(BLOCK_SETS)
             .pool_sizes(&pool_sizes)
             .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET);
 
-        let descriptor_pool = unsafe {
+        unsafe {
             device
                 .create_descriptor_pool(&desc_pool_info, None)
                 .unwrap()
-        };
-
-        DescriptorSetPool {
-            device,
-            descriptor_pool,
-
-            capacity_image_samplers,
-            capacity_sets,
-            capacity_uniform_buffers,
-            
-            allocated_image_samplers: 0,
-            allocated_sets: 0,
-            allocated_uniform_buffers: 0,
         }
     }
 
+    pub fn stats(&self) -> DescriptorPoolStats {
+        DescriptorPoolStats {
+            current_sets: self.current_sets,
+            peak_sets: self.peak_sets,
+            blocks: self.blocks.len(),
+            cached_sets: self.free_sets.values().map(|sets| sets.len() as u32).sum(),
+            allocations: self.allocations,
+            reuses: self.reuses,
+        }
+    }
 
-    pub fn allocate_descriptor_sets<'a>(&mut self, descriptor_set_layout: DescriptorSetLayout,
-                                        buffer_bindings: impl Iterator<Item=(u32, BufferResource)>,
-                                        image_bindings: impl Iterator<Item=(u32, &'a UniformImage)>) -> DescriptorSet {
-
+    /// Allocate from the newest block with free space, creating a new block if none has it
+    fn allocate_set(&mut self, descriptor_set_layout: DescriptorSetLayout) -> (DescriptorSet, usize) {
         let set_layouts = [descriptor_set_layout];
+        for (i, &pool) in self.blocks.iter().enumerate().rev() {
+            let alloc_info = DescriptorSetAllocateInfo::default()
+                .descriptor_pool(pool)
+                .set_layouts(&set_layouts);
+            match unsafe { self.device.allocate_descriptor_sets(&alloc_info) } {
+                Ok(sets) => return (sets[0], i),
+                Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY | vk::Result::ERROR_FRAGMENTED_POOL) => continue,
+                Err(e) => panic!("Failed to allocate descriptor set: {:?}", e),
+            }
+        }
+
+        let pool = Self::create_block(&self.device);
+        self.blocks.push(pool);
+        info!("Descriptor pool exhausted, created block #{}", self.blocks.len());
         let alloc_info = DescriptorSetAllocateInfo::default()
-            .descriptor_pool(self.descriptor_pool)
+            .descriptor_pool(pool)
             .set_layouts(&set_layouts);
         let descriptor_set = unsafe { self.device.allocate_descriptor_sets(&alloc_info).unwrap()[0] };
-        
+        (descriptor_set, self.blocks.len() - 1)
+    }
+
+    /// Returns descriptor set and index of the block it was allocated from.
+    /// Set released with the same layout is reused if available, all bindings are written again.
+    ///
+    /// Buffer bindings with range are dynamic uniform buffers
+    pub fn allocate_descriptor_sets<'a>(&mut self, descriptor_set_layout: DescriptorSetLayout,
+                                        buffer_bindings: impl Iterator<Item=(u32, BufferResource, Option<DeviceSize>)>,
+                                        image_bindings: impl Iterator<Item=(u32, &'a UniformImage)>) -> (DescriptorSet, usize) {
+
+        let recycled = self.free_sets.get_mut(&descriptor_set_layout).and_then(|sets| sets.pop());
+        let (descriptor_set, block) = match recycled {
+            Some(recycled) => {
+                self.reuses += 1;
+                recycled
+            }
+            None => {
+                self.allocations += 1;
+                self.allocate_set(descriptor_set_layout)
+            }
+        };
+        self.current_sets += 1;
+        self.peak_sets = self.peak_sets.max(self.current_sets);
 
         let buffer_bindings: Vec<_> = buffer_bindings.collect();
         let image_bindings: Vec<_> = image_bindings.collect();
-        
-        self.allocated_sets += 1;
-        self.allocated_uniform_buffers += buffer_bindings.len() as u32;
-        self.allocated_image_samplers += image_bindings.len() as u32;
-
-        // if self.allocated_sets > self.capacity_sets ||
-        //     self.allocated_uniform_buffers > self.capacity_uniform_buffers ||
-        //     self.allocated_image_samplers > self.capacity_image_samplers {
-        //     panic!("Descriptor set pool exceeded capacity");
-        // }
+
         // Update descriptor set
-        let buffer_infos: Vec<_> = buffer_bindings.iter().map(|(_, buffer)| {
+        let buffer_infos: Vec<_> = buffer_bindings.iter().map(|(_, buffer, dynamic_range)| {
             [
                 DescriptorBufferInfo::default()
                     .offset(0)
                     .buffer(buffer.buffer)
-                    .range(WHOLE_SIZE)
+                    .range(dynamic_range.unwrap_or(WHOLE_SIZE))
             ]
         }).collect();
         let image_infos: Vec<_> = image_bindings.iter().map(|(binding, image_sampler)| {
@@ -108,9 +173,14 @@ impl DescriptorSetPool {
         }).collect();
 
         // let mut image_info_i = 0;
-        let descriptor_writes: Vec<_> = buffer_bindings.iter().enumerate().map(|(i, (binding, _))| {
+        let descriptor_writes: Vec<_> = buffer_bindings.iter().enumerate().map(|(i, (binding, _, dynamic_range))| {
+            let descriptor_type = if dynamic_range.is_some() {
+                DescriptorType::UNIFORM_BUFFER_DYNAMIC
+            } else {
+                DescriptorType::UNIFORM_BUFFER
+            };
             WriteDescriptorSet::default()
-                .descriptor_type(DescriptorType::UNIFORM_BUFFER)
+                .descriptor_type(descriptor_type)
                 .descriptor_count(1)
                 .dst_set(descriptor_set)
                 .dst_binding(*binding)
@@ -130,16 +200,23 @@ impl DescriptorSetPool {
 
         unsafe { self.device.update_descriptor_sets(&descriptor_writes, &[]) }
 
-        descriptor_set
+        (descriptor_set, block)
     }
 
+    /// Keep set for reuse with the same layout. `block` is the index returned by `allocate_descriptor_sets`
+    pub fn release_descriptor_set(&mut self, descriptor_set: DescriptorSet, descriptor_set_layout: DescriptorSetLayout,
+                                  block: usize) {
+        self.free_sets.entry(descriptor_set_layout).or_default().push((descriptor_set, block));
+        self.current_sets -= 1;
+    }
 }
 
 impl Drop for DescriptorSetPool {
     fn drop(&mut self) {
         unsafe {
-            self.device
-                .destroy_descriptor_pool(self.descriptor_pool, None);
+            for &pool in &self.blocks {
+                self.device.destroy_descriptor_pool(pool, None);
+            }
         }
     }
 }
@@ -152,22 +229,29 @@ pub struct ObjectDescriptorSet {
     /// borrowed from the pipeline
     descriptor_set_layout: DescriptorSetLayout,
     descriptor_set: DescriptorSet,
+    /// Pool block the set was allocated from
+    pool_block: usize,
+    /// Passed on each bind, one per dynamic uniform buffer binding
+    dynamic_offsets: SmallVec<[u32; 2]>,
 }
 
 impl ObjectDescriptorSet {
     pub fn new<'a>(device: VkDeviceRef, descriptor_set_pool: &mut DescriptorSetPool,
                    descriptor_set_layout: DescriptorSetLayout,
-                   buffer_bindings: impl Iterator<Item=(u32, BufferResource)>,
-                   image_bindings: impl Iterator<Item=(u32, &'a UniformImage)>) -> ObjectDescriptorSet {
+                   buffer_bindings: impl Iterator<Item=(u32, BufferResource, Option<DeviceSize>)>,
+                   image_bindings: impl Iterator<Item=(u32, &'a UniformImage)>,
+                   dynamic_offsets: SmallVec<[u32; 2]>) -> ObjectDescriptorSet {
         let g = range_event_start!("[Vulkan] Create descriptor sets");
 
         // Ask pool to allocate descriptor set and perform writes
-        let descriptor_set = descriptor_set_pool.allocate_descriptor_sets(descriptor_set_layout, buffer_bindings, image_bindings);
+        let (descriptor_set, pool_block) = descriptor_set_pool.allocate_descriptor_sets(descriptor_set_layout, buffer_bindings, image_bindings);
         
         Self {
             device,
             descriptor_set_layout,
             descriptor_set,
+            pool_block,
+            dynamic_offsets,
         }
     }
 
@@ -175,23 +259,57 @@ impl ObjectDescriptorSet {
         self.descriptor_set_layout
     }
 
+    /// Point input attachment binding to the image view. Set must not be in use by pending command buffers
+    pub fn write_input_attachment(&self, binding: u32, image_view: vk::ImageView) {
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image_view)];
+        let descriptor_write = WriteDescriptorSet::default()
+            .descriptor_type(DescriptorType::INPUT_ATTACHMENT)
+            .descriptor_count(1)
+            .dst_set(self.descriptor_set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[descriptor_write], &[]) }
+    }
+
+    /// Point combined image sampler binding to the image. Set must not be in use by pending command buffers
+    pub fn write_image(&self, binding: u32, image: &UniformImage) {
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image.image_view)
+            .sampler(image.sampler)];
+        let descriptor_write = WriteDescriptorSet::default()
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .dst_set(self.descriptor_set)
+            .dst_binding(binding)
+            .dst_array_element(0)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[descriptor_write], &[]) }
+    }
+
     pub fn bind_sets(&self, command_buffer: CommandBuffer, pipeline_layout: PipelineLayout) {
+        self.bind_sets_at(command_buffer, pipeline_layout, 0);
+    }
+
+    /// Bind as set number `first_set` of the pipeline layout
+    pub fn bind_sets_at(&self, command_buffer: CommandBuffer, pipeline_layout: PipelineLayout, first_set: u32) {
         let descriptor_sets = [self.descriptor_set];
         unsafe {
             self.device.cmd_bind_descriptor_sets(
                 command_buffer,
                 PipelineBindPoint::GRAPHICS,
                 pipeline_layout,
-                0,
+                first_set,
                 &descriptor_sets,
-                &[],
+                &self.dynamic_offsets,
             );
         }
     }
     
     pub fn destroy(self, descriptor_pool: &mut DescriptorSetPool) {
-        unsafe {
-            self.device.free_descriptor_sets(descriptor_pool.descriptor_pool, &[self.descriptor_set]).unwrap();
-        }
+        descriptor_pool.release_descriptor_set(self.descriptor_set, self.descriptor_set_layout, self.pool_block);
     }
 }
\ No newline at end of file
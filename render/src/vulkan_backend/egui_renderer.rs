@@ -0,0 +1,467 @@
+use std::collections::BTreeMap;
+use std::mem::{offset_of, size_of};
+use ash::vk;
+use ash::vk::{BufferUsageFlags, DescriptorSet, DescriptorSetLayout, DescriptorType, DeviceSize, Extent2D, ImageView,
+              Pipeline, PipelineCache, PipelineLayout, SampleCountFlags, Sampler, ShaderStageFlags};
+use egui::epaint::{ImageDelta, Primitive, Vertex};
+use egui::{ClippedPrimitive, ImageData, TextureId, TexturesDelta};
+use log::{info, warn};
+use smallvec::SmallVec;
+use sparkles_macro::range_event_start;
+use crate::vulkan_backend::pipeline::spirv_words;
+use crate::vulkan_backend::render_pass::{RenderPassWrapper, MAX_COLOR_ATTACHMENTS};
+use crate::vulkan_backend::resource_manager::{BufferResource, ImageResource, ResourceManager};
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+use crate::vulkan_backend::wrappers::image::imageview_info_for_image;
+
+const VERTEX_SHADER: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/compiled/egui_vert.spv"));
+const FRAGMENT_SHADER: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/compiled/egui_frag.spv"));
+
+/// Max number of egui textures alive at once, font atlas is one of them
+const MAX_TEXTURES: u32 = 64;
+
+/// Tessellated egui frame, passed to `VulkanBackend::set_egui_output`
+pub struct EguiOutput {
+    /// Result of `egui::Context::tessellate`
+    pub primitives: Vec<ClippedPrimitive>,
+    pub textures_delta: TexturesDelta,
+    pub pixels_per_point: f32,
+}
+
+/// Texture managed by egui, with CPU copy for partial updates
+struct EguiTexture {
+    image: ImageResource,
+    image_view: ImageView,
+    descriptor_set: DescriptorSet,
+    /// RGBA8, premultiplied
+    pixels: Vec<u8>,
+    size: [usize; 2],
+}
+
+/// Range of the shared index buffer drawn with a single texture and clip rect
+struct EguiDraw {
+    texture_id: TextureId,
+    /// In points
+    clip_rect: egui::Rect,
+    first_index: u32,
+    index_count: u32,
+    vertex_offset: i32,
+}
+
+/// Draws egui output on top of the scene, in the last subpass of the main render pass.
+///
+/// Vertex and index buffers are shared by all frames in flight and grow to fit the largest frame.
+pub struct EguiRenderer {
+    device: VkDeviceRef,
+
+    pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+    descriptor_set_layout: DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    sampler: Sampler,
+
+    textures: BTreeMap<TextureId, EguiTexture>,
+    /// Freed after the frame, which may still use them, is drawn
+    pending_free: Vec<TextureId>,
+
+    vertex_buffer: Option<BufferResource>,
+    index_buffer: Option<BufferResource>,
+    draws: Vec<EguiDraw>,
+    pixels_per_point: f32,
+    /// Incremented with each new output, command buffers recorded for older output must be recorded again
+    generation: u64,
+}
+
+impl EguiRenderer {
+    pub fn new(device: VkDeviceRef, render_pass: &RenderPassWrapper, pipeline_cache: PipelineCache) -> Self {
+        let g = range_event_start!("Create egui renderer");
+
+        let bindings = [vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(ShaderStageFlags::FRAGMENT)];
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)
+        }.unwrap();
+
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(ShaderStageFlags::VERTEX)
+            .offset(0)
+            .size(size_of::<[f32; 2]>() as u32)];
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(&vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(&set_layouts)
+                .push_constant_ranges(&push_constant_ranges), None)
+        }.unwrap();
+
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .descriptor_count(MAX_TEXTURES)
+            .ty(DescriptorType::COMBINED_IMAGE_SAMPLER)];
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(&vk::DescriptorPoolCreateInfo::default()
+                .max_sets(MAX_TEXTURES)
+                .pool_sizes(&pool_sizes)
+                .flags(vk::DescriptorPoolCreateFlags::FREE_DESCRIPTOR_SET), None)
+        }.unwrap();
+
+        // egui expects clamped, linearly filtered textures
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
+            .max_lod(0.0);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }.unwrap();
+
+        let pipeline = Self::create_pipeline(&device, render_pass, pipeline_layout, pipeline_cache);
+        device.set_object_name(pipeline, "egui");
+        drop(g);
+
+        Self {
+            device,
+
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            descriptor_pool,
+            sampler,
+
+            textures: BTreeMap::new(),
+            pending_free: Vec::new(),
+
+            vertex_buffer: None,
+            index_buffer: None,
+            draws: Vec::new(),
+            pixels_per_point: 1.0,
+            generation: 0,
+        }
+    }
+
+    fn create_pipeline(device: &VkDeviceRef, render_pass: &RenderPassWrapper, pipeline_layout: PipelineLayout,
+                       pipeline_cache: PipelineCache) -> Pipeline {
+        let create_module = |code: &[u8]| {
+            let code = spirv_words(code).expect("Built-in egui shader");
+            unsafe { device.create_shader_module(&vk::ShaderModuleCreateInfo::default().code(&code), None) }.unwrap()
+        };
+        let vertex_module = create_module(VERTEX_SHADER);
+        let frag_module = create_module(FRAGMENT_SHADER);
+
+        let main_name = c"main";
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(main_name),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(ShaderStageFlags::FRAGMENT)
+                .module(frag_module)
+                .name(main_name),
+        ];
+
+        let binding_desc = [vk::VertexInputBindingDescription::default()
+            .binding(0)
+            .input_rate(vk::VertexInputRate::VERTEX)
+            .stride(size_of::<Vertex>() as u32)];
+        let attrib_desc = [
+            vk::VertexInputAttributeDescription::default()
+                .location(0)
+                .binding(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(offset_of!(Vertex, pos) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .location(1)
+                .binding(0)
+                .format(vk::Format::R32G32_SFLOAT)
+                .offset(offset_of!(Vertex, uv) as u32),
+            vk::VertexInputAttributeDescription::default()
+                .location(2)
+                .binding(0)
+                .format(vk::Format::R8G8B8A8_UNORM)
+                .offset(offset_of!(Vertex, color) as u32),
+        ];
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default()
+            .vertex_binding_descriptions(&binding_desc)
+            .vertex_attribute_descriptions(&attrib_desc);
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(render_pass.get_msaa_samples().unwrap_or(SampleCountFlags::TYPE_1));
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        let rast_info = vk::PipelineRasterizationStateCreateInfo::default()
+            .cull_mode(vk::CullModeFlags::NONE)
+            .line_width(1.0);
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+
+        // premultiplied alpha, extra attachments are not written
+        let subpass = render_pass.subpass_count() - 1;
+        let color_blend_attachment: SmallVec<[_; MAX_COLOR_ATTACHMENTS]> = (0..render_pass.color_attachment_count(subpass))
+            .map(|i| if i == 0 {
+                vk::PipelineColorBlendAttachmentState::default()
+                    .color_write_mask(vk::ColorComponentFlags::RGBA)
+                    .blend_enable(true)
+                    .src_color_blend_factor(vk::BlendFactor::ONE)
+                    .dst_color_blend_factor(vk::BlendFactor::ONE_MINUS_SRC_ALPHA)
+                    .color_blend_op(vk::BlendOp::ADD)
+                    .src_alpha_blend_factor(vk::BlendFactor::ONE_MINUS_DST_ALPHA)
+                    .dst_alpha_blend_factor(vk::BlendFactor::ONE)
+                    .alpha_blend_op(vk::BlendOp::ADD)
+            } else {
+                vk::PipelineColorBlendAttachmentState::default()
+                    .color_write_mask(vk::ColorComponentFlags::empty())
+            })
+            .collect();
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::default()
+            .attachments(&color_blend_attachment);
+        // UI is always on top
+        let depth_state = vk::PipelineDepthStencilStateCreateInfo::default();
+
+        let color_formats = render_pass.color_formats();
+        let mut rendering_info = render_pass.pipeline_rendering_info(&color_formats);
+        let mut pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
+            .layout(pipeline_layout)
+            .render_pass(*render_pass.get_render_pass())
+            .subpass(subpass)
+            .dynamic_state(&dynamic_state)
+            .multisample_state(&multisample_state)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .stages(&stages)
+            .rasterization_state(&rast_info)
+            .color_blend_state(&color_blend)
+            .viewport_state(&viewport_state)
+            .depth_stencil_state(&depth_state);
+        if render_pass.is_dynamic() {
+            pipeline_create_info = pipeline_create_info.push_next(&mut rendering_info);
+        }
+        let pipeline = unsafe { device.create_graphics_pipelines(pipeline_cache, &[pipeline_create_info], None).unwrap()[0] };
+
+        unsafe { device.destroy_shader_module(vertex_module, None); }
+        unsafe { device.destroy_shader_module(frag_module, None); }
+        pipeline
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Upload new output. Previous output must not be in use by GPU
+    pub fn update(&mut self, resource_manager: &mut ResourceManager, output: EguiOutput) -> anyhow::Result<()> {
+        let g = range_event_start!("[Vulkan] Update egui");
+        // textures freed by the previous output are not used anymore
+        for id in std::mem::take(&mut self.pending_free) {
+            self.free_texture(resource_manager, id);
+        }
+        for (id, delta) in &output.textures_delta.set {
+            self.set_texture(resource_manager, *id, delta)?;
+        }
+        self.pending_free = output.textures_delta.free;
+
+        self.pixels_per_point = output.pixels_per_point;
+        self.draws.clear();
+        let mut vertices: Vec<Vertex> = Vec::new();
+        let mut indices: Vec<u32> = Vec::new();
+        for ClippedPrimitive { clip_rect, primitive } in output.primitives {
+            let Primitive::Mesh(mesh) = primitive else {
+                warn!("egui paint callbacks are not supported");
+                continue;
+            };
+            if mesh.indices.is_empty() {
+                continue;
+            }
+            self.draws.push(EguiDraw {
+                texture_id: mesh.texture_id,
+                clip_rect,
+                first_index: indices.len() as u32,
+                index_count: mesh.indices.len() as u32,
+                vertex_offset: vertices.len() as i32,
+            });
+            vertices.extend_from_slice(&mesh.vertices);
+            indices.extend_from_slice(&mesh.indices);
+        }
+
+        if !vertices.is_empty() {
+            let vertex_buffer = Self::buffer_with_capacity(resource_manager, &mut self.vertex_buffer,
+                                                           size_of_val(vertices.as_slice()), BufferUsageFlags::VERTEX_BUFFER);
+            resource_manager.fill_buffer(vertex_buffer, &vertices, 0)?;
+            let index_buffer = Self::buffer_with_capacity(resource_manager, &mut self.index_buffer,
+                                                          size_of_val(indices.as_slice()), BufferUsageFlags::INDEX_BUFFER);
+            resource_manager.fill_buffer(index_buffer, &indices, 0)?;
+        }
+        self.generation += 1;
+        Ok(())
+    }
+
+    /// Grow buffer to the next power of two if it is smaller than `size`
+    fn buffer_with_capacity(resource_manager: &mut ResourceManager, buffer: &mut Option<BufferResource>,
+                            size: usize, usage: BufferUsageFlags) -> BufferResource {
+        match buffer {
+            Some(existing) if existing.size >= size as DeviceSize => *existing,
+            _ => {
+                if let Some(old) = buffer.take() {
+                    resource_manager.destroy_buffer(old);
+                }
+                let capacity = (size as DeviceSize).next_power_of_two();
+                info!("Growing egui buffer to {} bytes", capacity);
+                *buffer.insert(resource_manager.create_buffer(capacity, usage))
+            }
+        }
+    }
+
+    fn set_texture(&mut self, resource_manager: &mut ResourceManager, id: TextureId, delta: &ImageDelta) -> anyhow::Result<()> {
+        let delta_size = delta.image.size();
+        let delta_pixels: Vec<u8> = match &delta.image {
+            ImageData::Color(image) => image.pixels.iter().flat_map(|c| c.to_array()).collect(),
+            ImageData::Font(image) => image.srgba_pixels(None).flat_map(|c| c.to_array()).collect(),
+        };
+
+        match (delta.pos, self.textures.get_mut(&id)) {
+            (Some([x, y]), Some(texture)) => {
+                // partial update is applied to the CPU copy, and the whole image is uploaded again
+                let row_len = delta_size[0] * 4;
+                for row in 0..delta_size[1] {
+                    let dst = ((y + row) * texture.size[0] + x) * 4;
+                    texture.pixels[dst..dst + row_len].copy_from_slice(&delta_pixels[row * row_len..(row + 1) * row_len]);
+                }
+                resource_manager.fill_image(texture.image, &texture.pixels)?;
+            }
+            (Some(_), None) => warn!("Partial update of missing egui texture {:?}", id),
+            (None, _) => {
+                self.free_texture(resource_manager, id);
+                let texture = self.create_texture(resource_manager, id, delta_pixels, delta_size)?;
+                self.textures.insert(id, texture);
+            }
+        }
+        Ok(())
+    }
+
+    fn create_texture(&mut self, resource_manager: &mut ResourceManager, id: TextureId, pixels: Vec<u8>,
+                      size: [usize; 2]) -> anyhow::Result<EguiTexture> {
+        let extent = Extent2D {
+            width: size[0] as u32,
+            height: size[1] as u32,
+        };
+        let image = resource_manager.create_image(extent, vk::Format::R8G8B8A8_UNORM, vk::ImageTiling::OPTIMAL,
+                                                  vk::ImageUsageFlags::SAMPLED, SampleCountFlags::TYPE_1);
+        self.device.set_object_name(image.image, format_args!("egui texture {:?}", id));
+        resource_manager.fill_image(image, &pixels)?;
+
+        let imageview_info = imageview_info_for_image(image.image, image.info, vk::ImageAspectFlags::COLOR);
+        let image_view = unsafe { self.device.create_image_view(&imageview_info, None) }.unwrap();
+
+        let set_layouts = [self.descriptor_set_layout];
+        let alloc_info = vk::DescriptorSetAllocateInfo::default()
+            .descriptor_pool(self.descriptor_pool)
+            .set_layouts(&set_layouts);
+        let descriptor_set = unsafe { self.device.allocate_descriptor_sets(&alloc_info) }
+            .expect("Too many egui textures")[0];
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image_view)
+            .sampler(self.sampler)];
+        let write = vk::WriteDescriptorSet::default()
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .descriptor_count(1)
+            .dst_set(descriptor_set)
+            .dst_binding(0)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+
+        Ok(EguiTexture {
+            image,
+            image_view,
+            descriptor_set,
+            pixels,
+            size,
+        })
+    }
+
+    fn free_texture(&mut self, resource_manager: &mut ResourceManager, id: TextureId) {
+        let Some(texture) = self.textures.remove(&id) else {
+            return;
+        };
+        unsafe {
+            self.device.free_descriptor_sets(self.descriptor_pool, &[texture.descriptor_set]).unwrap();
+            self.device.destroy_image_view(texture.image_view, None);
+        }
+        resource_manager.destroy_image(texture.image);
+    }
+
+    /// Record draws of the last output. Must be recorded in the last subpass, viewport is changed to `render_area`
+    pub fn record_draw_commands(&self, command_buffer: vk::CommandBuffer, render_area: vk::Rect2D) {
+        let (Some(vertex_buffer), Some(index_buffer)) = (self.vertex_buffer, self.index_buffer) else {
+            return;
+        };
+        if self.draws.is_empty() {
+            return;
+        }
+        let screen_size = [
+            render_area.extent.width as f32 / self.pixels_per_point,
+            render_area.extent.height as f32 / self.pixels_per_point,
+        ];
+        let screen_size_bytes: Vec<u8> = screen_size.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        unsafe {
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[vertex_buffer.buffer], &[0]);
+            self.device.cmd_bind_index_buffer(command_buffer, index_buffer.buffer, 0, vk::IndexType::UINT32);
+            self.device.cmd_push_constants(command_buffer, self.pipeline_layout, ShaderStageFlags::VERTEX, 0,
+                                           &screen_size_bytes);
+        }
+
+        for draw in &self.draws {
+            let Some(texture) = self.textures.get(&draw.texture_id) else {
+                continue;
+            };
+            // clip rect in pixels, clamped to the render area
+            let min_x = (draw.clip_rect.min.x * self.pixels_per_point).round().clamp(0.0, render_area.extent.width as f32);
+            let min_y = (draw.clip_rect.min.y * self.pixels_per_point).round().clamp(0.0, render_area.extent.height as f32);
+            let max_x = (draw.clip_rect.max.x * self.pixels_per_point).round().clamp(min_x, render_area.extent.width as f32);
+            let max_y = (draw.clip_rect.max.y * self.pixels_per_point).round().clamp(min_y, render_area.extent.height as f32);
+            if max_x <= min_x || max_y <= min_y {
+                continue;
+            }
+            let scissor = vk::Rect2D {
+                offset: vk::Offset2D {
+                    x: render_area.offset.x + min_x as i32,
+                    y: render_area.offset.y + min_y as i32,
+                },
+                extent: Extent2D {
+                    width: (max_x - min_x) as u32,
+                    height: (max_y - min_y) as u32,
+                },
+            };
+            unsafe {
+                self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+                self.device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS,
+                                                     self.pipeline_layout, 0, &[texture.descriptor_set], &[]);
+                self.device.cmd_draw_indexed(command_buffer, draw.index_count, 1, draw.first_index,
+                                             draw.vertex_offset, 0);
+            }
+        }
+    }
+
+    /// Device must be idle
+    pub fn destroy(mut self, resource_manager: &mut ResourceManager) {
+        let ids: Vec<_> = self.textures.keys().copied().collect();
+        for id in ids {
+            self.free_texture(resource_manager, id);
+        }
+        for buffer in [self.vertex_buffer.take(), self.index_buffer.take()].into_iter().flatten() {
+            resource_manager.destroy_buffer(buffer);
+        }
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use ash::vk;
+use thiserror::Error;
+
+/// Errors of the vulkan backend which caller may want to handle.
+///
+/// Public functions return `anyhow::Error`, use `downcast_ref::<VulkanError>()` to inspect it.
+#[derive(Error, Debug)]
+pub enum VulkanError {
+    #[error("No suitable physical device found")]
+    NoSuitableDevice,
+    #[error("No queue family supports both graphics and presentation")]
+    NoQueueFamily,
+    #[error("Surface does not report any supported format")]
+    NoSurfaceFormat,
+    #[error("Surface format changed from {old:?} to {new:?}")]
+    SurfaceFormatChanged { old: vk::Format, new: vk::Format },
+    /// Swapchain must be recreated. Usually handled by the backend itself
+    #[error("Swapchain is out of date")]
+    SwapchainOutOfDate,
+    /// Device is no longer usable. Renderer must be reinitialized with `VulkanBackend::reinitialize`
+    #[error("Vulkan device lost")]
+    DeviceLost,
+    /// GPU did not finish a frame within `VulkanRenderConfig::frame_timeout`, likely a driver hang.
+    /// Handle like `DeviceLost`: frame synchronization is left inconsistent. The old renderer does not wait
+    /// for the device to become idle after this, its objects are destroyed anyway and the device is leaked,
+    /// so `VulkanBackend::reinitialize` does not block if the driver never recovers
+    #[error("Timed out waiting for GPU")]
+    Timeout,
+    #[error("Vulkan call failed: {0}")]
+    Vk(vk::Result),
+}
+
+impl From<vk::Result> for VulkanError {
+    fn from(res: vk::Result) -> Self {
+        match res {
+            vk::Result::ERROR_DEVICE_LOST => VulkanError::DeviceLost,
+            vk::Result::ERROR_OUT_OF_DATE_KHR => VulkanError::SwapchainOutOfDate,
+            vk::Result::TIMEOUT => VulkanError::Timeout,
+            res => VulkanError::Vk(res),
+        }
+    }
+}
+
+/// Convert vulkan result into error, keeping it distinguishable as `VulkanError`
+pub(crate) fn vk_error(res: vk::Result) -> anyhow::Error {
+    VulkanError::from(res).into()
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use ash::vk;
+use ash::vk::{AccessFlags, CommandBuffer, Image, ImageAspectFlags, ImageLayout, PipelineStageFlags};
+use smallvec::SmallVec;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+
+/// How a pass uses an image. Defines layout, pipeline stages and memory access of the use
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImageAccess {
+    /// Contents are not needed, e.g. before a pass which clears the image. Only for `FrameGraph::import`,
+    /// reading or writing an image with it panics
+    Undefined,
+    ColorAttachment,
+    DepthStencilAttachment,
+    /// Sampled or read as input attachment by fragment shaders
+    FragmentShaderRead,
+    TransferRead,
+    TransferWrite,
+    /// Presentation waits for the render finished semaphore, so no access has to be made visible
+    Present,
+}
+
+impl ImageAccess {
+    pub fn layout(self) -> ImageLayout {
+        match self {
+            ImageAccess::Undefined => ImageLayout::UNDEFINED,
+            ImageAccess::ColorAttachment => ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
+            ImageAccess::DepthStencilAttachment => ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+            ImageAccess::FragmentShaderRead => ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+            ImageAccess::TransferRead => ImageLayout::TRANSFER_SRC_OPTIMAL,
+            ImageAccess::TransferWrite => ImageLayout::TRANSFER_DST_OPTIMAL,
+            ImageAccess::Present => ImageLayout::PRESENT_SRC_KHR,
+        }
+    }
+
+    pub fn stages(self) -> PipelineStageFlags {
+        match self {
+            ImageAccess::Undefined => PipelineStageFlags::TOP_OF_PIPE,
+            ImageAccess::ColorAttachment => PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+            ImageAccess::DepthStencilAttachment => {
+                PipelineStageFlags::EARLY_FRAGMENT_TESTS | PipelineStageFlags::LATE_FRAGMENT_TESTS
+            }
+            ImageAccess::FragmentShaderRead => PipelineStageFlags::FRAGMENT_SHADER,
+            ImageAccess::TransferRead | ImageAccess::TransferWrite => PipelineStageFlags::TRANSFER,
+            ImageAccess::Present => PipelineStageFlags::BOTTOM_OF_PIPE,
+        }
+    }
+
+    pub fn access_mask(self) -> AccessFlags {
+        match self {
+            ImageAccess::Undefined | ImageAccess::Present => AccessFlags::empty(),
+            ImageAccess::ColorAttachment => AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ImageAccess::DepthStencilAttachment => {
+                AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE
+            }
+            ImageAccess::FragmentShaderRead => AccessFlags::SHADER_READ,
+            ImageAccess::TransferRead => AccessFlags::TRANSFER_READ,
+            ImageAccess::TransferWrite => AccessFlags::TRANSFER_WRITE,
+        }
+    }
+
+    fn write_mask(self) -> AccessFlags {
+        match self {
+            ImageAccess::ColorAttachment => AccessFlags::COLOR_ATTACHMENT_WRITE,
+            ImageAccess::DepthStencilAttachment => AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE,
+            ImageAccess::TransferWrite => AccessFlags::TRANSFER_WRITE,
+            _ => AccessFlags::empty(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ImageState {
+    aspect: ImageAspectFlags,
+    access: ImageAccess,
+    /// Written with `access` by a pass and not made visible to later ones yet
+    unflushed: bool,
+}
+
+impl ImageState {
+    /// State left by a pass with `image_use`
+    fn after(self, image_use: ImageUse) -> ImageState {
+        let (access, unflushed) = match image_use {
+            ImageUse::Read(access) | ImageUse::Output(access) => (access, false),
+            ImageUse::Write(access) => (access, true),
+        };
+        ImageState {
+            access,
+            unflushed,
+            ..self
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ImageUse {
+    Read(ImageAccess),
+    Write(ImageAccess),
+    /// Pass transitions the image itself, e.g. with render pass final layout and an external subpass dependency
+    Output(ImageAccess),
+}
+
+impl ImageUse {
+    fn access(self) -> ImageAccess {
+        match self {
+            ImageUse::Read(access) | ImageUse::Write(access) | ImageUse::Output(access) => access,
+        }
+    }
+}
+
+/// Linear sequence of passes recorded into a command buffer, with barriers inserted from declared image uses.
+///
+/// Passes are recorded immediately and in order, nothing is reordered or culled. Before each pass, images it
+/// reads or writes are transitioned from the state left by the previous use, and writes are made visible.
+/// Barriers are omitted where the previous pass has already left the image in the required state, so render
+/// passes which synchronize with subpass dependencies cost nothing. State is not kept between frames, images
+/// are imported with their state at the start of the command buffer
+pub struct FrameGraph<'a> {
+    device: &'a VkDeviceRef,
+    command_buffer: CommandBuffer,
+    images: BTreeMap<Image, ImageState>,
+}
+
+impl<'a> FrameGraph<'a> {
+    pub fn new(device: &'a VkDeviceRef, command_buffer: CommandBuffer) -> Self {
+        Self {
+            device,
+            command_buffer,
+            images: BTreeMap::new(),
+        }
+    }
+
+    /// Declare an image used by passes, in state `access` as left by previous command buffers.
+    /// Importing again replaces the tracked state
+    pub fn import(&mut self, image: Image, aspect: ImageAspectFlags, access: ImageAccess) {
+        self.images.insert(image, ImageState {
+            aspect,
+            access,
+            unflushed: false,
+        });
+    }
+
+    /// Current state of an imported image
+    pub fn image_access(&self, image: Image) -> Option<ImageAccess> {
+        self.images.get(&image).map(|state| state.access)
+    }
+
+    pub fn pass(&mut self, name: &'static str) -> PassBuilder<'_, 'a> {
+        PassBuilder {
+            graph: self,
+            name,
+            uses: SmallVec::new(),
+        }
+    }
+}
+
+/// Barrier before `image_use` of an image in `state`, with its source stages. Destination stages are the ones
+/// of the new access
+fn image_barrier(image: Image, state: ImageState, image_use: ImageUse)
+                 -> Option<(vk::ImageMemoryBarrier<'static>, PipelineStageFlags)> {
+    let (access, write) = match image_use {
+        ImageUse::Read(access) => (access, false),
+        ImageUse::Write(access) => (access, true),
+        ImageUse::Output(_) => return None,
+    };
+    // transition to UNDEFINED is not allowed, and there would be no contents to read
+    assert_ne!(access, ImageAccess::Undefined, "Frame graph: ImageAccess::Undefined can only be imported");
+    let layout_change = state.access.layout() != access.layout();
+    // write after write or read, and read after unflushed write
+    if !layout_change && !state.unflushed && !write {
+        return None;
+    }
+    let src_access = if state.unflushed {
+        state.access.write_mask()
+    } else {
+        AccessFlags::empty()
+    };
+    let barrier = vk::ImageMemoryBarrier::default()
+        .src_access_mask(src_access)
+        .dst_access_mask(access.access_mask())
+        .old_layout(state.access.layout())
+        .new_layout(access.layout())
+        .image(image)
+        .subresource_range(vk::ImageSubresourceRange::default()
+            .aspect_mask(state.aspect)
+            .level_count(vk::REMAINING_MIP_LEVELS)
+            .layer_count(vk::REMAINING_ARRAY_LAYERS));
+    Some((barrier, state.access.stages()))
+}
+
+/// Image uses of a pass, recorded with `record`
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut FrameGraph<'a>,
+    name: &'static str,
+    uses: SmallVec<[(Image, ImageUse); 8]>,
+}
+
+impl PassBuilder<'_, '_> {
+    /// Image is read with `access`, transitioned and made visible before the pass if needed
+    pub fn read(mut self, image: Image, access: ImageAccess) -> Self {
+        self.uses.push((image, ImageUse::Read(access)));
+        self
+    }
+
+    /// Image is written with `access`, after all previous uses. Following uses wait for the write
+    pub fn write(mut self, image: Image, access: ImageAccess) -> Self {
+        self.uses.push((image, ImageUse::Write(access)));
+        self
+    }
+
+    /// Pass leaves the image in `access` state and made visible to it on its own, no barrier is inserted
+    pub fn output(mut self, image: Image, access: ImageAccess) -> Self {
+        self.uses.push((image, ImageUse::Output(access)));
+        self
+    }
+
+    /// Insert barriers for declared uses and record the pass with `f`. Panics if an image was not imported
+    pub fn record<R>(self, f: impl FnOnce(CommandBuffer) -> R) -> R {
+        let graph = self.graph;
+        let mut barriers: SmallVec<[vk::ImageMemoryBarrier; 8]> = SmallVec::new();
+        let mut src_stages = PipelineStageFlags::empty();
+        let mut dst_stages = PipelineStageFlags::empty();
+        for (image, image_use) in &self.uses {
+            let state = *graph.images.get(image)
+                .unwrap_or_else(|| panic!("Frame graph: pass {} uses an image which was not imported", self.name));
+            if let Some((barrier, src)) = image_barrier(*image, state, *image_use) {
+                barriers.push(barrier);
+                src_stages |= src;
+                dst_stages |= image_use.access().stages();
+            }
+        }
+        if !barriers.is_empty() {
+            unsafe {
+                graph.device.cmd_pipeline_barrier(
+                    graph.command_buffer,
+                    src_stages,
+                    dst_stages,
+                    vk::DependencyFlags::empty(),
+                    &[],
+                    &[],
+                    &barriers,
+                );
+            }
+        }
+
+        let result = f(graph.command_buffer);
+
+        for (image, image_use) in &self.uses {
+            let state = graph.images.get_mut(image).unwrap();
+            *state = state.after(*image_use);
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ash::vk::{self, AccessFlags, Handle, Image, ImageAspectFlags, ImageLayout, PipelineStageFlags};
+    use super::{image_barrier, ImageAccess, ImageState, ImageUse};
+
+    fn image() -> Image {
+        Image::from_raw(1)
+    }
+
+    fn imported(access: ImageAccess) -> ImageState {
+        ImageState {
+            aspect: ImageAspectFlags::COLOR,
+            access,
+            unflushed: false,
+        }
+    }
+
+    fn barrier(state: ImageState, image_use: ImageUse) -> Option<(vk::ImageMemoryBarrier<'static>, PipelineStageFlags)> {
+        image_barrier(image(), state, image_use)
+    }
+
+    #[test]
+    fn read_after_write_changes_layout_and_flushes_write() {
+        let state = imported(ImageAccess::Undefined).after(ImageUse::Write(ImageAccess::ColorAttachment));
+        let (barrier, src_stages) = barrier(state, ImageUse::Read(ImageAccess::FragmentShaderRead)).unwrap();
+        assert_eq!(barrier.old_layout, ImageLayout::COLOR_ATTACHMENT_OPTIMAL);
+        assert_eq!(barrier.new_layout, ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+        assert_eq!(barrier.src_access_mask, AccessFlags::COLOR_ATTACHMENT_WRITE);
+        assert_eq!(barrier.dst_access_mask, AccessFlags::SHADER_READ);
+        assert_eq!(src_stages, PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+    }
+
+    #[test]
+    fn read_after_read_has_no_barrier() {
+        let state = imported(ImageAccess::TransferWrite).after(ImageUse::Read(ImageAccess::FragmentShaderRead));
+        assert!(barrier(state, ImageUse::Read(ImageAccess::FragmentShaderRead)).is_none());
+    }
+
+    #[test]
+    fn write_after_read_is_execution_dependency() {
+        let state = imported(ImageAccess::Undefined).after(ImageUse::Read(ImageAccess::ColorAttachment));
+        let (barrier, src_stages) = barrier(state, ImageUse::Write(ImageAccess::ColorAttachment)).unwrap();
+        assert_eq!(barrier.old_layout, barrier.new_layout);
+        assert_eq!(barrier.src_access_mask, AccessFlags::empty());
+        assert_eq!(src_stages, PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT);
+    }
+
+    #[test]
+    fn output_suppresses_barrier() {
+        let state = imported(ImageAccess::Undefined).after(ImageUse::Write(ImageAccess::ColorAttachment));
+        assert!(barrier(state, ImageUse::Output(ImageAccess::FragmentShaderRead)).is_none());
+
+        // the pass made the image visible in the new state itself
+        let state = state.after(ImageUse::Output(ImageAccess::FragmentShaderRead));
+        assert!(barrier(state, ImageUse::Read(ImageAccess::FragmentShaderRead)).is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "can only be imported")]
+    fn undefined_read_panics() {
+        let state = imported(ImageAccess::Undefined).after(ImageUse::Write(ImageAccess::ColorAttachment));
+        barrier(state, ImageUse::Read(ImageAccess::Undefined));
+    }
+
+    #[test]
+    #[should_panic(expected = "can only be imported")]
+    fn undefined_write_panics() {
+        barrier(imported(ImageAccess::ColorAttachment), ImageUse::Write(ImageAccess::Undefined));
+    }
+}
@@ -0,0 +1,106 @@
+use std::collections::VecDeque;
+
+/// Timings of a single rendered frame in milliseconds
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FrameTiming {
+    /// Time since the previous `render` call
+    pub cpu_frame_time: f32,
+    /// GPU time of the frame submitted `in_flight_frames` frames earlier, which finished by now.
+    ///
+    /// `None` if timestamp queries are not supported or results are not available yet.
+    pub gpu_frame_time: Option<f32>,
+}
+
+/// Min, max and average of a series of frame times in milliseconds
+#[derive(Clone, Copy, Debug)]
+pub struct FrameStats {
+    pub min: f32,
+    pub max: f32,
+    pub avg: f32,
+}
+
+/// Fixed-size ring buffer of recent frame timings, oldest first
+pub struct FrameHistory {
+    timings: VecDeque<FrameTiming>,
+    capacity: usize,
+}
+
+impl FrameHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            timings: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub(super) fn push(&mut self, timing: FrameTiming) {
+        if self.timings.len() == self.capacity {
+            self.timings.pop_front();
+        }
+        self.timings.push_back(timing);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item=&FrameTiming> {
+        self.timings.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.timings.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.timings.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.timings.clear();
+    }
+
+    pub fn cpu_stats(&self) -> Option<FrameStats> {
+        stats(self.timings.iter().map(|t| t.cpu_frame_time))
+    }
+
+    /// Only frames with GPU time available are taken into account
+    pub fn gpu_stats(&self) -> Option<FrameStats> {
+        stats(self.timings.iter().filter_map(|t| t.gpu_frame_time))
+    }
+
+    /// CPU frame time below which `p` percent of frames fit, e.g. `cpu_percentile(99.0)`
+    pub fn cpu_percentile(&self, p: f32) -> Option<f32> {
+        percentile(self.timings.iter().map(|t| t.cpu_frame_time).collect(), p)
+    }
+
+    pub fn gpu_percentile(&self, p: f32) -> Option<f32> {
+        percentile(self.timings.iter().filter_map(|t| t.gpu_frame_time).collect(), p)
+    }
+}
+
+fn stats(values: impl Iterator<Item=f32>) -> Option<FrameStats> {
+    let mut count = 0;
+    let mut res = FrameStats {
+        min: f32::MAX,
+        max: f32::MIN,
+        avg: 0.0,
+    };
+    for v in values {
+        res.min = res.min.min(v);
+        res.max = res.max.max(v);
+        res.avg += v;
+        count += 1;
+    }
+    if count == 0 {
+        return None;
+    }
+    res.avg /= count as f32;
+    Some(res)
+}
+
+/// Nearest-rank percentile
+fn percentile(mut values: Vec<f32>, p: f32) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(f32::total_cmp);
+    let rank = (p.clamp(0.0, 100.0) / 100.0 * values.len() as f32).ceil() as usize;
+    Some(values[rank.saturating_sub(1)])
+}
@@ -0,0 +1,236 @@
+use ash::prelude::VkResult;
+use ash::vk;
+use crate::vulkan_backend::error::vk_error;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+use crate::vulkan_backend::wrappers::queue::QueueSubmitter;
+
+/// What a frame submit signals on completion, CPU can wait for it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubmitSignal {
+    Fence(vk::Fence),
+    Timeline {
+        semaphore: vk::Semaphore,
+        value: u64,
+    },
+}
+
+impl SubmitSignal {
+    /// Whether `self` is reached when `other` is: same fence, or same timeline with a value not above
+    pub fn reached_by(self, other: SubmitSignal) -> bool {
+        match (self, other) {
+            (SubmitSignal::Fence(a), SubmitSignal::Fence(b)) => a == b,
+            (SubmitSignal::Timeline { semaphore: a, value }, SubmitSignal::Timeline { semaphore: b, value: reached }) =>
+                a == b && value <= reached,
+            _ => false,
+        }
+    }
+
+    pub fn wait(self, device: &VkDeviceRef, timeout: u64) -> VkResult<()> {
+        match self {
+            SubmitSignal::Fence(fence) => unsafe { device.wait_for_fences(&[fence], true, timeout) },
+            SubmitSignal::Timeline { semaphore, value } => device.wait_semaphore_value(semaphore, value, timeout),
+        }
+    }
+}
+
+enum SyncPrimitives {
+    /// One per frame in flight, reset before the frame is submitted again
+    Fences(Vec<vk::Fence>),
+    /// Frame number `n` signals value `n`
+    Timeline(vk::Semaphore),
+}
+
+/// Tracks completion of submitted frames of a surface.
+///
+/// Uses a single timeline semaphore when the device has them enabled, otherwise a fence per frame in flight.
+/// Frames are numbered from 1 in submit order.
+pub(super) struct FrameSync {
+    device: VkDeviceRef,
+    primitives: SyncPrimitives,
+    submitted_frames: u64,
+    /// Number of the frame pending in each slot, 0 if there is nothing to wait for
+    slot_frames: Vec<u64>,
+}
+
+impl FrameSync {
+    pub(super) fn new(device: VkDeviceRef, in_flight_frames: usize) -> anyhow::Result<FrameSync> {
+        let primitives = if device.timeline_semaphores_enabled() {
+            let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+                .semaphore_type(vk::SemaphoreType::TIMELINE)
+                .initial_value(0);
+            let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+            let semaphore = unsafe { device.create_semaphore(&create_info, None) }.map_err(vk_error)?;
+            SyncPrimitives::Timeline(semaphore)
+        } else {
+            let fences = (0..in_flight_frames).map(|_| unsafe {
+                device
+                    .create_fence(
+                        &vk::FenceCreateInfo::default().flags(vk::FenceCreateFlags::SIGNALED),
+                        None,
+                    )
+                    .map_err(vk_error)
+            }).collect::<anyhow::Result<_>>()?;
+            SyncPrimitives::Fences(fences)
+        };
+        Ok(FrameSync {
+            device,
+            primitives,
+            submitted_frames: 0,
+            slot_frames: vec![0; in_flight_frames],
+        })
+    }
+
+    pub(super) fn submitted_frames(&self) -> u64 {
+        self.submitted_frames
+    }
+
+    /// Signal which will be reached when the frame submitted from `slot` is finished
+    pub(super) fn slot_signal(&self, slot: usize) -> SubmitSignal {
+        match &self.primitives {
+            SyncPrimitives::Fences(fences) => SubmitSignal::Fence(fences[slot]),
+            SyncPrimitives::Timeline(semaphore) => SubmitSignal::Timeline {
+                semaphore: *semaphore,
+                value: self.slot_frames[slot],
+            },
+        }
+    }
+
+    /// Signal of the next submit, from `slot`
+    pub(super) fn next_signal(&self, slot: usize) -> SubmitSignal {
+        match &self.primitives {
+            SyncPrimitives::Fences(fences) => SubmitSignal::Fence(fences[slot]),
+            SyncPrimitives::Timeline(semaphore) => SubmitSignal::Timeline {
+                semaphore: *semaphore,
+                value: self.submitted_frames + 1,
+            },
+        }
+    }
+
+    /// Block until the previous frame submitted from `slot` is finished
+    pub(super) fn wait_slot(&self, slot: usize, timeout: u64) -> VkResult<()> {
+        if self.slot_frames[slot] == 0 {
+            return Ok(());
+        }
+        self.slot_signal(slot).wait(&self.device, timeout)
+    }
+
+    /// Block until frames submitted from all slots except `slot` are finished
+    pub(super) fn wait_other_slots(&self, slot: usize, timeout: u64) -> VkResult<()> {
+        match &self.primitives {
+            SyncPrimitives::Fences(fences) => {
+                let other_fences: Vec<_> = fences.iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != slot && self.slot_frames[*i] != 0)
+                    .map(|(_, fence)| *fence)
+                    .collect();
+                if other_fences.is_empty() {
+                    return Ok(());
+                }
+                unsafe { self.device.wait_for_fences(&other_fences, true, timeout) }
+            }
+            SyncPrimitives::Timeline(semaphore) => {
+                // frames finish in submit order, so waiting for the last one is enough
+                let last = self.slot_frames.iter()
+                    .enumerate()
+                    .filter(|(i, _)| *i != slot)
+                    .map(|(_, frame)| *frame)
+                    .max()
+                    .unwrap_or(0);
+                if last == 0 {
+                    return Ok(());
+                }
+                self.device.wait_semaphore_value(*semaphore, last, timeout)
+            }
+        }
+    }
+
+    /// Previous frame of the slot must be finished. Its fence is reset, so the slot must be submitted next
+    pub(super) fn begin_slot(&mut self, slot: usize) -> VkResult<()> {
+        if let SyncPrimitives::Fences(fences) = &self.primitives {
+            unsafe { self.device.reset_fences(&[fences[slot]]) }?;
+        }
+        self.slot_frames[slot] = 0;
+        Ok(())
+    }
+
+    /// Submit a frame from `slot`. Binary semaphores are waited and signaled as usual
+    pub(super) fn submit(&mut self, queue: &QueueSubmitter, slot: usize, submit_info: vk::SubmitInfo) -> VkResult<()> {
+        let frame = self.submitted_frames + 1;
+        match &self.primitives {
+            SyncPrimitives::Fences(fences) => unsafe {
+                queue.submit(&[submit_info], fences[slot])?;
+            }
+            SyncPrimitives::Timeline(semaphore) => {
+                // values of binary semaphores are ignored, but arrays must have matching lengths
+                let mut signal_semaphores: Vec<_> = unsafe {
+                    std::slice::from_raw_parts(submit_info.p_signal_semaphores, submit_info.signal_semaphore_count as usize)
+                }.to_vec();
+                signal_semaphores.push(*semaphore);
+                let mut signal_values = vec![0; signal_semaphores.len()];
+                *signal_values.last_mut().unwrap() = frame;
+                let wait_values = vec![0; submit_info.wait_semaphore_count as usize];
+                let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::default()
+                    .wait_semaphore_values(&wait_values)
+                    .signal_semaphore_values(&signal_values);
+                let submit_info = submit_info
+                    .signal_semaphores(&signal_semaphores)
+                    .push_next(&mut timeline_info);
+                unsafe {
+                    queue.submit(&[submit_info], vk::Fence::null())?;
+                }
+            }
+        }
+        self.submitted_frames = frame;
+        self.slot_frames[slot] = frame;
+        Ok(())
+    }
+
+    /// Number of the last frame such that it and all frames before it are finished
+    pub(super) fn completed_frames(&self) -> VkResult<u64> {
+        match &self.primitives {
+            SyncPrimitives::Fences(fences) => {
+                let mut completed = self.submitted_frames;
+                for (fence, frame) in fences.iter().zip(&self.slot_frames) {
+                    if *frame != 0 && !unsafe { self.device.get_fence_status(*fence) }? {
+                        completed = completed.min(frame - 1);
+                    }
+                }
+                Ok(completed)
+            }
+            SyncPrimitives::Timeline(semaphore) => self.device.semaphore_value(*semaphore),
+        }
+    }
+
+    /// Block until frame number `frame` is finished. Frames which were not submitted yet are not waited for
+    pub(super) fn wait_for_frame(&self, frame: u64, timeout: u64) -> VkResult<()> {
+        if frame == 0 || frame > self.submitted_frames {
+            return Ok(());
+        }
+        match &self.primitives {
+            SyncPrimitives::Fences(_) => {
+                // slot is reused only after its frame is finished
+                match self.slot_frames.iter().position(|f| *f == frame) {
+                    Some(slot) => self.wait_slot(slot, timeout),
+                    None => Ok(()),
+                }
+            }
+            SyncPrimitives::Timeline(semaphore) => self.device.wait_semaphore_value(*semaphore, frame, timeout),
+        }
+    }
+
+    /// Device must be idle
+    pub(super) fn destroy(&mut self) {
+        match &self.primitives {
+            SyncPrimitives::Fences(fences) => {
+                for &fence in fences {
+                    unsafe {
+                        self.device.destroy_fence(fence, None);
+                    }
+                }
+            }
+            SyncPrimitives::Timeline(semaphore) => unsafe {
+                self.device.destroy_semaphore(*semaphore, None);
+            },
+        }
+    }
+}
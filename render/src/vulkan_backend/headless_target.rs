@@ -0,0 +1,72 @@
+use ash::vk::{Extent2D, Format, Image, ImageAspectFlags, ImageTiling, ImageUsageFlags, ImageView, SampleCountFlags};
+use crate::vulkan_backend::resource_manager::{ImageResource, ResourceManager};
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+use crate::vulkan_backend::wrappers::image::imageview_info_for_image;
+
+/// Offscreen color images used in place of swapchain images by a surface without a window.
+///
+/// There is one image per frame in flight, so the image of a frame is free as soon as its slot is waited for
+/// and nothing has to be acquired. Images are left in PRESENT_SRC_KHR layout like swapchain images, device
+/// must have `VK_KHR_swapchain` enabled for it.
+pub struct HeadlessTarget {
+    device: VkDeviceRef,
+    images: Vec<ImageResource>,
+    image_views: Vec<ImageView>,
+    format: Format,
+    extent: Extent2D,
+}
+
+impl HeadlessTarget {
+    pub fn new(device: VkDeviceRef, resource_manager: &mut ResourceManager, extent: Extent2D, format: Format,
+               image_count: usize) -> Self {
+        let images: Vec<_> = (0..image_count).map(|i| {
+            let image = resource_manager.create_image(extent, format, ImageTiling::OPTIMAL,
+                                                      ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC,
+                                                      SampleCountFlags::TYPE_1);
+            device.set_object_name(image.image, format_args!("Headless image {}", i));
+            image
+        }).collect();
+        let image_views = images.iter().map(|image| {
+            let info = imageview_info_for_image(image.image, image.info, ImageAspectFlags::COLOR);
+            unsafe { device.create_image_view(&info, None).unwrap() }
+        }).collect();
+
+        HeadlessTarget {
+            device,
+            images,
+            image_views,
+            format,
+            extent,
+        }
+    }
+
+    pub fn get_image(&self, index: usize) -> Image {
+        self.images[index].image
+    }
+
+    pub fn get_image_views(&self) -> Vec<ImageView> {
+        self.image_views.clone()
+    }
+
+    pub fn get_image_view(&self, index: usize) -> ImageView {
+        self.image_views[index]
+    }
+
+    pub fn get_surface_format(&self) -> Format {
+        self.format
+    }
+
+    pub fn get_extent(&self) -> Extent2D {
+        self.extent
+    }
+
+    /// Device must be idle
+    pub fn destroy(mut self, resource_manager: &mut ResourceManager) {
+        for image_view in self.image_views.drain(..) {
+            unsafe { self.device.destroy_image_view(image_view, None); }
+        }
+        for image in self.images.drain(..) {
+            resource_manager.destroy_image(image);
+        }
+    }
+}
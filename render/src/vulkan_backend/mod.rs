@@ -1,3 +1,4 @@
+pub mod bindless;
 pub mod descriptor_sets;
 pub mod pipeline;
 pub mod render_pass;
@@ -5,69 +6,161 @@ pub mod resource_manager;
 pub mod swapchain_wrapper;
 pub mod wrappers;
 pub mod config;
+pub mod error;
+pub mod frame_graph;
+pub mod frame_history;
+pub mod frame_sync;
+pub mod headless_target;
+pub mod physical_device;
+pub mod post_process;
+pub mod render_target;
+pub mod surface_context;
+pub mod texture_loader;
+#[cfg(feature = "egui")]
+pub mod egui_renderer;
+#[cfg(feature = "shader_reflection")]
+pub mod shader_reflection;
+#[cfg(feature = "runtime_shader_compile")]
+pub mod shader_compiler;
 pub(super) mod object_resource_pool;
 
-use swapchain_wrapper::SwapchainWrapper;
+use swapchain_wrapper::{is_srgb_format, SwapchainWrapper};
 
 use log::{debug, error, info, warn};
 
 use ash::vk::{
-    self, make_api_version, ApplicationInfo, BufferUsageFlags, CommandBuffer,
-    CommandBufferBeginInfo, DeviceSize, Extent2D, FenceCreateFlags, PhysicalDevice,
-    PipelineBindPoint, PrimitiveTopology, Queue, RenderPassBeginInfo, Semaphore,
+    self, make_api_version, ApplicationInfo, BufferUsageFlags, DeviceSize, Extent2D,
+    PhysicalDevice, PipelineBindPoint, PrimitiveTopology, Queue,
 };
 
 use crate::vulkan_backend::descriptor_sets::ObjectDescriptorSet;
 use crate::vulkan_backend::pipeline::{VulkanPipeline};
-use crate::vulkan_backend::render_pass::RenderPassResources;
+use crate::vulkan_backend::render_pass::ClearBehavior;
 use crate::vulkan_backend::resource_manager::{BufferResource, ResourceManager};
-use crate::vulkan_backend::wrappers::capabilities_checker::CapabilitiesChecker;
+use crate::vulkan_backend::wrappers::capabilities_checker::{CapabilitiesChecker, FeatureSupport, IndirectDraws};
 use crate::vulkan_backend::wrappers::command_pool::VkCommandPool;
 use crate::vulkan_backend::wrappers::debug_utils::VkDebugUtils;
-use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+use crate::vulkan_backend::wrappers::device::{VkDevice, VkDeviceRef};
 use crate::vulkan_backend::wrappers::surface::{VkSurface, VkSurfaceRef};
+use crate::vulkan_backend::wrappers::instance::{VkInstance, VkInstanceRef};
+use crate::vulkan_backend::wrappers::pipeline_cache::{VkPipelineCache, VkPipelineCacheRef};
+use crate::vulkan_backend::wrappers::queue::QueueSubmitter;
 use render_pass::RenderPassWrapper;
-use sparkles_macro::{instant_event, range_event_start};
-use std::array::from_fn;
+use sparkles_macro::range_event_start;
 use std::ffi::{c_char, CString};
 use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
 use render_core::collect_state::CollectDrawStateUpdates;
-use crate::vulkan_backend::config::VulkanRenderConfig;
-use crate::vulkan_backend::object_resource_pool::ObjectResourcePool;
-
+use render_core::object_handles::UniformResourceId;
+use crate::backend::RenderBackend;
+pub use crate::backend::RenderResult;
+use crate::vulkan_backend::config::{DepthMode, OutputColorSpace, VulkanRenderConfig};
+use crate::vulkan_backend::descriptor_sets::DescriptorPoolStats;
+use crate::vulkan_backend::object_resource_pool::DrawStats;
+use crate::vulkan_backend::error::{vk_error, VulkanError};
+use crate::vulkan_backend::frame_history::FrameHistory;
+use crate::vulkan_backend::physical_device::{describe_physical_devices, format_api_version, negotiate_api_version, select_depth_format, select_physical_device, DeviceInfo, PhysicalDeviceDesc, MAX_API_VERSION};
+use crate::vulkan_backend::headless_target::HeadlessTarget;
+use crate::vulkan_backend::surface_context::{PresentTarget, SurfaceContext, SurfaceId};
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Vulkan renderer for one or more windows.
+///
+/// Instance, device, queue and resources are shared, each window has its own `SurfaceContext`.
+/// All windows must use the same surface format.
 pub struct VulkanBackend {
     config: VulkanRenderConfig,
 
-    debug_utils: VkDebugUtils,
-    surface: VkSurfaceRef,
+    /// `None` for external instance
+    debug_utils: Option<VkDebugUtils>,
+    instance: VkInstanceRef,
     physical_device: PhysicalDevice,
+    queue_family_index: u32,
     device: VkDeviceRef,
-    queue: Queue,
+    queue: QueueSubmitter,
     command_pool: VkCommandPool,
+    /// Nanoseconds per timestamp tick, `None` if timestamp queries are not supported
+    timestamp_period: Option<f32>,
+    device_info: DeviceInfo,
 
     resource_manager: ResourceManager,
+    pipeline_cache: VkPipelineCacheRef,
 
-    command_buffers: [CommandBuffer; 1],
-    image_available_semaphores: [Semaphore; 1],
-    render_finished_semaphores: [Semaphore; 1],
-    fences: [vk::Fence; 1],
-    cur_command_buffer: usize,
-    command_buffer_last_index: [Option<usize>; 1],
-
-    swapchain_wrapper: SwapchainWrapper,
-
-    object_resource_pool: ObjectResourcePool,
+    surfaces: BTreeMap<SurfaceId, SurfaceContext>,
+    next_surface_id: usize,
 
     // stuff for actual rendering
     render_pass: RenderPassWrapper,
-    render_pass_resources: RenderPassResources,
+
+    /// Instance and device are provided by the application
+    external: bool,
+
+    /// `render` does nothing while paused
+    paused: bool,
+    /// Windows which surfaces were destroyed by `pause`, created again on `resume`
+    released_windows: BTreeMap<SurfaceId, ReleasedWindow>,
+    /// A frame did not finish within `VulkanRenderConfig::frame_timeout`, the device may never become idle
+    gpu_hung: bool,
+}
+
+impl RenderBackend for VulkanBackend {
+    fn render(&mut self, id: SurfaceId, draw_state_diff: &mut impl CollectDrawStateUpdates,
+              clear_behavior: ClearBehavior) -> anyhow::Result<RenderResult> {
+        VulkanBackend::render(self, id, draw_state_diff, clear_behavior)
+    }
+}
+
+struct ReleasedWindow {
+    window_handle: RawWindowHandle,
+    display_handle: RawDisplayHandle,
+    window_size: (u32, u32),
+    viewport_rect: Option<vk::Rect2D>,
+}
+
+/// Handles created by the application for `VulkanBackend::from_existing`
+pub struct ExternalVulkanContext {
+    pub instance: ash::Instance,
+    pub physical_device: PhysicalDevice,
+    pub device: ash::Device,
+    /// After the backend is created, other submits to this queue must go through `VulkanBackend::queue_submitter`
+    pub queue: Queue,
+    pub queue_family_index: u32,
+    /// `api_version` the instance was created with, features of later versions are not used
+    pub api_version: u32,
+}
+
+/// Instance and device which the rest of renderer is created with
+struct DeviceContext {
+    instance: VkInstanceRef,
+    debug_utils: Option<VkDebugUtils>,
+    physical_device: PhysicalDevice,
+    queue_family_index: u32,
+    device: VkDeviceRef,
+    queue: Queue,
+    /// Instance `api_version`
+    api_version: u32,
+    external: bool,
 }
 
 impl VulkanBackend {
-    /// Initialize vulkan resources and use window to create surface
+    /// Initialize vulkan resources and use window to create surface. Window surface gets `SurfaceId::MAIN`
     ///
     /// Must be called from main thread!
     pub fn new_for_window(window_handle: RawWindowHandle, display_handle: RawDisplayHandle, window_size: (u32, u32), config: VulkanRenderConfig) -> anyhow::Result<Self> {
+        Self::init(Some((window_handle, display_handle)), window_size, config, SurfaceId::MAIN)
+    }
+
+    /// Initialize vulkan resources without a window. Frames of `SurfaceId::MAIN` are rendered to offscreen images
+    /// of `size` and read back with `read_frame`, e.g. for tests.
+    ///
+    /// Surface format is B8G8R8A8, sRGB with `OutputColorSpace::Linear`. Device still needs `VK_KHR_swapchain`
+    pub fn new_headless(size: (u32, u32), config: VulkanRenderConfig) -> anyhow::Result<Self> {
+        Self::init(None, size, config, SurfaceId::MAIN)
+    }
+
+    /// Headless without `window`
+    fn init(window: Option<(RawWindowHandle, RawDisplayHandle)>, window_size: (u32, u32),
+            config: VulkanRenderConfig, surface_id: SurfaceId) -> anyhow::Result<Self> {
         let g = range_event_start!("[Vulkan] INIT");
         info!(
             "Vulkan init started! Initializing for size: {:?}",
@@ -76,12 +169,14 @@ impl VulkanBackend {
 
         let app_name = CString::new("Hello Vulkan")?;
 
+        let api_version = negotiate_api_version(&ash::Entry::linked(), config.max_api_version.unwrap_or(MAX_API_VERSION))?;
+        info!("Requesting Vulkan API version {}", format_api_version(api_version));
         let app_info = ApplicationInfo::default()
             .application_name(&app_name)
             .application_version(make_api_version(0, 1, 0, 0))
             .engine_name(&app_name)
             .engine_version(make_api_version(0, 1, 0, 0))
-            .api_version(vk::API_VERSION_1_0);
+            .api_version(api_version);
 
         //define desired layers
         // 1. Khronos validation layers (optional)
@@ -94,12 +189,17 @@ impl VulkanBackend {
 
         //define desired extensions
         // 1 Debug utils
-        // 2,3 Required extensions for surface support (platform_specific surface + general surface)
+        // 2,3 Required extensions for surface support (platform_specific surface + general surface),
+        //     only general surface without window, as a dependency of VK_KHR_swapchain
         // 4 Portability enumeration (for moltenvk)
-        let surface_required_extensions =
-            ash_window::enumerate_required_extensions(display_handle)?;
-        let mut instance_extensions: Vec<*const c_char> = surface_required_extensions.to_vec();
+        let mut instance_extensions: Vec<*const c_char> = match window {
+            Some((_, display_handle)) => ash_window::enumerate_required_extensions(display_handle)?.to_vec(),
+            None => vec![ash::khr::surface::NAME.as_ptr()],
+        };
         instance_extensions.push(ash::ext::debug_utils::NAME.as_ptr());
+        if cfg!(feature = "validation_gpu_assisted") {
+            instance_extensions.push(ash::ext::validation_features::NAME.as_ptr());
+        }
 
         let mut debug_utils_messenger_info = VkDebugUtils::get_messenger_create_info();
         
@@ -110,357 +210,766 @@ impl VulkanBackend {
         let instance = caps_checker.create_instance(&app_info, &mut instance_layers_refs,
                                         &mut instance_extensions, &mut debug_utils_messenger_info)?;
 
-        let surface = VkSurface::new(instance.clone(), display_handle, window_handle)?;
+        let surface = window
+            .map(|(window_handle, display_handle)| VkSurface::new(instance.clone(), display_handle, window_handle))
+            .transpose()?;
 
         let debug_utils = VkDebugUtils::new(instance.clone())?;
         // instance is created. debug utils ready
 
         let physical_devices = unsafe { instance.enumerate_physical_devices()? };
 
-        let physical_device = *physical_devices
-            .iter()
-            .find(|&d| {
-                let properties = unsafe { instance.get_physical_device_properties(*d) };
-                properties.device_type == vk::PhysicalDeviceType::DISCRETE_GPU
-            })
-            .or_else(|| {
-                warn!("Discrete GPU was not found!");
-                physical_devices.iter().find(|&d| {
-                    let properties = unsafe { instance.get_physical_device_properties(*d) };
-                    properties.device_type == vk::PhysicalDeviceType::INTEGRATED_GPU
-                })
-            })
-            .or_else(|| {
-                warn!("Integrated GPU was not found!");
-                physical_devices.iter().find(|&d| {
-                    let properties = unsafe { instance.get_physical_device_properties(*d) };
-                    properties.device_type == vk::PhysicalDeviceType::CPU
-                })
-            })
-            .unwrap_or_else(|| {
-                panic!("No avaliable physical device found");
-            });
-
-        //select chosen physical device
-        let dev_name_array = unsafe {
-            instance
-                .get_physical_device_properties(physical_device)
-                .device_name
-        };
-        let dev_name = unsafe { std::ffi::CStr::from_ptr(dev_name_array.as_ptr()) };
-        info!("Chosen device: {}", dev_name.to_str().unwrap());
+        let physical_device = select_physical_device(&instance, &physical_devices, &config.device_preference)
+            .ok_or(VulkanError::NoSuitableDevice)?;
 
         let queue_family_properties =
             unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
         let queue_family_index = queue_family_properties
             .iter()
             .enumerate()
-            .find(|(_, p)| {
+            .find(|(i, p)| {
                 let support_graphics = p.queue_flags.contains(vk::QueueFlags::GRAPHICS);
-                let support_presentation = surface.query_presentation_support(physical_device);
+                let support_presentation = surface.as_ref()
+                    .is_none_or(|surface| surface.supports_queue_family(physical_device, *i as u32));
 
                 support_graphics && support_presentation
             })
             .map(|(i, _)| i as u32)
-            .unwrap_or_else(|| {
-                panic!("No available queue family found");
-            });
+            .ok_or(VulkanError::NoQueueFamily)?;
+
+        let mut device_extensions = vec![ash::khr::swapchain::NAME.as_ptr()];
+
+        let timeline_semaphore = if config.timeline_semaphores {
+            let support = caps_checker.check_timeline_semaphore(&instance, physical_device, api_version);
+            if support.is_none() {
+                warn!("Timeline semaphores are not supported, using fences for frame synchronization");
+            }
+            support
+        } else {
+            None
+        };
+        if timeline_semaphore == Some(FeatureSupport::Extension) {
+            device_extensions.push(ash::khr::timeline_semaphore::NAME.as_ptr());
+        }
+        let mut timeline_semaphore_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::default()
+            .timeline_semaphore(true);
+
+        // needs subpasses for post_subpass
+        let dynamic_rendering = if config.dynamic_rendering && !config.post_subpass {
+            let support = caps_checker.check_dynamic_rendering(&instance, physical_device, api_version);
+            if support.is_none() {
+                warn!("Dynamic rendering is not supported, using render pass objects");
+            }
+            support
+        } else {
+            None
+        };
+        if dynamic_rendering == Some(FeatureSupport::Extension) {
+            device_extensions.push(ash::khr::dynamic_rendering::NAME.as_ptr());
+        }
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default()
+            .dynamic_rendering(true);
+
+        let indirect_draws = if config.indirect_batches {
+            let indirect_draws = caps_checker.check_indirect_draws(&instance, physical_device);
+            if indirect_draws.is_none() {
+                warn!("drawIndirectFirstInstance is not supported, object batches are drawn directly");
+            }
+            indirect_draws
+        } else {
+            None
+        };
+        let descriptor_indexing = if config.bindless_textures {
+            let support = caps_checker.check_descriptor_indexing(&instance, physical_device, api_version,
+                                                                 bindless::BINDLESS_TEXTURE_COUNT);
+            if support.is_none() {
+                warn!("Descriptor indexing is not supported, bindless textures use a descriptor set per object");
+            }
+            support
+        } else {
+            None
+        };
+        if descriptor_indexing == Some(FeatureSupport::Extension) {
+            device_extensions.push(ash::ext::descriptor_indexing::NAME.as_ptr());
+        }
+        let mut descriptor_indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default()
+            .descriptor_binding_partially_bound(true)
+            .descriptor_binding_sampled_image_update_after_bind(true);
 
-        let device_extensions = vec![ash::khr::swapchain::NAME.as_ptr()];
+        let enabled_features = vk::PhysicalDeviceFeatures::default()
+            .draw_indirect_first_instance(indirect_draws.is_some())
+            .multi_draw_indirect(indirect_draws == Some(IndirectDraws::Multi));
 
         let queue_create_infos = [vk::DeviceQueueCreateInfo::default()
             .queue_family_index(queue_family_index)
             .queue_priorities(&[1.0])];
         let mut device_create_info = vk::DeviceCreateInfo::default()
             .queue_create_infos(&queue_create_infos)
-            .enabled_extension_names(&device_extensions);
+            .enabled_extension_names(&device_extensions)
+            .enabled_features(&enabled_features);
+        if timeline_semaphore.is_some() {
+            device_create_info = device_create_info.push_next(&mut timeline_semaphore_features);
+        }
+        if dynamic_rendering.is_some() {
+            device_create_info = device_create_info.push_next(&mut dynamic_rendering_features);
+        }
+        if descriptor_indexing.is_some() {
+            device_create_info = device_create_info.push_next(&mut descriptor_indexing_features);
+        }
 
         let device = caps_checker.create_device(
             instance.clone(),
             physical_device,
             &mut device_create_info,
         )?;
-
         let queue = unsafe { device.get_device_queue(queue_family_index, 0) };
+
+        let device_context = DeviceContext {
+            instance,
+            debug_utils: Some(debug_utils),
+            physical_device,
+            queue_family_index,
+            device,
+            queue,
+            api_version,
+            external: false,
+        };
+        let window = surface.zip(window).map(|(surface, (window_handle, display_handle))| (surface, window_handle, display_handle));
+        Self::init_with_device(device_context, window, window_size, config, surface_id)
+    }
+
+    /// Use instance and device created by the application, e.g. to share them with another vulkan user.
+    ///
+    /// # Safety
+    /// Instance must have surface extensions for the window enabled, device must have `VK_KHR_swapchain`
+    /// enabled, queue must belong to `queue_family_index` and support graphics and presentation.
+    /// External handles must outlive the renderer, they are not destroyed on drop.
+    pub unsafe fn from_existing(context: ExternalVulkanContext, window_handle: RawWindowHandle, display_handle: RawDisplayHandle,
+                                window_size: (u32, u32), config: VulkanRenderConfig) -> anyhow::Result<Self> {
+        let g = range_event_start!("[Vulkan] INIT from existing device");
+        let instance: VkInstanceRef = Arc::new(VkInstance::new_external(context.instance));
+        let device: VkDeviceRef = Arc::new(VkDevice::new_external(context.device, instance.clone()));
+        let surface = VkSurface::new(instance.clone(), display_handle, window_handle)?;
+        if !surface.supports_queue_family(context.physical_device, context.queue_family_index) {
+            anyhow::bail!("Window surface does not support presentation from the provided queue family");
+        }
+
+        let device_context = DeviceContext {
+            instance,
+            debug_utils: None,
+            physical_device: context.physical_device,
+            queue_family_index: context.queue_family_index,
+            device,
+            queue: context.queue,
+            api_version: context.api_version.max(vk::API_VERSION_1_0),
+            external: true,
+        };
+        Self::init_with_device(device_context, Some((surface, window_handle, display_handle)), window_size, config,
+                               SurfaceId::MAIN)
+    }
+
+    /// Surface `surface_id` is headless without `window`
+    fn init_with_device(device_context: DeviceContext, window: Option<(VkSurfaceRef, RawWindowHandle, RawDisplayHandle)>,
+                        window_size: (u32, u32), config: VulkanRenderConfig,
+                        surface_id: SurfaceId) -> anyhow::Result<Self> {
+        let DeviceContext {
+            instance,
+            debug_utils,
+            physical_device,
+            queue_family_index,
+            device,
+            queue,
+            api_version,
+            external,
+        } = device_context;
+        let queue = QueueSubmitter::new(device.clone(), queue, queue_family_index);
+
+        let queue_family_properties =
+            unsafe { instance.get_physical_device_queue_family_properties(physical_device) };
+
+        // timestamps are optional and used only for GPU frame time measurement
+        let device_limits = unsafe { instance.get_physical_device_properties(physical_device) }.limits;
+        let timestamps_supported = device_limits.timestamp_compute_and_graphics == vk::TRUE
+            && queue_family_properties[queue_family_index as usize].timestamp_valid_bits > 0;
+        if !timestamps_supported {
+            warn!("Timestamp queries are not supported, GPU frame time will not be available");
+        }
+
+        let timestamp_period = timestamps_supported.then_some(device_limits.timestamp_period);
+        let device_info = DeviceInfo::new(&instance, physical_device, api_version, timestamps_supported,
+                                          device.timeline_semaphores_enabled(), device.dynamic_rendering_enabled());
+        info!("Using Vulkan API version {} (device supports {})", format_api_version(device_info.enabled_api_version),
+              format_api_version(device_info.api_version));
+
         let command_pool = VkCommandPool::new(device.clone(), queue_family_index);
-        let command_buffers = command_pool.alloc_command_buffers(1);
-
-        let image_available_semaphores = from_fn(|_| unsafe {
-            device
-                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-                .unwrap()
-        });
-        let render_finished_semaphores = from_fn(|_| unsafe {
-            device
-                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
-                .unwrap()
-        });
-
-        let fences = from_fn(|_| unsafe {
-            device
-                .create_fence(
-                    &vk::FenceCreateInfo::default().flags(FenceCreateFlags::SIGNALED),
-                    None,
-                )
-                .unwrap()
-        });
 
         let mut resource_manager =
-            ResourceManager::new(physical_device, device.clone(), queue, &command_pool);
+            ResourceManager::new(physical_device, device.clone(), queue.clone(), &command_pool,
+                                 config.in_flight_frames.count());
 
         let extent = Extent2D {
             width: window_size.0,
             height: window_size.1,
         };
-        let swapchain_wrapper = SwapchainWrapper::new(
-            device.clone(),
-            physical_device,
-            extent,
-            surface.clone(),
-            None,
-        )?;
+        let target = match window {
+            Some((surface, window_handle, display_handle)) => PresentTarget::Window {
+                swapchain: SwapchainWrapper::new(
+                    device.clone(),
+                    physical_device,
+                    extent,
+                    surface.clone(),
+                    None,
+                    config.present_config(),
+                )?,
+                surface,
+                window_handle,
+                display_handle,
+            },
+            None => PresentTarget::Headless(HeadlessTarget::new(device.clone(), &mut resource_manager, extent,
+                                                                config.headless_format(), config.in_flight_frames.count())),
+        };
 
         let msaa_samples = config.get_msaa_samples();
 
+        let depth_format = select_depth_format(&instance, physical_device, config.depth_format);
+        if config.depth_mode == DepthMode::ReverseZ && !depth_format.is_float() {
+            warn!("Reverse-Z gives little precision benefit with fixed point depth format {:?}", depth_format);
+        }
         let render_pass = RenderPassWrapper::new(
             device.clone(),
-            swapchain_wrapper.get_surface_format(),
+            target.format(),
+            depth_format.format(),
+            config.depth_mode,
+            &config.extra_color_attachments,
             msaa_samples,
-        );
-        let render_pass_resources = render_pass.create_render_pass_resources(
-            swapchain_wrapper.get_image_views(),
-            swapchain_wrapper.get_extent(),
-            &mut resource_manager,
+            config.post_subpass,
+            device.dynamic_rendering_enabled(),
         );
 
-        let object_resource_pool = ObjectResourcePool::new(device.clone());
+        let pipeline_cache = Arc::new(VkPipelineCache::new(device.clone(), config.pipeline_cache_path.clone()));
+
+        let mut surface_context = SurfaceContext::new(
+            device.clone(),
+            target,
+            window_size,
+            &render_pass,
+            &mut resource_manager,
+            &command_pool,
+            pipeline_cache.clone(),
+            config.in_flight_frames.count(),
+            config.frame_timeout_ns(),
+            timestamp_period,
+            config.group_draws_by_pipeline,
+        )?;
+        if let Some(shader) = post_process::post_process_shader(&config, render_pass.get_surface_format()) {
+            surface_context.set_post_process(&render_pass, &mut resource_manager, pipeline_cache.get_pipeline_cache(),
+                                             Some(shader))?;
+        }
 
 
         Ok(VulkanBackend {
             config,
 
-            surface,
             debug_utils,
+            instance,
 
             physical_device,
+            queue_family_index,
             device,
             queue,
             command_pool,
+            timestamp_period,
+            device_info,
 
             resource_manager,
+            pipeline_cache,
 
-            swapchain_wrapper,
-            command_buffers: command_buffers.try_into().unwrap(),
-            image_available_semaphores,
-            render_finished_semaphores,
-            fences,
-            cur_command_buffer: 0,
-            command_buffer_last_index: [None; 1],
-
-            object_resource_pool,
+            surfaces: BTreeMap::from([(surface_id, surface_context)]),
+            next_surface_id: surface_id.0 + 1,
 
             render_pass,
-            render_pass_resources,
+            external,
+
+            paused: false,
+            released_windows: BTreeMap::new(),
+            gpu_hung: false,
         })
     }
 
-    pub fn recreate_resize(&mut self, new_extent: (u32, u32)) {
-        let g = range_event_start!("[Vulkan] Recreate swapchain");
-        let new_extent = Extent2D {
-            width: new_extent.0,
-            height: new_extent.1,
-        };
-        self.wait_idle();
+    /// Capabilities and limits of the chosen device
+    pub fn device_info(&self) -> &DeviceInfo {
+        &self.device_info
+    }
 
-        //clear states
-        self.command_buffer_last_index = [None; 1];
+    /// List available physical devices, e.g. to let user choose one with `DevicePreference::ByIndex`.
+    ///
+    /// Creates a temporary vulkan instance.
+    pub fn enumerate_devices() -> anyhow::Result<Vec<PhysicalDeviceDesc>> {
+        let g = range_event_start!("[Vulkan] Enumerate devices");
+        let entry = ash::Entry::linked();
+        let app_info = ApplicationInfo::default().api_version(vk::API_VERSION_1_0);
+        let mut create_info = vk::InstanceCreateInfo::default().application_info(&app_info);
+        let portability_extensions = [ash::khr::portability_enumeration::NAME.as_ptr()];
+        if cfg!(feature = "portability_subset") {
+            create_info = create_info.enabled_extension_names(&portability_extensions)
+                .flags(vk::InstanceCreateFlags::ENUMERATE_PORTABILITY_KHR);
+        }
+        let instance = VkInstance::new(unsafe { entry.create_instance(&create_info, None) }?);
+        let physical_devices = unsafe { instance.enumerate_physical_devices() }?;
+        Ok(describe_physical_devices(&instance, &physical_devices))
+    }
 
-        // 1. Destroy swapchain dependent resources
-        unsafe {
-            self.render_pass_resources
-                .destroy(&mut self.resource_manager);
+    /// Create surface for another window, sharing device and resources with existing ones.
+    ///
+    /// Window surface must support presentation from the queue used by renderer and have the same format
+    /// as the main window.
+    pub fn add_window(&mut self, window_handle: RawWindowHandle, display_handle: RawDisplayHandle, window_size: (u32, u32)) -> anyhow::Result<SurfaceId> {
+        let id = SurfaceId(self.next_surface_id);
+        self.add_window_with_id(window_handle, display_handle, window_size, id)?;
+        self.next_surface_id += 1;
+        Ok(id)
+    }
+
+    fn add_window_with_id(&mut self, window_handle: RawWindowHandle, display_handle: RawDisplayHandle,
+                          window_size: (u32, u32), id: SurfaceId) -> anyhow::Result<()> {
+        let g = range_event_start!("[Vulkan] Add window");
+        let surface = VkSurface::new(self.instance.clone(), display_handle, window_handle)?;
+        if !surface.supports_queue_family(self.physical_device, self.queue_family_index) {
+            anyhow::bail!("Window surface does not support presentation from the renderer queue");
         }
 
-        // 2. Recreate swapchain
-        let old_format = self.swapchain_wrapper.get_surface_format();
-        unsafe {
-            self.swapchain_wrapper
-                .recreate(self.physical_device, new_extent, self.surface.clone())
-                .unwrap()
+        let extent = Extent2D {
+            width: window_size.0,
+            height: window_size.1,
         };
-        let new_format = self.swapchain_wrapper.get_surface_format();
-        if new_format != old_format {
-            unimplemented!("Swapchain returned the wrong format");
+        let swapchain_wrapper = SwapchainWrapper::new(
+            self.device.clone(),
+            self.physical_device,
+            extent,
+            surface.clone(),
+            None,
+            self.config.present_config(),
+        )?;
+        if swapchain_wrapper.get_surface_format() != self.render_pass.get_surface_format() {
+            anyhow::bail!("Window surface format {:?} differs from render pass format {:?}",
+                swapchain_wrapper.get_surface_format(), self.render_pass.get_surface_format());
         }
 
-        // 3. Recreate swapchain_dependent resources
-        self.render_pass_resources = self.render_pass.create_render_pass_resources(
-            self.swapchain_wrapper.get_image_views(),
-            self.swapchain_wrapper.get_extent(),
+        let target = PresentTarget::Window {
+            surface,
+            window_handle,
+            display_handle,
+            swapchain: swapchain_wrapper,
+        };
+        self.add_surface_with_id(target, window_size, id)?;
+        info!("Window surface {:?} added", id);
+        Ok(())
+    }
+
+    /// Create a surface without a window, rendering to offscreen images of `size`, see `new_headless`
+    pub fn add_headless(&mut self, size: (u32, u32)) -> anyhow::Result<SurfaceId> {
+        let id = SurfaceId(self.next_surface_id);
+        self.add_headless_with_id(size, id)?;
+        self.next_surface_id += 1;
+        Ok(id)
+    }
+
+    fn add_headless_with_id(&mut self, size: (u32, u32), id: SurfaceId) -> anyhow::Result<()> {
+        let extent = Extent2D {
+            width: size.0,
+            height: size.1,
+        };
+        let target = PresentTarget::Headless(HeadlessTarget::new(self.device.clone(), &mut self.resource_manager, extent,
+                                                                 self.render_pass.get_surface_format(),
+                                                                 self.config.in_flight_frames.count()));
+        self.add_surface_with_id(target, size, id)?;
+        info!("Headless surface {:?} added", id);
+        Ok(())
+    }
+
+    fn add_surface_with_id(&mut self, target: PresentTarget, window_size: (u32, u32), id: SurfaceId) -> anyhow::Result<()> {
+        let mut surface_context = SurfaceContext::new(
+            self.device.clone(),
+            target,
+            window_size,
+            &self.render_pass,
             &mut self.resource_manager,
-        );
+            &self.command_pool,
+            self.pipeline_cache.clone(),
+            self.config.in_flight_frames.count(),
+            self.config.frame_timeout_ns(),
+            self.timestamp_period,
+            self.config.group_draws_by_pipeline,
+        )?;
+        if let Some(shader) = post_process::post_process_shader(&self.config, self.render_pass.get_surface_format()) {
+            surface_context.set_post_process(&self.render_pass, &mut self.resource_manager,
+                                             self.pipeline_cache.get_pipeline_cache(), Some(shader))?;
+        }
+        self.surfaces.insert(id, surface_context);
+        Ok(())
     }
 
-    pub fn render(&mut self, draw_state_diff: &mut impl CollectDrawStateUpdates, clear_color: [f32; 3]) -> anyhow::Result<()> {
-        let g = range_event_start!("[Vulkan] render");
-        let frame_index = self.cur_command_buffer;
-        self.cur_command_buffer = (frame_index + 1) % self.command_buffers.len();
-        let cur_fence = self.fences[frame_index];
-        let cur_command_buffer = self.command_buffers[frame_index];
-
-        // 1) Acquire next image
-        let (image_index, is_suboptimal) = unsafe {
-            let g = range_event_start!("[Vulkan] Wait for fences...");
-            self.device
-                .wait_for_fences(&[cur_fence], true, u64::MAX)
-                .unwrap();
-            drop(g);
-            self.device.reset_fences(&[cur_fence]).unwrap();
-
-
-            let g = range_event_start!("[Vulkan] Acquire next image...");
-            let res = self
-                .swapchain_wrapper
-                .swapchain_loader
-                .acquire_next_image(
-                    self.swapchain_wrapper.get_swapchain(),
-                    u64::MAX,
-                    self.image_available_semaphores[frame_index],
-                    vk::Fence::null(),
-                )
-                .expect("Failed to acquire next image.");
-
-            instant_event!("[Vulkan] New frame!");
-            res
+    /// Destroy window surface and all objects drawn to it. Must be called before window is destroyed.
+    pub fn remove_window(&mut self, id: SurfaceId) {
+        if self.released_windows.remove(&id).is_some() {
+            info!("Released window surface {:?} removed", id);
+            return;
+        }
+        let Some(surface_context) = self.surfaces.remove(&id) else {
+            warn!("Window surface {:?} does not exist", id);
+            return;
         };
-        if is_suboptimal {
-            warn!("Swapchain is suboptimal!");
+        self.wait_idle();
+        surface_context.destroy(&mut self.resource_manager, &self.command_pool);
+        info!("Window surface {:?} removed", id);
+    }
+
+    fn surface_mut(&mut self, id: SurfaceId) -> anyhow::Result<&mut SurfaceContext> {
+        self.surfaces.get_mut(&id).ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))
+    }
+
+    pub fn recreate_resize(&mut self, id: SurfaceId, new_extent: (u32, u32)) -> anyhow::Result<()> {
+        if let Some(released) = self.released_windows.get_mut(&id) {
+            // swapchain will be created with this size on resume
+            released.window_size = new_extent;
+            return Ok(());
         }
+        self.wait_idle();
+        let surface_context = self.surfaces.get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))?;
+        surface_context.recreate_resize(self.physical_device, &self.render_pass, &mut self.resource_manager, new_extent)
+    }
 
-        // 2) Update
-        let g = range_event_start!("[Vulkan] Update draw collect_state");
+    /// Draw and present a frame to the window surface. Each surface keeps its own objects, so
+    /// `draw_state_diff` must always be the same state for a given surface.
+    ///
+    /// Returns `RenderResult::Idle` without touching the state while renderer is paused.
+    pub fn render(&mut self, id: SurfaceId, draw_state_diff: &mut impl CollectDrawStateUpdates, clear_behavior: ClearBehavior) -> anyhow::Result<RenderResult> {
+        if self.paused {
+            return Ok(RenderResult::Idle);
+        }
+        let surface_context = self.surfaces.get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))?;
+        let res = surface_context.render(self.physical_device, &self.queue, &self.render_pass,
+                                         &mut self.resource_manager, draw_state_diff, clear_behavior);
+        if let Err(e) = &res {
+            if matches!(e.downcast_ref::<VulkanError>(), Some(VulkanError::Timeout)) {
+                self.gpu_hung = true;
+            }
+        }
+        res?;
+        Ok(RenderResult::Rendered)
+    }
 
-        // let uniform_state = draw_state_diff.collect_uniform_states();
-        self.object_resource_pool.update_objects(&mut self.resource_manager, draw_state_diff, &self.render_pass);
-        draw_state_diff.clear_updates();
-        drop(g);
+    /// Queue used by renderer, for submitting other work to it from any thread. See `QueueSubmitter`
+    /// for the synchronization contract
+    pub fn queue_submitter(&self) -> QueueSubmitter {
+        self.queue.clone()
+    }
 
-        // 3) record command buffer (if index was changed)
-        let image_index = image_index as usize;
-        if self.command_buffer_last_index[frame_index] != Some(image_index) {
-            self.record_draw(cur_command_buffer, image_index, clear_color);
-            self.command_buffer_last_index[frame_index] = Some(image_index);
-        };
+    /// Draw egui output on top of the scene of the window, starting from the next `render` call.
+    ///
+    /// Output stays on screen until replaced, pass empty primitives to remove it.
+    #[cfg(feature = "egui")]
+    pub fn set_egui_output(&mut self, id: SurfaceId, output: egui_renderer::EguiOutput) -> anyhow::Result<()> {
+        let surface_context = self.surfaces.get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))?;
+        surface_context.set_egui_output(output);
+        Ok(())
+    }
 
-        let g = range_event_start!("[Vulkan] Submit command buffer");
-        // 3.1) submit command buffer
-        let wait_semaphores = [self.image_available_semaphores[frame_index]];
-        let wait_dst_stage_mask = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
-        let command_buffers = [cur_command_buffer];
-        let signal_semaphores = [self.render_finished_semaphores[frame_index]];
-        let submit_infos = [vk::SubmitInfo::default()
-            .wait_semaphores(&wait_semaphores)
-            .wait_dst_stage_mask(&wait_dst_stage_mask)
-            .command_buffers(&command_buffers)
-            .signal_semaphores(&signal_semaphores)];
-        unsafe {
-            self.device
-                .queue_submit(self.queue, &submit_infos, self.fences[frame_index])
-                .unwrap();
-        }
-        drop(g);
-
-        // 4) present
-        let g = range_event_start!("[Vulkan] Queue present");
-        let swapchains = [self.swapchain_wrapper.get_swapchain()];
-        let semaphores = [self.render_finished_semaphores[frame_index]];
-        let image_indices = [image_index as u32];
-        let present_info = vk::PresentInfoKHR::default()
-            .swapchains(&swapchains)
-            .image_indices(&image_indices)
-            .wait_semaphores(&semaphores);
-
-        unsafe {
-            match self
-                .swapchain_wrapper
-                .swapchain_loader
-                .queue_present(self.queue, &present_info)
-            {
-                Ok(is_suboptimal) => {
-                    if is_suboptimal {
-                        warn!("swapchain suboptimal!");
-                    }
-                }
-                Err(e) => {
-                    error!("queue_present: {}", e);
-                }
+    /// Stop rendering and wait until GPU finishes all submitted work.
+    ///
+    /// With `release_surfaces`, window surfaces are destroyed together with their swapchains and objects,
+    /// to save memory in background or because the platform destroys the native window (Android).
+    /// Surface ids are kept, `resume` creates surfaces again, after which the draw state of every window
+    /// must be reset with `CollectDrawStateUpdates::reset_updates`.
+    pub fn pause(&mut self, release_surfaces: bool) -> anyhow::Result<()> {
+        let g = range_event_start!("[Vulkan] Pause");
+        self.paused = true;
+        self.queue.wait_idle().map_err(vk_error)?;
+
+        if release_surfaces {
+            // headless surfaces have no native window to lose and are kept
+            let window_ids: Vec<_> = self.surfaces.iter()
+                .filter(|(_, s)| s.window_handles().is_some())
+                .map(|(id, _)| *id)
+                .collect();
+            for id in window_ids {
+                let surface_context = self.surfaces.remove(&id).unwrap();
+                let (window_handle, display_handle) = surface_context.window_handles().unwrap();
+                self.released_windows.insert(id, ReleasedWindow {
+                    window_handle,
+                    display_handle,
+                    window_size: surface_context.window_size,
+                    viewport_rect: surface_context.viewport_rect,
+                });
+                surface_context.destroy(&mut self.resource_manager, &self.command_pool);
             }
+            info!("Renderer paused, window surfaces released");
+        }
+        else {
+            info!("Renderer paused");
         }
         Ok(())
     }
 
-    fn record_draw(&mut self, command_buffer: CommandBuffer, image_index: usize, clear_color: [f32; 3]) {
-        let device = &self.device;
-        let framebuffer = self.render_pass_resources.framebuffers[image_index];
-        let extent = self.swapchain_wrapper.get_extent();
+    /// Update native window of a surface released by `pause`, e.g. when Android recreates the window on resume
+    pub fn set_released_window_handle(&mut self, id: SurfaceId, window_handle: RawWindowHandle,
+                                      display_handle: RawDisplayHandle, window_size: (u32, u32)) -> anyhow::Result<()> {
+        let released = self.released_windows.get_mut(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} was not released", id))?;
+        released.window_handle = window_handle;
+        released.display_handle = display_handle;
+        released.window_size = window_size;
+        Ok(())
+    }
 
-        let g = range_event_start!("[Vulkan] Command buffer recording");
-        let command_buffer_begin_info = CommandBufferBeginInfo::default();
-        let clear_color = [clear_color[0], clear_color[1], clear_color[2], 1.0];
-        let clear_values = [
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: clear_color,
-                },
-            },
-            vk::ClearValue {
-                depth_stencil: vk::ClearDepthStencilValue {
-                    depth: 1.0,
-                    stencil: 0,
-                },
-            },
-            vk::ClearValue {
-                color: vk::ClearColorValue {
-                    float32: clear_color,
-                },
-            },
-        ];
-        let render_pass_begin_info = RenderPassBeginInfo::default()
-            .render_pass(*self.render_pass.get_render_pass())
-            .framebuffer(framebuffer)
-            .render_area(extent.into())
-            .clear_values(&clear_values);
-
-        let viewport = vk::Viewport::default()
-            .width(extent.width as f32)
-            .height(extent.height as f32);
-        let scissors = extent.into();
-        unsafe {
-            device
-                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
-                .unwrap();
-            device.cmd_begin_render_pass(
-                command_buffer,
-                &render_pass_begin_info,
-                vk::SubpassContents::INLINE,
-            );
-
-            //bind dynamic states
-            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
-            device.cmd_set_scissor(command_buffer, 0, &[scissors]);
-
-            // draw object states
-            self.object_resource_pool.record_draw_commands(command_buffer);
-
-            device.cmd_end_render_pass(command_buffer);
-            device.end_command_buffer(command_buffer).unwrap();
+    /// Continue rendering, creating window surfaces released by `pause` again
+    pub fn resume(&mut self) -> anyhow::Result<()> {
+        let g = range_event_start!("[Vulkan] Resume");
+        for (id, released) in std::mem::take(&mut self.released_windows) {
+            self.add_window_with_id(released.window_handle, released.display_handle, released.window_size, id)?;
+            self.surface_mut(id)?.viewport_rect = released.viewport_rect;
         }
+        self.paused = false;
+        info!("Renderer resumed");
+        Ok(())
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Switch between FIFO (vsync) and preferred present modes of the config, recreating swapchains of all windows
+    pub fn set_vsync(&mut self, vsync: bool) -> anyhow::Result<()> {
+        if self.config.vsync == vsync {
+            return Ok(());
+        }
+        let g = range_event_start!("[Vulkan] Set vsync");
+        self.config.vsync = vsync;
+        // all frames are finished, so no fence waits on a frame from the old swapchain
+        self.wait_idle();
+        for surface_context in self.surfaces.values_mut() {
+            surface_context.set_vsync(self.physical_device, &self.render_pass, &mut self.resource_manager, vsync)?;
+        }
+        info!("Vsync {}", if vsync { "enabled" } else { "disabled" });
+        Ok(())
+    }
+
+    /// Render the scene of every window into an offscreen image, then draw it to the swapchain image through
+    /// a full-screen triangle with the given SPIR-V fragment shader, e.g. for bloom or vignette. `None` disables
+    /// post-processing. egui is drawn as part of the scene and is post-processed too.
+    ///
+    /// See `PostProcess` for the shader interface. Command buffers are recorded every frame while enabled,
+    /// to update time.
+    ///
+    /// Replaces the built-in sRGB encoding pass used for `OutputColorSpace::Linear` with a UNORM surface,
+    /// so the shader must encode its output in this case.
+    pub fn set_post_process(&mut self, fragment_shader: Option<&[u8]>) -> anyhow::Result<()> {
+        let g = range_event_start!("[Vulkan] Set post-process");
+        self.wait_idle();
+        self.config.post_process_shader = fragment_shader.map(|shader| shader.to_vec());
+        let shader = post_process::post_process_shader(&self.config, self.render_pass.get_surface_format());
+        for surface_context in self.surfaces.values_mut() {
+            surface_context.set_post_process(&self.render_pass, &mut self.resource_manager,
+                                             self.pipeline_cache.get_pipeline_cache(), shader)?;
+        }
+        Ok(())
+    }
+
+    /// Color space shaders and clear colors must write in with the selected surface format.
+    ///
+    /// Differs from `VulkanRenderConfig::output_color_space` only when the preferred UNORM format is not
+    /// supported and an sRGB one is used instead.
+    pub fn color_space(&self) -> OutputColorSpace {
+        if is_srgb_format(self.render_pass.get_surface_format()) {
+            OutputColorSpace::Linear
+        } else {
+            self.config.output_color_space
+        }
+    }
+
+    /// Constrain rendering to a sub-region of the window. `None` renders to the full extent.
+    ///
+    /// Only this region is cleared, contents outside of it are undefined. If region no longer fits
+    /// after resize, it is clipped to the new extent.
+    pub fn set_viewport_rect(&mut self, id: SurfaceId, rect: Option<vk::Rect2D>) -> anyhow::Result<()> {
+        let surface_context = self.surface_mut(id)?;
+        if let Some(rect) = rect {
+            let extent = surface_context.get_extent();
+            if rect.offset.x < 0 || rect.offset.y < 0 {
+                anyhow::bail!("Viewport rect {:?} has a negative offset", rect);
+            }
+            // offsets are non-negative, sums can still overflow with huge sizes
+            let right = (rect.offset.x as u32).checked_add(rect.extent.width);
+            let bottom = (rect.offset.y as u32).checked_add(rect.extent.height);
+            let fits = right.is_some_and(|right| right <= extent.width)
+                && bottom.is_some_and(|bottom| bottom <= extent.height);
+            if !fits {
+                anyhow::bail!("Viewport rect {:?} is outside of swapchain extent {:?}", rect, extent);
+            }
+        }
+        if surface_context.viewport_rect != rect {
+            surface_context.viewport_rect = rect;
+            surface_context.invalidate_command_buffers();
+        }
+        Ok(())
+    }
+
+    /// Duration of the most recent finished frame of the window on GPU in milliseconds.
+    ///
+    /// `None` if timestamp queries are not supported or no frame has finished yet.
+    pub fn last_gpu_frame_time(&self, id: SurfaceId) -> Option<f32> {
+        self.surfaces.get(&id).and_then(|s| s.last_gpu_frame_time)
+    }
+
+    /// Durations of named GPU scopes for the most recent finished frame of the window in milliseconds
+    pub fn last_gpu_scopes(&self, id: SurfaceId) -> &[(String, f32)] {
+        self.surfaces.get(&id).map(|s| s.last_gpu_scopes.as_slice()).unwrap_or(&[])
+    }
+
+    /// CPU and GPU timings of recent frames of the window
+    pub fn frame_history(&self, id: SurfaceId) -> Option<&FrameHistory> {
+        self.surfaces.get(&id).map(|s| &s.frame_history)
+    }
+
+    /// Objects of the window existing in renderer, including hidden ones
+    pub fn object_count(&self, id: SurfaceId) -> Option<usize> {
+        self.surfaces.get(&id).map(|s| s.object_count())
+    }
+
+    /// Images of the window which are still loaded by the loader thread, objects sample a transparent placeholder
+    /// in place of them. Loaded images are uploaded at the start of `render`, so this drops to zero only after it
+    pub fn loading_image_count(&self, id: SurfaceId) -> Option<usize> {
+        self.surfaces.get(&id).map(|s| s.loading_image_count())
+    }
+
+    /// Draws and pipeline binds of the last recorded frame of the window. Command buffers of unchanged
+    /// frames are reused without recording
+    pub fn draw_stats(&self, id: SurfaceId) -> Option<DrawStats> {
+        self.surfaces.get(&id).map(|s| s.draw_stats())
+    }
+
+    /// Bytes written to attribute, uniform and indirect args buffers since the backend was created, e.g. to
+    /// check that updates of single fields don't upload whole structs
+    pub fn buffer_upload_bytes(&self) -> u64 {
+        self.resource_manager.buffer_upload_bytes()
+    }
+
+    /// Current contents of a uniform buffer of the window, including updates not submitted yet. `None` if the
+    /// surface or the buffer does not exist.
+    ///
+    /// Slow and synchronous, see `ResourceManager::read_buffer`. Meant for debugging and tests
+    pub fn read_uniform_buffer(&mut self, id: SurfaceId, uniform_id: UniformResourceId) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(buffer) = self.surfaces.get(&id).and_then(|s| s.uniform_buffer(uniform_id)) else {
+            return Ok(None);
+        };
+        self.resource_manager.read_buffer(buffer, 0, buffer.size as usize).map(Some)
+    }
+
+    /// Descriptor sets allocated for objects of the window
+    pub fn descriptor_pool_stats(&self, id: SurfaceId) -> Option<DescriptorPoolStats> {
+        self.surfaces.get(&id).map(|s| s.descriptor_pool_stats())
+    }
+
+    /// Number of errors reported by validation layers and the driver so far, 0 with an external instance
+    pub fn validation_error_count(&self) -> usize {
+        self.debug_utils.as_ref().map_or(0, VkDebugUtils::error_count)
+    }
+
+    /// Number of frames of the window submitted so far. Frames are numbered from 1
+    pub fn submitted_frames(&self, id: SurfaceId) -> Option<u64> {
+        self.surfaces.get(&id).map(|s| s.frame_sync().submitted_frames())
+    }
+
+    /// Number of the last frame of the window such that it and all frames before it are finished on GPU.
+    ///
+    /// Costs a single query with timeline semaphores (`VulkanRenderConfig::timeline_semaphores`),
+    /// otherwise a fence status query per frame in flight
+    pub fn completed_frames(&self, id: SurfaceId) -> anyhow::Result<u64> {
+        let surface_context = self.surfaces.get(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))?;
+        surface_context.frame_sync().completed_frames().map_err(vk_error)
+    }
+
+    /// Block until frame number `frame` of the window is finished on GPU, at most for
+    /// `VulkanRenderConfig::frame_timeout`. Returns immediately for frames which were not submitted yet
+    pub fn wait_for_frame(&self, id: SurfaceId, frame: u64) -> anyhow::Result<()> {
+        let surface_context = self.surfaces.get(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))?;
+        surface_context.frame_sync().wait_for_frame(frame, self.config.frame_timeout_ns()).map_err(vk_error)
+    }
+
+    /// RGBA8 texels of the last frame rendered to a headless surface, row by row, and their extent.
+    ///
+    /// Slow and synchronous: waits until GPU finishes the frame. Meant for tests, e.g. with `util::golden`
+    pub fn read_frame(&mut self, id: SurfaceId) -> anyhow::Result<(Vec<u8>, Extent2D)> {
+        let surface_context = self.surfaces.get(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))?;
+        let texels = surface_context.read_frame(&mut self.resource_manager, None)?;
+        Ok((texels, surface_context.get_extent()))
+    }
+
+    /// Like `read_frame`, for `VulkanRenderConfig::extra_color_attachments[index]` of the last frame.
+    /// Attachment format must be a 4-byte RGBA or BGRA one, without MSAA
+    pub fn read_extra_color_attachment(&mut self, id: SurfaceId, index: usize) -> anyhow::Result<(Vec<u8>, Extent2D)> {
+        let surface_context = self.surfaces.get(&id)
+            .ok_or_else(|| anyhow::anyhow!("Window surface {:?} does not exist", id))?;
+        let texels = surface_context.read_frame(&mut self.resource_manager, Some(index))?;
+        Ok((texels, surface_context.get_extent()))
+    }
+
+    /// Destroy all vulkan objects and initialize renderer from scratch for the same windows.
+    ///
+    /// Used to recover after `VulkanError::DeviceLost`. Surface ids are preserved. All resources are lost,
+    /// so the draw state of every window must be reset with `CollectDrawStateUpdates::reset_updates`
+    /// before the next `render` call.
+    ///
+    /// Not supported for renderer created with `from_existing`, application owns the lost device.
+    pub fn reinitialize(self) -> anyhow::Result<Self> {
+        let g = range_event_start!("[Vulkan] Reinitialize");
+        if self.external {
+            anyhow::bail!("Renderer with external device can't be reinitialized");
+        }
+        let config = self.config.clone();
+        let mut windows: Vec<_> = self.surfaces.iter()
+            .map(|(id, s)| (*id, s.window_handles(), s.window_size, s.viewport_rect))
+            .chain(self.released_windows.iter()
+                .map(|(id, w)| (*id, Some((w.window_handle, w.display_handle)), w.window_size, w.viewport_rect)))
+            .collect();
+        windows.sort_by_key(|w| w.0);
+        let next_surface_id = self.next_surface_id;
+        // old surfaces must be destroyed before new ones are created for the same windows
+        drop(self);
+
+        let Some(&(first_id, window, window_size, _)) = windows.first() else {
+            anyhow::bail!("Renderer has no windows to reinitialize for");
+        };
+        let mut backend = Self::init(window, window_size, config, first_id)?;
+        for &(id, window, window_size, _) in &windows[1..] {
+            match window {
+                Some((window_handle, display_handle)) => backend.add_window_with_id(window_handle, display_handle, window_size, id)?,
+                None => backend.add_headless_with_id(window_size, id)?,
+            }
+        }
+        for &(id, _, _, viewport_rect) in &windows {
+            backend.surface_mut(id)?.viewport_rect = viewport_rect;
+        }
+        backend.next_surface_id = next_surface_id;
+        Ok(backend)
     }
 
     fn wait_idle(&self) {
+        if self.gpu_hung {
+            // vkDeviceWaitIdle has no timeout and would block forever on a hung driver
+            warn!("GPU timed out before, not waiting for device idle");
+            return;
+        }
         let start = std::time::Instant::now();
-        unsafe {
-            self.device.device_wait_idle().unwrap();
+        // device can be lost already, resources can still be destroyed in this case
+        if let Err(e) = self.queue.wait_device_idle() {
+            error!("device_wait_idle: {}", e);
         }
         let end = std::time::Instant::now();
         debug!("Waited for idle for {:?}", end - start);
@@ -470,26 +979,20 @@ impl VulkanBackend {
 impl Drop for VulkanBackend {
     fn drop(&mut self) {
         info!("vulkan: drop");
-        self.wait_idle();
-        unsafe {
-            self.render_pass_resources
-                .destroy(&mut self.resource_manager);
-        }
-
-        for semaphore in self.image_available_semaphores {
-            unsafe {
-                self.device.destroy_semaphore(semaphore, None);
-            }
+        if self.gpu_hung {
+            // transfers submitted with the hung frame would be waited for when buffers are destroyed
+            self.resource_manager.abandon_pending();
         }
-        for semaphore in self.render_finished_semaphores {
-            unsafe {
-                self.device.destroy_semaphore(semaphore, None);
-            }
+        self.wait_idle();
+        for (_, surface_context) in std::mem::take(&mut self.surfaces) {
+            surface_context.destroy(&mut self.resource_manager, &self.command_pool);
         }
-        for fence in self.fences {
-            unsafe {
-                self.device.destroy_fence(fence, None);
-            }
+        if self.gpu_hung {
+            // objects are destroyed without waiting, so windows can get new swapchains, but destroying the
+            // device itself may block in the driver. Instance is kept alive for it
+            warn!("Leaking Vulkan device after GPU timeout");
+            std::mem::forget(self.device.clone());
+            std::mem::forget(self.instance.clone());
         }
     }
 }
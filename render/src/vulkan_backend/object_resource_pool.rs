@@ -1,51 +1,169 @@
 use std::any::TypeId;
-use std::collections::{btree_map, BTreeMap};
+use std::cmp::Reverse;
+use std::collections::{btree_map, BTreeMap, BTreeSet};
 use std::collections::btree_map::Entry;
-use std::path::Path;
 use ash::vk;
-use ash::vk::{BufferUsageFlags, DeviceSize, Extent2D, ImageTiling, ImageView, PipelineBindPoint, PrimitiveTopology, SampleCountFlags};
-use log::info;
+use ash::vk::{BufferUsageFlags, DeviceSize, Extent2D, ImageTiling, ImageView, PipelineBindPoint, PrimitiveTopology, SampleCountFlags,
+              ShaderStageFlags};
+use log::{error, info, warn};
 use smallvec::SmallVec;
 use render_core::collect_state::{CollectDrawStateUpdates, GraphicsUpdateCmd};
 use render_core::collect_state::buffer_updates::BufferUpdateData;
-use render_core::object_handles::{ObjectId, UniformResourceId};
+use render_core::collect_state::object_updates::ObjectBatchCreate;
+use render_core::pipeline::{PipelineDescWrapper, UniformBindingType, UniformBindingsDesc};
+use render_core::object_handles::{ObjectId, UniformResourceId, MISSING_TEXTURE_ID};
 use render_core::{BufferUpdateCmd, ObjectUpdate2DCmd, UniformBufferCmd};
 use render_core::collect_state::uniform_updates::ImageCmd;
-use crate::util::get_resource;
-use crate::util::image::read_image_from_bytes;
-use crate::vulkan_backend::descriptor_sets::{DescriptorSetPool, ObjectDescriptorSet};
+use crate::util::ktx2::Ktx2Texture;
+use crate::vulkan_backend::bindless::TextureTable;
+use crate::vulkan_backend::descriptor_sets::{DescriptorPoolStats, DescriptorSetPool, ObjectDescriptorSet};
 use crate::vulkan_backend::pipeline::{VulkanPipeline};
 use crate::vulkan_backend::render_pass::RenderPassWrapper;
-use crate::vulkan_backend::resource_manager::{BufferResource, ResourceManager};
+use crate::vulkan_backend::render_target::RenderTarget;
+use crate::vulkan_backend::texture_loader::{LoadedImage, TextureLoader};
+use crate::vulkan_backend::resource_manager::{BufferResource, ImageResource, ResourceManager};
+use crate::vulkan_backend::wrappers::capabilities_checker::IndirectDraws;
 use crate::vulkan_backend::wrappers::device::VkDeviceRef;
-use crate::vulkan_backend::wrappers::image::imageview_info_for_image;
+use crate::vulkan_backend::wrappers::image::{imageview_info_for_array, imageview_info_for_image};
+use crate::vulkan_backend::wrappers::pipeline_cache::VkPipelineCacheRef;
 
 /// Represented by a single instance attrib buffer and fixed draw count number
 pub struct ObjectDrawState {
+    /// Can be shared with other objects created in the same batch
     vertex_buffer_per_ins: BufferResource,
+    /// Offset of object attributes in the vertex buffer
+    vertex_buffer_offset: DeviceSize,
+    /// Uniform buffers bound to vertex bindings 1.., from `UniformBindingsDesc::vertex_buffers`
+    extra_vertex_buffers: SmallVec<[vk::Buffer; 2]>,
     vertex_count: usize,
     instance_count: usize,
     descriptor_set: ObjectDescriptorSet,
     pipeline_id: TypeId,
+    z_order: i32,
+    visible: bool,
+    subpass: u32,
+    /// Drawn into this render target instead of the main render pass
+    render_target: Option<UniformResourceId>,
+    /// Binding of the post subpass input, rewritten when render pass resources are recreated
+    input_attachment_binding: Option<u32>,
+    /// Drawn together with other objects of its batch from an indirect args buffer
+    indirect_batch: Option<u32>,
+    /// Present for objects of `PipelineDesc::BINDLESS_TEXTURES` pipelines
+    texture: Option<ObjectTexture>,
+    /// Clip rect in framebuffer pixels, `None` is the whole render area. Not applied to indirect batches,
+    /// which are drawn at once
+    scissor: Option<vk::Rect2D>,
+}
+
+/// Texture of an object, sampled through the texture array at set 1
+struct ObjectTexture {
+    image_id: UniformResourceId,
+    /// Pushed before the draw: slot in the texture table, or 0 of `set`
+    index: u32,
+    /// Set with the single texture, used without descriptor indexing
+    set: Option<ObjectDescriptorSet>,
+}
+
+impl ObjectDrawState {
+    /// Bind `attributes` at binding 0 and buffers of the other bindings from their start
+    unsafe fn bind_vertex_buffers(&self, device: &VkDeviceRef, command_buffer: vk::CommandBuffer,
+                                  attributes: vk::Buffer, offset: DeviceSize) {
+        let buffers: SmallVec<[vk::Buffer; 3]> = std::iter::once(attributes)
+            .chain(self.extra_vertex_buffers.iter().copied())
+            .collect();
+        let mut offsets: SmallVec<[DeviceSize; 3]> = smallvec::smallvec![0; buffers.len()];
+        offsets[0] = offset;
+        device.cmd_bind_vertex_buffers(command_buffer, 0, &buffers, &offsets);
+    }
+}
+
+/// Objects created in one `Object2DBatch`, drawn with indirect draws from a device-local args buffer.
+///
+/// Members share the pipeline, the attributes buffer and uniform bindings, so the batch is drawn with
+/// descriptor sets of any of them. Args are rewritten only when members are hidden, shown or destroyed.
+struct IndirectBatch {
+    /// Members in creation order, instance `i` of the attributes buffer is `ids[i]`
+    ids: Vec<ObjectId>,
+    vertex_buffer: BufferResource,
+    /// Size of attributes of a single object
+    stride: DeviceSize,
+    args_buffer: BufferResource,
+    /// Commands for visible members at the start of `args_buffer`
+    draw_count: u32,
+    dirty: bool,
+}
+
+/// Commands recorded for objects by the last recording of a frame
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DrawStats {
+    pub draws: u32,
+    /// Pipeline is bound only when it differs from the one of the previous draw
+    pub pipeline_binds: u32,
+    /// Included in `draws`, each may draw many objects
+    pub indirect_draws: u32,
+}
+
+impl DrawStats {
+    fn add(&mut self, other: DrawStats) {
+        self.draws += other.draws;
+        self.pipeline_binds += other.pipeline_binds;
+        self.indirect_draws += other.indirect_draws;
+    }
 }
 
 pub struct UniformImage {
+    pub image: ImageResource,
     pub image_view: ImageView,
     pub sampler: vk::Sampler,
     pub dev_ref: VkDeviceRef,
 }
 impl UniformImage {
-    pub fn new(image_data: Vec<u8>, extent: Extent2D, resource_manager: &mut ResourceManager, device: VkDeviceRef) -> Self {
+    pub fn new(image_data: Vec<u8>, extent: Extent2D, resource_manager: &mut ResourceManager, device: VkDeviceRef,
+               name: &str) -> anyhow::Result<Self> {
         let image = resource_manager.create_image(extent, vk::Format::R8G8B8A8_UNORM, ImageTiling::OPTIMAL,
                                                   vk::ImageUsageFlags::SAMPLED, SampleCountFlags::TYPE_1);
+        device.set_object_name(image.image, name);
+
+        resource_manager.fill_image(image, image_data.as_slice())?;
+        Ok(Self::from_image(image, resource_manager, device))
+    }
+
+    /// Compressed texture keeps its format, all levels from the file are uploaded
+    pub fn from_ktx2(texture: Ktx2Texture, resource_manager: &mut ResourceManager, device: VkDeviceRef,
+                     name: &str) -> anyhow::Result<Self> {
+        let image = resource_manager.create_texture_image(texture.extent, texture.format, texture.levels.len() as u32, 1);
+        device.set_object_name(image.image, name);
+
+        let levels: Vec<&[u8]> = texture.levels.iter().map(Vec::as_slice).collect();
+        resource_manager.fill_image_levels(image, &levels)?;
+        Ok(Self::from_image(image, resource_manager, device))
+    }
 
-        resource_manager.fill_image(image, image_data.as_slice());
+    /// RGBA8 layers one after another in `image_data`, viewed as a 2d array
+    pub fn new_array(image_data: Vec<u8>, extent: Extent2D, layers: u32, resource_manager: &mut ResourceManager,
+                     device: VkDeviceRef, name: &str) -> anyhow::Result<Self> {
+        let image = resource_manager.create_texture_image(extent, vk::Format::R8G8B8A8_UNORM, 1, layers);
+        device.set_object_name(image.image, name);
+        resource_manager.fill_image(image, image_data.as_slice())?;
 
+        let imageview_info = imageview_info_for_array(image.image, image.info, vk::ImageAspectFlags::COLOR);
+        let imageview = unsafe { device.create_image_view(&imageview_info, None) }.unwrap();
+        Ok(UniformImage {
+            image,
+            image_view: imageview,
+            sampler: resource_manager.create_sampler(),
+            dev_ref: device,
+        })
+    }
+
+    /// Sample an image created with SAMPLED usage
+    pub fn from_image(image: ImageResource, resource_manager: &mut ResourceManager, device: VkDeviceRef) -> Self {
         let imageview_info = imageview_info_for_image(image.image, image.info, vk::ImageAspectFlags::COLOR);
         let imageview = unsafe { device.create_image_view(&imageview_info, None) }.unwrap();
         let sampler = resource_manager.create_sampler();
 
         UniformImage {
+            image,
             image_view: imageview,
             sampler,
             dev_ref: device,
@@ -53,6 +171,31 @@ impl UniformImage {
     }
 }
 
+/// Built-in image with 2d and single layer array views, to match the type of the binding it replaces
+struct BuiltinImage {
+    image: UniformImage,
+    array: UniformImage,
+}
+
+impl BuiltinImage {
+    fn new(data: Vec<u8>, extent: Extent2D, resource_manager: &mut ResourceManager, device: VkDeviceRef,
+           name: &str) -> anyhow::Result<Self> {
+        Ok(Self {
+            array: UniformImage::new_array(data.clone(), extent, 1, resource_manager, device.clone(),
+                                           &format!("{} array", name))?,
+            image: UniformImage::new(data, extent, resource_manager, device, name)?,
+        })
+    }
+
+    fn get(&self, array: bool) -> &UniformImage {
+        if array {
+            &self.array
+        } else {
+            &self.image
+        }
+    }
+}
+
 impl Drop for UniformImage {
     fn drop(&mut self) {
         unsafe {
@@ -65,30 +208,196 @@ impl Drop for UniformImage {
 pub struct ObjectResourcePool {
     device: VkDeviceRef,
     descriptor_set_pool: DescriptorSetPool,
+    pipeline_cache: VkPipelineCacheRef,
 
-    pipelines: BTreeMap<TypeId, VulkanPipeline>,
+    /// Pipelines of render targets are created separately with their render pass
+    pipelines: BTreeMap<(TypeId, Option<UniformResourceId>), VulkanPipeline>,
     objects: BTreeMap<ObjectId, ObjectDrawState>,
+    /// Draw order: ascending z_order, then pipeline if `group_by_pipeline`, then descending id for determinism
+    draw_order: BTreeSet<(i32, Option<TypeId>, Reverse<ObjectId>)>,
+    group_by_pipeline: bool,
+    draw_stats: DrawStats,
+    /// Enabled on the device with `VulkanRenderConfig::indirect_batches`
+    indirect_draws: Option<IndirectDraws>,
+    indirect_batches: BTreeMap<u32, IndirectBatch>,
+    next_indirect_batch: u32,
+    /// Number of objects using each attributes buffer
+    vertex_buffer_users: BTreeMap<vk::Buffer, usize>,
     uniform_buffers: BTreeMap<UniformResourceId, BufferResource>,
     image_resources: BTreeMap<UniformResourceId, UniformImage>,
+    /// Images requested from `texture_loader`, with object bindings which use placeholder until loaded
+    loading_images: BTreeMap<UniformResourceId, Vec<(ObjectId, u32)>>,
+    /// Bound as `missing_texture`
+    failed_images: BTreeSet<UniformResourceId>,
+    /// Created with `ImageCmd::CreateArray`, replaced with array views of built-in images
+    array_images: BTreeSet<UniformResourceId>,
+    /// Transparent 1x1 image bound while the image is loading. Created with `missing_texture` on first update
+    loading_placeholder: Option<BuiltinImage>,
+    /// Checker image of `MISSING_TEXTURE_ID`
+    missing_texture: Option<BuiltinImage>,
+    /// Started with the first image request
+    texture_loader: Option<TextureLoader>,
+    /// Textures of bindless pipelines, present with descriptor indexing
+    texture_table: Option<TextureTable>,
+    /// Color images are in `image_resources` under the same id
+    render_targets: BTreeMap<UniformResourceId, RenderTarget>,
+    /// Color of subpass 0, read by post subpass objects
+    post_input_view: Option<ImageView>,
 }
 
 impl ObjectResourcePool {
-    pub fn new(device: VkDeviceRef) -> Self {
+    pub fn new(device: VkDeviceRef, pipeline_cache: VkPipelineCacheRef) -> Self {
         let descriptor_set_pool = DescriptorSetPool::new(device.clone());
+        let indirect_draws = device.indirect_draws();
+        let texture_table = device.descriptor_indexing_enabled().then(|| TextureTable::new(device.clone()));
         ObjectResourcePool {
             device,
             descriptor_set_pool,
+            pipeline_cache,
 
             objects: BTreeMap::new(),
+            draw_order: BTreeSet::new(),
+            group_by_pipeline: false,
+            draw_stats: DrawStats::default(),
+            indirect_draws,
+            indirect_batches: BTreeMap::new(),
+            next_indirect_batch: 0,
+            vertex_buffer_users: BTreeMap::new(),
             pipelines: BTreeMap::new(),
             uniform_buffers: BTreeMap::new(),
             image_resources: BTreeMap::new(),
+            loading_images: BTreeMap::new(),
+            failed_images: BTreeSet::new(),
+            array_images: BTreeSet::new(),
+            loading_placeholder: None,
+            missing_texture: None,
+            texture_loader: None,
+            texture_table,
+            render_targets: BTreeMap::new(),
+            post_input_view: None,
+        }
+    }
+
+    /// Order objects with equal z_order by pipeline, so it is bound once for all of them. Must be set before
+    /// objects are created
+    pub fn set_group_by_pipeline(&mut self, group_by_pipeline: bool) {
+        debug_assert!(self.objects.is_empty(), "Draw order is changed after objects were created");
+        self.group_by_pipeline = group_by_pipeline;
+    }
+
+    fn draw_order_key(&self, z_order: i32, pipeline_id: TypeId, id: ObjectId) -> (i32, Option<TypeId>, Reverse<ObjectId>) {
+        (z_order, self.group_by_pipeline.then_some(pipeline_id), Reverse(id))
+    }
+
+    pub fn draw_stats(&self) -> DrawStats {
+        self.draw_stats
+    }
+
+    pub fn uniform_buffer(&self, id: UniformResourceId) -> Option<BufferResource> {
+        self.uniform_buffers.get(&id).copied()
+    }
+
+    /// Objects existing in renderer, including hidden ones
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    /// Images not loaded or not uploaded yet, bound as the placeholder
+    pub fn loading_image_count(&self) -> usize {
+        self.loading_images.len()
+    }
+
+    pub fn descriptor_pool_stats(&self) -> DescriptorPoolStats {
+        self.descriptor_set_pool.stats()
+    }
+
+    /// Must be called after render pass resources are recreated, while objects are not in use by GPU
+    pub fn set_post_input(&mut self, image_view: Option<ImageView>) {
+        self.post_input_view = image_view;
+        let Some(image_view) = image_view else {
+            return;
+        };
+        for object in self.objects.values() {
+            if let Some(binding) = object.input_attachment_binding {
+                object.descriptor_set.write_input_attachment(binding, image_view);
+            }
+        }
+    }
+
+    fn create_builtin_images(&mut self, resource_manager: &mut ResourceManager) -> anyhow::Result<()> {
+        if self.missing_texture.is_some() {
+            return Ok(());
+        }
+        let extent = Extent2D { width: 1, height: 1 };
+        self.loading_placeholder = Some(BuiltinImage::new(vec![0; 4], extent, resource_manager, self.device.clone(),
+                                                          "Loading placeholder")?);
+        const MAGENTA: [u8; 4] = [255, 0, 255, 255];
+        const BLACK: [u8; 4] = [0, 0, 0, 255];
+        let checker = [MAGENTA, BLACK, BLACK, MAGENTA].concat();
+        self.missing_texture = Some(BuiltinImage::new(checker, Extent2D { width: 2, height: 2 }, resource_manager,
+                                                      self.device.clone(), "Missing texture")?);
+        Ok(())
+    }
+
+    /// Images finished loading and `upload_loaded_images` will rewrite descriptor sets
+    pub fn has_loaded_images(&mut self) -> bool {
+        self.texture_loader.as_mut().is_some_and(|loader| loader.has_ready())
+    }
+
+    /// Upload images decoded by the loader thread and bind them in place of the placeholder.
+    /// Descriptor sets must not be in use by GPU. Returns true if command buffers must be recorded again
+    pub fn upload_loaded_images(&mut self, resource_manager: &mut ResourceManager) -> anyhow::Result<bool> {
+        let Some(loader) = self.texture_loader.as_mut() else {
+            return Ok(false);
+        };
+        let loaded = loader.take_ready();
+        let changed = !loaded.is_empty();
+        for LoadedImage { id, name, result } in loaded {
+            let image = match result {
+                Ok(image) => image.upload(resource_manager, self.device.clone(), &name)?,
+                Err(e) => {
+                    error!("Failed to load image {} with id {}, using missing texture: {:?}", name, id, e);
+                    let missing_texture = self.missing_texture.as_ref().unwrap().get(self.array_images.contains(&id));
+                    for (object_id, binding) in self.loading_images.remove(&id).unwrap_or_default() {
+                        if let Some(object) = self.objects.get(&object_id) {
+                            object.descriptor_set.write_image(binding, missing_texture);
+                        }
+                    }
+                    self.rebind_bindless_texture(id, missing_texture);
+                    self.failed_images.insert(id);
+                    continue;
+                }
+            };
+            info!("Image resource with id {} is loaded", id);
+            for (object_id, binding) in self.loading_images.remove(&id).unwrap_or_default() {
+                if let Some(object) = self.objects.get(&object_id) {
+                    object.descriptor_set.write_image(binding, &image);
+                }
+            }
+            self.rebind_bindless_texture(id, &image);
+            self.image_resources.insert(id, image);
+        }
+        Ok(changed)
+    }
+
+    /// Replace the placeholder of a loaded or failed bindless texture, in its slot or in sets of its objects
+    fn rebind_bindless_texture(&self, id: UniformResourceId, image: &UniformImage) {
+        if let Some(texture_table) = &self.texture_table {
+            texture_table.update(id, image);
+        }
+        let object_sets = self.objects.values()
+            .filter_map(|object| object.texture.as_ref())
+            .filter(|texture| texture.image_id == id)
+            .filter_map(|texture| texture.set.as_ref());
+        for set in object_sets {
+            set.write_image(0, image);
         }
     }
 
     pub fn update_objects<'a>(&mut self, resource_manager: &mut ResourceManager,
                               draw_state_updates: &mut impl CollectDrawStateUpdates,
-                              render_pass: &RenderPassWrapper) {
+                              render_pass: &RenderPassWrapper) -> anyhow::Result<()> {
+        self.create_builtin_images(resource_manager)?;
         let updates_iter = draw_state_updates.collect_updates();
         for update_cmd in updates_iter {
             match update_cmd {
@@ -96,81 +405,135 @@ impl ObjectResourcePool {
                     ObjectUpdate2DCmd::Create {
                         pipeline_desc,
                         uniform_bindings_desc: uniform_bindings,
-                        initial_state
+                        initial_state,
+                        z_order,
+                        render_target,
+                        instance_count,
                     } => {
-                        let entry = self.objects.entry(id);
-                        let Entry::Vacant(entry) = entry else {
-                            panic!("Renderer update: object already exists");
-                        };
-                        let entry = entry.insert({
-                            info!("Creating new object with id: {}", id);
-                            let pipeline_desc = pipeline_desc();
-                            let pipeline_entry = self.pipelines.entry(pipeline_desc.id).or_insert_with(|| {
-                                info!("Creating new pipeline with id: {:?}, Desc: {:?}", pipeline_desc.id, &pipeline_desc);
-
-                                let pipeline_desc = pipeline_desc.clone();
-                                let pipeline = VulkanPipeline::new(
-                                    self.device.clone(),
-                                    render_pass,
-                                    pipeline_desc,
-                                );
-                                pipeline
-                            });
-
-                            let descriptor_set = ObjectDescriptorSet::new(self.device.clone(),
-                                                                          &mut self.descriptor_set_pool, pipeline_entry.get_descriptor_set_layout(),
-                                                                          uniform_bindings.buffer_bindings.iter().map(|(binding, buffer_id)| {
-                                                                              (*binding, *self.uniform_buffers.get(buffer_id).unwrap())
-                                                                          }),
-                                                                          uniform_bindings.image_bindings.iter().map(|(binding, image_id)| {
-                                                                              (*binding, self.image_resources.get(image_id).unwrap())
-                                                                          }));
-
-                            // create vertex buffer for per-instance attributes
-                            let vertex_data = initial_state.modified_bytes;
-                            let vertex_buffer_per_ins = resource_manager.create_buffer(
-                                vertex_data.len() as DeviceSize,
-                                BufferUsageFlags::VERTEX_BUFFER,
-                            );
-
-                            // for now, it is 1
-                            let instance_count = 1;
+                        info!("Creating new object with id: {}", id);
 
-                            ObjectDrawState {
-                                vertex_buffer_per_ins,
-                                vertex_count: instance_count * pipeline_desc.vertices_per_instance,
-                                instance_count,
-                                descriptor_set,
-                                pipeline_id: pipeline_desc.id,
-                            }
-                        });
+                        // create vertex buffer for per-instance attributes
+                        let vertex_data = initial_state.modified_bytes;
+                        let vertex_buffer_per_ins = resource_manager.create_buffer(
+                            vertex_data.len() as DeviceSize,
+                            BufferUsageFlags::VERTEX_BUFFER,
+                        );
+                        self.device.set_object_name(vertex_buffer_per_ins.buffer,
+                                                    format_args!("Object {} attributes", id));
+                        self.create_object(id, pipeline_desc(), &uniform_bindings, render_pass, render_target,
+                                           vertex_buffer_per_ins, 0, z_order, instance_count);
 
                         info!("Updating object with id: {}. State: {:?}", id, initial_state);
 
                         // update per-instance attributes
-                        let vertex_data = initial_state.modified_bytes;
-                        resource_manager.fill_buffer(entry.vertex_buffer_per_ins, &vertex_data, initial_state.buffer_offset);
+                        resource_manager.fill_buffer(vertex_buffer_per_ins, vertex_data, initial_state.buffer_offset)?;
                     }
                     ObjectUpdate2DCmd::AttribUpdate(buffer_update) => match buffer_update {
                         BufferUpdateCmd::Update(BufferUpdateData { modified_bytes, buffer_offset }) => {
                             info!("Updating object with id: {}.", id);
                             let entry = self.objects.get_mut(&id).expect("Renderer update: object does not exist");
-                            resource_manager.fill_buffer(entry.vertex_buffer_per_ins, &modified_bytes, buffer_offset);
+                            resource_manager.fill_buffer(entry.vertex_buffer_per_ins, modified_bytes,
+                                                         entry.vertex_buffer_offset as usize + buffer_offset)?;
                         }
                         _ => {
                             unimplemented!("Renderer update: object attrib update is not implemented");
                         }
                     }
+                    ObjectUpdate2DCmd::SetVisible(visible) => {
+                        let entry = self.objects.get_mut(&id).expect("Renderer update: object does not exist");
+                        if entry.visible != visible {
+                            if let Some(batch) = entry.indirect_batch {
+                                self.indirect_batches.get_mut(&batch).unwrap().dirty = true;
+                            }
+                        }
+                        entry.visible = visible;
+                    }
+                    ObjectUpdate2DCmd::SetInstanceCount(instance_count) => {
+                        let entry = self.objects.get_mut(&id).expect("Renderer update: object does not exist");
+                        entry.instance_count = instance_count as usize;
+                    }
+                    ObjectUpdate2DCmd::SetScissor(scissor) => {
+                        let entry = self.objects.get_mut(&id).expect("Renderer update: object does not exist");
+                        entry.scissor = scissor;
+                    }
                     ObjectUpdate2DCmd::Destroy => {
                         let entry = self.objects.remove(&id).expect("Renderer update: object does not exist");
                         info!("Destroying object with id: {}", id);
+                        let key = self.draw_order_key(entry.z_order, entry.pipeline_id, id);
+                        self.draw_order.remove(&key);
+
+                        if let Some(batch_id) = entry.indirect_batch {
+                            let batch = self.indirect_batches.get_mut(&batch_id).unwrap();
+                            batch.ids.retain(|member| *member != id);
+                            batch.dirty = true;
+                            if batch.ids.is_empty() {
+                                let batch = self.indirect_batches.remove(&batch_id).unwrap();
+                                resource_manager.destroy_buffer(batch.args_buffer);
+                            }
+                        }
                         
                         // destroy DescriptorSet
                         let descriptor_pool = &mut self.descriptor_set_pool;
                         entry.descriptor_set.destroy(descriptor_pool);
+                        if let Some(set) = entry.texture.and_then(|texture| texture.set) {
+                            set.destroy(descriptor_pool);
+                        }
                         
-                        // destroy attrib buffer
-                        resource_manager.destroy_buffer(entry.vertex_buffer_per_ins);
+                        // destroy attrib buffer when the last object of the batch is gone
+                        let users = self.vertex_buffer_users.get_mut(&entry.vertex_buffer_per_ins.buffer).unwrap();
+                        *users -= 1;
+                        if *users == 0 {
+                            self.vertex_buffer_users.remove(&entry.vertex_buffer_per_ins.buffer);
+                            resource_manager.destroy_buffer(entry.vertex_buffer_per_ins);
+                        }
+                    }
+                }
+                GraphicsUpdateCmd::Object2DBatch(ObjectBatchCreate {
+                    ids,
+                    pipeline_desc,
+                    uniform_bindings_desc: uniform_bindings,
+                    initial_state,
+                    z_order,
+                    render_target,
+                }) => {
+                    info!("Creating {} objects in batch", ids.len());
+                    let pipeline_desc = pipeline_desc();
+                    let stride = initial_state.len() / ids.len();
+
+                    // single buffer and single upload for the whole batch
+                    let vertex_buffer_per_ins = resource_manager.create_buffer(
+                        initial_state.len() as DeviceSize,
+                        BufferUsageFlags::VERTEX_BUFFER,
+                    );
+                    self.device.set_object_name(vertex_buffer_per_ins.buffer,
+                                                format_args!("Objects {}..={} attributes", ids[0], ids[ids.len() - 1]));
+                    for (i, id) in ids.iter().enumerate() {
+                        self.create_object(*id, pipeline_desc.clone(), &uniform_bindings, render_pass, render_target,
+                                           vertex_buffer_per_ins, (i * stride) as DeviceSize, z_order, 1);
+                    }
+                    resource_manager.fill_buffer(vertex_buffer_per_ins, &initial_state, 0)?;
+
+                    // instances of the commands select attributes, which are shared by instanced pipelines
+                    if self.indirect_draws.is_some() && ids.len() > 1 && !pipeline_desc.instanced {
+                        let args_buffer = resource_manager.create_buffer(
+                            (ids.len() * size_of::<vk::DrawIndirectCommand>()) as DeviceSize,
+                            BufferUsageFlags::INDIRECT_BUFFER,
+                        );
+                        self.device.set_object_name(args_buffer.buffer,
+                                                    format_args!("Objects {}..={} draw args", ids[0], ids[ids.len() - 1]));
+                        let batch_id = self.next_indirect_batch;
+                        self.next_indirect_batch += 1;
+                        for id in &ids {
+                            self.objects.get_mut(id).unwrap().indirect_batch = Some(batch_id);
+                        }
+                        self.indirect_batches.insert(batch_id, IndirectBatch {
+                            ids,
+                            vertex_buffer: vertex_buffer_per_ins,
+                            stride: stride as DeviceSize,
+                            args_buffer,
+                            draw_count: 0,
+                            dirty: true,
+                        });
                     }
                 }
                 GraphicsUpdateCmd::UniformBuffer(id, uniform_cmd) => match uniform_cmd {
@@ -181,26 +544,29 @@ impl ObjectResourcePool {
                         };
                         let entry = entry.insert({
                             info!("Creating new uniform buffer with id: {}", id);
+                            // can be bound to `PipelineDesc::VERTEX_BINDINGS` as well
                             let buffer = resource_manager.create_buffer(
                                 modified_bytes.len() as DeviceSize,
-                                BufferUsageFlags::UNIFORM_BUFFER,
+                                BufferUsageFlags::UNIFORM_BUFFER | BufferUsageFlags::VERTEX_BUFFER,
                             );
+                            self.device.set_object_name(buffer.buffer, format_args!("Uniform buffer {}", id));
                             buffer
                         });
                         info!("Updating uniform buffer with id: {}", id);
-                        resource_manager.fill_buffer(*entry, &modified_bytes, buffer_offset);
+                        resource_manager.fill_buffer(*entry, &modified_bytes, buffer_offset)?;
                     }
                     UniformBufferCmd::Update(buffer_update) => match buffer_update {
                         BufferUpdateCmd::Update(BufferUpdateData { modified_bytes, buffer_offset }) => {
                             // info!("Updating uniform buffer with id: {}.", id);
                             let entry = self.uniform_buffers.get(&id).expect("Renderer update: uniform buffer does not exist");
-                            resource_manager.fill_buffer(*entry, &modified_bytes, buffer_offset);
+                            resource_manager.fill_buffer(*entry, &modified_bytes, buffer_offset)?;
                         }
                         BufferUpdateCmd::Resize(new_size) => {
                             unimplemented!("Renderer update: uniform buffer resize is not implemented");
                         }
                         BufferUpdateCmd::Rearrange(copy_ops) => {
-                            unimplemented!("Renderer update: uniform buffer rearrange is not implemented");
+                            let entry = self.uniform_buffers.get(&id).expect("Renderer update: uniform buffer does not exist");
+                            resource_manager.copy_within_buffer(*entry, &copy_ops)?;
                         }
                     }
                     UniformBufferCmd::Destroy => {
@@ -209,17 +575,34 @@ impl ObjectResourcePool {
                 }
                 GraphicsUpdateCmd::Image(id, image_cmd) => match image_cmd {
                     ImageCmd::Create(path) => {
-                        let entry = self.image_resources.entry(id);
-                        let Entry::Vacant(entry) = entry else {
+                        if self.image_resources.contains_key(&id) || self.loading_images.contains_key(&id) {
+                            panic!("Renderer update: image resource already exists");
+                        }
+                        info!("Loading new image resource with id: {} from {}", id, path);
+                        self.texture_loader.get_or_insert_with(|| TextureLoader::new(resource_manager))
+                            .request(id, path);
+                        self.loading_images.insert(id, Vec::new());
+                    }
+                    ImageCmd::CreateArray(paths) => {
+                        if self.image_resources.contains_key(&id) || self.loading_images.contains_key(&id) {
+                            panic!("Renderer update: image resource already exists");
+                        }
+                        info!("Loading new texture array with id: {} from {} images", id, paths.len());
+                        self.texture_loader.get_or_insert_with(|| TextureLoader::new(resource_manager))
+                            .request_array(id, paths);
+                        self.array_images.insert(id);
+                        self.loading_images.insert(id, Vec::new());
+                    }
+                    ImageCmd::CreateRenderTarget { width, height } => {
+                        let Entry::Vacant(entry) = self.image_resources.entry(id) else {
                             panic!("Renderer update: image resource already exists");
                         };
-                        let entry = entry.insert({
-                            info!("Creating new image resource with id: {}", id);
-                            let data = get_resource(Path::join("resources".as_ref(), path)).unwrap();
-                            let (image_data, extent) = read_image_from_bytes(data).unwrap();
-                            info!("Image extent: {:?}", extent);
-                            UniformImage::new(image_data, extent, resource_manager, self.device.clone())
-                        });
+                        info!("Creating new render target with id: {}, extent: {}x{}", id, width, height);
+                        let (render_target, color) = RenderTarget::new(self.device.clone(), resource_manager,
+                                                                       Extent2D { width, height }, render_pass,
+                                                                       &format!("Render target {}", id));
+                        entry.insert(color);
+                        self.render_targets.insert(id, render_target);
                     }
                     ImageCmd::Destroy => {
                         unimplemented!("Renderer update: uniform resource destroy is not implemented");
@@ -227,23 +610,506 @@ impl ObjectResourcePool {
                 }
             }
         }
+        self.update_indirect_args(resource_manager)
     }
 
-    pub fn record_draw_commands(&mut self, command_buffer: vk::CommandBuffer) {
-        for (id, draw_state) in self.objects.iter_mut().rev() {
-            let pipeline = self.pipelines.get(&draw_state.pipeline_id).unwrap();
+    /// Rewrite args of batches whose members were hidden, shown or destroyed, with a draw per visible member
+    fn update_indirect_args(&mut self, resource_manager: &mut ResourceManager) -> anyhow::Result<()> {
+        for batch in self.indirect_batches.values_mut().filter(|batch| batch.dirty) {
+            batch.dirty = false;
+            let commands: Vec<_> = batch.ids.iter()
+                .map(|id| &self.objects[id])
+                .filter(|object| object.visible)
+                .map(|object| vk::DrawIndirectCommand {
+                    vertex_count: object.vertex_count as u32,
+                    instance_count: object.instance_count as u32,
+                    first_vertex: 0,
+                    first_instance: (object.vertex_buffer_offset / batch.stride) as u32,
+                })
+                .collect();
+            batch.draw_count = commands.len() as u32;
+            if !commands.is_empty() {
+                resource_manager.fill_buffer(batch.args_buffer, &commands, 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_object(&mut self, id: ObjectId, pipeline_desc: PipelineDescWrapper, uniform_bindings: &UniformBindingsDesc,
+                     render_pass: &RenderPassWrapper, render_target: Option<UniformResourceId>,
+                     vertex_buffer_per_ins: BufferResource, vertex_buffer_offset: DeviceSize, z_order: i32,
+                     instance_count: u32) {
+        let entry = self.objects.entry(id);
+        let Entry::Vacant(entry) = entry else {
+            panic!("Renderer update: object already exists");
+        };
+        let render_pass = match render_target {
+            Some(target_id) => {
+                assert_eq!(pipeline_desc.subpass, 0, "Pipeline {} of object {} drawn into render target must use subpass 0",
+                           pipeline_desc.name, id);
+                self.render_targets.get(&target_id).expect("Renderer update: render target does not exist").render_pass()
+            }
+            None => render_pass,
+        };
+        let pipeline_entry = self.pipelines.entry((pipeline_desc.id, render_target)).or_insert_with(|| {
+            info!("Creating new pipeline with id: {:?}, Desc: {:?}", pipeline_desc.id, &pipeline_desc);
+
+            let pipeline_desc = pipeline_desc.clone();
+            let pipeline = VulkanPipeline::new(
+                self.device.clone(),
+                render_pass,
+                pipeline_desc,
+                self.pipeline_cache.get_pipeline_cache(),
+            );
+            pipeline
+        });
+
+        let dynamic_range = |binding: u32| pipeline_desc.uniform_bindings.iter().find_map(|(b, ty)| match ty {
+            UniformBindingType::UniformBufferDynamic { range } if *b == binding => Some(*range),
+            _ => None,
+        });
+        let dynamic_binding_count = pipeline_desc.uniform_bindings.iter()
+            .filter(|(_, ty)| matches!(ty, UniformBindingType::UniformBufferDynamic { .. }))
+            .count();
+        assert_eq!(uniform_bindings.dynamic_offsets.len(), dynamic_binding_count,
+                   "Object {} of pipeline {} must have an offset for each dynamic uniform buffer binding",
+                   id, pipeline_desc.name);
+
+        let descriptor_set = ObjectDescriptorSet::new(self.device.clone(),
+                                                      &mut self.descriptor_set_pool, pipeline_entry.get_descriptor_set_layout(),
+                                                      uniform_bindings.buffer_bindings.iter().map(|(binding, buffer_id)| {
+                                                          (*binding, *self.uniform_buffers.get(buffer_id).unwrap(), dynamic_range(*binding))
+                                                      }),
+                                                      uniform_bindings.image_bindings.iter().map(|(binding, image_id)| {
+                                                          let image = match self.image_resources.get(image_id) {
+                                                              Some(image) => image,
+                                                              None if *image_id == MISSING_TEXTURE_ID || self.failed_images.contains(image_id) => {
+                                                                  self.missing_texture.as_ref().unwrap().get(self.array_images.contains(image_id))
+                                                              }
+                                                              None => {
+                                                                  // rebound in `upload_loaded_images`
+                                                                  self.loading_images.get_mut(image_id)
+                                                                      .expect("Renderer update: image resource does not exist")
+                                                                      .push((id, *binding));
+                                                                  self.loading_placeholder.as_ref().unwrap().get(self.array_images.contains(image_id))
+                                                              }
+                                                          };
+                                                          (*binding, image)
+                                                      }),
+                                                      uniform_bindings.dynamic_offsets.clone());
+
+        let extra_vertex_buffers = (1..pipeline_desc.attributes.binding_count()).map(|binding| {
+            let (_, buffer_id) = uniform_bindings.vertex_buffers.iter()
+                .find(|(b, _)| *b == binding)
+                .unwrap_or_else(|| panic!("Object {} of pipeline {} has no buffer for vertex binding {}",
+                                          id, pipeline_desc.name, binding));
+            self.uniform_buffers.get(buffer_id).expect("Renderer update: vertex buffer does not exist").buffer
+        }).collect();
+
+        let input_attachment_binding = pipeline_desc.uniform_bindings.iter()
+            .find(|(_, ty)| matches!(ty, UniformBindingType::InputAttachment))
+            .map(|(binding, _)| *binding);
+        if let (Some(binding), Some(image_view)) = (input_attachment_binding, self.post_input_view) {
+            descriptor_set.write_input_attachment(binding, image_view);
+        }
+
+        let texture = if pipeline_desc.bindless_textures {
+            let image_id = uniform_bindings.bindless_texture
+                .unwrap_or_else(|| panic!("Object {} of pipeline {} has no bindless texture", id, pipeline_desc.name));
+            assert!(!self.array_images.contains(&image_id), "Bindless texture {} of object {} is a texture array",
+                    image_id, id);
+            let image = match self.image_resources.get(&image_id) {
+                Some(image) => image,
+                None if image_id == MISSING_TEXTURE_ID || self.failed_images.contains(&image_id) => {
+                    &self.missing_texture.as_ref().unwrap().image
+                }
+                None => {
+                    // rebound in `upload_loaded_images`
+                    assert!(self.loading_images.contains_key(&image_id), "Renderer update: image resource does not exist");
+                    &self.loading_placeholder.as_ref().unwrap().image
+                }
+            };
+            Some(match &mut self.texture_table {
+                Some(texture_table) => ObjectTexture {
+                    image_id,
+                    index: texture_table.slot(image_id, image),
+                    set: None,
+                },
+                None => ObjectTexture {
+                    image_id,
+                    index: 0,
+                    set: Some(ObjectDescriptorSet::new(self.device.clone(), &mut self.descriptor_set_pool,
+                                                       pipeline_entry.get_texture_set_layout().unwrap(),
+                                                       std::iter::empty(), std::iter::once((0, image)),
+                                                       SmallVec::new())),
+                },
+            })
+        } else {
+            None
+        };
+
+        assert!(instance_count == 1 || pipeline_desc.instanced,
+                "Object {} of pipeline {} has {} instances, but the pipeline is not instanced",
+                id, pipeline_desc.name, instance_count);
+
+        entry.insert(ObjectDrawState {
+            vertex_buffer_per_ins,
+            vertex_buffer_offset,
+            extra_vertex_buffers,
+            vertex_count: pipeline_desc.vertices_per_instance,
+            instance_count: instance_count as usize,
+            descriptor_set,
+            pipeline_id: pipeline_desc.id,
+            z_order,
+            visible: true,
+            subpass: pipeline_desc.subpass,
+            render_target,
+            input_attachment_binding,
+            indirect_batch: None,
+            texture,
+            scissor: None,
+        });
+        *self.vertex_buffer_users.entry(vertex_buffer_per_ins.buffer).or_insert(0) += 1;
+        let key = self.draw_order_key(z_order, pipeline_desc.id, id);
+        self.draw_order.insert(key);
+    }
+
+    /// Color images of render targets, written by `record_render_targets`
+    pub fn render_target_images(&self) -> impl Iterator<Item = vk::Image> + '_ {
+        self.render_targets.keys().map(|id| self.image_resources[id].image.image)
+    }
+
+    /// Records a render pass for each render target with its objects. Must be called outside of a render pass,
+    /// before passes which sample the targets.
+    pub fn record_render_targets(&mut self, command_buffer: vk::CommandBuffer) {
+        self.draw_stats = DrawStats::default();
+        for (id, render_target) in &self.render_targets {
+            render_target.begin(command_buffer);
+            let stats = self.record_subpass_draw_commands(command_buffer, 0, Some(*id), render_target.extent().into());
+            self.draw_stats.add(stats);
+            render_target.end(command_buffer);
+        }
+    }
+
+    /// Draws objects of each subpass in order, advancing render pass to the next subpass between them.
+    /// `render_area` is the scissor of objects without their own, and it is left set after the last draw
+    pub fn record_draw_commands(&mut self, command_buffer: vk::CommandBuffer, subpass_count: u32,
+                                render_area: vk::Rect2D) {
+        for subpass in 0..subpass_count {
+            if subpass > 0 {
+                unsafe { self.device.cmd_next_subpass(command_buffer, vk::SubpassContents::INLINE) };
+            }
+            let stats = self.record_subpass_draw_commands(command_buffer, subpass, None, render_area);
+            self.draw_stats.add(stats);
+        }
+    }
+
+    /// Scissor is `render_area` before and after the call
+    fn record_subpass_draw_commands(&self, command_buffer: vk::CommandBuffer, subpass: u32,
+                                    render_target: Option<UniformResourceId>, render_area: vk::Rect2D) -> DrawStats {
+        let mut stats = DrawStats::default();
+        let mut bound_pipeline = None;
+        let mut current_scissor = render_area;
+        // batch is drawn in place of its first visible member in draw order
+        let mut drawn_batches = BTreeSet::new();
+        for (_, _, Reverse(id)) in &self.draw_order {
+            let draw_state = &self.objects[id];
+            if !draw_state.visible || draw_state.instance_count == 0 || draw_state.subpass != subpass
+                || draw_state.render_target != render_target {
+                continue;
+            }
+            let batch = draw_state.indirect_batch.map(|batch_id| (batch_id, &self.indirect_batches[&batch_id]));
+            if let Some((batch_id, _)) = batch {
+                if !drawn_batches.insert(batch_id) {
+                    continue;
+                }
+            }
+            let scissor = match draw_state.scissor {
+                Some(rect) if batch.is_none() => match intersect_rects(rect, render_area) {
+                    Some(scissor) => scissor,
+                    // clipped out entirely
+                    None => continue,
+                },
+                _ => render_area,
+            };
+            let pipeline = self.pipelines.get(&(draw_state.pipeline_id, render_target)).unwrap();
             unsafe {
-                self.device.cmd_bind_pipeline(
-                    command_buffer,
-                    PipelineBindPoint::GRAPHICS,
-                    pipeline.get_pipeline(),
-                );
-                self.device.cmd_bind_vertex_buffers(command_buffer, 0, &[draw_state.vertex_buffer_per_ins.buffer], &[0]);
+                if scissor != current_scissor {
+                    self.device.cmd_set_scissor(command_buffer, 0, &[scissor]);
+                    current_scissor = scissor;
+                }
+                if bound_pipeline != Some(draw_state.pipeline_id) {
+                    self.device.cmd_bind_pipeline(
+                        command_buffer,
+                        PipelineBindPoint::GRAPHICS,
+                        pipeline.get_pipeline(),
+                    );
+                    bound_pipeline = Some(draw_state.pipeline_id);
+                    stats.pipeline_binds += 1;
+                    // stays bound while objects of the pipeline rebind set 0
+                    if let (Some(texture_table), Some(_)) = (&self.texture_table, pipeline.get_texture_set_layout()) {
+                        self.device.cmd_bind_descriptor_sets(command_buffer, PipelineBindPoint::GRAPHICS,
+                                                             pipeline.get_pipeline_layout(), 1,
+                                                             &[texture_table.descriptor_set()], &[]);
+                    }
+                }
                 draw_state.descriptor_set.bind_sets(command_buffer, pipeline.get_pipeline_layout());
+                if let Some(texture) = &draw_state.texture {
+                    if let Some(set) = &texture.set {
+                        set.bind_sets_at(command_buffer, pipeline.get_pipeline_layout(), 1);
+                    }
+                    self.device.cmd_push_constants(command_buffer, pipeline.get_pipeline_layout(),
+                                                   ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT, 0,
+                                                   &texture.index.to_ne_bytes());
+                }
+                if let Some((_, batch)) = batch {
+                    // instances are addressed with first_instance of each command
+                    draw_state.bind_vertex_buffers(&self.device, command_buffer, batch.vertex_buffer.buffer, 0);
+                    let command_size = size_of::<vk::DrawIndirectCommand>() as u32;
+                    if self.indirect_draws == Some(IndirectDraws::Multi) {
+                        self.device.cmd_draw_indirect(command_buffer, batch.args_buffer.buffer, 0, batch.draw_count,
+                                                      command_size);
+                        stats.draws += 1;
+                        stats.indirect_draws += 1;
+                    } else {
+                        for i in 0..batch.draw_count {
+                            self.device.cmd_draw_indirect(command_buffer, batch.args_buffer.buffer,
+                                                          (i * command_size) as DeviceSize, 1, command_size);
+                        }
+                        stats.draws += batch.draw_count;
+                        stats.indirect_draws += batch.draw_count;
+                    }
+                    continue;
+                }
+                draw_state.bind_vertex_buffers(&self.device, command_buffer, draw_state.vertex_buffer_per_ins.buffer,
+                                               draw_state.vertex_buffer_offset);
                 //draw
                 self.device.cmd_draw(command_buffer, draw_state.vertex_count as u32,
                                      draw_state.instance_count as u32, 0, 0);
             }
+            stats.draws += 1;
+        }
+        if current_scissor != render_area {
+            unsafe { self.device.cmd_set_scissor(command_buffer, 0, &[render_area]) };
         }
+        stats
     }
-}
\ No newline at end of file
+}
+
+/// Update which destroys resources, which frames in flight may still use
+pub fn releases_resources(update: &GraphicsUpdateCmd) -> bool {
+    matches!(update, GraphicsUpdateCmd::Object2D(_, ObjectUpdate2DCmd::Destroy)
+        | GraphicsUpdateCmd::UniformBuffer(_, UniformBufferCmd::Destroy))
+}
+
+/// Update which changes draw commands, unlike writes to contents of existing buffers
+pub fn changes_draw_commands(update: &GraphicsUpdateCmd) -> bool {
+    !matches!(update,
+        GraphicsUpdateCmd::Object2D(_, ObjectUpdate2DCmd::AttribUpdate(BufferUpdateCmd::Update(_)))
+        | GraphicsUpdateCmd::UniformBuffer(_, UniformBufferCmd::Update(BufferUpdateCmd::Update(_) | BufferUpdateCmd::Rearrange(_))))
+}
+
+/// Overlap of two rects, `None` if it is empty
+fn intersect_rects(a: vk::Rect2D, b: vk::Rect2D) -> Option<vk::Rect2D> {
+    let x0 = a.offset.x.max(b.offset.x) as i64;
+    let y0 = a.offset.y.max(b.offset.y) as i64;
+    let x1 = (a.offset.x as i64 + a.extent.width as i64).min(b.offset.x as i64 + b.extent.width as i64);
+    let y1 = (a.offset.y as i64 + a.extent.height as i64).min(b.offset.y as i64 + b.extent.height as i64);
+    if x1 <= x0 || y1 <= y0 {
+        return None;
+    }
+    Some(vk::Rect2D {
+        offset: vk::Offset2D { x: x0 as i32, y: y0 as i32 },
+        extent: vk::Extent2D { width: (x1 - x0) as u32, height: (y1 - y0) as u32 },
+    })
+}
+
+impl ObjectResourcePool {
+    /// Destroy attribute buffers, uniform buffers and images, which are owned by ResourceManager.
+    ///
+    /// Must be called before drop, when GPU is not using them anymore.
+    pub fn release_resources(&mut self, resource_manager: &mut ResourceManager) {
+        for (_, draw_state) in self.objects.iter() {
+            let buffer = draw_state.vertex_buffer_per_ins;
+            if self.vertex_buffer_users.remove(&buffer.buffer).is_some() {
+                resource_manager.destroy_buffer(buffer);
+            }
+        }
+        for (_, batch) in std::mem::take(&mut self.indirect_batches) {
+            resource_manager.destroy_buffer(batch.args_buffer);
+        }
+        for (_, buffer) in std::mem::take(&mut self.uniform_buffers) {
+            resource_manager.destroy_buffer(buffer);
+        }
+        // framebuffers use color views of image resources
+        for (_, render_target) in std::mem::take(&mut self.render_targets) {
+            render_target.destroy(resource_manager);
+        }
+        self.loading_images.clear();
+        self.failed_images.clear();
+        self.array_images.clear();
+        let builtin = [self.loading_placeholder.take(), self.missing_texture.take()];
+        let builtin = builtin.into_iter().flatten().flat_map(|builtin| [builtin.image, builtin.array]);
+        for image in std::mem::take(&mut self.image_resources).into_values().chain(builtin) {
+            let image_resource = image.image;
+            // view is destroyed before the image
+            drop(image);
+            resource_manager.destroy_image(image_resource);
+        }
+    }
+}
+
+impl Drop for ObjectResourcePool {
+    fn drop(&mut self) {
+        // objects which were never destroyed by the scene
+        if cfg!(debug_assertions) && !self.objects.is_empty() {
+            let ids: Vec<_> = self.objects.keys().collect();
+            warn!("{} objects were not destroyed explicitly: {:?}", ids.len(), ids);
+        }
+        unsafe {
+            self.device.device_wait_idle().unwrap();
+        }
+
+        // order: descriptor sets, image views, pipelines with their layouts, then descriptor pool on field drop
+        for (_, draw_state) in std::mem::take(&mut self.objects) {
+            draw_state.descriptor_set.destroy(&mut self.descriptor_set_pool);
+            if let Some(set) = draw_state.texture.and_then(|texture| texture.set) {
+                set.destroy(&mut self.descriptor_set_pool);
+            }
+        }
+        self.draw_order.clear();
+        self.render_targets.clear();
+        self.image_resources.clear();
+        self.loading_placeholder = None;
+        self.missing_texture = None;
+        self.pipelines.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Range;
+    use render_core::collect_state::{CollectDrawStateUpdates, GraphicsUpdateCmd};
+    use render_core::collect_state::buffer_updates::BufferUpdateData;
+    use render_core::object_handles::get_new_uniform_id;
+    use render_core::{BufferUpdateCmd, UniformBufferCmd};
+    use crate::vulkan_backend::config::VulkanRenderConfig;
+    use crate::vulkan_backend::render_pass::ClearBehavior;
+    use crate::vulkan_backend::resource_manager::HostAccessPolicy;
+    use crate::vulkan_backend::surface_context::SurfaceId;
+    use crate::vulkan_backend::VulkanBackend;
+    use super::*;
+
+    /// Creates a uniform buffer from `data` and rearranges it with `copy_ops` in the same update
+    struct RearrangedBuffer {
+        id: UniformResourceId,
+        data: Vec<u8>,
+        copy_ops: Vec<(Range<usize>, usize)>,
+        uploaded: bool,
+    }
+
+    impl CollectDrawStateUpdates for RearrangedBuffer {
+        fn collect_updates(&self) -> impl Iterator<Item=GraphicsUpdateCmd<'_>> {
+            let updates = (!self.uploaded).then(|| [
+                GraphicsUpdateCmd::uniform_buffer_update(self.id, UniformBufferCmd::Create(BufferUpdateData {
+                    modified_bytes: &self.data,
+                    buffer_offset: 0,
+                })),
+                GraphicsUpdateCmd::uniform_buffer_update(self.id, UniformBufferCmd::Update(
+                    BufferUpdateCmd::Rearrange(self.copy_ops.clone()))),
+            ]);
+            updates.into_iter().flatten()
+        }
+
+        fn clear_updates(&mut self) {
+            self.uploaded = true;
+        }
+
+        fn reset_updates(&mut self) {
+            self.uploaded = false;
+        }
+    }
+
+    fn rearrange_and_read(force_staging: bool) {
+        let mut backend = VulkanBackend::new_headless((16, 16), VulkanRenderConfig::default()).unwrap();
+        if force_staging {
+            if let HostAccessPolicy::SingleBuffer(memory_type) = backend.resource_manager.host_access_policy {
+                // host visible memory is a valid device memory type as well
+                backend.resource_manager.host_access_policy = HostAccessPolicy::UseStaging {
+                    host_memory_type: memory_type,
+                    device_memory_type: memory_type,
+                };
+            }
+        }
+
+        let data: Vec<u8> = (0..64).collect();
+        // destination of the first copy overlaps source of the second one
+        let copy_ops = vec![(16..48, 0), (0..16, 48)];
+        let mut buffer = RearrangedBuffer {
+            id: get_new_uniform_id(),
+            data: data.clone(),
+            copy_ops,
+            uploaded: false,
+        };
+        backend.render(SurfaceId::MAIN, &mut buffer, ClearBehavior::Clear([0.0; 3])).unwrap();
+
+        let expected: Vec<u8> = [&data[16..48], &data[32..48], &data[0..16]].concat();
+        let read = backend.read_uniform_buffer(SurfaceId::MAIN, buffer.id).unwrap().unwrap();
+        assert_eq!(read, expected);
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn rearrange_reads_sources_before_writing() {
+        rearrange_and_read(false);
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn rearrange_through_staging_buffer() {
+        rearrange_and_read(true);
+    }
+
+    fn rect(x: i32, y: i32, width: u32, height: u32) -> vk::Rect2D {
+        vk::Rect2D {
+            offset: vk::Offset2D { x, y },
+            extent: vk::Extent2D { width, height },
+        }
+    }
+
+    #[test]
+    fn contained_rect_is_intersection() {
+        let inner = rect(10, 20, 30, 40);
+        assert_eq!(intersect_rects(rect(0, 0, 100, 100), inner), Some(inner));
+        assert_eq!(intersect_rects(inner, rect(0, 0, 100, 100)), Some(inner));
+    }
+
+    #[test]
+    fn partial_overlap() {
+        assert_eq!(intersect_rects(rect(0, 0, 10, 10), rect(5, 6, 10, 10)), Some(rect(5, 6, 5, 4)));
+    }
+
+    #[test]
+    fn negative_offset_is_clipped() {
+        assert_eq!(intersect_rects(rect(-10, -5, 20, 20), rect(0, 0, 100, 100)), Some(rect(0, 0, 10, 15)));
+    }
+
+    #[test]
+    fn touching_edges_do_not_intersect() {
+        assert_eq!(intersect_rects(rect(0, 0, 10, 10), rect(10, 0, 10, 10)), None);
+        assert_eq!(intersect_rects(rect(0, 0, 10, 10), rect(0, 10, 10, 10)), None);
+        assert_eq!(intersect_rects(rect(0, 0, 10, 10), rect(10, 10, 10, 10)), None);
+    }
+
+    #[test]
+    fn max_extents_do_not_overflow() {
+        let whole = rect(0, 0, u32::MAX, u32::MAX);
+        assert_eq!(intersect_rects(whole, whole), Some(whole));
+        assert_eq!(intersect_rects(whole, rect(5, 5, 10, 10)), Some(rect(5, 5, 10, 10)));
+        assert_eq!(intersect_rects(rect(-1, -1, u32::MAX, u32::MAX), whole),
+                   Some(rect(0, 0, u32::MAX - 1, u32::MAX - 1)));
+        assert_eq!(intersect_rects(rect(i32::MAX, 0, u32::MAX, 1), whole),
+                   Some(rect(i32::MAX, 0, i32::MAX as u32 + 1, 1)));
+    }
+}
@@ -0,0 +1,161 @@
+use std::ffi::CStr;
+use ash::{Entry, Instance};
+use ash::vk;
+use ash::vk::{PhysicalDevice, PhysicalDeviceType, SampleCountFlags};
+use log::{info, warn};
+use crate::vulkan_backend::config::{DepthFormat, DevicePreference};
+
+/// Highest Vulkan API version requested by default, see `VulkanRenderConfig::max_api_version`
+pub const MAX_API_VERSION: u32 = vk::API_VERSION_1_3;
+
+/// Highest instance version supported by the loader, limited by `max`. Never lower than 1.0
+pub(super) fn negotiate_api_version(entry: &Entry, max: u32) -> anyhow::Result<u32> {
+    // 1.0 loaders don't have vkEnumerateInstanceVersion
+    let supported = unsafe { entry.try_enumerate_instance_version() }?.unwrap_or(vk::API_VERSION_1_0);
+    Ok(supported.min(max).max(vk::API_VERSION_1_0))
+}
+
+/// "major.minor.patch" of a version encoded with `vk::make_api_version`
+pub fn format_api_version(version: u32) -> String {
+    format!("{}.{}.{}", vk::api_version_major(version), vk::api_version_minor(version), vk::api_version_patch(version))
+}
+
+/// Physical device as reported by `VulkanBackend::enumerate_devices`
+#[derive(Debug, Clone)]
+pub struct PhysicalDeviceDesc {
+    /// Index to be used with `DevicePreference::ByIndex`
+    pub index: usize,
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+}
+
+/// Capabilities of the device chosen by renderer
+#[derive(Debug, Clone)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub device_type: PhysicalDeviceType,
+    /// Vendor-specific encoding
+    pub driver_version: u32,
+    /// Supported by the device. Decode with `vk::api_version_major` and friends
+    pub api_version: u32,
+    /// Used by renderer: lower of the device version and the version instance was created with.
+    /// Features promoted to core up to this version are available without extensions
+    pub enabled_api_version: u32,
+    /// Highest sample count supported for both color and depth attachments
+    pub max_msaa_samples: u32,
+    /// Max width and height of a 2D image
+    pub max_texture_size: u32,
+    pub timestamps_supported: bool,
+    /// Frames are tracked with a timeline semaphore, see `VulkanRenderConfig::timeline_semaphores`
+    pub timeline_semaphores: bool,
+    /// Dynamic rendering is enabled on the device, see `VulkanRenderConfig::dynamic_rendering`
+    pub dynamic_rendering: bool,
+    pub anisotropy_supported: bool,
+    pub max_anisotropy: f32,
+}
+
+impl DeviceInfo {
+    pub(super) fn new(instance: &Instance, physical_device: PhysicalDevice, instance_api_version: u32,
+                      timestamps_supported: bool, timeline_semaphores: bool, dynamic_rendering: bool) -> DeviceInfo {
+        let properties = unsafe { instance.get_physical_device_properties(physical_device) };
+        let features = unsafe { instance.get_physical_device_features(physical_device) };
+        let limits = properties.limits;
+
+        let sample_counts = limits.framebuffer_color_sample_counts & limits.framebuffer_depth_sample_counts;
+        let max_msaa_samples = [
+            SampleCountFlags::TYPE_64, SampleCountFlags::TYPE_32, SampleCountFlags::TYPE_16,
+            SampleCountFlags::TYPE_8, SampleCountFlags::TYPE_4, SampleCountFlags::TYPE_2,
+        ].into_iter()
+            .find(|s| sample_counts.contains(*s))
+            .map(|s| s.as_raw())
+            .unwrap_or(1);
+
+        let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+        DeviceInfo {
+            name: name.to_string_lossy().into_owned(),
+            device_type: properties.device_type,
+            driver_version: properties.driver_version,
+            api_version: properties.api_version,
+            enabled_api_version: properties.api_version.min(instance_api_version),
+            max_msaa_samples,
+            max_texture_size: limits.max_image_dimension2_d,
+            timestamps_supported,
+            timeline_semaphores,
+            dynamic_rendering,
+            anisotropy_supported: features.sampler_anisotropy == vk::TRUE,
+            max_anisotropy: limits.max_sampler_anisotropy,
+        }
+    }
+}
+
+/// Preferred depth format if it can be used as attachment, otherwise the first supported fallback.
+///
+/// Formats with stencil fall back to each other first, D16 is always supported.
+pub(super) fn select_depth_format(instance: &Instance, physical_device: PhysicalDevice, preferred: DepthFormat) -> DepthFormat {
+    let fallbacks: &[DepthFormat] = match preferred {
+        DepthFormat::D16 => &[],
+        DepthFormat::D32 => &[DepthFormat::D32S8],
+        DepthFormat::D24S8 => &[DepthFormat::D32S8],
+        DepthFormat::D32S8 => &[DepthFormat::D24S8],
+    };
+    let supported = |format: &DepthFormat| {
+        let properties = unsafe { instance.get_physical_device_format_properties(physical_device, format.format()) };
+        properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::DEPTH_STENCIL_ATTACHMENT)
+    };
+
+    let chosen = std::iter::once(&preferred)
+        .chain(fallbacks)
+        .find(|f| supported(f))
+        .copied()
+        .unwrap_or(DepthFormat::D16);
+    if chosen != preferred {
+        warn!("Depth format {:?} is not supported, using {:?}", preferred, chosen);
+    }
+    chosen
+}
+
+pub(super) fn describe_physical_devices(instance: &Instance, physical_devices: &[PhysicalDevice]) -> Vec<PhysicalDeviceDesc> {
+    physical_devices.iter().enumerate().map(|(index, d)| {
+        let properties = unsafe { instance.get_physical_device_properties(*d) };
+        let name = unsafe { CStr::from_ptr(properties.device_name.as_ptr()) };
+        PhysicalDeviceDesc {
+            index,
+            name: name.to_string_lossy().into_owned(),
+            device_type: properties.device_type,
+        }
+    }).collect()
+}
+
+/// Pick physical device according to preference.
+///
+/// If preferred device is not found, falls back to `DevicePreference::HighPerformance`.
+pub(super) fn select_physical_device(instance: &Instance, physical_devices: &[PhysicalDevice],
+                                     preference: &DevicePreference) -> Option<PhysicalDevice> {
+    let descs = describe_physical_devices(instance, physical_devices);
+    for desc in &descs {
+        info!("Available device {}: {} ({:?})", desc.index, desc.name, desc.device_type);
+    }
+
+    let find_by_type = |types: &[PhysicalDeviceType]| {
+        types.iter().find_map(|ty| descs.iter().find(|d| d.device_type == *ty))
+    };
+    let high_performance = [PhysicalDeviceType::DISCRETE_GPU, PhysicalDeviceType::INTEGRATED_GPU, PhysicalDeviceType::CPU];
+
+    let preferred = match preference {
+        DevicePreference::HighPerformance => find_by_type(&high_performance),
+        DevicePreference::LowPower => find_by_type(&[PhysicalDeviceType::INTEGRATED_GPU,
+            PhysicalDeviceType::DISCRETE_GPU, PhysicalDeviceType::CPU]),
+        DevicePreference::ByName(name) => {
+            let name = name.to_lowercase();
+            descs.iter().find(|d| d.name.to_lowercase().contains(&name))
+        }
+        DevicePreference::ByIndex(index) => descs.get(*index),
+    };
+
+    let chosen = preferred.or_else(|| {
+        warn!("Device matching {:?} was not found, choosing by performance", preference);
+        find_by_type(&high_performance)
+    })?;
+    info!("Chosen device {}: {} ({:?})", chosen.index, chosen.name, chosen.device_type);
+    Some(physical_devices[chosen.index])
+}
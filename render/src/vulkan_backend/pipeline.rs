@@ -1,47 +1,80 @@
 use std::ffi::CStr;
 use ash::vk;
-use ash::vk::{ColorComponentFlags, CompareOp, CullModeFlags, DescriptorSetLayout, DescriptorSetLayoutBinding,
+use ash::vk::{ColorComponentFlags, CullModeFlags, DescriptorSetLayout, DescriptorSetLayoutBinding,
               DescriptorType, DynamicState, Format, GraphicsPipelineCreateInfo, Pipeline, PipelineCache,
-              PipelineCacheCreateInfo, PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo,
+              PipelineColorBlendAttachmentState, PipelineColorBlendStateCreateInfo,
               PipelineDepthStencilStateCreateInfo, PipelineDynamicStateCreateInfo, PipelineInputAssemblyStateCreateInfo,
               PipelineLayout, PipelineLayoutCreateInfo, PipelineMultisampleStateCreateInfo, PipelineRasterizationStateCreateInfo,
               PipelineShaderStageCreateInfo, PipelineVertexInputStateCreateInfo, PipelineViewportStateCreateInfo, PrimitiveTopology,
-              SampleCountFlags, ShaderModuleCreateInfo, ShaderStageFlags, VertexInputAttributeDescription, VertexInputBindingDescription, FALSE};
-use log::info;
+              SampleCountFlags, ShaderModuleCreateInfo, ShaderStageFlags, StencilOpState, VertexInputAttributeDescription, VertexInputBindingDescription, FALSE};
+use log::{info, warn};
 use smallvec::{smallvec, SmallVec};
 use sparkles_macro::range_event_start;
 use render_core::layout::MemberMeta;
 use render_core::layout::types::GlslTypeVariant;
-use render_core::pipeline::{PipelineDescWrapper, UniformBindingType, VertexAssembly};
-use crate::vulkan_backend::render_pass::RenderPassWrapper;
+use render_core::pipeline::{PipelineDescWrapper, SpecConstValue, UniformBindingType, VertexAssembly, BINDLESS_TEXTURE_COUNT_ID,
+                            SPIRV_HEADER_SIZE, SPIRV_MAGIC};
+use crate::vulkan_backend::bindless;
+use crate::vulkan_backend::render_pass::{RenderPassWrapper, MAX_COLOR_ATTACHMENTS};
 use crate::vulkan_backend::wrappers::device::VkDeviceRef;
 
 pub struct VulkanPipeline {
     device: VkDeviceRef,
     pipeline: Pipeline,
     pipeline_layout: PipelineLayout,
-    pipeline_cache: PipelineCache,
     descriptor_set_layout: DescriptorSetLayout,
+    /// Set 1 of `PipelineDesc::BINDLESS_TEXTURES` pipelines
+    texture_set_layout: Option<DescriptorSetLayout>,
 }
 
 impl VulkanPipeline {
     pub fn new(device: VkDeviceRef, render_pass: &RenderPassWrapper,
-               mut pipeline_desc: PipelineDescWrapper) -> VulkanPipeline {
+               pipeline_desc: PipelineDescWrapper, pipeline_cache: PipelineCache) -> VulkanPipeline {
+        let (vertex_shader, fragment_shader) = (pipeline_desc.vertex_shader, pipeline_desc.fragment_shader);
+        Self::with_shaders(device, render_pass, pipeline_desc, vertex_shader, fragment_shader, pipeline_cache)
+    }
+
+    /// Compile GLSL sources of both stages at runtime, shaders of `pipeline_desc` are ignored.
+    /// Compilation errors are returned with the compiler log, other failures panic as in `new`
+    #[cfg(feature = "runtime_shader_compile")]
+    pub fn from_glsl(device: VkDeviceRef, render_pass: &RenderPassWrapper, vertex_source: &str, fragment_source: &str,
+                     pipeline_desc: PipelineDescWrapper, pipeline_cache: PipelineCache) -> anyhow::Result<VulkanPipeline> {
+        use crate::vulkan_backend::shader_compiler::{compile_glsl, ShaderStage};
+
+        let vertex_shader = compile_glsl(vertex_source, ShaderStage::Vertex, pipeline_desc.name)?;
+        let fragment_shader = compile_glsl(fragment_source, ShaderStage::Fragment, pipeline_desc.name)?;
+        Ok(Self::with_shaders(device, render_pass, pipeline_desc, &vertex_shader, &fragment_shader, pipeline_cache))
+    }
+
+    fn with_shaders(device: VkDeviceRef, render_pass: &RenderPassWrapper, mut pipeline_desc: PipelineDescWrapper,
+                    vertex_shader: &[u8], fragment_shader: &[u8], pipeline_cache: PipelineCache) -> VulkanPipeline {
         let g = range_event_start!("Create pipeline");
 
+        assert!(pipeline_desc.subpass < render_pass.subpass_count(),
+                "Pipeline {} uses subpass {}, but render pass has {} subpasses",
+                pipeline_desc.name, pipeline_desc.subpass, render_pass.subpass_count());
+        #[cfg(feature = "shader_reflection")]
+        crate::vulkan_backend::shader_reflection::check_pipeline_desc(&pipeline_desc, vertex_shader, fragment_shader);
+
         // 1. Create layout
         let uniform_bindings_desc = pipeline_desc.uniform_bindings;
 
         let bindings_desc = uniform_bindings_desc.into_iter().map(|(binding, binding_type)| {
-            let descriptor_type = match binding_type {
-                UniformBindingType::UniformBuffer => DescriptorType::UNIFORM_BUFFER,
-                UniformBindingType::CombinedImageSampler => DescriptorType::COMBINED_IMAGE_SAMPLER,
+            let (descriptor_type, stage_flags) = match binding_type {
+                UniformBindingType::UniformBuffer => (DescriptorType::UNIFORM_BUFFER,
+                                                      ShaderStageFlags::FRAGMENT | ShaderStageFlags::VERTEX),
+                UniformBindingType::UniformBufferDynamic { .. } => (DescriptorType::UNIFORM_BUFFER_DYNAMIC,
+                                                                    ShaderStageFlags::FRAGMENT | ShaderStageFlags::VERTEX),
+                UniformBindingType::CombinedImageSampler => (DescriptorType::COMBINED_IMAGE_SAMPLER,
+                                                             ShaderStageFlags::FRAGMENT | ShaderStageFlags::VERTEX),
+                // input attachments are fragment only
+                UniformBindingType::InputAttachment => (DescriptorType::INPUT_ATTACHMENT, ShaderStageFlags::FRAGMENT),
             };
             DescriptorSetLayoutBinding::default()
                 .binding(binding)
                 .descriptor_count(1)
                 .descriptor_type(descriptor_type)
-                .stage_flags(ShaderStageFlags::FRAGMENT | ShaderStageFlags::VERTEX)
+                .stage_flags(stage_flags)
         }).collect::<Vec<_>>();
         info!("Descriptor set layout bindings: {:?}", bindings_desc);
         let descriptor_set_layout_info =
@@ -53,33 +86,53 @@ impl VulkanPipeline {
                 .unwrap()
         };
 
-        let set_layoutst = [descriptor_set_layout];
+        // texture index is pushed per object
+        let texture_set_layout = pipeline_desc.bindless_textures.then(|| bindless::texture_set_layout(&device));
+        let set_layouts: SmallVec<[_; 2]> = std::iter::once(descriptor_set_layout).chain(texture_set_layout).collect();
+        let push_constant_ranges: SmallVec<[_; 1]> = texture_set_layout.map(|_| vk::PushConstantRange::default()
+            .stage_flags(ShaderStageFlags::VERTEX | ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<u32>() as u32)).into_iter().collect();
         let pipeline_layout_info = PipelineLayoutCreateInfo::default()
-            .set_layouts(&set_layoutst);
+            .set_layouts(&set_layouts)
+            .push_constant_ranges(&push_constant_ranges);
         let pipeline_layout = unsafe { device.create_pipeline_layout(&pipeline_layout_info, None).unwrap() };
 
         // shaders
-        let vert_code = pipeline_desc.vertex_shader;
-        let vert_code: Vec<u32> = vert_code.chunks(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap())).collect();
+        let vert_code = spirv_words(vertex_shader)
+            .unwrap_or_else(|e| panic!("Vertex shader of pipeline {}: {}", pipeline_desc.name, e));
         let vertex_module = unsafe { device.create_shader_module(
             &ShaderModuleCreateInfo::default().code(&vert_code), None)
         }.unwrap();
 
-        let frag_code = pipeline_desc.fragment_shader;
-        let frag_code: Vec<u32> = frag_code.chunks(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap())).collect();
+        let frag_code = spirv_words(fragment_shader)
+            .unwrap_or_else(|e| panic!("Fragment shader of pipeline {}: {}", pipeline_desc.name, e));
         let frag_module = unsafe { device.create_shader_module(
             &ShaderModuleCreateInfo::default().code(&frag_code), None)
         }.unwrap();
 
+        // constants missing from a module are ignored, so both stages share them
+        let texture_count = pipeline_desc.bindless_textures
+            .then(|| (BINDLESS_TEXTURE_COUNT_ID, SpecConstValue::Uint(bindless::texture_count(&device))));
+        let specialization_constants: Vec<_> = pipeline_desc.specialization_constants.iter().copied()
+            .chain(texture_count)
+            .collect();
+        let (specialization_entries, specialization_data) = specialization_map(&specialization_constants);
+        let specialization_info = vk::SpecializationInfo::default()
+            .map_entries(&specialization_entries)
+            .data(&specialization_data);
+
         let main_name = unsafe { CStr::from_bytes_with_nul_unchecked(b"main\0") };
         let vert_stage = PipelineShaderStageCreateInfo::default()
             .stage(ShaderStageFlags::VERTEX)
             .module(vertex_module)
-            .name(main_name);
+            .name(main_name)
+            .specialization_info(&specialization_info);
         let frag_stage = PipelineShaderStageCreateInfo::default()
             .stage(ShaderStageFlags::FRAGMENT)
             .module(frag_module)
-            .name(main_name);
+            .name(main_name)
+            .specialization_info(&specialization_info);
 
         // pipeline parts
         let multisample_state = PipelineMultisampleStateCreateInfo::default()
@@ -98,9 +151,10 @@ impl VulkanPipeline {
             .viewport_count(1)
             .scissor_count(1);
 
-        // enable blending
-        let color_blend_attachment =
-            [PipelineColorBlendAttachmentState::default()
+        // enable blending for the main attachment, extra attachments may have formats without blending support.
+        // Post subpass writes only the main attachment
+        let color_blend_attachment: SmallVec<[_; MAX_COLOR_ATTACHMENTS]> =
+            std::iter::once(PipelineColorBlendAttachmentState::default()
                 .color_write_mask(ColorComponentFlags::RGBA)
                 .blend_enable(true)
                 .src_color_blend_factor(vk::BlendFactor::SRC_ALPHA)
@@ -109,20 +163,47 @@ impl VulkanPipeline {
                 .src_alpha_blend_factor(vk::BlendFactor::ONE)
                 .dst_alpha_blend_factor(vk::BlendFactor::ZERO)
                 .alpha_blend_op(vk::BlendOp::ADD)
-            ];
+            ).chain(render_pass.get_extra_color_formats().iter().map(|_| PipelineColorBlendAttachmentState::default()
+                .color_write_mask(ColorComponentFlags::RGBA)
+                .blend_enable(false)
+            )).take(render_pass.color_attachment_count(pipeline_desc.subpass)).collect();
         let color_blend = PipelineColorBlendStateCreateInfo::default()
             .attachments(&color_blend_attachment);
 
-        let depth_state = PipelineDepthStencilStateCreateInfo::default()
-            .depth_test_enable(true)
-            .depth_write_enable(true)
-            .depth_compare_op(CompareOp::LESS);
+        // post subpass has no depth attachment
+        let depth_enabled = pipeline_desc.subpass == 0;
+        let mut depth_state = PipelineDepthStencilStateCreateInfo::default()
+            .depth_test_enable(depth_enabled)
+            .depth_write_enable(depth_enabled)
+            .depth_compare_op(render_pass.get_depth_mode().compare_op());
+        match pipeline_desc.stencil {
+            Some(stencil) if render_pass.has_stencil() => {
+                let op_state = StencilOpState::default()
+                    .compare_op(stencil.compare_op)
+                    .pass_op(stencil.pass_op)
+                    .fail_op(stencil.fail_op)
+                    .depth_fail_op(stencil.depth_fail_op)
+                    .reference(stencil.reference)
+                    .compare_mask(stencil.compare_mask)
+                    .write_mask(stencil.write_mask);
+                depth_state = depth_state
+                    .stencil_test_enable(true)
+                    .front(op_state)
+                    .back(op_state);
+            }
+            Some(_) => warn!("Pipeline {} uses stencil test, but depth format {:?} has no stencil",
+                             pipeline_desc.name, render_pass.get_depth_format()),
+            None => {}
+        }
 
 
         let stages = [vert_stage, frag_stage];
-        let pipeline_create_info = GraphicsPipelineCreateInfo::default()
+        let color_formats = render_pass.color_formats();
+        let mut rendering_info = render_pass.pipeline_rendering_info(&color_formats);
+        let mut pipeline_create_info = GraphicsPipelineCreateInfo::default()
             .layout(pipeline_layout)
             .render_pass(*render_pass.get_render_pass())
+            .subpass(pipeline_desc.subpass)
             .dynamic_state(&dynamic_state)
             .multisample_state(&multisample_state)
 
@@ -133,12 +214,13 @@ impl VulkanPipeline {
             .color_blend_state(&color_blend)
             .viewport_state(&viewport_state)
             .depth_stencil_state(&depth_state);
-
-        let pipeline_cache = unsafe {
-            device.create_pipeline_cache(&PipelineCacheCreateInfo::default(), None).unwrap()
-        };
+        if render_pass.is_dynamic() {
+            pipeline_create_info = pipeline_create_info.push_next(&mut rendering_info);
+        }
 
         let pipeline = unsafe { device.create_graphics_pipelines(pipeline_cache, &[pipeline_create_info], None).unwrap()[0] };
+        device.set_object_name(pipeline, pipeline_desc.name);
+        device.set_object_name(pipeline_layout, format_args!("{} layout", pipeline_desc.name));
 
         //destroy shader modules
         unsafe { device.destroy_shader_module(vertex_module, None); }
@@ -149,8 +231,8 @@ impl VulkanPipeline {
             
             pipeline,
             pipeline_layout,
-            pipeline_cache,
             descriptor_set_layout,
+            texture_set_layout,
         }
     }
 
@@ -164,6 +246,9 @@ impl VulkanPipeline {
     pub fn get_descriptor_set_layout(&self) -> DescriptorSetLayout {
         self.descriptor_set_layout
     }
+    pub fn get_texture_set_layout(&self) -> Option<DescriptorSetLayout> {
+        self.texture_set_layout
+    }
 }
 
 fn get_assembly_create_info(assembly: &VertexAssembly) -> PipelineInputAssemblyStateCreateInfo {
@@ -186,9 +271,93 @@ impl Drop for VulkanPipeline {
         let g = range_event_start!("[Vulkan] Destroy pipeline");
         unsafe {
             self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            if let Some(texture_set_layout) = self.texture_set_layout {
+                self.device.destroy_descriptor_set_layout(texture_set_layout, None);
+            }
             self.device.destroy_pipeline_layout(self.pipeline_layout, None);
-            self.device.destroy_pipeline_cache(self.pipeline_cache, None);
             self.device.destroy_pipeline(self.pipeline, None);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Map entries and data of specialization constants, each value takes 4 bytes in order of `constants`
+pub(crate) fn specialization_map(constants: &[(u32, SpecConstValue)]) -> (Vec<vk::SpecializationMapEntry>, Vec<u8>) {
+    let data = constants.iter()
+        .flat_map(|(_, value)| value.to_bytes())
+        .collect();
+    let entries = constants.iter()
+        .enumerate()
+        .map(|(i, (constant_id, _))| vk::SpecializationMapEntry {
+            constant_id: *constant_id,
+            offset: i as u32 * 4,
+            size: 4,
+        })
+        .collect();
+    (entries, data)
+}
+
+/// Validate SPIR-V binary and convert it to words
+pub(crate) fn spirv_words(code: &[u8]) -> anyhow::Result<Vec<u32>> {
+    if !code.len().is_multiple_of(4) {
+        anyhow::bail!("SPIR-V size {} is not a multiple of 4", code.len());
+    }
+    if code.len() < SPIRV_HEADER_SIZE {
+        anyhow::bail!("SPIR-V size {} is smaller than its header", code.len());
+    }
+    let words: Vec<u32> = code.chunks_exact(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap())).collect();
+    if words[0] != SPIRV_MAGIC {
+        anyhow::bail!("not a SPIR-V binary, magic number is {:#010x}", words[0]);
+    }
+    Ok(words)
+}
+
+#[cfg(test)]
+mod tests {
+    use render_core::pipeline::{SpecConstValue, BINDLESS_TEXTURE_COUNT_ID};
+    use super::specialization_map;
+
+    fn entries(constants: &[(u32, SpecConstValue)]) -> Vec<(u32, u32, usize)> {
+        specialization_map(constants).0.iter()
+            .map(|entry| (entry.constant_id, entry.offset, entry.size))
+            .collect()
+    }
+
+    #[test]
+    fn entries_are_packed_in_order() {
+        let constants = [
+            (3, SpecConstValue::Uint(7)),
+            (0, SpecConstValue::Float(0.5)),
+            (BINDLESS_TEXTURE_COUNT_ID, SpecConstValue::Uint(16)),
+        ];
+        assert_eq!(entries(&constants), [(3, 0, 4), (0, 4, 4), (BINDLESS_TEXTURE_COUNT_ID, 8, 4)]);
+
+        let (_, data) = specialization_map(&constants);
+        assert_eq!(data.len(), 12);
+        assert_eq!(data[0..4], 7u32.to_ne_bytes());
+        assert_eq!(data[4..8], 0.5f32.to_ne_bytes());
+        assert_eq!(data[8..12], 16u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn bool_is_vk_bool32() {
+        let (_, data) = specialization_map(&[(0, SpecConstValue::Bool(true)), (1, SpecConstValue::Bool(false))]);
+        assert_eq!(data[0..4], 1u32.to_ne_bytes());
+        assert_eq!(data[4..8], 0u32.to_ne_bytes());
+    }
+
+    #[test]
+    fn variants_differ_only_in_data() {
+        let a = [(0, SpecConstValue::Int(-1)), (1, SpecConstValue::Bool(false))];
+        let b = [(0, SpecConstValue::Int(2)), (1, SpecConstValue::Bool(true))];
+        assert_eq!(entries(&a), entries(&b));
+        assert_ne!(specialization_map(&a).1, specialization_map(&b).1);
+        assert_eq!(specialization_map(&a).1[0..4], (-1i32).to_ne_bytes());
+    }
+
+    #[test]
+    fn no_constants() {
+        let (entries, data) = specialization_map(&[]);
+        assert!(entries.is_empty());
+        assert!(data.is_empty());
+    }
+}
@@ -0,0 +1,350 @@
+use std::time::Instant;
+use ash::vk;
+use ash::vk::{CommandBuffer, DescriptorSet, DescriptorSetLayout, DescriptorType, Extent2D, Framebuffer, ImageAspectFlags,
+              ImageTiling, ImageUsageFlags, ImageView, Pipeline, PipelineCache, PipelineLayout, RenderPass,
+              SampleCountFlags, Sampler, ShaderStageFlags};
+use sparkles_macro::range_event_start;
+use crate::vulkan_backend::config::{OutputColorSpace, VulkanRenderConfig};
+use crate::vulkan_backend::pipeline::spirv_words;
+use crate::vulkan_backend::render_pass::{ClearBehavior, DynamicColorTarget, RenderPassResources, RenderPassWrapper};
+use crate::vulkan_backend::resource_manager::{ImageResource, ResourceManager};
+use crate::vulkan_backend::swapchain_wrapper::is_srgb_format;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+use crate::vulkan_backend::wrappers::image::imageview_info_for_image;
+
+const VERTEX_SHADER: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/compiled/post_process_vert.spv"));
+
+/// Built-in post-process for linear output to a UNORM surface
+const SRGB_ENCODE_SHADER: &[u8] = include_bytes!(concat!(env!("CARGO_MANIFEST_DIR"), "/shaders/compiled/srgb_encode_frag.spv"));
+
+/// Post-process shader used by surfaces: the one from config, or sRGB encoding when linear output can't be
+/// encoded by the surface format
+pub fn post_process_shader(config: &VulkanRenderConfig, surface_format: vk::Format) -> Option<&[u8]> {
+    let needs_encoding = config.output_color_space == OutputColorSpace::Linear && !is_srgb_format(surface_format);
+    config.post_process_shader.as_deref()
+        .or(needs_encoding.then_some(SRGB_ENCODE_SHADER))
+}
+
+/// Scene is rendered into an offscreen image, then a full-screen triangle samples it with the user fragment shader
+/// and writes the swapchain image.
+///
+/// Fragment shader interface:
+/// - `layout(location = 0) in vec2 uv`, (0, 0) is the top left corner
+/// - `layout(set = 0, binding = 0) uniform sampler2D scene`
+/// - `layout(push_constant) uniform Params { vec2 resolution; float time; }`, resolution in pixels and
+///   time in seconds since the post-process was set
+/// - `layout(location = 0) out vec4 color`
+pub struct PostProcess {
+    device: VkDeviceRef,
+
+    /// Compatible with the main render passes, color is sampled afterwards
+    scene_render_pass: RenderPass,
+    scene_load_render_pass: RenderPass,
+    /// Writes the swapchain image
+    render_pass: RenderPass,
+
+    pipeline: Pipeline,
+    pipeline_layout: PipelineLayout,
+    descriptor_set_layout: DescriptorSetLayout,
+    descriptor_pool: vk::DescriptorPool,
+    descriptor_set: DescriptorSet,
+    sampler: Sampler,
+
+    // swapchain dependent
+    scene_image: Option<(ImageResource, ImageView)>,
+    /// Main render pass attachments with the scene image instead of the swapchain image, empty with dynamic rendering
+    scene_framebuffers: Vec<Framebuffer>,
+    framebuffers: Vec<Framebuffer>,
+
+    start: Instant,
+}
+
+impl PostProcess {
+    /// Swapchain dependent resources must be created with `create_resources` before the first frame
+    pub fn new(device: VkDeviceRef, render_pass: &RenderPassWrapper, pipeline_cache: PipelineCache,
+               fragment_shader: &[u8]) -> anyhow::Result<Self> {
+        let g = range_event_start!("Create post-process");
+        let fragment_code = spirv_words(fragment_shader).map_err(|e| anyhow::anyhow!("Post-process shader: {}", e))?;
+
+        let (scene_render_pass, scene_load_render_pass) = render_pass.create_sampled_render_passes();
+        let post_render_pass = Self::create_render_pass(&device, render_pass.get_surface_format());
+        device.set_object_name(post_render_pass, "Post-process render pass");
+
+        let bindings = [vk::DescriptorSetLayoutBinding::default()
+            .binding(0)
+            .descriptor_count(1)
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .stage_flags(ShaderStageFlags::FRAGMENT)];
+        let descriptor_set_layout = unsafe {
+            device.create_descriptor_set_layout(&vk::DescriptorSetLayoutCreateInfo::default().bindings(&bindings), None)
+        }.unwrap();
+
+        let push_constant_ranges = [vk::PushConstantRange::default()
+            .stage_flags(ShaderStageFlags::FRAGMENT)
+            .offset(0)
+            .size(size_of::<[f32; 3]>() as u32)];
+        let set_layouts = [descriptor_set_layout];
+        let pipeline_layout = unsafe {
+            device.create_pipeline_layout(&vk::PipelineLayoutCreateInfo::default()
+                .set_layouts(&set_layouts)
+                .push_constant_ranges(&push_constant_ranges), None)
+        }.unwrap();
+
+        let pool_sizes = [vk::DescriptorPoolSize::default()
+            .descriptor_count(1)
+            .ty(DescriptorType::COMBINED_IMAGE_SAMPLER)];
+        let descriptor_pool = unsafe {
+            device.create_descriptor_pool(&vk::DescriptorPoolCreateInfo::default()
+                .max_sets(1)
+                .pool_sizes(&pool_sizes), None)
+        }.unwrap();
+        let descriptor_set = unsafe {
+            device.allocate_descriptor_sets(&vk::DescriptorSetAllocateInfo::default()
+                .descriptor_pool(descriptor_pool)
+                .set_layouts(&set_layouts))
+        }.unwrap()[0];
+
+        // scene has the size of the screen, filtering matters only for distorted uv
+        let sampler_info = vk::SamplerCreateInfo::default()
+            .mag_filter(vk::Filter::LINEAR)
+            .min_filter(vk::Filter::LINEAR)
+            .address_mode_u(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_v(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .address_mode_w(vk::SamplerAddressMode::CLAMP_TO_EDGE)
+            .max_lod(0.0);
+        let sampler = unsafe { device.create_sampler(&sampler_info, None) }.unwrap();
+
+        let pipeline = Self::create_pipeline(&device, post_render_pass, pipeline_layout, pipeline_cache, &fragment_code);
+        device.set_object_name(pipeline, "Post-process");
+        drop(g);
+
+        Ok(Self {
+            device,
+
+            scene_render_pass,
+            scene_load_render_pass,
+            render_pass: post_render_pass,
+
+            pipeline,
+            pipeline_layout,
+            descriptor_set_layout,
+            descriptor_pool,
+            descriptor_set,
+            sampler,
+
+            scene_image: None,
+            scene_framebuffers: Vec::new(),
+            framebuffers: Vec::new(),
+
+            start: Instant::now(),
+        })
+    }
+
+    fn create_render_pass(device: &VkDeviceRef, surface_format: vk::Format) -> RenderPass {
+        // every pixel is written by the full-screen triangle
+        let attachments = [vk::AttachmentDescription::default()
+            .format(surface_format)
+            .samples(SampleCountFlags::TYPE_1)
+            .load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+            .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .initial_layout(vk::ImageLayout::UNDEFINED)
+            .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)];
+        let color_attachment_refs = [vk::AttachmentReference::default()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let subpasses = [vk::SubpassDescription::default()
+            .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)];
+        // swapchain image is acquired at this stage
+        let dependencies = [vk::SubpassDependency::default()
+            .src_subpass(vk::SUBPASS_EXTERNAL)
+            .dst_subpass(0)
+            .src_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_stage_mask(vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+            .dst_access_mask(vk::AccessFlags::COLOR_ATTACHMENT_WRITE)];
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
+    }
+
+    fn create_pipeline(device: &VkDeviceRef, render_pass: RenderPass, pipeline_layout: PipelineLayout,
+                       pipeline_cache: PipelineCache, fragment_code: &[u32]) -> Pipeline {
+        let create_module = |code: &[u32]| {
+            unsafe { device.create_shader_module(&vk::ShaderModuleCreateInfo::default().code(code), None) }.unwrap()
+        };
+        let vertex_code = spirv_words(VERTEX_SHADER).expect("Built-in post-process vertex shader");
+        let vertex_module = create_module(&vertex_code);
+        let frag_module = create_module(fragment_code);
+
+        let main_name = c"main";
+        let stages = [
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(ShaderStageFlags::VERTEX)
+                .module(vertex_module)
+                .name(main_name),
+            vk::PipelineShaderStageCreateInfo::default()
+                .stage(ShaderStageFlags::FRAGMENT)
+                .module(frag_module)
+                .name(main_name),
+        ];
+
+        // vertices are generated from gl_VertexIndex
+        let vertex_input = vk::PipelineVertexInputStateCreateInfo::default();
+        let input_assembly = vk::PipelineInputAssemblyStateCreateInfo::default()
+            .topology(vk::PrimitiveTopology::TRIANGLE_LIST);
+        let multisample_state = vk::PipelineMultisampleStateCreateInfo::default()
+            .rasterization_samples(SampleCountFlags::TYPE_1);
+        let dynamic_state = vk::PipelineDynamicStateCreateInfo::default()
+            .dynamic_states(&[vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR]);
+        let rast_info = vk::PipelineRasterizationStateCreateInfo::default()
+            .cull_mode(vk::CullModeFlags::NONE)
+            .line_width(1.0);
+        let viewport_state = vk::PipelineViewportStateCreateInfo::default()
+            .viewport_count(1)
+            .scissor_count(1);
+        let color_blend_attachment = [vk::PipelineColorBlendAttachmentState::default()
+            .color_write_mask(vk::ColorComponentFlags::RGBA)];
+        let color_blend = vk::PipelineColorBlendStateCreateInfo::default()
+            .attachments(&color_blend_attachment);
+        let depth_state = vk::PipelineDepthStencilStateCreateInfo::default();
+
+        let pipeline_create_info = vk::GraphicsPipelineCreateInfo::default()
+            .layout(pipeline_layout)
+            .render_pass(render_pass)
+            .subpass(0)
+            .dynamic_state(&dynamic_state)
+            .multisample_state(&multisample_state)
+            .vertex_input_state(&vertex_input)
+            .input_assembly_state(&input_assembly)
+            .stages(&stages)
+            .rasterization_state(&rast_info)
+            .color_blend_state(&color_blend)
+            .viewport_state(&viewport_state)
+            .depth_stencil_state(&depth_state);
+        let pipeline = unsafe { device.create_graphics_pipelines(pipeline_cache, &[pipeline_create_info], None).unwrap()[0] };
+
+        unsafe { device.destroy_shader_module(vertex_module, None); }
+        unsafe { device.destroy_shader_module(frag_module, None); }
+        pipeline
+    }
+
+    /// Create scene image and framebuffers for the swapchain. Device must be idle
+    pub fn create_resources(&mut self, resource_manager: &mut ResourceManager, render_pass: &RenderPassWrapper,
+                            render_pass_resources: &RenderPassResources, image_views: &[ImageView], extent: Extent2D) {
+        let image = resource_manager.create_image(extent, render_pass.get_surface_format(), ImageTiling::OPTIMAL,
+                                                  ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::SAMPLED,
+                                                  SampleCountFlags::TYPE_1);
+        self.device.set_object_name(image.image, "Post-process scene");
+        let info = imageview_info_for_image(image.image, image.info, ImageAspectFlags::COLOR);
+        let image_view = unsafe { self.device.create_image_view(&info, None).unwrap() };
+
+        let image_info = [vk::DescriptorImageInfo::default()
+            .image_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image_view(image_view)
+            .sampler(self.sampler)];
+        let write = vk::WriteDescriptorSet::default()
+            .dst_set(self.descriptor_set)
+            .dst_binding(0)
+            .descriptor_type(DescriptorType::COMBINED_IMAGE_SAMPLER)
+            .image_info(&image_info);
+        unsafe { self.device.update_descriptor_sets(&[write], &[]) };
+
+        let create_framebuffer = |render_pass: RenderPass, attachments: &[ImageView]| {
+            let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+                .render_pass(render_pass)
+                .attachments(attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            unsafe { self.device.create_framebuffer(&framebuffer_create_info, None).unwrap() }
+        };
+        if !render_pass.is_dynamic() {
+            self.scene_framebuffers = (0..image_views.len()).map(|i| {
+                create_framebuffer(self.scene_render_pass, &render_pass_resources.framebuffer_attachments(i, image_view))
+            }).collect();
+        }
+        self.framebuffers = image_views.iter().map(|view| create_framebuffer(self.render_pass, &[*view])).collect();
+        self.scene_image = Some((image, image_view));
+    }
+
+    /// Device must be idle
+    pub fn destroy_resources(&mut self, resource_manager: &mut ResourceManager) {
+        for framebuffer in self.scene_framebuffers.drain(..).chain(self.framebuffers.drain(..)) {
+            unsafe { self.device.destroy_framebuffer(framebuffer, None); }
+        }
+        if let Some((image, image_view)) = self.scene_image.take() {
+            unsafe { self.device.destroy_image_view(image_view, None); }
+            resource_manager.destroy_image(image);
+        }
+    }
+
+    /// Render pass to begin the scene with, instead of `RenderPassWrapper::get_render_pass_for`
+    pub fn scene_render_pass_for(&self, clear_behavior: ClearBehavior) -> RenderPass {
+        match clear_behavior {
+            ClearBehavior::Clear(_) => self.scene_render_pass,
+            ClearBehavior::Load => self.scene_load_render_pass,
+        }
+    }
+
+    pub fn scene_framebuffer(&self, image_index: usize) -> Framebuffer {
+        self.scene_framebuffers[image_index]
+    }
+
+    /// Scene image as the color target of the main pass with dynamic rendering
+    pub fn scene_target(&self) -> DynamicColorTarget {
+        let (image, view) = self.scene_image.as_ref().expect("Post-process resources are not created");
+        DynamicColorTarget {
+            image: image.image,
+            view: *view,
+            sampled: true,
+        }
+    }
+
+    /// Record the full-screen pass into the swapchain image, after the scene render pass has ended
+    pub fn record_draw_commands(&self, command_buffer: CommandBuffer, image_index: usize, extent: Extent2D) {
+        let render_area: vk::Rect2D = extent.into();
+        let render_pass_begin_info = vk::RenderPassBeginInfo::default()
+            .render_pass(self.render_pass)
+            .framebuffer(self.framebuffers[image_index])
+            .render_area(render_area);
+        let viewport = vk::Viewport::default()
+            .width(extent.width as f32)
+            .height(extent.height as f32)
+            .max_depth(1.0);
+        let params = [extent.width as f32, extent.height as f32, self.start.elapsed().as_secs_f32()];
+        let params_bytes: Vec<u8> = params.iter().flat_map(|v| v.to_ne_bytes()).collect();
+        unsafe {
+            self.device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+            self.device.cmd_bind_pipeline(command_buffer, vk::PipelineBindPoint::GRAPHICS, self.pipeline);
+            self.device.cmd_bind_descriptor_sets(command_buffer, vk::PipelineBindPoint::GRAPHICS,
+                                                 self.pipeline_layout, 0, &[self.descriptor_set], &[]);
+            self.device.cmd_push_constants(command_buffer, self.pipeline_layout, ShaderStageFlags::FRAGMENT, 0,
+                                           &params_bytes);
+            self.device.cmd_draw(command_buffer, 3, 1, 0, 0);
+            self.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    /// Device must be idle
+    pub fn destroy(mut self, resource_manager: &mut ResourceManager) {
+        self.destroy_resources(resource_manager);
+        unsafe {
+            self.device.destroy_pipeline(self.pipeline, None);
+            self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            self.device.destroy_descriptor_set_layout(self.descriptor_set_layout, None);
+            self.device.destroy_descriptor_pool(self.descriptor_pool, None);
+            self.device.destroy_sampler(self.sampler, None);
+            self.device.destroy_render_pass(self.scene_render_pass, None);
+            self.device.destroy_render_pass(self.scene_load_render_pass, None);
+            self.device.destroy_render_pass(self.render_pass, None);
+        }
+    }
+}
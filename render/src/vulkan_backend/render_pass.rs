@@ -1,17 +1,27 @@
 
 use ash::{vk};
-use ash::vk::{AccessFlags, AttachmentLoadOp, Extent2D, Format, Framebuffer, ImageAspectFlags, ImageTiling, ImageUsageFlags, ImageView, PipelineBindPoint, PipelineStageFlags, RenderPass, SampleCountFlags};
+use ash::vk::{AccessFlags, AttachmentLoadOp, CommandBuffer, Extent2D, Format, Framebuffer, Image, ImageAspectFlags, ImageLayout, ImageTiling, ImageUsageFlags, ImageView, PipelineBindPoint, PipelineStageFlags, RenderPass, SampleCountFlags};
+use log::warn;
+use smallvec::SmallVec;
 use sparkles_macro::range_event_start;
+use crate::vulkan_backend::config::DepthMode;
 use crate::vulkan_backend::wrappers::image::imageview_info_for_image;
 use crate::vulkan_backend::resource_manager::{ImageResource, ResourceManager};
 use crate::vulkan_backend::wrappers::device::VkDeviceRef;
 
+/// Max number of color attachments written by pipelines, including the main one
+pub const MAX_COLOR_ATTACHMENTS: usize = 4;
+
 // this one depends on swapchain
 pub struct RenderPassResources {
     device: VkDeviceRef,
     pub framebuffers: Vec<Framebuffer>,
 
     pub swapchain_image_set: Vec<SwapchainImageSet>,
+    /// Additional color attachments for each swapchain image
+    pub extra_color_targets: Vec<SmallVec<[(ImageResource, ImageView); MAX_COLOR_ATTACHMENTS - 1]>>,
+    /// Scene color written by the first subpass and read by the post subpass, shared by all framebuffers
+    pub post_input: Option<(ImageResource, ImageView)>,
 }
 
 pub enum SwapchainImageSet {
@@ -28,6 +38,21 @@ pub enum SwapchainImageSet {
 }
 
 impl RenderPassResources {
+    /// Attachments of the framebuffer for swapchain image `index`, with `color_view` as the final color attachment
+    pub fn framebuffer_attachments(&self, index: usize, color_view: ImageView) -> SmallVec<[ImageView; MAX_COLOR_ATTACHMENTS + 2]> {
+        let mut attachments: SmallVec<[ImageView; MAX_COLOR_ATTACHMENTS + 2]> = match &self.swapchain_image_set[index] {
+            SwapchainImageSet::NoMSAA { depth_imageview, .. } => {
+                [color_view, *depth_imageview].into_iter().collect()
+            },
+            SwapchainImageSet::WithMSAA { depth_imageview, color_imageview, .. } => {
+                [color_view, *depth_imageview, *color_imageview].into_iter().collect()
+            }
+        };
+        attachments.extend(self.post_input.iter().map(|(_, imageview)| *imageview));
+        attachments.extend(self.extra_color_targets[index].iter().map(|(_, imageview)| *imageview));
+        attachments
+    }
+
     pub unsafe fn destroy(&mut self, resource_manager: &mut ResourceManager) {
         // framebuffers
         for framebuffer in self.framebuffers.drain(..) {
@@ -51,30 +76,251 @@ impl RenderPassResources {
                 }
             }
         }
+
+        for (image, imageview) in self.extra_color_targets.drain(..).flatten().chain(self.post_input.take()) {
+            unsafe { self.device.destroy_image_view(imageview, None)};
+            resource_manager.destroy_image(image);
+        }
     }
 }
 
+pub use crate::backend::ClearBehavior;
+
+/// Final color image of the main pass with dynamic rendering: swapchain image, or scene image of post-process
+#[derive(Clone, Copy, Debug)]
+pub struct DynamicColorTarget {
+    pub image: Image,
+    pub view: ImageView,
+    /// Image is sampled after the pass instead of presented
+    pub sampled: bool,
+}
+
 pub struct RenderPassWrapper {
     device: VkDeviceRef,
     render_pass: RenderPass,
+    /// Compatible with `render_pass`, but color attachment is loaded instead of cleared
+    load_render_pass: RenderPass,
 
     msaa_samples: Option<SampleCountFlags>,
-    surface_format: Format
+    surface_format: Format,
+    depth_format: Format,
+    extra_color_formats: SmallVec<[Format; MAX_COLOR_ATTACHMENTS - 1]>,
+    post_subpass: bool,
+    depth_mode: DepthMode,
+    /// Render passes and framebuffers are not created, see `cmd_begin_rendering`
+    dynamic_rendering: bool,
+}
+
+/// Swapchain image, depth and MSAA color or post subpass input, if used
+fn main_attachment_count(msaa_samples: Option<SampleCountFlags>, post_subpass: bool) -> usize {
+    if msaa_samples.is_some() || post_subpass { 3 } else { 2 }
+}
+
+/// Depth formats with stencil component
+fn has_stencil(depth_format: Format) -> bool {
+    matches!(depth_format, Format::D16_UNORM_S8_UINT | Format::D24_UNORM_S8_UINT | Format::D32_SFLOAT_S8_UINT)
 }
 
 impl RenderPassWrapper {
-    pub fn new(device: VkDeviceRef, surface_format: Format, msaa_samples: Option<SampleCountFlags>) -> Self {
+    /// `extra_color_formats` are additional color attachments, written by fragment shader outputs at locations 1..
+    /// They are cleared with zeros, have the same sample count as the main attachment and are not resolved.
+    ///
+    /// With `post_subpass`, objects of subpass 0 are rendered into an intermediate scene color attachment, and
+    /// objects of subpass 1 read it as input attachment and write the swapchain image. MSAA is not supported
+    /// in this mode and is disabled.
+    ///
+    /// With `dynamic_rendering`, render pass objects are not created and the pass is recorded with
+    /// `cmd_begin_rendering`. Device must have dynamic rendering enabled. Ignored with `post_subpass`, which
+    /// needs subpasses.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(device: VkDeviceRef, surface_format: Format, depth_format: Format, depth_mode: DepthMode,
+               extra_color_formats: &[Format], mut msaa_samples: Option<SampleCountFlags>, post_subpass: bool,
+               mut dynamic_rendering: bool) -> Self {
         let g = range_event_start!("Create render pass");
 
-        let intermediate_sample_count = msaa_samples.unwrap_or(SampleCountFlags::TYPE_1);
-        let render_pass = {
+        if post_subpass && msaa_samples.is_some() {
+            warn!("MSAA is not supported together with post subpass, disabling MSAA");
+            msaa_samples = None;
+        }
+        if post_subpass && dynamic_rendering {
+            warn!("Dynamic rendering is not supported together with post subpass, using render pass objects");
+            dynamic_rendering = false;
+        }
 
-            let load_op = if msaa_samples.is_some() {
-                AttachmentLoadOp::DONT_CARE
+        let mut extra_color_formats: SmallVec<_> = extra_color_formats.iter().copied().collect();
+        if extra_color_formats.len() > MAX_COLOR_ATTACHMENTS - 1 {
+            warn!("At most {} color attachments are supported, extra ones are ignored", MAX_COLOR_ATTACHMENTS);
+            extra_color_formats.truncate(MAX_COLOR_ATTACHMENTS - 1);
+        }
+
+        let (render_pass, load_render_pass) = if dynamic_rendering {
+            (RenderPass::null(), RenderPass::null())
+        } else {
+            let render_pass = Self::create_render_pass(&device, surface_format, depth_format, &extra_color_formats,
+                                                       msaa_samples, post_subpass, false, false);
+            let load_render_pass = Self::create_render_pass(&device, surface_format, depth_format, &extra_color_formats,
+                                                            msaa_samples, post_subpass, true, false);
+            device.set_object_name(render_pass, "Main render pass");
+            device.set_object_name(load_render_pass, "Main render pass (load)");
+            (render_pass, load_render_pass)
+        };
+
+        Self {
+            device,
+
+            render_pass,
+            load_render_pass,
+
+            msaa_samples,
+            surface_format,
+            depth_format,
+            extra_color_formats,
+            post_subpass,
+            depth_mode,
+            dynamic_rendering,
+        }
+    }
+
+    /// Single subpass render pass drawing into a sampled color image and a depth attachment.
+    ///
+    /// Color is left in SHADER_READ_ONLY_OPTIMAL layout at the end of the pass, so it can be sampled by
+    /// the passes recorded after it.
+    pub fn new_offscreen(device: VkDeviceRef, color_format: Format, depth_format: Format, depth_mode: DepthMode) -> Self {
+        let render_pass = Self::create_offscreen_render_pass(&device, color_format, depth_format, false);
+        let load_render_pass = Self::create_offscreen_render_pass(&device, color_format, depth_format, true);
+        device.set_object_name(render_pass, "Offscreen render pass");
+        device.set_object_name(load_render_pass, "Offscreen render pass (load)");
+
+        Self {
+            device,
+
+            render_pass,
+            load_render_pass,
+
+            msaa_samples: None,
+            surface_format: color_format,
+            depth_format,
+            extra_color_formats: SmallVec::new(),
+            post_subpass: false,
+            depth_mode,
+            dynamic_rendering: false,
+        }
+    }
+
+    fn create_offscreen_render_pass(device: &VkDeviceRef, color_format: Format, depth_format: Format,
+                                    load_color: bool) -> RenderPass {
+        // loaded image was sampled last time
+        let (color_load_op, color_initial_layout) = if load_color {
+            (AttachmentLoadOp::LOAD, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+        } else {
+            (AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED)
+        };
+        let stencil_load_op = if has_stencil(depth_format) {
+            AttachmentLoadOp::CLEAR
+        } else {
+            AttachmentLoadOp::DONT_CARE
+        };
+        let attachments = [
+            // 0. color, sampled after the pass
+            vk::AttachmentDescription::default()
+                .format(color_format)
+                .samples(SampleCountFlags::TYPE_1)
+                .load_op(color_load_op)
+                .store_op(vk::AttachmentStoreOp::STORE)
+                .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(color_initial_layout)
+                .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL),
+            // 1. depth
+            vk::AttachmentDescription::default()
+                .format(depth_format)
+                .samples(SampleCountFlags::TYPE_1)
+                .load_op(vk::AttachmentLoadOp::CLEAR)
+                .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .stencil_load_op(stencil_load_op)
+                .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                .initial_layout(vk::ImageLayout::UNDEFINED)
+                .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
+        ];
+        let color_attachment_refs = [vk::AttachmentReference::default()
+            .attachment(0)
+            .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+        let depth_attachment_ref = vk::AttachmentReference::default()
+            .attachment(1)
+            .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
+        let subpasses = [vk::SubpassDescription::default()
+            .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+            .color_attachments(&color_attachment_refs)
+            .depth_stencil_attachment(&depth_attachment_ref)];
+
+        let color_access = if load_color {
+            AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE
+        } else {
+            AccessFlags::COLOR_ATTACHMENT_WRITE
+        };
+        let dependencies = [
+            // color may still be sampled by the previous frame
+            vk::SubpassDependency::default()
+                .src_subpass(vk::SUBPASS_EXTERNAL)
+                .dst_subpass(0)
+                .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | PipelineStageFlags::FRAGMENT_SHADER)
+                .src_access_mask(AccessFlags::empty())
+                .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+                .dst_access_mask(color_access | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE),
+            // transition to SHADER_READ_ONLY_OPTIMAL must finish before the following passes sample it
+            vk::SubpassDependency::default()
+                .src_subpass(0)
+                .dst_subpass(vk::SUBPASS_EXTERNAL)
+                .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER)
+                .dst_access_mask(AccessFlags::SHADER_READ),
+        ];
+
+        let render_pass_create_info = vk::RenderPassCreateInfo::default()
+            .attachments(&attachments)
+            .subpasses(&subpasses)
+            .dependencies(&dependencies);
+        unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn create_render_pass(device: &VkDeviceRef, surface_format: Format, depth_format: Format, extra_color_formats: &[Format],
+                          msaa_samples: Option<SampleCountFlags>, post_subpass: bool, load_color: bool,
+                          sampled_color: bool) -> RenderPass {
+        let intermediate_sample_count = msaa_samples.unwrap_or(SampleCountFlags::TYPE_1);
+        // final color is either presented or sampled after the render pass
+        let final_color_layout = if sampled_color {
+            vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        } else {
+            vk::ImageLayout::PRESENT_SRC_KHR
+        };
+        // loaded image was presented last time
+        let (color_load_op, color_initial_layout) = if load_color {
+            (AttachmentLoadOp::LOAD, vk::ImageLayout::PRESENT_SRC_KHR)
+        } else {
+            (AttachmentLoadOp::CLEAR, vk::ImageLayout::UNDEFINED)
+        };
+        {
+
+            let (load_op, initial_layout) = if msaa_samples.is_some() {
+                (AttachmentLoadOp::DONT_CARE, vk::ImageLayout::UNDEFINED)
+            } else if post_subpass && !load_color {
+                // fully written by the post subpass
+                (AttachmentLoadOp::DONT_CARE, vk::ImageLayout::UNDEFINED)
+            } else if load_color {
+                (color_load_op, final_color_layout)
             } else {
+                (color_load_op, color_initial_layout)
+            };
+            // stencil is cleared together with depth
+            let stencil_load_op = if has_stencil(depth_format) {
                 AttachmentLoadOp::CLEAR
+            } else {
+                AttachmentLoadOp::DONT_CARE
             };
-            let attachments = [
+            let main_attachments = [
                 // 0. final color attachment (resolve attachment)
                 vk::AttachmentDescription::default()
                     .format(surface_format)
@@ -83,96 +329,246 @@ impl RenderPassWrapper {
                     .store_op(vk::AttachmentStoreOp::STORE)
                     .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
                     .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
-                    .initial_layout(vk::ImageLayout::UNDEFINED)
-                    .final_layout(vk::ImageLayout::PRESENT_SRC_KHR),
+                    .initial_layout(initial_layout)
+                    .final_layout(final_color_layout),
 
                 // 1. depth attachment
                 vk::AttachmentDescription::default()
-                    .format(Format::D16_UNORM)
+                    .format(depth_format)
                     .samples(intermediate_sample_count)
                     .load_op(vk::AttachmentLoadOp::CLEAR)
                     .store_op(vk::AttachmentStoreOp::DONT_CARE)
-                    .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                    .stencil_load_op(stencil_load_op)
                     .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
                     .initial_layout(vk::ImageLayout::UNDEFINED)
                     .final_layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL),
 
                 // 2. Color attachment
+                if post_subpass {
+                    // scene color, input of the post subpass
+                    vk::AttachmentDescription::default()
+                        .format(surface_format)
+                        .samples(SampleCountFlags::TYPE_1)
+                        .load_op(AttachmentLoadOp::CLEAR)
+                        .store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .initial_layout(vk::ImageLayout::UNDEFINED)
+                        .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+                } else {
+                    vk::AttachmentDescription::default()
+                        .format(surface_format)
+                        .samples(intermediate_sample_count)
+                        .load_op(color_load_op)
+                        .store_op(vk::AttachmentStoreOp::STORE)
+                        .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
+                        .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
+                        .initial_layout(color_initial_layout)
+                        .final_layout(vk::ImageLayout::PRESENT_SRC_KHR)
+                },
+            ];
+            let main_attachment_count = main_attachment_count(msaa_samples, post_subpass);
+            let mut attachments: SmallVec<[_; MAX_COLOR_ATTACHMENTS + 2]> = main_attachments.into_iter()
+                .take(main_attachment_count)
+                .collect();
+            // 2.. or 3.. extra color attachments
+            let extra_color_first = attachments.len() as u32;
+            attachments.extend(extra_color_formats.iter().map(|format| {
                 vk::AttachmentDescription::default()
-                    .format(surface_format)
+                    .format(*format)
                     .samples(intermediate_sample_count)
-                    .load_op(vk::AttachmentLoadOp::CLEAR)
+                    .load_op(AttachmentLoadOp::CLEAR)
                     .store_op(vk::AttachmentStoreOp::STORE)
                     .stencil_load_op(vk::AttachmentLoadOp::DONT_CARE)
                     .stencil_store_op(vk::AttachmentStoreOp::DONT_CARE)
                     .initial_layout(vk::ImageLayout::UNDEFINED)
-                    .final_layout(vk::ImageLayout::PRESENT_SRC_KHR),
-            ];
+                    .final_layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            }));
 
             let resolve_attachment_i = 0;
-            let color_attachment_i = if msaa_samples.is_some() {
+            let color_attachment_i = if msaa_samples.is_some() || post_subpass {
                 2
             }
             else {
                 0
             };
 
-            let color_attachment_refs = [vk::AttachmentReference::default()
-                .attachment(color_attachment_i)
-                .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+            let color_attachment_refs: SmallVec<[_; MAX_COLOR_ATTACHMENTS]> = std::iter::once(color_attachment_i)
+                .chain((0..extra_color_formats.len() as u32).map(|i| extra_color_first + i))
+                .map(|i| vk::AttachmentReference::default()
+                    .attachment(i)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+                .collect();
             let depth_attachment_ref = vk::AttachmentReference::default()
                 .attachment(1)
                 .layout(vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL);
-            let resolve_attachment_ref = [vk::AttachmentReference::default()
-                .attachment(resolve_attachment_i)
+            // extra attachments are not resolved
+            let resolve_attachment_ref: SmallVec<[_; MAX_COLOR_ATTACHMENTS]> = std::iter::once(resolve_attachment_i)
+                .chain(extra_color_formats.iter().map(|_| vk::ATTACHMENT_UNUSED))
+                .map(|i| vk::AttachmentReference::default()
+                    .attachment(i)
+                    .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL))
+                .collect();
+
+            let post_color_refs = [vk::AttachmentReference::default()
+                .attachment(0)
                 .layout(vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL)];
+            let post_input_refs = [vk::AttachmentReference::default()
+                .attachment(2)
+                .layout(vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)];
 
-            let mut subpasses = [vk::SubpassDescription::default()
+            let mut subpasses: SmallVec<[_; 2]> = [vk::SubpassDescription::default()
                 .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
                 .color_attachments(&color_attachment_refs)
-                .depth_stencil_attachment(&depth_attachment_ref)];
+                .depth_stencil_attachment(&depth_attachment_ref)].into_iter().collect();
             if msaa_samples.is_some() {
                 subpasses[0] = subpasses[0].resolve_attachments(&resolve_attachment_ref);
             }
-            let dependencies = [vk::SubpassDependency::default()
+            if post_subpass {
+                subpasses.push(vk::SubpassDescription::default()
+                    .pipeline_bind_point(PipelineBindPoint::GRAPHICS)
+                    .color_attachments(&post_color_refs)
+                    .input_attachments(&post_input_refs));
+            }
+            let color_access = if load_color {
+                AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE
+            } else {
+                AccessFlags::COLOR_ATTACHMENT_WRITE
+            };
+            // shared scene color may still be read by the post subpass or pass of the previous frame
+            let src_stage = if post_subpass || sampled_color {
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::EARLY_FRAGMENT_TESTS
+                    | PipelineStageFlags::FRAGMENT_SHADER
+            } else {
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::EARLY_FRAGMENT_TESTS
+            };
+            let mut dependencies: SmallVec<[_; 3]> = [vk::SubpassDependency::default()
                 .src_subpass(vk::SUBPASS_EXTERNAL)
                 .dst_subpass(0)
-                .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::EARLY_FRAGMENT_TESTS)
+                .src_stage_mask(src_stage)
                 .src_access_mask(AccessFlags::empty())
                 .dst_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::EARLY_FRAGMENT_TESTS)
-                .dst_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)];
+                .dst_access_mask(color_access | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)].into_iter().collect();
+            if post_subpass {
+                // each fragment reads only the scene color at its own position
+                dependencies.push(vk::SubpassDependency::default()
+                    .src_subpass(0)
+                    .dst_subpass(1)
+                    .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER)
+                    .dst_access_mask(AccessFlags::INPUT_ATTACHMENT_READ)
+                    .dependency_flags(vk::DependencyFlags::BY_REGION));
+            }
+            if sampled_color {
+                // transition to SHADER_READ_ONLY_OPTIMAL must finish before the following passes sample it
+                dependencies.push(vk::SubpassDependency::default()
+                    .src_subpass(if post_subpass { 1 } else { 0 })
+                    .dst_subpass(vk::SUBPASS_EXTERNAL)
+                    .src_stage_mask(PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT)
+                    .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                    .dst_stage_mask(PipelineStageFlags::FRAGMENT_SHADER)
+                    .dst_access_mask(AccessFlags::SHADER_READ));
+            }
 
             let render_pass_create_info =
                 vk::RenderPassCreateInfo::default()
+                    .attachments(&attachments)
                     .subpasses(&subpasses)
                     .dependencies(&dependencies);
-            if msaa_samples.is_some() {
-                let render_pass_create_info = render_pass_create_info.attachments(&attachments);
-                unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
-            }
-            else {
-                let render_pass_create_info = render_pass_create_info.attachments(&attachments[..2]);
-                unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
-            }
+            unsafe { device.create_render_pass(&render_pass_create_info, None).unwrap() }
 
-        };
-
-        Self {
-            device,
-
-            render_pass,
+        }
+    }
 
-            msaa_samples,
-            surface_format,
+    /// Render passes compatible with the main ones, which leave the final color in SHADER_READ_ONLY_OPTIMAL
+    /// layout instead of presenting it, for rendering the scene into an image sampled afterwards.
+    ///
+    /// Returned as (clear, load) pair, destroyed by caller. Null handles with dynamic rendering.
+    pub fn create_sampled_render_passes(&self) -> (RenderPass, RenderPass) {
+        if self.dynamic_rendering {
+            return (RenderPass::null(), RenderPass::null());
         }
+        let create = |load_color| Self::create_render_pass(&self.device, self.surface_format, self.depth_format,
+                                                           &self.extra_color_formats, self.msaa_samples,
+                                                           self.post_subpass, load_color, true);
+        let render_pass = create(false);
+        let load_render_pass = create(true);
+        self.device.set_object_name(render_pass, "Sampled scene render pass");
+        self.device.set_object_name(load_render_pass, "Sampled scene render pass (load)");
+        (render_pass, load_render_pass)
     }
 
     pub fn get_render_pass(&self) -> &RenderPass {
         &self.render_pass
     }
+
+    /// Main pass is recorded with `cmd_begin_rendering`, render pass handles are null
+    pub fn is_dynamic(&self) -> bool {
+        self.dynamic_rendering
+    }
+
+    /// Formats of color attachments written by pipelines of subpass 0, starting with the main one
+    pub fn color_formats(&self) -> SmallVec<[Format; MAX_COLOR_ATTACHMENTS]> {
+        std::iter::once(self.surface_format).chain(self.extra_color_formats.iter().copied()).collect()
+    }
+
+    /// Attachment formats to chain to pipeline create info with dynamic rendering, `color_formats` are
+    /// from `color_formats`
+    pub fn pipeline_rendering_info<'a>(&self, color_formats: &'a [Format]) -> vk::PipelineRenderingCreateInfo<'a> {
+        let stencil_format = if self.has_stencil() { self.depth_format } else { Format::UNDEFINED };
+        vk::PipelineRenderingCreateInfo::default()
+            .color_attachment_formats(color_formats)
+            .depth_attachment_format(self.depth_format)
+            .stencil_attachment_format(stencil_format)
+    }
+
+    /// Render pass to begin with given clear behavior. Both variants are compatible with the same
+    /// framebuffers and pipelines.
+    pub fn get_render_pass_for(&self, clear_behavior: ClearBehavior) -> RenderPass {
+        match clear_behavior {
+            ClearBehavior::Clear(_) => self.render_pass,
+            ClearBehavior::Load => self.load_render_pass,
+        }
+    }
     pub fn get_msaa_samples(&self) -> Option<SampleCountFlags> {
         self.msaa_samples
     }
+    pub fn get_surface_format(&self) -> Format {
+        self.surface_format
+    }
+    pub fn get_depth_format(&self) -> Format {
+        self.depth_format
+    }
+    pub fn has_stencil(&self) -> bool {
+        has_stencil(self.depth_format)
+    }
+    /// Depth clear value and compare op of pipelines
+    pub fn get_depth_mode(&self) -> DepthMode {
+        self.depth_mode
+    }
+    /// Formats of color attachments after the main one
+    pub fn get_extra_color_formats(&self) -> &[Format] {
+        &self.extra_color_formats
+    }
+    /// Color attachments written by pipelines of the subpass
+    pub fn color_attachment_count(&self, subpass: u32) -> usize {
+        if subpass == 0 {
+            1 + self.extra_color_formats.len()
+        } else {
+            1
+        }
+    }
+    pub fn has_post_subpass(&self) -> bool {
+        self.post_subpass
+    }
+    pub fn subpass_count(&self) -> u32 {
+        if self.post_subpass { 2 } else { 1 }
+    }
+    /// Number of attachments before extra color attachments
+    pub fn main_attachment_count(&self) -> usize {
+        main_attachment_count(self.msaa_samples, self.post_subpass)
+    }
 
     pub fn create_render_pass_resources(&self, image_views: Vec<ImageView>, extent: Extent2D,
                     resource_manager: &mut ResourceManager) -> RenderPassResources {
@@ -181,14 +577,21 @@ impl RenderPassWrapper {
 
 
         let mut swapchain_image_set = Vec::with_capacity(swapchain_image_cnt);
-        for _ in 0..swapchain_image_cnt {
+        let mut extra_color_targets: Vec<SmallVec<_>> = Vec::with_capacity(swapchain_image_cnt);
+        for i in 0..swapchain_image_cnt {
             let msaa_samples = self.msaa_samples.unwrap_or(SampleCountFlags::TYPE_1);
             let depth_image =
-                resource_manager.create_image(extent, Format::D16_UNORM, ImageTiling::OPTIMAL,
+                resource_manager.create_image(extent, self.depth_format, ImageTiling::OPTIMAL,
                                               ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, msaa_samples);
             let img_info = depth_image.info;
-            let info = imageview_info_for_image(depth_image.image, img_info, ImageAspectFlags::DEPTH);
+            let depth_aspect = if self.has_stencil() {
+                ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+            } else {
+                ImageAspectFlags::DEPTH
+            };
+            let info = imageview_info_for_image(depth_image.image, img_info, depth_aspect);
             let depth_imageview = unsafe { self.device.create_image_view(&info, None).unwrap() };
+            self.device.set_object_name(depth_image.image, format_args!("Depth image {}", i));
 
             if self.msaa_samples.is_some() {
                 let color_image =
@@ -197,42 +600,240 @@ impl RenderPassWrapper {
                 let img_info = color_image.info;
                 let info = imageview_info_for_image(color_image.image, img_info, ImageAspectFlags::COLOR);
                 let color_imageview = unsafe { self.device.create_image_view(&info, None).unwrap() };
+                self.device.set_object_name(color_image.image, format_args!("MSAA color image {}", i));
 
                 swapchain_image_set.push(SwapchainImageSet::WithMSAA {depth_image, depth_imageview, color_image, color_imageview});
             }
             else {
                 swapchain_image_set.push(SwapchainImageSet::NoMSAA {depth_image, depth_imageview});
             }
-        }
 
-        let framebuffers = swapchain_image_set.iter()
-            .zip(image_views.iter())
-            .map(|(image_set, resolve_imageview)| {
-
-                let framebuffer_create_info = vk::FramebufferCreateInfo::default()
-                    .render_pass(self.render_pass)
-                    .width(extent.width)
-                    .height(extent.height)
-                    .layers(1);
-
-                match image_set {
-                    SwapchainImageSet::NoMSAA { depth_image, depth_imageview } => {
-                        let attachments = [*resolve_imageview, *depth_imageview];
-                        let framebuffer_create_info = framebuffer_create_info.attachments(&attachments);
-                        unsafe { self.device.create_framebuffer(&framebuffer_create_info, None).unwrap() }
-                    },
-                    SwapchainImageSet::WithMSAA { depth_image, depth_imageview, color_image, color_imageview } => {
-                        let attachments = [*resolve_imageview, *depth_imageview, *color_imageview];
-                        let framebuffer_create_info = framebuffer_create_info.attachments(&attachments);
-                        unsafe { self.device.create_framebuffer(&framebuffer_create_info, None).unwrap() }
-                    }
-                }
-        }).collect::<Vec<_>>();
+            extra_color_targets.push(self.extra_color_formats.iter().enumerate().map(|(j, format)| {
+                let image = resource_manager.create_image(extent, *format, ImageTiling::OPTIMAL,
+                                                          ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::SAMPLED
+                                                              | ImageUsageFlags::INPUT_ATTACHMENT | ImageUsageFlags::TRANSFER_SRC,
+                                                          msaa_samples);
+                let info = imageview_info_for_image(image.image, image.info, ImageAspectFlags::COLOR);
+                let imageview = unsafe { self.device.create_image_view(&info, None).unwrap() };
+                self.device.set_object_name(image.image, format_args!("Color target {} image {}", j + 1, i));
+                (image, imageview)
+            }).collect());
+        }
 
-        RenderPassResources {
+        let post_input = self.post_subpass.then(|| {
+            let image = resource_manager.create_image(extent, self.surface_format, ImageTiling::OPTIMAL,
+                                                      ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::INPUT_ATTACHMENT,
+                                                      SampleCountFlags::TYPE_1);
+            let info = imageview_info_for_image(image.image, image.info, ImageAspectFlags::COLOR);
+            let imageview = unsafe { self.device.create_image_view(&info, None).unwrap() };
+            self.device.set_object_name(image.image, "Post subpass input");
+            (image, imageview)
+        });
+
+        let mut resources = RenderPassResources {
             device: self.device.clone(),
             swapchain_image_set,
-            framebuffers,
+            extra_color_targets,
+            post_input,
+            framebuffers: Vec::new(),
+        };
+        if self.dynamic_rendering {
+            return resources;
+        }
+        resources.framebuffers = image_views.iter().enumerate().map(|(i, resolve_imageview)| {
+            let attachments = resources.framebuffer_attachments(i, *resolve_imageview);
+            let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+                .render_pass(self.render_pass)
+                .attachments(&attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            unsafe { self.device.create_framebuffer(&framebuffer_create_info, None).unwrap() }
+        }).collect();
+        resources
+    }
+
+    /// Begin the main pass with dynamic rendering, equivalent to beginning `get_render_pass_for(clear_behavior)`.
+    ///
+    /// Attachments are transitioned with barriers, as render pass objects do with their layouts: `target`
+    /// from the layout it was left in by `cmd_end_rendering` (or undefined when cleared), depth and extra
+    /// attachments from undefined. MSAA color keeps COLOR_ATTACHMENT_OPTIMAL layout between frames and
+    /// is resolved into `target`.
+    pub fn cmd_begin_rendering(&self, command_buffer: CommandBuffer, resources: &RenderPassResources, image_index: usize,
+                               target: DynamicColorTarget, clear_behavior: ClearBehavior, render_area: vk::Rect2D) {
+        let (load_op, clear_color) = match clear_behavior {
+            ClearBehavior::Clear(c) => (AttachmentLoadOp::CLEAR, [c[0], c[1], c[2], 1.0]),
+            ClearBehavior::Load => (AttachmentLoadOp::LOAD, [0.0; 4]),
+        };
+        let final_target_layout = if target.sampled {
+            ImageLayout::SHADER_READ_ONLY_OPTIMAL
+        } else {
+            ImageLayout::PRESENT_SRC_KHR
+        };
+        let color_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+        let depth_aspect = if self.has_stencil() {
+            ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+        } else {
+            ImageAspectFlags::DEPTH
+        };
+        // loaded contents were written by the previous frame
+        let color_barrier = |image: Image, old_layout: ImageLayout| vk::ImageMemoryBarrier::default()
+            .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(AccessFlags::COLOR_ATTACHMENT_READ | AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .old_layout(old_layout)
+            .new_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .image(image)
+            .subresource_range(color_range);
+
+        let (depth_image, depth_view, msaa_color) = match &resources.swapchain_image_set[image_index] {
+            SwapchainImageSet::NoMSAA { depth_image, depth_imageview } => (depth_image.image, *depth_imageview, None),
+            SwapchainImageSet::WithMSAA { depth_image, depth_imageview, color_image, color_imageview } =>
+                (depth_image.image, *depth_imageview, Some((color_image.image, *color_imageview))),
+        };
+        let mut barriers: SmallVec<[vk::ImageMemoryBarrier; MAX_COLOR_ATTACHMENTS + 2]> = SmallVec::new();
+        // with MSAA, the multisampled image is loaded and the target is fully written by resolve
+        let target_old_layout = if load_op == AttachmentLoadOp::LOAD && msaa_color.is_none() {
+            final_target_layout
+        } else {
+            ImageLayout::UNDEFINED
+        };
+        barriers.push(color_barrier(target.image, target_old_layout));
+        if let Some((image, _)) = msaa_color {
+            let old_layout = if load_op == AttachmentLoadOp::LOAD {
+                ImageLayout::COLOR_ATTACHMENT_OPTIMAL
+            } else {
+                ImageLayout::UNDEFINED
+            };
+            barriers.push(color_barrier(image, old_layout));
+        }
+        barriers.extend(resources.extra_color_targets[image_index].iter()
+            .map(|(image, _)| color_barrier(image.image, ImageLayout::UNDEFINED)));
+        barriers.push(vk::ImageMemoryBarrier::default()
+            .src_access_mask(AccessFlags::empty())
+            .dst_access_mask(AccessFlags::DEPTH_STENCIL_ATTACHMENT_READ | AccessFlags::DEPTH_STENCIL_ATTACHMENT_WRITE)
+            .old_layout(ImageLayout::UNDEFINED)
+            .new_layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .image(depth_image)
+            .subresource_range(vk::ImageSubresourceRange::default()
+                .aspect_mask(depth_aspect)
+                .level_count(1)
+                .layer_count(1)));
+        // sampled target may still be read by the previous frame
+        let src_stage = if target.sampled {
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::LATE_FRAGMENT_TESTS
+                | PipelineStageFlags::FRAGMENT_SHADER
+        } else {
+            PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::LATE_FRAGMENT_TESTS
+        };
+
+        let color_clear = vk::ClearValue {
+            color: vk::ClearColorValue { float32: clear_color },
+        };
+        let main_attachment = match msaa_color {
+            Some((_, view)) => vk::RenderingAttachmentInfo::default()
+                .image_view(view)
+                .image_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .resolve_mode(vk::ResolveModeFlags::AVERAGE)
+                .resolve_image_view(target.view)
+                .resolve_image_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+            None => vk::RenderingAttachmentInfo::default()
+                .image_view(target.view)
+                .image_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL),
+        }
+            .load_op(load_op)
+            .store_op(vk::AttachmentStoreOp::STORE)
+            .clear_value(color_clear);
+        let color_attachments: SmallVec<[_; MAX_COLOR_ATTACHMENTS]> = std::iter::once(main_attachment)
+            .chain(resources.extra_color_targets[image_index].iter().map(|(_, view)| {
+                vk::RenderingAttachmentInfo::default()
+                    .image_view(*view)
+                    .image_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                    .load_op(AttachmentLoadOp::CLEAR)
+                    .store_op(vk::AttachmentStoreOp::STORE)
+                    .clear_value(vk::ClearValue::default())
+            }))
+            .collect();
+        // stencil is cleared together with depth
+        let depth_attachment = vk::RenderingAttachmentInfo::default()
+            .image_view(depth_view)
+            .image_layout(ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL)
+            .load_op(AttachmentLoadOp::CLEAR)
+            .store_op(vk::AttachmentStoreOp::DONT_CARE)
+            .clear_value(vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.depth_mode.clear_value(),
+                    stencil: 0,
+                },
+            });
+        let mut rendering_info = vk::RenderingInfo::default()
+            .render_area(render_area)
+            .layer_count(1)
+            .color_attachments(&color_attachments)
+            .depth_attachment(&depth_attachment);
+        if self.has_stencil() {
+            rendering_info = rendering_info.stencil_attachment(&depth_attachment);
+        }
+
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                src_stage,
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT | PipelineStageFlags::EARLY_FRAGMENT_TESTS,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
+        }
+        self.device.begin_dynamic_rendering(command_buffer, &rendering_info);
+    }
+
+    /// End the main pass begun with `cmd_begin_rendering`, leaving `target` in PRESENT_SRC_KHR layout, or
+    /// SHADER_READ_ONLY_OPTIMAL if it is sampled. Extra attachments are left in SHADER_READ_ONLY_OPTIMAL
+    pub fn cmd_end_rendering(&self, command_buffer: CommandBuffer, resources: &RenderPassResources, image_index: usize,
+                             target: DynamicColorTarget) {
+        self.device.end_dynamic_rendering(command_buffer);
+
+        let color_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+        let read_barrier = |image: Image| vk::ImageMemoryBarrier::default()
+            .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+            .dst_access_mask(AccessFlags::SHADER_READ)
+            .old_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+            .new_layout(ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .image(image)
+            .subresource_range(color_range);
+        let mut barriers: SmallVec<[vk::ImageMemoryBarrier; MAX_COLOR_ATTACHMENTS]> = resources.extra_color_targets[image_index]
+            .iter()
+            .map(|(image, _)| read_barrier(image.image))
+            .collect();
+        // presentation waits for the render finished semaphore, no access to make visible
+        barriers.push(if target.sampled {
+            read_barrier(target.image)
+        } else {
+            vk::ImageMemoryBarrier::default()
+                .src_access_mask(AccessFlags::COLOR_ATTACHMENT_WRITE)
+                .dst_access_mask(AccessFlags::empty())
+                .old_layout(ImageLayout::COLOR_ATTACHMENT_OPTIMAL)
+                .new_layout(ImageLayout::PRESENT_SRC_KHR)
+                .image(target.image)
+                .subresource_range(color_range)
+        });
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                command_buffer,
+                PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                PipelineStageFlags::FRAGMENT_SHADER | PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &barriers,
+            );
         }
     }
 }
@@ -241,6 +842,9 @@ impl Drop for RenderPassWrapper {
     fn drop(&mut self) {
         let g = range_event_start!("[Vulkan] Destroy render pass");
         //render pass
-        unsafe { self.device.destroy_render_pass(self.render_pass, None); }
+        unsafe {
+            self.device.destroy_render_pass(self.render_pass, None);
+            self.device.destroy_render_pass(self.load_render_pass, None);
+        }
     }
 }
\ No newline at end of file
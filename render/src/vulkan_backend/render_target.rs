@@ -0,0 +1,131 @@
+use ash::vk;
+use ash::vk::{CommandBuffer, Extent2D, Framebuffer, ImageAspectFlags, ImageTiling, ImageUsageFlags, ImageView, RenderPassBeginInfo, SampleCountFlags};
+use crate::vulkan_backend::object_resource_pool::UniformImage;
+use crate::vulkan_backend::render_pass::RenderPassWrapper;
+use crate::vulkan_backend::resource_manager::{ImageResource, ResourceManager};
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+use crate::vulkan_backend::wrappers::image::imageview_info_for_image;
+
+/// Offscreen color and depth images with their own render pass and framebuffer.
+///
+/// Color image is owned by the `UniformImage` returned from `new`, which is sampled by passes recorded later.
+pub struct RenderTarget {
+    device: VkDeviceRef,
+    render_pass: RenderPassWrapper,
+    framebuffer: Framebuffer,
+    depth_image: ImageResource,
+    depth_imageview: ImageView,
+    extent: Extent2D,
+}
+
+impl RenderTarget {
+    /// Color and depth formats and depth mode are the same as of `main_render_pass`
+    pub fn new(device: VkDeviceRef, resource_manager: &mut ResourceManager, extent: Extent2D,
+               main_render_pass: &RenderPassWrapper, name: &str) -> (Self, UniformImage) {
+        let color_format = main_render_pass.get_surface_format();
+        let depth_format = main_render_pass.get_depth_format();
+        let render_pass = RenderPassWrapper::new_offscreen(device.clone(), color_format, depth_format,
+                                                           main_render_pass.get_depth_mode());
+
+        let color_image = resource_manager.create_image(extent, color_format, ImageTiling::OPTIMAL,
+                                                        ImageUsageFlags::COLOR_ATTACHMENT | ImageUsageFlags::SAMPLED,
+                                                        SampleCountFlags::TYPE_1);
+        device.set_object_name(color_image.image, format_args!("{} color", name));
+        let color = UniformImage::from_image(color_image, resource_manager, device.clone());
+
+        let depth_image = resource_manager.create_image(extent, depth_format, ImageTiling::OPTIMAL,
+                                                        ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT, SampleCountFlags::TYPE_1);
+        device.set_object_name(depth_image.image, format_args!("{} depth", name));
+        let depth_aspect = if render_pass.has_stencil() {
+            ImageAspectFlags::DEPTH | ImageAspectFlags::STENCIL
+        } else {
+            ImageAspectFlags::DEPTH
+        };
+        let info = imageview_info_for_image(depth_image.image, depth_image.info, depth_aspect);
+        let depth_imageview = unsafe { device.create_image_view(&info, None).unwrap() };
+
+        let attachments = [color.image_view, depth_imageview];
+        let framebuffer_create_info = vk::FramebufferCreateInfo::default()
+            .render_pass(*render_pass.get_render_pass())
+            .attachments(&attachments)
+            .width(extent.width)
+            .height(extent.height)
+            .layers(1);
+        let framebuffer = unsafe { device.create_framebuffer(&framebuffer_create_info, None).unwrap() };
+
+        let render_target = RenderTarget {
+            device,
+            render_pass,
+            framebuffer,
+            depth_image,
+            depth_imageview,
+            extent,
+        };
+        (render_target, color)
+    }
+
+    /// Pipelines drawing into this target are created with this render pass
+    pub fn render_pass(&self) -> &RenderPassWrapper {
+        &self.render_pass
+    }
+
+    pub fn extent(&self) -> Extent2D {
+        self.extent
+    }
+
+    /// Begin render pass clearing color with transparent black, and cover the whole target with viewport and scissor
+    pub fn begin(&self, command_buffer: CommandBuffer) {
+        let clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: [0.0; 4],
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: self.render_pass.get_depth_mode().clear_value(),
+                    stencil: 0,
+                },
+            },
+        ];
+        let render_area: vk::Rect2D = self.extent.into();
+        let render_pass_begin_info = RenderPassBeginInfo::default()
+            .render_pass(*self.render_pass.get_render_pass())
+            .framebuffer(self.framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+        let viewport = vk::Viewport::default()
+            .width(self.extent.width as f32)
+            .height(self.extent.height as f32)
+            .max_depth(1.0);
+        unsafe {
+            self.device.cmd_begin_render_pass(command_buffer, &render_pass_begin_info, vk::SubpassContents::INLINE);
+            self.device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            self.device.cmd_set_scissor(command_buffer, 0, &[render_area]);
+        }
+    }
+
+    /// Color image is in SHADER_READ_ONLY_OPTIMAL layout after this
+    pub fn end(&self, command_buffer: CommandBuffer) {
+        unsafe {
+            self.device.cmd_end_render_pass(command_buffer);
+        }
+    }
+
+    /// Destroy depth image. Color image is destroyed together with its `UniformImage`
+    pub fn destroy(self, resource_manager: &mut ResourceManager) {
+        let depth_image = self.depth_image;
+        // framebuffer and views are destroyed before the image
+        drop(self);
+        resource_manager.destroy_image(depth_image);
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_framebuffer(self.framebuffer, None);
+            self.device.destroy_image_view(self.depth_imageview, None);
+        }
+    }
+}
@@ -1,10 +1,20 @@
+use crate::vulkan_backend::error::vk_error;
+use crate::vulkan_backend::frame_sync::SubmitSignal;
 use crate::vulkan_backend::wrappers::command_pool::VkCommandPool;
 use crate::vulkan_backend::wrappers::device::VkDeviceRef;
 use crate::vulkan_backend::wrappers::image::image_2d_info;
+use crate::vulkan_backend::wrappers::queue::QueueSubmitter;
+use ash::prelude::VkResult;
 use ash::vk::{self, CommandBufferUsageFlags, DeviceSize, Extent2D, Extent3D, ImageCreateInfo, SampleCountFlags, Sampler};
+use std::collections::VecDeque;
 use std::fmt::Debug;
+use log::{debug, error, warn};
+use std::ops::Range;
 use sparkles_macro::range_event_start;
 
+/// Staging buffer is never smaller, so small per-frame updates do not reallocate it
+const MIN_STAGING_SIZE: DeviceSize = 64 * 1024;
+
 #[derive(Debug)]
 pub enum HostAccessPolicy {
     UseStaging {
@@ -14,6 +24,16 @@ pub enum HostAccessPolicy {
     SingleBuffer(usize),
 }
 
+/// Range for flush or invalidate of non-coherent memory: offset is rounded down and end is rounded up
+/// to `atom_size`, as required by the spec. End is clamped to the allocation size.
+fn non_coherent_range(offset: DeviceSize, size: DeviceSize, atom_size: DeviceSize,
+                      allocation_size: DeviceSize) -> Range<DeviceSize> {
+    let atom_size = atom_size.max(1);
+    let start = offset / atom_size * atom_size;
+    let end = (offset + size).div_ceil(atom_size) * atom_size;
+    start..end.min(allocation_size)
+}
+
 /// User is responsible for not using this buffer after it's destroyed
 #[derive(Clone, Copy)]
 pub struct BufferResource {
@@ -33,31 +53,153 @@ pub struct ImageResource {
     extent: Extent3D,
 }
 
+/// Staging ranges of a batch, tracked in debug builds to catch a write without a copy out of it, whose data
+/// would silently never reach its destination
+#[derive(Default)]
+struct StagingWrites {
+    /// Staging buffer, written range and whether a copy from the range was recorded
+    writes: Vec<(vk::Buffer, Range<DeviceSize>, bool)>,
+}
+
+impl StagingWrites {
+    fn record_write(&mut self, staging: vk::Buffer, range: Range<DeviceSize>) {
+        self.writes.push((staging, range, false));
+    }
+
+    /// Copy reading `range` of the staging buffer was recorded
+    fn record_copy(&mut self, staging: vk::Buffer, range: Range<DeviceSize>) {
+        for (buffer, written, copied) in &mut self.writes {
+            if *buffer == staging && written.start < range.end && range.start < written.end {
+                *copied = true;
+            }
+        }
+    }
+
+    /// Forget all writes, returning those which were never copied from
+    fn take_uncopied(&mut self) -> Vec<(vk::Buffer, Range<DeviceSize>)> {
+        self.writes.drain(..)
+            .filter(|(_, _, copied)| !copied)
+            .map(|(buffer, range, _)| (buffer, range))
+            .collect()
+    }
+}
+
+/// Command buffer and staging memory of one batch of buffer copies
+struct TransferBatch {
+    command_buffer: vk::CommandBuffer,
+    staging_buffer: Option<BufferResource>,
+    /// Staging buffers replaced while the batch was recorded and buffers destroyed while it was recorded or
+    /// executing, freed when it is finished
+    retired_buffers: Vec<BufferResource>,
+    /// Reached when the submitted batch is finished: `transfer_completed_fence` or a frame signal
+    signal: Option<SubmitSignal>,
+    /// Empty in release builds
+    staging_writes: StagingWrites,
+}
+
+impl TransferBatch {
+    fn new(command_buffer: vk::CommandBuffer) -> Self {
+        Self {
+            command_buffer,
+            staging_buffer: None,
+            retired_buffers: Vec::new(),
+            signal: None,
+            staging_writes: StagingWrites::default(),
+        }
+    }
+
+    /// Staging memory of the batch is about to be reused or freed
+    fn release_staging_writes(&mut self) {
+        for (staging, range) in self.staging_writes.take_uncopied() {
+            warn!("Staging range {:?} of buffer {:?} was written, but no copy from it was recorded", range, staging);
+        }
+    }
+
+    /// Block until the submitted batch is finished, after which its command buffer and staging memory can be reused.
+    /// Fails if the device is lost, the batch stays pending then
+    fn wait(&mut self, device: &VkDeviceRef) -> VkResult<()> {
+        if let Some(signal) = self.signal {
+            signal.wait(device, u64::MAX)?;
+            self.signal = None;
+        }
+        self.release_staging_writes();
+        self.free_retired_buffers(device);
+        Ok(())
+    }
+
+    fn free_retired_buffers(&mut self, device: &VkDeviceRef) {
+        for buffer in self.retired_buffers.drain(..) {
+            unsafe {
+                device.free_memory(buffer.memory, None);
+                device.destroy_buffer(buffer.buffer, None);
+            }
+        }
+    }
+
+    /// Batch must be finished
+    fn destroy(&mut self, device: &VkDeviceRef) {
+        self.release_staging_writes();
+        self.retired_buffers.extend(self.staging_buffer.take());
+        self.free_retired_buffers(device);
+    }
+}
+
+/// Allocates buffers and images and uploads data to them.
+///
+/// `fill_image` is synchronous: data is in the image when it returns. When a staging buffer is used,
+/// `fill_buffer` and `copy_within_buffer` only record copies into a batch, which is submitted together with
+/// the next frame (see `take_transfer_batch`) and ends with a single barrier before it. Call `upload_now`
+/// where completion matters (readback, one-shot init uploads). Destroyed buffers are freed when the batch
+/// which may still copy into them is finished.
+///
+/// Batches are used in turn, one per frame in flight but at least two, so a new one can be recorded while
+/// frames submitted with the others are executing. Each batch starts after reads of the frames submitted before
+/// it, which therefore see buffer contents of their own updates. With more than one frame in flight, host visible
+/// device memory is written through a staging buffer as well, instead of directly while frames may read it.
 pub struct ResourceManager {
     pub host_access_policy: HostAccessPolicy,
-    staging_buffer: Option<BufferResource>,
 
     image_resources: Vec<ImageResource>,
     buffer_resources: Vec<BufferResource>,
     sampler_resources: Vec<Sampler>,
 
     device: VkDeviceRef,
-    queue: vk::Queue,
-    command_buffer: vk::CommandBuffer,
+    physical_device: vk::PhysicalDevice,
+    queue: QueueSubmitter,
+    /// Batch which is recorded or was submitted last
+    transfer: TransferBatch,
+    /// Batches submitted before `transfer`, oldest first
+    previous_transfers: VecDeque<TransferBatch>,
+    /// `transfer` command buffer is begun and has copies which were not submitted yet
+    transfer_recording: bool,
+    /// Buffers written by copies recorded after the last transfer -> transfer barrier
+    transfer_dst_buffers: Vec<vk::Buffer>,
+    /// Bytes of the current staging buffer used by the recorded batch
+    staging_offset: DeviceSize,
+    /// Used for batches submitted by `upload_now`
     transfer_completed_fence: vk::Fence,
+    /// Set by `abandon_pending`: submitted batches may never finish, so they are not waited for
+    abandoned: bool,
 
     memory_types: Vec<vk::MemoryType>,
+    /// Alignment of flushed ranges of non-coherent memory
+    non_coherent_atom_size: DeviceSize,
+    /// Total size of `fill_buffer` writes
+    buffer_upload_bytes: u64,
 }
 
 impl ResourceManager {
     pub fn new(
         physical_device: vk::PhysicalDevice,
         device: VkDeviceRef,
-        queue: vk::Queue,
+        queue: QueueSubmitter,
         command_pool: &VkCommandPool,
+        in_flight_frames: usize,
     ) -> Self {
-        // allocate command buffer
-        let command_buffer = command_pool.alloc_command_buffers(1)[0];
+        // allocate command buffers
+        let mut transfers: VecDeque<_> = command_pool.alloc_command_buffers(in_flight_frames.max(2) as u32).into_iter()
+            .map(TransferBatch::new)
+            .collect();
 
         //query memory properties info
         let memory_properties = unsafe {
@@ -132,8 +274,20 @@ impl ResourceManager {
                 }
             };
 
+        // frames in flight may read buffers while they are written from host
+        let host_access_policy = match host_access_policy {
+            HostAccessPolicy::SingleBuffer(memory_type) if in_flight_frames > 1 => HostAccessPolicy::UseStaging {
+                host_memory_type: memory_type,
+                device_memory_type: memory_type,
+            },
+            policy => policy,
+        };
         println!("Host access policy: {:?}", host_access_policy);
 
+        let non_coherent_atom_size = unsafe {
+            device.instance().get_physical_device_properties(physical_device)
+        }.limits.non_coherent_atom_size;
+
         let fence = unsafe {
             device
                 .create_fence(
@@ -151,12 +305,19 @@ impl ResourceManager {
             sampler_resources: Vec::new(),
 
             device,
+            physical_device,
             queue,
-            command_buffer,
-            staging_buffer: None,
+            transfer: transfers.pop_back().unwrap(),
+            previous_transfers: transfers,
+            transfer_recording: false,
+            transfer_dst_buffers: Vec::new(),
+            staging_offset: 0,
+            buffer_upload_bytes: 0,
             transfer_completed_fence: fence,
+            abandoned: false,
 
             memory_types: memory_properties.memory_types.to_vec(),
+            non_coherent_atom_size,
         }
     }
 
@@ -170,7 +331,8 @@ impl ResourceManager {
             device_memory_type: _,
         } = self.host_access_policy
         {
-            usage |= vk::BufferUsageFlags::TRANSFER_DST;
+            // source usage is needed to copy within the buffer
+            usage |= vk::BufferUsageFlags::TRANSFER_DST | vk::BufferUsageFlags::TRANSFER_SRC;
         }
         let buffer_create_info = vk::BufferCreateInfo::default()
             .size(size)
@@ -206,6 +368,156 @@ impl ResourceManager {
         res
     }
     
+    /// Submit recorded transfers on their own and block until all submitted transfers are finished.
+    /// Fails with `VulkanError::DeviceLost` if the device is lost
+    pub fn upload_now(&mut self) -> anyhow::Result<()> {
+        if self.abandoned {
+            return Ok(());
+        }
+        if let Some(command_buffer) = self.end_transfer_batch() {
+            unsafe {
+                self.device
+                    .reset_fences(&[self.transfer_completed_fence])
+                    .map_err(vk_error)?;
+                let command_buffers = [command_buffer];
+                let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+                self.queue
+                    .submit(&[submit_info], self.transfer_completed_fence)
+                    .map_err(vk_error)?;
+            }
+            self.transfer.signal = Some(SubmitSignal::Fence(self.transfer_completed_fence));
+        }
+        self.transfer.wait(&self.device).map_err(vk_error)?;
+        for batch in &mut self.previous_transfers {
+            batch.wait(&self.device).map_err(vk_error)?;
+        }
+        Ok(())
+    }
+
+    /// Forget submitted batches without waiting for them, after GPU did not finish a frame in time.
+    ///
+    /// Recorded copies are dropped. Later `upload_now` calls, including the one on drop, neither submit nor wait,
+    /// so resources can be destroyed while the hung work is still pending
+    pub fn abandon_pending(&mut self) {
+        if self.end_transfer_batch().is_some() {
+            warn!("Recorded buffer transfers are dropped");
+        }
+        for batch in std::iter::once(&mut self.transfer).chain(&mut self.previous_transfers) {
+            batch.signal = None;
+        }
+        self.abandoned = true;
+    }
+
+    /// End the batch of copies recorded since the last frame.
+    ///
+    /// Returned command buffer must be submitted before the frame command buffer in the same `queue_submit`,
+    /// which reaches `signal`. After waiting for a fence signal, and before resetting the fence, call
+    /// `frame_finished`. `None` if nothing was recorded
+    pub fn take_transfer_batch(&mut self, signal: SubmitSignal) -> Option<vk::CommandBuffer> {
+        let command_buffer = self.end_transfer_batch()?;
+        self.transfer.signal = Some(signal);
+        Some(command_buffer)
+    }
+
+    /// Batches submitted with `signal` are finished, fence passed to `take_transfer_batch` may be reset after this
+    pub fn frame_finished(&mut self, signal: SubmitSignal) {
+        for batch in std::iter::once(&mut self.transfer).chain(&mut self.previous_transfers) {
+            if batch.signal.is_some_and(|s| s.reached_by(signal)) {
+                batch.signal = None;
+                batch.free_retired_buffers(&self.device);
+            }
+        }
+    }
+
+    /// Begin the transfer command buffer, unless the current batch is still recorded
+    fn begin_transfer_batch(&mut self) -> anyhow::Result<()> {
+        if self.transfer_recording {
+            return Ok(());
+        }
+        // later batches may still be executing, the oldest one is reused
+        let oldest = self.previous_transfers.pop_front().unwrap();
+        let previous = std::mem::replace(&mut self.transfer, oldest);
+        self.previous_transfers.push_back(previous);
+        self.transfer.wait(&self.device).map_err(vk_error)?;
+        self.staging_offset = 0;
+        unsafe {
+            self.device
+                .begin_command_buffer(
+                    self.transfer.command_buffer,
+                    &vk::CommandBufferBeginInfo::default()
+                        .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
+                )
+                .map_err(vk_error)?;
+            // frames in flight, submitted before this batch, must finish reading buffers before they are written
+            self.device.cmd_pipeline_barrier(
+                self.transfer.command_buffer,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT
+                    | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[],
+            );
+        }
+        self.transfer_recording = true;
+        Ok(())
+    }
+
+    /// Copies within one batch may execute in any order, so writing a buffer again needs a barrier
+    fn record_transfer_dst(&mut self, buffer: vk::Buffer) {
+        if self.transfer_dst_buffers.contains(&buffer) {
+            let memory_barrier = vk::MemoryBarrier::default()
+                .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+                .dst_access_mask(vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE);
+            unsafe {
+                self.device.cmd_pipeline_barrier(
+                    self.transfer.command_buffer,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::PipelineStageFlags::TRANSFER,
+                    vk::DependencyFlags::empty(),
+                    &[memory_barrier],
+                    &[],
+                    &[],
+                );
+            }
+            self.transfer_dst_buffers.clear();
+        }
+        self.transfer_dst_buffers.push(buffer);
+    }
+
+    /// Finish the batch with a single barrier from all copies to indirect args, vertex input and shader reads,
+    /// and to copies of the next batch, which may start while this one is still executing
+    fn end_transfer_batch(&mut self) -> Option<vk::CommandBuffer> {
+        if !self.transfer_recording {
+            return None;
+        }
+        let memory_barrier = vk::MemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::INDIRECT_COMMAND_READ | vk::AccessFlags::VERTEX_ATTRIBUTE_READ
+                | vk::AccessFlags::INDEX_READ | vk::AccessFlags::UNIFORM_READ | vk::AccessFlags::SHADER_READ
+                | vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE);
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.transfer.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::DRAW_INDIRECT | vk::PipelineStageFlags::VERTEX_INPUT
+                    | vk::PipelineStageFlags::VERTEX_SHADER | vk::PipelineStageFlags::FRAGMENT_SHADER
+                    | vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[],
+            );
+            self.device.end_command_buffer(self.transfer.command_buffer).unwrap();
+        }
+        self.transfer_recording = false;
+        self.transfer_dst_buffers.clear();
+        Some(self.transfer.command_buffer)
+    }
+
+    /// Buffer is freed without waiting, after the batch which may still copy into it is finished. Frames which read
+    /// the buffer must be finished already
     pub fn destroy_buffer(&mut self, buffer: BufferResource) {
         if let Some(index) = self
             .buffer_resources
@@ -215,16 +527,31 @@ impl ResourceManager {
             self.buffer_resources.swap_remove(index);
         }
 
+        // batches finish in submit order, so the recorded or the last submitted one finishes after all copies
+        if self.transfer_recording || self.transfer.signal.is_some() {
+            self.transfer.retired_buffers.push(buffer);
+            return;
+        }
         unsafe {
             self.device.free_memory(buffer.memory, None);
             self.device.destroy_buffer(buffer.buffer, None);
         }
     }
 
-    pub fn fill_buffer<T: Copy + Debug>(&mut self, resource: BufferResource, data: &[T], offset: usize) {
-        //size checktransfer_completed_fence
+    /// Bytes written to buffers with `fill_buffer` since creation
+    pub fn buffer_upload_bytes(&self) -> u64 {
+        self.buffer_upload_bytes
+    }
+
+    /// Panics if `offset + size_of_val(data)` exceeds the buffer size. Fails with `VulkanError::DeviceLost` if
+    /// the device is lost while waiting for staging memory
+    pub fn fill_buffer<T: Copy + Debug>(&mut self, resource: BufferResource, data: &[T], offset: usize) -> anyhow::Result<()> {
         let size = size_of_val(data) as vk::DeviceSize;
-        assert!(size <= resource.size);
+        // stale offset after a resize would silently corrupt memory
+        assert!(offset as vk::DeviceSize + size <= resource.size,
+                "Buffer {:?} update is out of bounds: offset {} + {} bytes > buffer size {}",
+                resource.buffer, offset, size, resource.size);
+        self.buffer_upload_bytes += size;
 
         match self.host_access_policy {
             HostAccessPolicy::SingleBuffer(_) => {
@@ -248,65 +575,17 @@ impl ResourceManager {
                 host_memory_type,
                 device_memory_type: _,
             } => {
-                unsafe {
-                    self.device
-                        .wait_for_fences(&[self.transfer_completed_fence], true, u64::MAX)
-                        .unwrap();
-                    self.device
-                        .reset_fences(&[self.transfer_completed_fence])
-                        .unwrap();
-
-                    self.device
-                        .begin_command_buffer(
-                            self.command_buffer,
-                            &vk::CommandBufferBeginInfo::default()
-                                .flags(CommandBufferUsageFlags::ONE_TIME_SUBMIT),
-                        )
-                        .unwrap();
-                }
-
-                // write to stahing
-                // transfer staging -> device_local
-                //  transfer | vertex_input barrier
-                let staging_buffer: BufferResource;
-
-                if let Some(staging) = self.staging_buffer.take() {
-                    staging_buffer = staging;
-                } else {
-                    let buffer_create_info = vk::BufferCreateInfo::default()
-                        .size(size)
-                        .usage(vk::BufferUsageFlags::TRANSFER_SRC)
-                        .sharing_mode(vk::SharingMode::EXCLUSIVE);
-
-                    let buffer =
-                        unsafe { self.device.create_buffer(&buffer_create_info, None) }.unwrap();
+                self.begin_transfer_batch()?;
+                self.record_transfer_dst(resource.buffer);
 
-                    let memory_requirements =
-                        unsafe { self.device.get_buffer_memory_requirements(buffer) };
-
-                    let memory_allocate_info = vk::MemoryAllocateInfo::default()
-                        .allocation_size(memory_requirements.size)
-                        .memory_type_index(host_memory_type as u32);
-
-                    let memory =
-                        unsafe { self.device.allocate_memory(&memory_allocate_info, None) }
-                            .unwrap();
-
-                    unsafe { self.device.bind_buffer_memory(buffer, memory, 0) }.unwrap();
-
-                    staging_buffer = BufferResource {
-                        buffer,
-                        memory,
-                        size,
-                    };
-                }
+                let (staging_buffer, staging_offset) = self.alloc_staging(size, host_memory_type);
                 unsafe {
                     let mem_ptr = self
                         .device
                         .map_memory(
                             staging_buffer.memory,
-                            offset as DeviceSize,
-                            vk::WHOLE_SIZE,
+                            staging_offset,
+                            size,
                             vk::MemoryMapFlags::empty(),
                         )
                         .unwrap();
@@ -315,49 +594,322 @@ impl ResourceManager {
                     self.device.unmap_memory(staging_buffer.memory);
                 }
 
-                let copy_region = vk::BufferCopy::default().size(size);
+                let copy_region = vk::BufferCopy::default()
+                    .src_offset(staging_offset)
+                    .dst_offset(offset as DeviceSize)
+                    .size(size);
 
+                self.record_staging_copy(staging_buffer.buffer, &[copy_region]);
                 unsafe {
                     self.device.cmd_copy_buffer(
-                        self.command_buffer,
+                        self.transfer.command_buffer,
                         staging_buffer.buffer,
                         resource.buffer,
                         &[copy_region],
                     );
                 }
+            }
+        }
+        Ok(())
+    }
+    /// Copy of `len` bytes of the buffer starting at `offset`, for debugging and tests.
+    ///
+    /// Slow and synchronous: recorded uploads are submitted first, then device-local memory is copied into
+    /// a temporary host-visible buffer and waited for. Buffers in host-visible memory are mapped directly.
+    /// GPU must not write the buffer meanwhile. Panics if the range exceeds the buffer size
+    pub fn read_buffer(&mut self, resource: BufferResource, offset: usize, len: usize) -> anyhow::Result<Vec<u8>> {
+        assert!((offset + len) as DeviceSize <= resource.size,
+                "Buffer {:?} read is out of bounds: offset {} + {} bytes > buffer size {}",
+                resource.buffer, offset, len, resource.size);
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        self.upload_now()?;
 
-                //barrier transfer write to vertex shader read
-                let buffer_memory_barrier = vk::BufferMemoryBarrier::default()
+        match self.host_access_policy {
+            HostAccessPolicy::SingleBuffer(_) => unsafe {
+                let mem_ptr = self.device
+                    .map_memory(resource.memory, offset as DeviceSize, len as DeviceSize, vk::MemoryMapFlags::empty())
+                    .unwrap();
+                let data = std::slice::from_raw_parts(mem_ptr as *const u8, len).to_vec();
+                self.device.unmap_memory(resource.memory);
+                Ok(data)
+            }
+            HostAccessPolicy::UseStaging {
+                host_memory_type,
+                device_memory_type: _,
+            } => {
+                // not shared with uploads, freed right after the copy
+                let readback = self.create_staging_buffer(len as DeviceSize, host_memory_type);
+                self.begin_transfer_batch()?;
+                let copy_region = vk::BufferCopy::default()
+                    .src_offset(offset as DeviceSize)
+                    .dst_offset(0)
+                    .size(len as DeviceSize);
+                let memory_barrier = vk::MemoryBarrier::default()
                     .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
-                    .dst_access_mask(vk::AccessFlags::VERTEX_ATTRIBUTE_READ)
-                    .buffer(resource.buffer)
-                    .offset(0)
-                    .size(vk::WHOLE_SIZE);
-
+                    .dst_access_mask(vk::AccessFlags::HOST_READ);
                 unsafe {
+                    self.device.cmd_copy_buffer(self.transfer.command_buffer, resource.buffer, readback.buffer,
+                                                &[copy_region]);
                     self.device.cmd_pipeline_barrier(
-                        self.command_buffer,
+                        self.transfer.command_buffer,
                         vk::PipelineStageFlags::TRANSFER,
-                        vk::PipelineStageFlags::VERTEX_INPUT,
+                        vk::PipelineStageFlags::HOST,
                         vk::DependencyFlags::empty(),
+                        &[memory_barrier],
                         &[],
-                        &[buffer_memory_barrier],
                         &[],
                     );
                 }
-                self.staging_buffer = Some(staging_buffer);
+                self.upload_now()?;
 
                 unsafe {
-                    self.device.end_command_buffer(self.command_buffer).unwrap();
-                    let command_buffers = [self.command_buffer];
-                    let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
-                    self.device
-                        .queue_submit(self.queue, &[submit_info], self.transfer_completed_fence)
+                    let mem_ptr = self.device
+                        .map_memory(readback.memory, 0, len as DeviceSize, vk::MemoryMapFlags::empty())
                         .unwrap();
+                    let data = std::slice::from_raw_parts(mem_ptr as *const u8, len).to_vec();
+                    self.device.unmap_memory(readback.memory);
+                    self.device.free_memory(readback.memory, None);
+                    self.device.destroy_buffer(readback.buffer, None);
+                    Ok(data)
+                }
+            }
+        }
+    }
+
+    /// Texels of a single-sampled color image with 4 bytes per texel, tightly packed row by row.
+    ///
+    /// Slow and synchronous like `read_buffer`: image is copied into a temporary host-visible buffer after all
+    /// previously submitted work, and waited for. Image must be in `layout`, it is returned to it after the copy
+    pub fn read_image(&mut self, image: vk::Image, extent: Extent2D, layout: vk::ImageLayout) -> anyhow::Result<Vec<u8>> {
+        let len = extent.width as usize * extent.height as usize * 4;
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        let host_memory_type = match self.host_access_policy {
+            HostAccessPolicy::SingleBuffer(memory_type) => memory_type,
+            HostAccessPolicy::UseStaging { host_memory_type, .. } => host_memory_type,
+        };
+        self.upload_now()?;
+
+        let readback = self.create_staging_buffer(len as DeviceSize, host_memory_type);
+        self.begin_transfer_batch()?;
+        let subresource_range = vk::ImageSubresourceRange::default()
+            .aspect_mask(vk::ImageAspectFlags::COLOR)
+            .level_count(1)
+            .layer_count(1);
+        // earlier submits of the queue may still write the image
+        let to_transfer = vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::MEMORY_WRITE)
+            .dst_access_mask(vk::AccessFlags::TRANSFER_READ)
+            .old_layout(layout)
+            .new_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .image(image)
+            .subresource_range(subresource_range);
+        let from_transfer = vk::ImageMemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::empty())
+            .dst_access_mask(vk::AccessFlags::empty())
+            .old_layout(vk::ImageLayout::TRANSFER_SRC_OPTIMAL)
+            .new_layout(layout)
+            .image(image)
+            .subresource_range(subresource_range);
+        let copy_region = vk::BufferImageCopy::default()
+            .image_subresource(vk::ImageSubresourceLayers::default()
+                .aspect_mask(vk::ImageAspectFlags::COLOR)
+                .layer_count(1))
+            .image_extent(Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            });
+        let memory_barrier = vk::MemoryBarrier::default()
+            .src_access_mask(vk::AccessFlags::TRANSFER_WRITE)
+            .dst_access_mask(vk::AccessFlags::HOST_READ);
+        unsafe {
+            self.device.cmd_pipeline_barrier(
+                self.transfer.command_buffer,
+                vk::PipelineStageFlags::ALL_COMMANDS,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            self.device.cmd_copy_image_to_buffer(self.transfer.command_buffer, image,
+                                                 vk::ImageLayout::TRANSFER_SRC_OPTIMAL, readback.buffer, &[copy_region]);
+            self.device.cmd_pipeline_barrier(
+                self.transfer.command_buffer,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST | vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[memory_barrier],
+                &[],
+                &[from_transfer],
+            );
+        }
+        self.upload_now()?;
+
+        unsafe {
+            let mem_ptr = self.device
+                .map_memory(readback.memory, 0, len as DeviceSize, vk::MemoryMapFlags::empty())
+                .unwrap();
+            let data = std::slice::from_raw_parts(mem_ptr as *const u8, len).to_vec();
+            self.device.unmap_memory(readback.memory);
+            self.device.free_memory(readback.memory, None);
+            self.device.destroy_buffer(readback.buffer, None);
+            Ok(data)
+        }
+    }
+
+    /// Move regions of the buffer: each `(src_range, dst_offset)` pair copies bytes of `src_range` to `dst_offset`.
+    ///
+    /// All source ranges are read before anything is written, so sources and destinations may overlap.
+    /// Fails with `VulkanError::DeviceLost` like `fill_buffer`
+    pub fn copy_within_buffer(&mut self, resource: BufferResource, copy_ops: &[(Range<usize>, usize)]) -> anyhow::Result<()> {
+        for (src, dst) in copy_ops {
+            assert!(src.end as DeviceSize <= resource.size && (dst + src.len()) as DeviceSize <= resource.size,
+                    "Copy {:?} -> {} is out of buffer bounds ({} bytes)", src, dst, resource.size);
+        }
+        let total_size: usize = copy_ops.iter().map(|(src, _)| src.len()).sum();
+        if total_size == 0 {
+            return Ok(());
+        }
+
+        match self.host_access_policy {
+            HostAccessPolicy::SingleBuffer(_) => unsafe {
+                let mem_ptr = self
+                    .device
+                    .map_memory(resource.memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
+                    .unwrap();
+                let mem_slice = std::slice::from_raw_parts_mut(mem_ptr as *mut u8, resource.size as usize);
+                let sources: Vec<Vec<u8>> = copy_ops.iter().map(|(src, _)| mem_slice[src.clone()].to_vec()).collect();
+                for ((_, dst), data) in copy_ops.iter().zip(sources) {
+                    mem_slice[*dst..*dst + data.len()].copy_from_slice(&data);
+                }
+                self.device.unmap_memory(resource.memory);
+            }
+            HostAccessPolicy::UseStaging {
+                host_memory_type,
+                device_memory_type: _,
+            } => {
+                // vkCmdCopyBuffer does not allow overlapping regions, so data goes through staging buffer:
+                // buffer -> staging, barrier, staging -> buffer
+                self.begin_transfer_batch()?;
+                // sources may be written by earlier copies of the batch
+                self.record_transfer_dst(resource.buffer);
+                let (staging_buffer, staging_offset) = self.alloc_staging(total_size as DeviceSize, host_memory_type);
+                let mut to_staging = Vec::with_capacity(copy_ops.len());
+                let mut from_staging = Vec::with_capacity(copy_ops.len());
+                let mut staging_offset = staging_offset;
+                for (src, dst) in copy_ops.iter().filter(|(src, _)| !src.is_empty()) {
+                    to_staging.push(vk::BufferCopy::default()
+                        .src_offset(src.start as DeviceSize)
+                        .dst_offset(staging_offset)
+                        .size(src.len() as DeviceSize));
+                    from_staging.push(vk::BufferCopy::default()
+                        .src_offset(staging_offset)
+                        .dst_offset(*dst as DeviceSize)
+                        .size(src.len() as DeviceSize));
+                    staging_offset += src.len() as DeviceSize;
+                }
+
+                unsafe {
+                    self.device.cmd_copy_buffer(self.transfer.command_buffer, resource.buffer, staging_buffer.buffer, &to_staging);
+
+                    // staging must be written and buffer must be read before copying back
+                    let memory_barrier = vk::MemoryBarrier::default()
+                        .src_access_mask(vk::AccessFlags::TRANSFER_WRITE | vk::AccessFlags::TRANSFER_READ)
+                        .dst_access_mask(vk::AccessFlags::TRANSFER_READ | vk::AccessFlags::TRANSFER_WRITE);
+                    self.device.cmd_pipeline_barrier(
+                        self.transfer.command_buffer,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::PipelineStageFlags::TRANSFER,
+                        vk::DependencyFlags::empty(),
+                        &[memory_barrier],
+                        &[],
+                        &[],
+                    );
+
+                    self.device.cmd_copy_buffer(self.transfer.command_buffer, staging_buffer.buffer, resource.buffer, &from_staging);
                 }
+                self.record_staging_copy(staging_buffer.buffer, &from_staging);
+            }
+        }
+        Ok(())
+    }
+
+    /// Space for `size` bytes in the staging buffer, returned with its offset.
+    ///
+    /// Staging buffer is shared by all copies of the batch. When it is full, it is retired until the batch is
+    /// finished and replaced with a larger one. In debug builds, the batch warns when it is finished if no copy out
+    /// of the range was recorded with `record_staging_copy`.
+    fn alloc_staging(&mut self, size: DeviceSize, host_memory_type: usize) -> (BufferResource, DeviceSize) {
+        let (staging, offset) = self.alloc_staging_range(size, host_memory_type);
+        if cfg!(debug_assertions) {
+            self.transfer.staging_writes.record_write(staging.buffer, offset..offset + size);
+        }
+        (staging, offset)
+    }
+
+    /// Copies out of the staging buffer were recorded for `regions`, see `StagingWrites`
+    fn record_staging_copy(&mut self, staging: vk::Buffer, regions: &[vk::BufferCopy]) {
+        if cfg!(debug_assertions) {
+            for region in regions {
+                self.transfer.staging_writes.record_copy(staging, region.src_offset..region.src_offset + region.size);
+            }
+        }
+    }
+
+    /// `alloc_staging` without tracking
+    fn alloc_staging_range(&mut self, size: DeviceSize, host_memory_type: usize) -> (BufferResource, DeviceSize) {
+        let offset = self.staging_offset.next_multiple_of(16);
+        let mut new_size = size.max(MIN_STAGING_SIZE);
+        if let Some(staging) = self.transfer.staging_buffer.take() {
+            if offset + size <= staging.size {
+                self.transfer.staging_buffer = Some(staging);
+                self.staging_offset = offset + size;
+                return (staging, offset);
             }
+            new_size = new_size.max(staging.size * 2);
+            self.transfer.retired_buffers.push(staging);
+        }
+
+        let staging = self.create_staging_buffer(new_size, host_memory_type);
+        self.transfer.staging_buffer = Some(staging);
+        self.staging_offset = size;
+        (staging, 0)
+    }
+
+    fn create_staging_buffer(&mut self, size: DeviceSize, host_memory_type: usize) -> BufferResource {
+        let buffer_create_info = vk::BufferCreateInfo::default()
+            .size(size)
+            .usage(vk::BufferUsageFlags::TRANSFER_SRC | vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE);
+
+        let buffer =
+            unsafe { self.device.create_buffer(&buffer_create_info, None) }.unwrap();
+
+        let memory_requirements =
+            unsafe { self.device.get_buffer_memory_requirements(buffer) };
+
+        let memory_allocate_info = vk::MemoryAllocateInfo::default()
+            .allocation_size(memory_requirements.size)
+            .memory_type_index(host_memory_type as u32);
+
+        let memory =
+            unsafe { self.device.allocate_memory(&memory_allocate_info, None) }
+                .unwrap();
+
+        unsafe { self.device.bind_buffer_memory(buffer, memory, 0) }.unwrap();
+
+        BufferResource {
+            buffer,
+            memory,
+            size,
         }
     }
+
     pub fn cmd_barrier_after_vertex_buffer_use(
         &mut self,
         device: &ash::Device,
@@ -419,24 +971,29 @@ impl ResourceManager {
         usage: vk::ImageUsageFlags,
         sample_count: SampleCountFlags,
     ) -> ImageResource {
-        let extent = Extent3D::from(extent);
         let image_create_info = image_2d_info(format, usage | vk::ImageUsageFlags::TRANSFER_DST, extent, sample_count, tiling);
+        self.create_image_with_info(image_create_info)
+    }
 
+    fn create_image_with_info(&mut self, image_create_info: ImageCreateInfo<'static>) -> ImageResource {
+        let (format, extent) = (image_create_info.format, image_create_info.extent);
         let image = unsafe { self.device.create_image(&image_create_info, None) }.unwrap();
 
         let memory_requirements = unsafe { self.device.get_image_memory_requirements(image) };
 
+        // some formats are not supported in device local memory on integrated GPUs
         let memory_type_device = self
-            .memory_types
-            .iter()
-            .enumerate()
-            .position(|(i, memory_type)| {
-                memory_requirements.memory_type_bits & (1 << i) != 0
-                    && memory_type
-                        .property_flags
-                        .contains(vk::MemoryPropertyFlags::DEVICE_LOCAL)
-            })
-            .unwrap();
+            .select_memory_type(memory_requirements.memory_type_bits,
+                                &[vk::MemoryPropertyFlags::DEVICE_LOCAL, vk::MemoryPropertyFlags::HOST_VISIBLE])
+            .expect("No memory type is compatible with the image");
+        let flags = self.memory_types[memory_type_device].property_flags;
+        if flags.contains(vk::MemoryPropertyFlags::DEVICE_LOCAL) {
+            debug!("Image {:?} {}x{}: memory type {} ({:?})", format, extent.width, extent.height,
+                   memory_type_device, flags);
+        } else {
+            warn!("Image {:?} {}x{}: no device local memory type, using {} ({:?})", format, extent.width,
+                  extent.height, memory_type_device, flags);
+        }
 
         let memory_allocate_info = vk::MemoryAllocateInfo::default()
             .allocation_size(memory_requirements.size)
@@ -459,6 +1016,34 @@ impl ResourceManager {
         res
     }
 
+    /// Sampled image with `mip_levels` levels and `array_layers` layers, to be filled with `fill_image_levels`
+    pub fn create_texture_image(&mut self, extent: Extent2D, format: vk::Format, mip_levels: u32,
+                                array_layers: u32) -> ImageResource {
+        let info = image_2d_info(format, vk::ImageUsageFlags::SAMPLED | vk::ImageUsageFlags::TRANSFER_DST, extent,
+                                 SampleCountFlags::TYPE_1, vk::ImageTiling::OPTIMAL)
+            .mip_levels(mip_levels.max(1))
+            .array_layers(array_layers.max(1));
+        self.create_image_with_info(info)
+    }
+
+    /// Format can be sampled from an image with optimal tiling
+    pub fn supports_sampled_format(&self, format: vk::Format) -> bool {
+        let properties = unsafe {
+            self.device.instance().get_physical_device_format_properties(self.physical_device, format)
+        };
+        properties.optimal_tiling_features.contains(vk::FormatFeatureFlags::SAMPLED_IMAGE)
+    }
+
+    /// Memory type allowed by `type_bits`, with flags of the first satisfied preference.
+    /// Falls back to any allowed type, `None` only if `type_bits` allows nothing
+    fn select_memory_type(&self, type_bits: u32, preferences: &[vk::MemoryPropertyFlags]) -> Option<usize> {
+        let allowed = |i: usize| type_bits & (1 << i) != 0;
+        preferences.iter()
+            .find_map(|flags| (0..self.memory_types.len())
+                .find(|&i| allowed(i) && self.memory_types[i].property_flags.contains(*flags)))
+            .or_else(|| (0..self.memory_types.len()).find(|&i| allowed(i)))
+    }
+
     pub fn destroy_image(&mut self, image: ImageResource) {
         if let Some(index) = self
             .image_resources
@@ -474,10 +1059,27 @@ impl ResourceManager {
         unsafe { self.device.destroy_image(image.image, None) };
     }
 
+    pub fn fill_image(&mut self, image_resource: ImageResource, data: &[u8]) -> anyhow::Result<()> {
+        self.fill_image_levels(image_resource, &[data])
+    }
+
+    /// Upload data of mip levels starting from level 0, tightly packed as by `vkCmdCopyBufferToImage`.
+    /// Data of a level contains all array layers one after another.
+    /// Image is left in SHADER_READ_ONLY_OPTIMAL layout. Fails with `VulkanError::DeviceLost` if the device is lost
     // TODO: save buffer or free it
-    pub fn fill_image(&mut self, image_resource: ImageResource, data: &[u8]) {
+    pub fn fill_image_levels(&mut self, image_resource: ImageResource, levels: &[&[u8]]) -> anyhow::Result<()> {
+        // command buffer is shared with buffer transfers
+        self.upload_now()?;
+
+        // offsets must be multiple of the texel block size, which is at most 16 bytes
+        let mut offsets = Vec::with_capacity(levels.len());
+        let mut total_size = 0;
+        for level in levels {
+            offsets.push(total_size);
+            total_size = (total_size + level.len()).next_multiple_of(16);
+        }
         let buffer_create_info = vk::BufferCreateInfo::default()
-            .size(data.len() as u64)
+            .size(total_size as u64)
             .usage(vk::BufferUsageFlags::TRANSFER_SRC)
             .sharing_mode(vk::SharingMode::EXCLUSIVE);
 
@@ -485,16 +1087,11 @@ impl ResourceManager {
 
         let memory_requirements = unsafe { self.device.get_buffer_memory_requirements(buffer) };
 
-        let memory_type_host = self
-            .memory_types
-            .iter()
-            .enumerate()
-            .position(|(i, memory_type)| {
-                memory_requirements.memory_type_bits & (1 << i) != 0
-                    && memory_type
-                        .property_flags
-                        .contains(vk::MemoryPropertyFlags::HOST_VISIBLE)
-            })
+        // coherent memory is preferred, but host visible is enough with explicit flush
+        let memory_type_host = (0..self.memory_types.len())
+            .filter(|&i| memory_requirements.memory_type_bits & (1 << i) != 0
+                && self.memory_types[i].property_flags.contains(vk::MemoryPropertyFlags::HOST_VISIBLE))
+            .min_by_key(|&i| !self.memory_types[i].property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT))
             .unwrap();
 
         let memory_allocate_info = vk::MemoryAllocateInfo::default()
@@ -510,29 +1107,48 @@ impl ResourceManager {
                 .device
                 .map_memory(memory, 0, vk::WHOLE_SIZE, vk::MemoryMapFlags::empty())
                 .unwrap();
-            let mem_slice = std::slice::from_raw_parts_mut(mem_ptr as *mut u8, data.len());
-            mem_slice.copy_from_slice(data);
+            let mem_slice = std::slice::from_raw_parts_mut(mem_ptr as *mut u8, total_size);
+            for (level, &offset) in levels.iter().zip(&offsets) {
+                mem_slice[offset..offset + level.len()].copy_from_slice(level);
+            }
+            if !self.memory_types[memory_type_host].property_flags.contains(vk::MemoryPropertyFlags::HOST_COHERENT) {
+                let range = non_coherent_range(0, total_size as DeviceSize, self.non_coherent_atom_size,
+                                               memory_requirements.size);
+                let ranges = [vk::MappedMemoryRange::default()
+                    .memory(memory)
+                    .offset(range.start)
+                    .size(range.end - range.start)];
+                self.device.flush_mapped_memory_ranges(&ranges).unwrap();
+            }
             self.device.unmap_memory(memory);
         }
 
-        let copy_region = vk::BufferImageCopy::default()
-            .image_subresource(
-                vk::ImageSubresourceLayers::default()
-                    .aspect_mask(vk::ImageAspectFlags::COLOR)
-                    .mip_level(0)
-                    .base_array_layer(0)
-                    .layer_count(1),
-            )
-            .image_extent(image_resource.extent);
+        let copy_regions: Vec<_> = offsets.iter().enumerate().map(|(level, &offset)| {
+            let extent = image_resource.extent;
+            vk::BufferImageCopy::default()
+                .buffer_offset(offset as DeviceSize)
+                .image_subresource(
+                    vk::ImageSubresourceLayers::default()
+                        .aspect_mask(vk::ImageAspectFlags::COLOR)
+                        .mip_level(level as u32)
+                        .base_array_layer(0)
+                        .layer_count(image_resource.info.array_layers),
+                )
+                .image_extent(Extent3D {
+                    width: (extent.width >> level).max(1),
+                    height: (extent.height >> level).max(1),
+                    depth: 1,
+                })
+        }).collect();
 
         unsafe {
             self.device
                 .begin_command_buffer(
-                    self.command_buffer,
+                    self.transfer.command_buffer,
                     &vk::CommandBufferBeginInfo::default()
                         .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT),
                 )
-                .unwrap();
+                .map_err(vk_error)?;
 
             // transition image layout from undefined to transfer destination
             let image_memory_barrier = vk::ImageMemoryBarrier::default()
@@ -545,13 +1161,13 @@ impl ResourceManager {
                     vk::ImageSubresourceRange::default()
                         .aspect_mask(vk::ImageAspectFlags::COLOR)
                         .base_mip_level(0)
-                        .level_count(1)
+                        .level_count(levels.len() as u32)
                         .base_array_layer(0)
-                        .layer_count(1),
+                        .layer_count(image_resource.info.array_layers),
                 );
 
             self.device.cmd_pipeline_barrier(
-                self.command_buffer,
+                self.transfer.command_buffer,
                 vk::PipelineStageFlags::TOP_OF_PIPE,
                 vk::PipelineStageFlags::TRANSFER,
                 vk::DependencyFlags::empty(),
@@ -561,11 +1177,11 @@ impl ResourceManager {
             );
 
             self.device.cmd_copy_buffer_to_image(
-                self.command_buffer,
+                self.transfer.command_buffer,
                 buffer,
                 image_resource.image,
                 vk::ImageLayout::TRANSFER_DST_OPTIMAL,
-                &[copy_region],
+                &copy_regions,
             );
 
             // transition image layout from transfer destination to shader read
@@ -579,13 +1195,13 @@ impl ResourceManager {
                     vk::ImageSubresourceRange::default()
                         .aspect_mask(vk::ImageAspectFlags::COLOR)
                         .base_mip_level(0)
-                        .level_count(1)
+                        .level_count(levels.len() as u32)
                         .base_array_layer(0)
-                        .layer_count(1),
+                        .layer_count(image_resource.info.array_layers),
                 );
 
             self.device.cmd_pipeline_barrier(
-                self.command_buffer,
+                self.transfer.command_buffer,
                 vk::PipelineStageFlags::TRANSFER,
                 vk::PipelineStageFlags::FRAGMENT_SHADER,
                 vk::DependencyFlags::empty(),
@@ -594,20 +1210,21 @@ impl ResourceManager {
                 &[image_memory_barrier],
             );
 
-            self.device.end_command_buffer(self.command_buffer).unwrap();
+            self.device.end_command_buffer(self.transfer.command_buffer).map_err(vk_error)?;
 
-            let command_buffers = [self.command_buffer];
+            let command_buffers = [self.transfer.command_buffer];
             let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
 
-            self.device
-                .queue_submit(self.queue, &[submit_info], vk::Fence::null())
-                .unwrap();
+            self.queue
+                .submit(&[submit_info], vk::Fence::null())
+                .map_err(vk_error)?;
 
-            self.device.queue_wait_idle(self.queue).unwrap();
+            self.queue.wait_idle().map_err(vk_error)?;
 
             self.device.free_memory(memory, None);
             self.device.destroy_buffer(buffer, None);
         }
+        Ok(())
     }
 
     pub fn create_sampler(&mut self) -> Sampler {
@@ -625,7 +1242,7 @@ impl ResourceManager {
             .compare_op(vk::CompareOp::ALWAYS)
             .mipmap_mode(vk::SamplerMipmapMode::LINEAR)
             .min_lod(0.0)
-            .max_lod(0.0)
+            .max_lod(vk::LOD_CLAMP_NONE)
             .mip_lod_bias(0.0);
 
         let sampler = unsafe { self.device.create_sampler(&sampler_create_info, None) }.unwrap();
@@ -637,6 +1254,25 @@ impl ResourceManager {
 impl Drop for ResourceManager {
     fn drop(&mut self) {
         let g = range_event_start!("[Vulkan] Destroy resource manager");
+        // lost device does not use the resources anymore
+        if let Err(e) = self.upload_now() {
+            error!("Failed to finish transfers before destroying resources: {}", e);
+        }
+        // cheap leak detector: everything freed here was not destroyed by its owner
+        if cfg!(debug_assertions) && !(self.buffer_resources.is_empty() && self.image_resources.is_empty()) {
+            warn!("{} buffers and {} images were not destroyed explicitly",
+                  self.buffer_resources.len(), self.image_resources.len());
+            for buffer in &self.buffer_resources {
+                warn!("  buffer {:?}, {} bytes", buffer.buffer, buffer.size);
+            }
+            for image in &self.image_resources {
+                warn!("  image {:?}, {}x{}, {:?}", image.image, image.extent.width, image.extent.height, image.info.format);
+            }
+        }
+        self.transfer.destroy(&self.device);
+        for batch in &mut self.previous_transfers {
+            batch.destroy(&self.device);
+        }
         for image_res in self.image_resources.drain(..) {
             unsafe {
                 self.device.free_memory(image_res.memory, None);
@@ -661,3 +1297,218 @@ impl Drop for ResourceManager {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use ash::vk;
+    use ash::vk::Handle;
+    use crate::vulkan_backend::config::VulkanRenderConfig;
+    use crate::vulkan_backend::frame_sync::SubmitSignal;
+    use crate::vulkan_backend::VulkanBackend;
+    use super::{non_coherent_range, HostAccessPolicy, StagingWrites};
+
+    #[test]
+    fn misaligned_small_write_covers_whole_atoms() {
+        // 4 bytes in the middle of the second 256 byte atom
+        assert_eq!(non_coherent_range(300, 4, 256, 4096), 256..512);
+        // crossing an atom boundary takes both atoms
+        assert_eq!(non_coherent_range(250, 10, 256, 4096), 0..512);
+    }
+
+    #[test]
+    fn aligned_write_is_unchanged() {
+        assert_eq!(non_coherent_range(512, 256, 256, 4096), 512..768);
+    }
+
+    #[test]
+    fn end_is_clamped_to_allocation() {
+        // allocation size is not a multiple of the atom size
+        assert_eq!(non_coherent_range(1000, 10, 256, 1020), 768..1020);
+    }
+
+    #[test]
+    fn zero_atom_size_is_byte_granular() {
+        assert_eq!(non_coherent_range(3, 5, 0, 64), 3..8);
+    }
+
+    #[test]
+    fn staging_write_without_copy_is_reported() {
+        let staging = vk::Buffer::null();
+        let mut writes = StagingWrites::default();
+        writes.record_write(staging, 0..64);
+        writes.record_write(staging, 64..80);
+        writes.record_write(staging, 80..96);
+        // part of the range is enough, e.g. one of several regions of `copy_within_buffer`
+        writes.record_copy(staging, 16..32);
+        writes.record_copy(staging, 80..96);
+        assert_eq!(writes.take_uncopied(), vec![(staging, 64..80)]);
+        assert!(writes.take_uncopied().is_empty());
+    }
+
+    #[test]
+    fn copy_from_other_staging_buffer_does_not_count() {
+        let staging = vk::Buffer::null();
+        let other = vk::Buffer::from_raw(1);
+        let mut writes = StagingWrites::default();
+        writes.record_write(staging, 0..16);
+        writes.record_copy(other, 0..16);
+        assert_eq!(writes.take_uncopied(), vec![(staging, 0..16)]);
+    }
+
+    fn timeline_semaphore(device: &ash::Device) -> vk::Semaphore {
+        let mut type_info = vk::SemaphoreTypeCreateInfo::default()
+            .semaphore_type(vk::SemaphoreType::TIMELINE)
+            .initial_value(0);
+        let create_info = vk::SemaphoreCreateInfo::default().push_next(&mut type_info);
+        unsafe { device.create_semaphore(&create_info, None) }.unwrap()
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn staging_reuse_waits_only_for_batch_before_previous() {
+        let config = VulkanRenderConfig {
+            timeline_semaphores: true,
+            ..Default::default()
+        };
+        let mut backend = VulkanBackend::new_headless((16, 16), config).unwrap();
+        let resource_manager = &mut backend.resource_manager;
+        let device = resource_manager.device.clone();
+        assert!(device.timeline_semaphores_enabled(), "Device has no timeline semaphores");
+        if let HostAccessPolicy::SingleBuffer(memory_type) = resource_manager.host_access_policy {
+            resource_manager.host_access_policy = HostAccessPolicy::UseStaging {
+                host_memory_type: memory_type,
+                device_memory_type: memory_type,
+            };
+        }
+        let buffer = resource_manager.create_buffer(64, vk::BufferUsageFlags::UNIFORM_BUFFER);
+        resource_manager.upload_now().unwrap();
+
+        // first batch does not start on GPU until `gate` is signaled from host
+        let gate = timeline_semaphore(&device);
+        let first_done = timeline_semaphore(&device);
+        resource_manager.fill_buffer(buffer, &[1u8; 64], 0).unwrap();
+        let command_buffer = resource_manager
+            .take_transfer_batch(SubmitSignal::Timeline { semaphore: first_done, value: 1 })
+            .unwrap();
+        let command_buffers = [command_buffer];
+        let wait_semaphores = [gate];
+        let wait_stages = [vk::PipelineStageFlags::TRANSFER];
+        let signal_semaphores = [first_done];
+        let values = [1];
+        let mut timeline_info = vk::TimelineSemaphoreSubmitInfo::default()
+            .wait_semaphore_values(&values)
+            .signal_semaphore_values(&values);
+        let submit_info = vk::SubmitInfo::default()
+            .command_buffers(&command_buffers)
+            .wait_semaphores(&wait_semaphores)
+            .wait_dst_stage_mask(&wait_stages)
+            .signal_semaphores(&signal_semaphores)
+            .push_next(&mut timeline_info);
+        unsafe { resource_manager.queue.submit(&[submit_info], vk::Fence::null()) }.unwrap();
+
+        let opener = {
+            let device = device.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_secs(1));
+                device.signal_semaphore_value(gate, 1).unwrap();
+            })
+        };
+
+        // second batch has its own command buffer and staging memory, recording it must not block
+        resource_manager.fill_buffer(buffer, &[2u8; 64], 0).unwrap();
+        assert_eq!(device.semaphore_value(first_done).unwrap(), 0,
+                   "Recording the next batch waited for the previous submit");
+        let second_done = unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None) }.unwrap();
+        let command_buffer = resource_manager.take_transfer_batch(SubmitSignal::Fence(second_done)).unwrap();
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+        unsafe { resource_manager.queue.submit(&[submit_info], second_done) }.unwrap();
+
+        // third batch reuses staging memory of the first one, which must be finished by then
+        resource_manager.fill_buffer(buffer, &[3u8; 64], 0).unwrap();
+        assert_eq!(device.semaphore_value(first_done).unwrap(), 1);
+
+        assert_eq!(resource_manager.read_buffer(buffer, 0, 64).unwrap(), vec![3u8; 64]);
+        opener.join().unwrap();
+        unsafe {
+            device.destroy_fence(second_done, None);
+            device.destroy_semaphore(first_done, None);
+            device.destroy_semaphore(gate, None);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn destroyed_buffer_is_freed_with_its_batch() {
+        let mut backend = VulkanBackend::new_headless((16, 16), VulkanRenderConfig::default()).unwrap();
+        let resource_manager = &mut backend.resource_manager;
+        let device = resource_manager.device.clone();
+        if let HostAccessPolicy::SingleBuffer(memory_type) = resource_manager.host_access_policy {
+            resource_manager.host_access_policy = HostAccessPolicy::UseStaging {
+                host_memory_type: memory_type,
+                device_memory_type: memory_type,
+            };
+        }
+        let buffer = resource_manager.create_buffer(64, vk::BufferUsageFlags::VERTEX_BUFFER);
+        resource_manager.fill_buffer(buffer, &[1u8; 64], 0).unwrap();
+
+        // copy into the buffer is still recorded, it is neither submitted nor waited for
+        resource_manager.destroy_buffer(buffer);
+        assert!(resource_manager.transfer_recording);
+        assert!(resource_manager.transfer.retired_buffers.iter().any(|b| b.buffer == buffer.buffer));
+
+        let frame_done = unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None) }.unwrap();
+        let signal = SubmitSignal::Fence(frame_done);
+        let command_buffer = resource_manager.take_transfer_batch(signal).unwrap();
+        let command_buffers = [command_buffer];
+        let submit_info = vk::SubmitInfo::default().command_buffers(&command_buffers);
+        unsafe {
+            resource_manager.queue.submit(&[submit_info], frame_done).unwrap();
+            device.wait_for_fences(&[frame_done], true, u64::MAX).unwrap();
+        }
+        resource_manager.frame_finished(signal);
+        assert!(resource_manager.transfer.retired_buffers.is_empty());
+        unsafe {
+            device.destroy_fence(frame_done, None);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs a Vulkan device"]
+    fn drop_after_gpu_timeout_does_not_wait_for_transfers() {
+        let mut backend = VulkanBackend::new_headless((16, 16), VulkanRenderConfig::default()).unwrap();
+        let resource_manager = &mut backend.resource_manager;
+        let device = resource_manager.device.clone();
+        if let HostAccessPolicy::SingleBuffer(memory_type) = resource_manager.host_access_policy {
+            resource_manager.host_access_policy = HostAccessPolicy::UseStaging {
+                host_memory_type: memory_type,
+                device_memory_type: memory_type,
+            };
+        }
+        // not destroyed explicitly, freed by the resource manager on drop
+        let buffer = resource_manager.create_buffer(64, vk::BufferUsageFlags::UNIFORM_BUFFER);
+        resource_manager.fill_buffer(buffer, &[1u8; 64], 0).unwrap();
+        // batch is never submitted, so its fence is never signaled, like a frame of a hung GPU
+        let never_signaled = unsafe { device.create_fence(&vk::FenceCreateInfo::default(), None) }.unwrap();
+        resource_manager.take_transfer_batch(SubmitSignal::Fence(never_signaled)).unwrap();
+        backend.gpu_hung = true;
+
+        // backend is not `Send`, a blocked drop can't be interrupted otherwise
+        let (sender, receiver) = mpsc::channel::<()>();
+        let watchdog = thread::spawn(move || {
+            if receiver.recv_timeout(Duration::from_secs(5)).is_err() {
+                eprintln!("Drop waited for a transfer batch of the hung frame");
+                std::process::abort();
+            }
+        });
+        drop(backend);
+        sender.send(()).unwrap();
+        watchdog.join().unwrap();
+        unsafe {
+            device.destroy_fence(never_signaled, None);
+        }
+    }
+}
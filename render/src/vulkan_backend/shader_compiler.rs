@@ -0,0 +1,43 @@
+use std::fmt;
+use anyhow::anyhow;
+use log::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShaderStage {
+    Vertex,
+    Fragment,
+}
+
+impl fmt::Display for ShaderStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ShaderStage::Vertex => write!(f, "vertex"),
+            ShaderStage::Fragment => write!(f, "fragment"),
+        }
+    }
+}
+
+/// Compile GLSL source of one stage to SPIR-V with libshaderc, for prototyping and hot reload.
+///
+/// `name` tags the source in the compiler log, which has a `name:line: error: ...` line per error.
+/// `#include` is not supported, shaders built with `render_build` should be loaded as SPIR-V instead
+pub fn compile_glsl(source: &str, stage: ShaderStage, name: &str) -> anyhow::Result<Vec<u8>> {
+    let mut compiler = shaderc::Compiler::new().ok_or_else(|| anyhow!("Failed to create shaderc compiler"))?;
+    let mut options = shaderc::CompileOptions::new().ok_or_else(|| anyhow!("Failed to create shaderc options"))?;
+    options.set_target_env(shaderc::TargetEnv::Vulkan, shaderc::EnvVersion::Vulkan1_0 as u32);
+    let kind = match stage {
+        ShaderStage::Vertex => shaderc::ShaderKind::Vertex,
+        ShaderStage::Fragment => shaderc::ShaderKind::Fragment,
+    };
+    let artifact = compiler.compile_into_spirv(source, kind, name, "main", Some(&options))
+        .map_err(|e| match e {
+            shaderc::Error::CompilationError(count, log) => {
+                anyhow!("Failed to compile {} shader {}, {} errors:\n{}", stage, name, count, log.trim_end())
+            }
+            e => anyhow!("Failed to compile {} shader {}: {}", stage, name, e),
+        })?;
+    if artifact.get_num_warnings() > 0 {
+        warn!("Warnings in {} shader {}:\n{}", stage, name, artifact.get_warning_messages().trim_end());
+    }
+    Ok(artifact.as_binary_u8().to_vec())
+}
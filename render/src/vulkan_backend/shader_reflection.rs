@@ -0,0 +1,254 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use anyhow::anyhow;
+use log::warn;
+use rspirv::dr::{Instruction, Module, Operand};
+use rspirv::spirv::{Decoration, Dim, Op, StorageClass, Word};
+use render_core::pipeline::{PipelineDescWrapper, UniformBindingType};
+
+/// Kind of descriptor a shader variable is accessed through
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DescriptorKind {
+    UniformBuffer,
+    StorageBuffer,
+    CombinedImageSampler,
+    SampledImage,
+    StorageImage,
+    Sampler,
+    InputAttachment,
+    Other,
+}
+
+impl DescriptorKind {
+    fn declared(binding_type: &UniformBindingType) -> Self {
+        match binding_type {
+            UniformBindingType::UniformBuffer | UniformBindingType::UniformBufferDynamic { .. } => {
+                DescriptorKind::UniformBuffer
+            }
+            UniformBindingType::CombinedImageSampler => DescriptorKind::CombinedImageSampler,
+            UniformBindingType::InputAttachment => DescriptorKind::InputAttachment,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReflectedBinding {
+    pub set: u32,
+    pub binding: u32,
+    pub kind: DescriptorKind,
+    /// From debug info, if the module has it
+    pub name: Option<String>,
+}
+
+impl fmt::Display for ReflectedBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "set {} binding {} ({:?}", self.set, self.binding, self.kind)?;
+        if let Some(name) = &self.name {
+            write!(f, " `{}`", name)?;
+        }
+        write!(f, ")")
+    }
+}
+
+/// Descriptor bindings and vertex inputs used by a SPIR-V module
+#[derive(Debug, Default)]
+pub struct ShaderReflection {
+    pub bindings: Vec<ReflectedBinding>,
+    /// Locations of `Input` variables, a matrix takes one per column. Only meaningful for vertex shaders
+    pub input_locations: Vec<u32>,
+}
+
+impl ShaderReflection {
+    pub fn new(spirv: &[u8]) -> anyhow::Result<Self> {
+        let module = rspirv::dr::load_bytes(spirv).map_err(|e| anyhow!("Invalid SPIR-V module: {:?}", e))?;
+        Ok(Reflector::new(&module).reflect())
+    }
+}
+
+/// Decorations, names and type instructions of a module by result id
+struct Reflector<'a> {
+    module: &'a Module,
+    decorations: BTreeMap<Word, Vec<(Decoration, Option<u32>)>>,
+    names: BTreeMap<Word, &'a str>,
+    types: BTreeMap<Word, &'a Instruction>,
+}
+
+impl<'a> Reflector<'a> {
+    fn new(module: &'a Module) -> Self {
+        let mut decorations: BTreeMap<Word, Vec<_>> = BTreeMap::new();
+        for inst in module.annotations.iter().filter(|inst| inst.class.opcode == Op::Decorate) {
+            if let [Operand::IdRef(target), Operand::Decoration(decoration), rest @ ..] = inst.operands.as_slice() {
+                let value = match rest.first() {
+                    Some(Operand::LiteralInt32(value)) => Some(*value),
+                    _ => None,
+                };
+                decorations.entry(*target).or_default().push((*decoration, value));
+            }
+        }
+        let names = module.debug_names.iter()
+            .filter(|inst| inst.class.opcode == Op::Name)
+            .filter_map(|inst| match inst.operands.as_slice() {
+                [Operand::IdRef(target), Operand::LiteralString(name), ..] => Some((*target, name.as_str())),
+                _ => None,
+            })
+            .collect();
+        let types = module.types_global_values.iter()
+            .filter_map(|inst| inst.result_id.map(|id| (id, inst)))
+            .collect();
+        Self {
+            module,
+            decorations,
+            names,
+            types,
+        }
+    }
+
+    fn decoration(&self, id: Word, decoration: Decoration) -> Option<Option<u32>> {
+        self.decorations.get(&id)?.iter()
+            .find(|(d, _)| *d == decoration)
+            .map(|(_, value)| *value)
+    }
+
+    fn id_operand(inst: &Instruction, index: usize) -> Option<Word> {
+        match inst.operands.get(index) {
+            Some(Operand::IdRef(id)) => Some(*id),
+            _ => None,
+        }
+    }
+
+    /// Element type of arrays, which take a single binding
+    fn strip_arrays(&self, mut type_id: Word) -> Option<&'a Instruction> {
+        loop {
+            let inst = *self.types.get(&type_id)?;
+            match inst.class.opcode {
+                Op::TypeArray | Op::TypeRuntimeArray => type_id = Self::id_operand(inst, 0)?,
+                _ => return Some(inst),
+            }
+        }
+    }
+
+    fn descriptor_kind(&self, storage_class: StorageClass, pointee: Word) -> DescriptorKind {
+        let Some(ty) = self.strip_arrays(pointee) else {
+            return DescriptorKind::Other;
+        };
+        let ty_id = ty.result_id.unwrap_or_default();
+        match (storage_class, ty.class.opcode) {
+            (StorageClass::Uniform, Op::TypeStruct) if self.decoration(ty_id, Decoration::BufferBlock).is_some() => {
+                DescriptorKind::StorageBuffer
+            }
+            (StorageClass::Uniform, Op::TypeStruct) => DescriptorKind::UniformBuffer,
+            (StorageClass::StorageBuffer, _) => DescriptorKind::StorageBuffer,
+            (_, Op::TypeSampledImage) => DescriptorKind::CombinedImageSampler,
+            (_, Op::TypeSampler) => DescriptorKind::Sampler,
+            (_, Op::TypeImage) => match (ty.operands.get(1), ty.operands.get(5)) {
+                (Some(Operand::Dim(Dim::DimSubpassData)), _) => DescriptorKind::InputAttachment,
+                (_, Some(Operand::LiteralInt32(2))) => DescriptorKind::StorageImage,
+                _ => DescriptorKind::SampledImage,
+            },
+            _ => DescriptorKind::Other,
+        }
+    }
+
+    fn location_count(&self, type_id: Word) -> u32 {
+        match self.types.get(&type_id) {
+            Some(inst) if inst.class.opcode == Op::TypeMatrix => match inst.operands.get(1) {
+                Some(Operand::LiteralInt32(columns)) => *columns,
+                _ => 1,
+            },
+            _ => 1,
+        }
+    }
+
+    fn reflect(&self) -> ShaderReflection {
+        let mut reflection = ShaderReflection::default();
+        for inst in self.module.types_global_values.iter().filter(|inst| inst.class.opcode == Op::Variable) {
+            let (Some(id), Some(pointer_type)) = (inst.result_id, inst.result_type) else {
+                continue;
+            };
+            let Some(Operand::StorageClass(storage_class)) = inst.operands.first() else {
+                continue;
+            };
+            let pointee = self.types.get(&pointer_type).and_then(|pointer| Self::id_operand(pointer, 1));
+            let Some(pointee) = pointee else {
+                continue;
+            };
+            match storage_class {
+                StorageClass::Uniform | StorageClass::UniformConstant | StorageClass::StorageBuffer => {
+                    let (Some(Some(set)), Some(Some(binding))) = (self.decoration(id, Decoration::DescriptorSet),
+                                                                  self.decoration(id, Decoration::Binding)) else {
+                        continue;
+                    };
+                    reflection.bindings.push(ReflectedBinding {
+                        set,
+                        binding,
+                        kind: self.descriptor_kind(*storage_class, pointee),
+                        name: self.names.get(&id).map(|name| name.to_string()),
+                    });
+                }
+                StorageClass::Input => {
+                    // built-ins like gl_VertexIndex have no location
+                    if let Some(Some(location)) = self.decoration(id, Decoration::Location) {
+                        reflection.input_locations.extend(location..location + self.location_count(pointee));
+                    }
+                }
+                _ => {}
+            }
+        }
+        reflection
+    }
+}
+
+/// Check descriptor bindings and vertex attributes of `desc` against shaders it is created with.
+///
+/// Panics with all mismatches listed: bindings used by shaders but not declared or declared with another
+/// descriptor type, sets other than 0, and vertex inputs without an attribute. Declared bindings which no
+/// shader uses are only logged
+pub fn check_pipeline_desc(desc: &PipelineDescWrapper, vertex_shader: &[u8], fragment_shader: &[u8]) {
+    let stages = [("vertex", vertex_shader), ("fragment", fragment_shader)].map(|(stage, spirv)| {
+        let reflection = ShaderReflection::new(spirv)
+            .unwrap_or_else(|e| panic!("Failed to reflect {} shader of pipeline {}: {}", stage, desc.name, e));
+        (stage, reflection)
+    });
+
+    let mut errors = Vec::new();
+    let mut used: BTreeMap<u32, DescriptorKind> = BTreeMap::new();
+    for (stage, reflection) in &stages {
+        for binding in &reflection.bindings {
+            if binding.set != 0 {
+                let texture_array = desc.bindless_textures && binding.set == 1 && binding.binding == 0
+                    && binding.kind == DescriptorKind::CombinedImageSampler;
+                if !texture_array {
+                    errors.push(format!("{} shader uses {}, only set 0 and the bindless texture array are bound",
+                                        stage, binding));
+                }
+                continue;
+            }
+            let declared = desc.uniform_bindings.iter().find(|(b, _)| *b == binding.binding);
+            match declared {
+                None => errors.push(format!("{} shader uses {}, which is not declared in get_uniform_bindings",
+                                            stage, binding)),
+                Some((_, ty)) if DescriptorKind::declared(ty) != binding.kind => {
+                    errors.push(format!("{} shader uses {}, but it is declared as {:?}", stage, binding, ty))
+                }
+                Some(_) => {}
+            }
+            used.insert(binding.binding, binding.kind);
+        }
+    }
+    for (binding, ty) in &desc.uniform_bindings {
+        if !used.contains_key(binding) {
+            warn!("Pipeline {}: binding {} ({:?}) is declared but not used by shaders", desc.name, binding, ty);
+        }
+    }
+
+    let (_, vertex) = &stages[0];
+    for location in &vertex.input_locations {
+        if !desc.attributes.locations().any(|l| l == *location) {
+            errors.push(format!("vertex shader reads input location {}, which has no attribute", location));
+        }
+    }
+
+    if !errors.is_empty() {
+        panic!("Pipeline {} does not match its shaders:\n  {}", desc.name, errors.join("\n  "));
+    }
+}
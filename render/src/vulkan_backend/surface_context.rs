@@ -0,0 +1,794 @@
+use std::time::Instant;
+use ash::vk::{self, CommandBuffer, CommandBufferBeginInfo, Extent2D, Format, Image, ImageView, PhysicalDevice, RenderPassBeginInfo, Semaphore};
+use log::{debug, error, trace, warn};
+use raw_window_handle::{RawDisplayHandle, RawWindowHandle};
+use smallvec::SmallVec;
+use render_core::collect_state::CollectDrawStateUpdates;
+use render_core::object_handles::UniformResourceId;
+use sparkles_macro::{instant_event, range_event_start};
+use crate::vulkan_backend::descriptor_sets::DescriptorPoolStats;
+use crate::vulkan_backend::error::{vk_error, VulkanError};
+#[cfg(feature = "egui")]
+use crate::vulkan_backend::egui_renderer::{EguiOutput, EguiRenderer};
+use crate::vulkan_backend::frame_graph::{FrameGraph, ImageAccess};
+use crate::vulkan_backend::frame_history::{FrameHistory, FrameTiming};
+use crate::vulkan_backend::frame_sync::FrameSync;
+use crate::vulkan_backend::headless_target::HeadlessTarget;
+use crate::vulkan_backend::object_resource_pool::{self, DrawStats, ObjectResourcePool};
+use crate::vulkan_backend::post_process::PostProcess;
+use crate::vulkan_backend::render_pass::{ClearBehavior, DynamicColorTarget, RenderPassResources, RenderPassWrapper, MAX_COLOR_ATTACHMENTS};
+use crate::vulkan_backend::resource_manager::{BufferResource, ResourceManager};
+use crate::vulkan_backend::swapchain_wrapper::SwapchainWrapper;
+use crate::vulkan_backend::wrappers::command_pool::VkCommandPool;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+use crate::vulkan_backend::wrappers::pipeline_cache::VkPipelineCacheRef;
+use crate::vulkan_backend::wrappers::queue::QueueSubmitter;
+use crate::vulkan_backend::wrappers::surface::VkSurfaceRef;
+use crate::vulkan_backend::wrappers::timestamp_pool::TimestampPool;
+
+/// Number of frames kept in `FrameHistory`
+const FRAME_HISTORY_LEN: usize = 240;
+
+pub use crate::backend::SurfaceId;
+
+/// Images which frames of a surface are rendered to
+pub(super) enum PresentTarget {
+    /// Frames are presented to the swapchain of a window surface
+    Window {
+        surface: VkSurfaceRef,
+        /// Kept to create the surface again on reinitialization
+        window_handle: RawWindowHandle,
+        display_handle: RawDisplayHandle,
+        swapchain: SwapchainWrapper,
+    },
+    /// No window, frames stay in offscreen images until read back
+    Headless(HeadlessTarget),
+}
+
+impl PresentTarget {
+    fn image(&self, index: usize) -> Image {
+        match self {
+            PresentTarget::Window { swapchain, .. } => swapchain.swapchain_images[index],
+            PresentTarget::Headless(headless) => headless.get_image(index),
+        }
+    }
+
+    fn image_view(&self, index: usize) -> ImageView {
+        match self {
+            PresentTarget::Window { swapchain, .. } => swapchain.get_image_view(index),
+            PresentTarget::Headless(headless) => headless.get_image_view(index),
+        }
+    }
+
+    fn image_views(&self) -> Vec<ImageView> {
+        match self {
+            PresentTarget::Window { swapchain, .. } => swapchain.get_image_views(),
+            PresentTarget::Headless(headless) => headless.get_image_views(),
+        }
+    }
+
+    pub(super) fn format(&self) -> Format {
+        match self {
+            PresentTarget::Window { swapchain, .. } => swapchain.get_surface_format(),
+            PresentTarget::Headless(headless) => headless.get_surface_format(),
+        }
+    }
+
+    fn extent(&self) -> Extent2D {
+        match self {
+            PresentTarget::Window { swapchain, .. } => swapchain.get_extent(),
+            PresentTarget::Headless(headless) => headless.get_extent(),
+        }
+    }
+}
+
+/// Everything which belongs to a single window: swapchain, per-frame sync resources and drawn objects.
+///
+/// Device, queue and resource manager are shared between all surfaces.
+pub(super) struct SurfaceContext {
+    device: VkDeviceRef,
+
+    // per-frame resources, sized by `VulkanRenderConfig::in_flight_frames`
+    command_buffers: Vec<CommandBuffer>,
+    image_available_semaphores: Vec<Semaphore>,
+    render_finished_semaphores: Vec<Semaphore>,
+    frame_sync: FrameSync,
+    /// `u64::MAX` waits forever
+    frame_timeout_ns: u64,
+    cur_command_buffer: usize,
+    /// Image index and clear behavior the command buffer was recorded for
+    command_buffer_last_state: Vec<Option<(usize, ClearBehavior)>>,
+    timestamp_pools: Vec<Option<TimestampPool>>,
+    pub(super) last_gpu_frame_time: Option<f32>,
+    pub(super) last_gpu_scopes: Vec<(String, f32)>,
+    pub(super) frame_history: FrameHistory,
+    last_render_start: Option<Instant>,
+
+    target: PresentTarget,
+    /// Swapchain images which were rendered at least once and have contents to load
+    swapchain_image_initialized: Vec<bool>,
+    /// Image of the last submitted frame
+    last_image_index: Option<usize>,
+    /// Last known window size, used when swapchain must be recreated from `render`
+    pub(super) window_size: (u32, u32),
+    /// Region of the swapchain image to render into, full extent if `None`
+    pub(super) viewport_rect: Option<vk::Rect2D>,
+
+    object_resource_pool: ObjectResourcePool,
+    render_pass_resources: RenderPassResources,
+    /// Scene is rendered offscreen and drawn to the swapchain image through a full-screen pass
+    post_process: Option<PostProcess>,
+
+    #[cfg(feature = "egui")]
+    egui_renderer: EguiRenderer,
+    /// Applied on the next frame, when GPU is done with the previous output
+    #[cfg(feature = "egui")]
+    pending_egui_output: Option<EguiOutput>,
+    /// egui output generation each command buffer was recorded with
+    #[cfg(feature = "egui")]
+    egui_recorded_generation: Vec<u64>,
+}
+
+impl SurfaceContext {
+    #[allow(clippy::too_many_arguments)]
+    pub(super) fn new(device: VkDeviceRef, target: PresentTarget, window_size: (u32, u32), render_pass: &RenderPassWrapper,
+                      resource_manager: &mut ResourceManager, command_pool: &VkCommandPool,
+                      pipeline_cache: VkPipelineCacheRef, in_flight_frames: usize, frame_timeout_ns: u64,
+                      timestamp_period: Option<f32>, group_draws_by_pipeline: bool) -> anyhow::Result<SurfaceContext> {
+        let command_buffers = command_pool.alloc_command_buffers(in_flight_frames as u32);
+
+        let image_available_semaphores = (0..in_flight_frames).map(|_| unsafe {
+            device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                .map_err(vk_error)
+        }).collect::<anyhow::Result<_>>()?;
+        let render_finished_semaphores = (0..in_flight_frames).map(|_| unsafe {
+            device
+                .create_semaphore(&vk::SemaphoreCreateInfo::default(), None)
+                .map_err(vk_error)
+        }).collect::<anyhow::Result<_>>()?;
+
+        let frame_sync = FrameSync::new(device.clone(), in_flight_frames)?;
+
+        let timestamp_pools = (0..in_flight_frames).map(|_| {
+            timestamp_period.map(|period| TimestampPool::new(device.clone(), 10, period))
+        }).collect();
+
+        let render_pass_resources = render_pass.create_render_pass_resources(
+            target.image_views(),
+            target.extent(),
+            resource_manager,
+        );
+
+        #[cfg(feature = "egui")]
+        let egui_renderer = EguiRenderer::new(device.clone(), render_pass, pipeline_cache.get_pipeline_cache());
+        let mut object_resource_pool = ObjectResourcePool::new(device.clone(), pipeline_cache);
+        object_resource_pool.set_group_by_pipeline(group_draws_by_pipeline);
+        object_resource_pool.set_post_input(render_pass_resources.post_input.as_ref().map(|(_, view)| *view));
+
+        Ok(SurfaceContext {
+            device,
+
+            command_buffers,
+            image_available_semaphores,
+            render_finished_semaphores,
+            frame_sync,
+            frame_timeout_ns,
+            cur_command_buffer: 0,
+            command_buffer_last_state: vec![None; in_flight_frames],
+            timestamp_pools,
+            last_gpu_frame_time: None,
+            last_gpu_scopes: Vec::new(),
+            frame_history: FrameHistory::new(FRAME_HISTORY_LEN),
+            last_render_start: None,
+
+            swapchain_image_initialized: vec![false; render_pass_resources.swapchain_image_set.len()],
+            last_image_index: None,
+            target,
+            window_size,
+            viewport_rect: None,
+
+            object_resource_pool,
+            render_pass_resources,
+            post_process: None,
+
+            #[cfg(feature = "egui")]
+            egui_renderer,
+            #[cfg(feature = "egui")]
+            pending_egui_output: None,
+            #[cfg(feature = "egui")]
+            egui_recorded_generation: vec![0; in_flight_frames],
+        })
+    }
+
+    pub(super) fn get_extent(&self) -> Extent2D {
+        self.target.extent()
+    }
+
+    /// `None` for headless surface
+    pub(super) fn window_handles(&self) -> Option<(RawWindowHandle, RawDisplayHandle)> {
+        match &self.target {
+            PresentTarget::Window { window_handle, display_handle, .. } => Some((*window_handle, *display_handle)),
+            PresentTarget::Headless(_) => None,
+        }
+    }
+
+    /// RGBA8 texels of the last frame of a headless surface. Extra color attachment `index` of the main pass is
+    /// read instead with `Some(index)`, it must have a 4-byte RGBA or BGRA format.
+    ///
+    /// Slow and synchronous, see `ResourceManager::read_image`
+    pub(super) fn read_frame(&self, resource_manager: &mut ResourceManager, extra_color_target: Option<usize>) -> anyhow::Result<Vec<u8>> {
+        if !matches!(self.target, PresentTarget::Headless(_)) {
+            anyhow::bail!("Frames can be read only from headless surfaces");
+        }
+        let image_index = self.last_image_index.ok_or_else(|| anyhow::anyhow!("No frame was rendered yet"))?;
+        let (image, format, layout) = match extra_color_target {
+            None => (self.target.image(image_index), self.target.format(), vk::ImageLayout::PRESENT_SRC_KHR),
+            Some(index) => {
+                let (image, _) = self.render_pass_resources.extra_color_targets[image_index].get(index)
+                    .ok_or_else(|| anyhow::anyhow!("Extra color attachment {} does not exist", index))?;
+                if image.info.samples != vk::SampleCountFlags::TYPE_1 {
+                    anyhow::bail!("Multisampled color attachments can't be read");
+                }
+                (image.image, image.info.format, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            }
+        };
+        let mut texels = resource_manager.read_image(image, self.target.extent(), layout)?;
+        match format {
+            Format::R8G8B8A8_UNORM | Format::R8G8B8A8_SRGB => {}
+            Format::B8G8R8A8_UNORM | Format::B8G8R8A8_SRGB => {
+                for texel in texels.chunks_exact_mut(4) {
+                    texel.swap(0, 2);
+                }
+            }
+            _ => anyhow::bail!("Image format {:?} can't be read as RGBA8", format),
+        }
+        Ok(texels)
+    }
+
+    pub(super) fn object_count(&self) -> usize {
+        self.object_resource_pool.object_count()
+    }
+
+    pub(super) fn loading_image_count(&self) -> usize {
+        self.object_resource_pool.loading_image_count()
+    }
+
+    pub(super) fn frame_sync(&self) -> &FrameSync {
+        &self.frame_sync
+    }
+
+    pub(super) fn draw_stats(&self) -> DrawStats {
+        self.object_resource_pool.draw_stats()
+    }
+
+    pub(super) fn uniform_buffer(&self, id: UniformResourceId) -> Option<BufferResource> {
+        self.object_resource_pool.uniform_buffer(id)
+    }
+
+    pub(super) fn descriptor_pool_stats(&self) -> DescriptorPoolStats {
+        self.object_resource_pool.descriptor_pool_stats()
+    }
+
+    /// Replaces egui output drawn on top of the scene, starting from the next frame
+    #[cfg(feature = "egui")]
+    pub(super) fn set_egui_output(&mut self, output: EguiOutput) {
+        self.pending_egui_output = Some(output);
+    }
+
+    /// Replace post-process fragment shader, `None` renders the scene directly to the swapchain image.
+    ///
+    /// Device must be idle
+    pub(super) fn set_post_process(&mut self, render_pass: &RenderPassWrapper, resource_manager: &mut ResourceManager,
+                                   pipeline_cache: vk::PipelineCache, fragment_shader: Option<&[u8]>) -> anyhow::Result<()> {
+        let post_process = fragment_shader
+            .map(|shader| PostProcess::new(self.device.clone(), render_pass, pipeline_cache, shader))
+            .transpose()?;
+        if let Some(old) = std::mem::replace(&mut self.post_process, post_process) {
+            old.destroy(resource_manager);
+        }
+        self.create_post_process_resources(render_pass, resource_manager);
+        // loaded image changes between swapchain and scene image
+        self.swapchain_image_initialized.fill(false);
+        self.invalidate_command_buffers();
+        Ok(())
+    }
+
+    fn create_post_process_resources(&mut self, render_pass: &RenderPassWrapper, resource_manager: &mut ResourceManager) {
+        if let Some(post_process) = &mut self.post_process {
+            post_process.create_resources(resource_manager, render_pass, &self.render_pass_resources,
+                                          &self.target.image_views(), self.target.extent());
+        }
+    }
+
+    /// Force command buffers to be recorded again on the next frame
+    pub(super) fn invalidate_command_buffers(&mut self) {
+        self.command_buffer_last_state.fill(None);
+    }
+
+    /// Device must be idle
+    pub(super) fn recreate_resize(&mut self, physical_device: PhysicalDevice, render_pass: &RenderPassWrapper,
+                                  resource_manager: &mut ResourceManager, new_extent: (u32, u32)) -> anyhow::Result<()> {
+        let g = range_event_start!("[Vulkan] Recreate swapchain");
+        self.window_size = new_extent;
+        let new_extent = Extent2D {
+            width: new_extent.0,
+            height: new_extent.1,
+        };
+
+        //clear states
+        self.command_buffer_last_state.fill(None);
+
+        // 1. Destroy swapchain dependent resources
+        unsafe {
+            self.render_pass_resources
+                .destroy(resource_manager);
+        }
+        if let Some(post_process) = &mut self.post_process {
+            post_process.destroy_resources(resource_manager);
+        }
+
+        // 2. Recreate swapchain
+        let old_format = self.target.format();
+        match &mut self.target {
+            PresentTarget::Window { surface, swapchain, .. } => unsafe {
+                swapchain.recreate(physical_device, new_extent, surface.clone())?
+            },
+            PresentTarget::Headless(headless) => {
+                let resized = HeadlessTarget::new(self.device.clone(), resource_manager, new_extent, old_format,
+                                                  self.command_buffers.len());
+                std::mem::replace(headless, resized).destroy(resource_manager);
+            }
+        }
+        self.last_image_index = None;
+        let new_format = self.target.format();
+        if new_format != old_format {
+            // render pass and pipelines are created for the old format
+            return Err(VulkanError::SurfaceFormatChanged { old: old_format, new: new_format }.into());
+        }
+
+        // 3. Recreate swapchain_dependent resources
+        self.render_pass_resources = render_pass.create_render_pass_resources(
+            self.target.image_views(),
+            self.target.extent(),
+            resource_manager,
+        );
+        self.swapchain_image_initialized = vec![false; self.render_pass_resources.swapchain_image_set.len()];
+        self.object_resource_pool.set_post_input(self.render_pass_resources.post_input.as_ref().map(|(_, view)| *view));
+        self.create_post_process_resources(render_pass, resource_manager);
+        Ok(())
+    }
+
+    /// Recreate swapchain with new present mode, keeping its size
+    pub(super) fn set_vsync(&mut self, physical_device: PhysicalDevice, render_pass: &RenderPassWrapper,
+                            resource_manager: &mut ResourceManager, vsync: bool) -> anyhow::Result<()> {
+        let PresentTarget::Window { swapchain, .. } = &mut self.target else {
+            return Ok(());
+        };
+        swapchain.set_vsync(vsync);
+        if self.window_size.0 == 0 || self.window_size.1 == 0 {
+            // applied when window gets a size
+            return Ok(());
+        }
+        self.recreate_resize(physical_device, render_pass, resource_manager, self.window_size)
+    }
+
+    /// Recreate swapchain for the last known window size.
+    ///
+    /// Skipped when window has zero size: surface can't be presented to until next resize.
+    fn recreate_swapchain(&mut self, physical_device: PhysicalDevice, queue: &QueueSubmitter, render_pass: &RenderPassWrapper,
+                          resource_manager: &mut ResourceManager) -> anyhow::Result<()> {
+        if self.window_size.0 == 0 || self.window_size.1 == 0 {
+            warn!("Swapchain recreation skipped: window has zero size");
+            return Ok(());
+        }
+        let start = Instant::now();
+        if let Err(e) = queue.wait_device_idle() {
+            error!("device_wait_idle: {}", e);
+        }
+        debug!("Waited for idle for {:?}", start.elapsed());
+        self.recreate_resize(physical_device, render_pass, resource_manager, self.window_size)
+    }
+
+    pub(super) fn render(&mut self, physical_device: PhysicalDevice, queue: &QueueSubmitter, render_pass: &RenderPassWrapper,
+                         resource_manager: &mut ResourceManager, draw_state_diff: &mut impl CollectDrawStateUpdates,
+                         clear_behavior: ClearBehavior) -> anyhow::Result<()> {
+        let g = range_event_start!("[Vulkan] render");
+        let frame_index = self.cur_command_buffer;
+        self.cur_command_buffer = (frame_index + 1) % self.command_buffers.len();
+        let prev_signal = self.frame_sync.slot_signal(frame_index);
+        let cur_command_buffer = self.command_buffers[frame_index];
+
+        // 1) Acquire next image
+        let (image_index, is_suboptimal) = unsafe {
+            let g = range_event_start!("[Vulkan] Wait for fences...");
+            self.frame_sync
+                .wait_slot(frame_index, self.frame_timeout_ns)
+                .map_err(vk_error)?;
+            drop(g);
+            resource_manager.frame_finished(prev_signal);
+
+            // previous submission of this frame is finished, timestamps can be read
+            let mut gpu_frame_time = None;
+            if let Some(timestamp_pool) = &self.timestamp_pools[frame_index] {
+                let scopes = timestamp_pool.read_scopes();
+                if let Some((_, frame_time)) = scopes.iter().find(|(name, _)| name == "frame") {
+                    trace!("GPU draw time: {}ms", frame_time);
+                    self.last_gpu_frame_time = Some(*frame_time);
+                    gpu_frame_time = Some(*frame_time);
+                }
+                if !scopes.is_empty() {
+                    self.last_gpu_scopes = scopes;
+                }
+            }
+
+            let now = Instant::now();
+            if let Some(last_render_start) = self.last_render_start {
+                self.frame_history.push(FrameTiming {
+                    cpu_frame_time: (now - last_render_start).as_secs_f32() * 1000.0,
+                    gpu_frame_time,
+                });
+            }
+            self.last_render_start = Some(now);
+
+
+            let g = range_event_start!("[Vulkan] Acquire next image...");
+            let res = match &self.target {
+                PresentTarget::Window { swapchain, .. } => swapchain.swapchain_loader.acquire_next_image(
+                    swapchain.get_swapchain(),
+                    self.frame_timeout_ns,
+                    self.image_available_semaphores[frame_index],
+                    vk::Fence::null(),
+                ),
+                // each frame in flight has its own image, free after waiting for the slot
+                PresentTarget::Headless(_) => Ok((frame_index as u32, false)),
+            };
+            let res = match res {
+                Ok(res) => res,
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    // fence is not reset yet, so this frame can be skipped safely
+                    warn!("Swapchain is out of date on acquire, recreating...");
+                    drop(g);
+                    return self.recreate_swapchain(physical_device, queue, render_pass, resource_manager);
+                }
+                Err(e) => return Err(vk_error(e)),
+            };
+            // reset only when frame is going to be submitted
+            self.frame_sync.begin_slot(frame_index).map_err(vk_error)?;
+
+            instant_event!("[Vulkan] New frame!");
+            res
+        };
+        if is_suboptimal {
+            warn!("Swapchain is suboptimal!");
+        }
+
+        // 2) Update
+        let g = range_event_start!("[Vulkan] Update draw collect_state");
+
+        // buffer writes are ordered after frames in flight on the queue, but descriptor sets and destroyed
+        // resources are not, so other frames in flight must finish before they are changed
+        #[cfg(feature = "egui")]
+        let has_egui_update = self.pending_egui_output.is_some();
+        #[cfg(not(feature = "egui"))]
+        let has_egui_update = false;
+        let has_loaded_images = self.object_resource_pool.has_loaded_images();
+        let (releases_resources, changes_draw_commands) = draw_state_diff.collect_updates()
+            .fold((false, false), |(releases, changes), update| {
+                (releases || object_resource_pool::releases_resources(&update),
+                 changes || object_resource_pool::changes_draw_commands(&update))
+            });
+        if self.command_buffers.len() > 1 && (has_egui_update || has_loaded_images || releases_resources) {
+            let g = range_event_start!("[Vulkan] Wait for frames in flight");
+            // current slot is already reset and would never be signaled
+            self.frame_sync
+                .wait_other_slots(frame_index, self.frame_timeout_ns)
+                .map_err(vk_error)?;
+        }
+        if self.object_resource_pool.upload_loaded_images(resource_manager)? {
+            // recorded command buffers are invalidated by descriptor set writes
+            self.invalidate_command_buffers();
+        }
+        self.object_resource_pool.update_objects(resource_manager, draw_state_diff, render_pass)?;
+        draw_state_diff.clear_updates();
+        if changes_draw_commands {
+            // command buffers of other frames are recorded again when their slots are reused
+            self.invalidate_command_buffers();
+        }
+        #[cfg(feature = "egui")]
+        if let Some(output) = self.pending_egui_output.take() {
+            self.egui_renderer.update(resource_manager, output)?;
+        }
+        drop(g);
+
+        // 3) record command buffer (if index or clear behavior was changed)
+        let image_index = image_index as usize;
+        let clear_behavior = if clear_behavior == ClearBehavior::Load && !self.swapchain_image_initialized[image_index] {
+            // nothing to load yet
+            ClearBehavior::Clear([0.0; 3])
+        } else {
+            clear_behavior
+        };
+        #[cfg(feature = "egui")]
+        let egui_changed = self.egui_recorded_generation[frame_index] != self.egui_renderer.generation();
+        #[cfg(not(feature = "egui"))]
+        let egui_changed = false;
+        // post-process time changes every frame
+        let post_process_active = self.post_process.is_some();
+        if egui_changed || post_process_active || self.command_buffer_last_state[frame_index] != Some((image_index, clear_behavior)) {
+            self.record_draw(render_pass, cur_command_buffer, frame_index, image_index, clear_behavior)?;
+            self.command_buffer_last_state[frame_index] = Some((image_index, clear_behavior));
+            #[cfg(feature = "egui")]
+            {
+                self.egui_recorded_generation[frame_index] = self.egui_renderer.generation();
+            }
+        };
+        self.swapchain_image_initialized[image_index] = true;
+
+        let g = range_event_start!("[Vulkan] Submit command buffer");
+        // 3.1) submit command buffer
+        // headless frames are not acquired and not presented
+        let (wait_semaphores, signal_semaphores) = match self.target {
+            PresentTarget::Window { .. } => (&self.image_available_semaphores[frame_index..=frame_index],
+                                             &self.render_finished_semaphores[frame_index..=frame_index]),
+            PresentTarget::Headless(_) => (&[][..], &[][..]),
+        };
+        let wait_dst_stage_mask = [vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT];
+        let wait_dst_stage_mask = &wait_dst_stage_mask[..wait_semaphores.len()];
+        // buffer transfers of this frame go first in the same submit, ending with a single barrier
+        let transfer_command_buffer = resource_manager.take_transfer_batch(self.frame_sync.next_signal(frame_index));
+        let command_buffers: Vec<_> = transfer_command_buffer.into_iter().chain([cur_command_buffer]).collect();
+        let submit_info = vk::SubmitInfo::default()
+            .wait_semaphores(wait_semaphores)
+            .wait_dst_stage_mask(wait_dst_stage_mask)
+            .command_buffers(&command_buffers)
+            .signal_semaphores(signal_semaphores);
+        self.frame_sync
+            .submit(queue, frame_index, submit_info)
+            .map_err(vk_error)?;
+        if let Some(timestamp_pool) = &mut self.timestamp_pools[frame_index] {
+            timestamp_pool.mark_submitted();
+        }
+        self.last_image_index = Some(image_index);
+        drop(g);
+
+        // 4) present
+        let PresentTarget::Window { swapchain, .. } = &self.target else {
+            return Ok(());
+        };
+        let g = range_event_start!("[Vulkan] Queue present");
+        let swapchains = [swapchain.get_swapchain()];
+        let semaphores = [self.render_finished_semaphores[frame_index]];
+        let image_indices = [image_index as u32];
+        let present_info = vk::PresentInfoKHR::default()
+            .swapchains(&swapchains)
+            .image_indices(&image_indices)
+            .wait_semaphores(&semaphores);
+
+        let present_outdated = unsafe {
+            match queue.present(&swapchain.swapchain_loader, &present_info) {
+                Ok(is_suboptimal) => {
+                    if is_suboptimal {
+                        warn!("swapchain suboptimal!");
+                    }
+                    is_suboptimal
+                }
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                    warn!("Swapchain is out of date on present");
+                    true
+                }
+                Err(vk::Result::ERROR_DEVICE_LOST) => {
+                    return Err(vk_error(vk::Result::ERROR_DEVICE_LOST));
+                }
+                Err(e) => {
+                    error!("queue_present: {}", e);
+                    false
+                }
+            }
+        };
+        drop(g);
+
+        if is_suboptimal || present_outdated {
+            self.recreate_swapchain(physical_device, queue, render_pass, resource_manager)?;
+        }
+        Ok(())
+    }
+
+    fn record_draw(&mut self, render_pass: &RenderPassWrapper, command_buffer: CommandBuffer, frame_index: usize,
+                   image_index: usize, clear_behavior: ClearBehavior) -> anyhow::Result<()> {
+        let device = &self.device;
+        let dynamic_target = match &self.post_process {
+            Some(post_process) => post_process.scene_target(),
+            None => DynamicColorTarget {
+                image: self.target.image(image_index),
+                view: self.target.image_view(image_index),
+                sampled: false,
+            },
+        };
+        // no framebuffers with dynamic rendering
+        let (framebuffer, scene_render_pass) = match &self.post_process {
+            _ if render_pass.is_dynamic() => (vk::Framebuffer::null(), vk::RenderPass::null()),
+            Some(post_process) => (post_process.scene_framebuffer(image_index), post_process.scene_render_pass_for(clear_behavior)),
+            None => (self.render_pass_resources.framebuffers[image_index], render_pass.get_render_pass_for(clear_behavior)),
+        };
+        let extent = self.target.extent();
+
+        let g = range_event_start!("[Vulkan] Command buffer recording");
+        let command_buffer_begin_info = CommandBufferBeginInfo::default();
+        // ignored by load render pass
+        let clear_color = match clear_behavior {
+            ClearBehavior::Clear(c) => [c[0], c[1], c[2], 1.0],
+            ClearBehavior::Load => [0.0, 0.0, 0.0, 1.0],
+        };
+        let main_clear_values = [
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear_color,
+                },
+            },
+            vk::ClearValue {
+                depth_stencil: vk::ClearDepthStencilValue {
+                    depth: render_pass.get_depth_mode().clear_value(),
+                    stencil: 0,
+                },
+            },
+            vk::ClearValue {
+                color: vk::ClearColorValue {
+                    float32: clear_color,
+                },
+            },
+        ];
+        // indexed by attachment, third one exists only with MSAA or post subpass
+        let clear_values: SmallVec<[vk::ClearValue; MAX_COLOR_ATTACHMENTS + 2]> = main_clear_values.into_iter()
+            .take(render_pass.main_attachment_count())
+            .chain(render_pass.get_extra_color_formats().iter().map(|_| vk::ClearValue::default()))
+            .collect();
+        let render_area = match self.viewport_rect {
+            Some(rect) => {
+                // extent could shrink after the rect was set
+                let x = (rect.offset.x.max(0) as u32).min(extent.width);
+                let y = (rect.offset.y.max(0) as u32).min(extent.height);
+                vk::Rect2D {
+                    offset: vk::Offset2D { x: x as i32, y: y as i32 },
+                    extent: Extent2D {
+                        width: rect.extent.width.min(extent.width - x),
+                        height: rect.extent.height.min(extent.height - y),
+                    },
+                }
+            }
+            None => extent.into(),
+        };
+        // clear is applied only to render area
+        let render_pass_begin_info = RenderPassBeginInfo::default()
+            .render_pass(scene_render_pass)
+            .framebuffer(framebuffer)
+            .render_area(render_area)
+            .clear_values(&clear_values);
+
+        let viewport = vk::Viewport::default()
+            .x(render_area.offset.x as f32)
+            .y(render_area.offset.y as f32)
+            .width(render_area.extent.width as f32)
+            .height(render_area.extent.height as f32)
+            .max_depth(1.0);
+        let scissors = render_area;
+        unsafe {
+            device
+                .begin_command_buffer(command_buffer, &command_buffer_begin_info)
+                .map_err(vk_error)?;
+        }
+        if let Some(timestamp_pool) = &mut self.timestamp_pools[frame_index] {
+            timestamp_pool.cmd_reset(command_buffer);
+            timestamp_pool.begin_scope(command_buffer, "frame");
+        }
+
+        // all passes transition their color targets with render pass layouts or their own barriers,
+        // so the graph only checks that each pass finds its inputs in the expected state
+        let mut graph = FrameGraph::new(device, command_buffer);
+        let render_target_images: SmallVec<[vk::Image; 4]> = self.object_resource_pool.render_target_images().collect();
+        for image in &render_target_images {
+            graph.import(*image, vk::ImageAspectFlags::COLOR, ImageAccess::Undefined);
+        }
+        let swapchain_image = self.target.image(image_index);
+        graph.import(swapchain_image, vk::ImageAspectFlags::COLOR, ImageAccess::Undefined);
+        graph.import(dynamic_target.image, vk::ImageAspectFlags::COLOR, ImageAccess::Undefined);
+        let main_output = if dynamic_target.sampled {
+            ImageAccess::FragmentShaderRead
+        } else {
+            ImageAccess::Present
+        };
+
+        let mut targets_pass = graph.pass("render targets");
+        for image in &render_target_images {
+            targets_pass = targets_pass.output(*image, ImageAccess::FragmentShaderRead);
+        }
+        targets_pass.record(|command_buffer| self.object_resource_pool.record_render_targets(command_buffer));
+
+        let mut main_pass = graph.pass("main");
+        for image in &render_target_images {
+            main_pass = main_pass.read(*image, ImageAccess::FragmentShaderRead);
+        }
+        main_pass.output(dynamic_target.image, main_output).record(|command_buffer| unsafe {
+            if let Some(timestamp_pool) = &mut self.timestamp_pools[frame_index] {
+                timestamp_pool.begin_scope(command_buffer, "main");
+            }
+            if render_pass.is_dynamic() {
+                render_pass.cmd_begin_rendering(command_buffer, &self.render_pass_resources, image_index, dynamic_target,
+                                                clear_behavior, render_area);
+            } else {
+                device.cmd_begin_render_pass(
+                    command_buffer,
+                    &render_pass_begin_info,
+                    vk::SubpassContents::INLINE,
+                );
+            }
+
+            //bind dynamic states
+            device.cmd_set_viewport(command_buffer, 0, &[viewport]);
+            device.cmd_set_scissor(command_buffer, 0, &[scissors]);
+
+            // draw object states
+            self.object_resource_pool.record_draw_commands(command_buffer, render_pass.subpass_count(), scissors);
+            #[cfg(feature = "egui")]
+            self.egui_renderer.record_draw_commands(command_buffer, render_area);
+
+            if render_pass.is_dynamic() {
+                render_pass.cmd_end_rendering(command_buffer, &self.render_pass_resources, image_index, dynamic_target);
+            } else {
+                device.cmd_end_render_pass(command_buffer);
+            }
+            if let Some(timestamp_pool) = &mut self.timestamp_pools[frame_index] {
+                timestamp_pool.end_scope(command_buffer, "main");
+            }
+        });
+        if let Some(post_process) = &self.post_process {
+            graph.pass("post-process")
+                .read(dynamic_target.image, ImageAccess::FragmentShaderRead)
+                .output(swapchain_image, ImageAccess::Present)
+                .record(|command_buffer| post_process.record_draw_commands(command_buffer, image_index, extent));
+        }
+        debug_assert_eq!(graph.image_access(swapchain_image), Some(ImageAccess::Present),
+                         "Swapchain image is not left in PRESENT_SRC_KHR layout");
+
+        if let Some(timestamp_pool) = &mut self.timestamp_pools[frame_index] {
+            timestamp_pool.end_scope(command_buffer, "frame");
+        }
+        unsafe {
+            device.end_command_buffer(command_buffer).map_err(vk_error)?;
+        }
+        Ok(())
+    }
+
+    /// Device must be idle
+    pub(super) fn destroy(mut self, resource_manager: &mut ResourceManager, command_pool: &VkCommandPool) {
+        unsafe {
+            self.render_pass_resources
+                .destroy(resource_manager);
+        }
+        if let Some(post_process) = self.post_process.take() {
+            post_process.destroy(resource_manager);
+        }
+        if let PresentTarget::Headless(headless) = self.target {
+            headless.destroy(resource_manager);
+        }
+        #[cfg(feature = "egui")]
+        self.egui_renderer.destroy(resource_manager);
+        self.object_resource_pool.release_resources(resource_manager);
+        command_pool.free_command_buffers(&self.command_buffers);
+
+        for &semaphore in &self.image_available_semaphores {
+            unsafe {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+        }
+        for &semaphore in &self.render_finished_semaphores {
+            unsafe {
+                self.device.destroy_semaphore(semaphore, None);
+            }
+        }
+        for slot in 0..self.command_buffers.len() {
+            resource_manager.frame_finished(self.frame_sync.slot_signal(slot));
+        }
+        self.frame_sync.destroy();
+    }
+}
@@ -5,8 +5,25 @@ use log::info;
 use sparkles_macro::range_event_start;
 use crate::vulkan_backend::wrappers::device::VkDeviceRef;
 use crate::vulkan_backend::wrappers::image::{image_2d_info, imageview_info_for_image, swapchain_info};
+use crate::vulkan_backend::error::{vk_error, VulkanError};
 use crate::vulkan_backend::wrappers::surface::VkSurfaceRef;
 
+/// Swapchain settings from `VulkanRenderConfig`
+#[derive(Clone, Debug, Default)]
+pub struct PresentConfig {
+    pub vsync: bool,
+    pub present_modes: Vec<PresentModeKHR>,
+    pub image_count: Option<u32>,
+    /// Prefer sRGB surface formats, which encode linear colors on write
+    pub srgb_format: bool,
+}
+
+/// Surface formats which apply sRGB encoding when written
+pub fn is_srgb_format(format: Format) -> bool {
+    matches!(format, Format::B8G8R8A8_SRGB | Format::R8G8B8A8_SRGB | Format::A8B8G8R8_SRGB_PACK32
+        | Format::B8G8R8_SRGB | Format::R8G8B8_SRGB)
+}
+
 pub struct SwapchainWrapper {
     swapchain: SwapchainKHR,
     pub swapchain_loader: swapchain::Device,
@@ -14,6 +31,7 @@ pub struct SwapchainWrapper {
     swapchain_image_views: Vec<ImageView>,
     swapchain_format: Format,
     pub swapchain_extent: Extent2D,
+    present_config: PresentConfig,
 
     device: VkDeviceRef,
     surface: VkSurfaceRef
@@ -21,37 +39,51 @@ pub struct SwapchainWrapper {
 
 impl SwapchainWrapper {
     pub fn new(device: VkDeviceRef, physical_device: PhysicalDevice,
-               extent: Extent2D, surface_ref: VkSurfaceRef, old_swapchain: Option<SwapchainKHR>) -> anyhow::Result<SwapchainWrapper> {
+               extent: Extent2D, surface_ref: VkSurfaceRef, old_swapchain: Option<SwapchainKHR>,
+               present_config: PresentConfig) -> anyhow::Result<SwapchainWrapper> {
         let g = range_event_start!("[Vulkan] Init swapchain");
 
         let surface_loader = surface_ref.loader();
         let surface = surface_ref.surface();
 
         // TODO: replace with wrapper methods
-        let surface_capabilities = unsafe { surface_loader.get_physical_device_surface_capabilities(physical_device, *surface)? };
-        let surface_formats = unsafe { surface_loader.get_physical_device_surface_formats(physical_device, *surface)? };
-        let surface_present_modes = unsafe { surface_loader.get_physical_device_surface_present_modes(physical_device, *surface)? };
-
-        //prefer B8G8R8A8_UNORM and SRGB_NONLINEAR
-        let surface_format = surface_formats.iter().find(|f| {
-            f.format == vk::Format::B8G8R8A8_UNORM && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
-        }).unwrap_or_else(|| {
-            surface_formats.first().unwrap()
-        });
-        //prefer MAILBOX then IMMEDIATE or default FIFO
-        let present_mode = surface_present_modes.iter().find(|m| {
-            **m == vk::PresentModeKHR::MAILBOX
-        }).unwrap_or_else(|| {
-            surface_present_modes.iter().find(|m| {
-                **m == vk::PresentModeKHR::IMMEDIATE
-            }).unwrap_or_else(|| {
-                surface_present_modes.first().unwrap()
-            })
-        });
-        // let present_mode = &PresentModeKHR::FIFO;
-
-        // 1 additional image, so we can acquire 2 images at a time.
-        let image_count = surface_capabilities.min_image_count + 1;
+        let surface_capabilities = unsafe { surface_loader.get_physical_device_surface_capabilities(physical_device, *surface).map_err(vk_error)? };
+        let surface_formats = unsafe { surface_loader.get_physical_device_surface_formats(physical_device, *surface).map_err(vk_error)? };
+        let surface_present_modes = unsafe { surface_loader.get_physical_device_surface_present_modes(physical_device, *surface).map_err(vk_error)? };
+
+        // prefer B8G8R8A8 with SRGB_NONLINEAR color space, UNORM unless sRGB format is requested
+        let preferred_formats: &[Format] = if present_config.srgb_format {
+            &[vk::Format::B8G8R8A8_SRGB, vk::Format::R8G8B8A8_SRGB, vk::Format::B8G8R8A8_UNORM]
+        } else {
+            &[vk::Format::B8G8R8A8_UNORM]
+        };
+        let surface_format = preferred_formats.iter().find_map(|format| surface_formats.iter().find(|f| {
+            f.format == *format && f.color_space == vk::ColorSpaceKHR::SRGB_NONLINEAR
+        })).or_else(|| {
+            surface_formats.first()
+        }).ok_or(VulkanError::NoSurfaceFormat)?;
+        // FIFO only with vsync, otherwise first supported of preferred modes or default FIFO
+        let preferred_modes: &[PresentModeKHR] = if present_config.vsync {
+            &[PresentModeKHR::FIFO]
+        } else if present_config.present_modes.is_empty() {
+            &[PresentModeKHR::MAILBOX, PresentModeKHR::IMMEDIATE]
+        } else {
+            &present_config.present_modes
+        };
+        let present_mode = &preferred_modes.iter()
+            .find(|m| surface_present_modes.contains(m))
+            .copied()
+            // always supported
+            .unwrap_or(PresentModeKHR::FIFO);
+
+        // by default 1 additional image, so we can acquire 2 images at a time.
+        let mut image_count = present_config.image_count
+            .unwrap_or(surface_capabilities.min_image_count + 1)
+            .max(surface_capabilities.min_image_count);
+        // 0 means no limit
+        if surface_capabilities.max_image_count != 0 {
+            image_count = image_count.min(surface_capabilities.max_image_count);
+        }
         info!("\n\tCreating swapchain...\n\tPresent mode: {:?}\n\tSwapchain image count: {:?}, Color space: {:?}, Image formate: {:?}", present_mode, image_count, surface_format.color_space, surface_format.format);
 
         let swapchain_extent = if surface_capabilities.current_extent.width != u32::MAX {
@@ -83,13 +115,13 @@ impl SwapchainWrapper {
             swapchain_create_info
         };
 
-        let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None)? };
-        let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain)? };
+        let swapchain = unsafe { swapchain_loader.create_swapchain(&swapchain_create_info, None).map_err(vk_error)? };
+        let swapchain_images = unsafe { swapchain_loader.get_swapchain_images(swapchain).map_err(vk_error)? };
 
         let swapchain_image_views = swapchain_images.iter().map(|image| {
             let image_view_create_info = imageview_info_for_image(*image, swapchain_image_info, ImageAspectFlags::COLOR);
-            unsafe { device.create_image_view(&image_view_create_info, None).unwrap() }
-        }).collect::<Vec<_>>();
+            unsafe { device.create_image_view(&image_view_create_info, None).map_err(vk_error) }
+        }).collect::<anyhow::Result<Vec<_>>>()?;
         
         Ok(SwapchainWrapper {
             swapchain,
@@ -98,6 +130,7 @@ impl SwapchainWrapper {
             swapchain_image_views,
             swapchain_format: surface_format.format,
             swapchain_extent,
+            present_config,
 
             device,
             surface: surface_ref
@@ -110,6 +143,9 @@ impl SwapchainWrapper {
     pub fn get_image_views(&self) -> Vec<ImageView> {
         self.swapchain_image_views.clone()
     }
+    pub fn get_image_view(&self, index: usize) -> ImageView {
+        self.swapchain_image_views[index]
+    }
 
     pub fn get_surface_format(&self) -> Format {
         self.swapchain_format
@@ -119,6 +155,11 @@ impl SwapchainWrapper {
         self.swapchain_extent
     }
 
+    /// Takes effect on the next `recreate`
+    pub fn set_vsync(&mut self, vsync: bool) {
+        self.present_config.vsync = vsync;
+    }
+
 
     /// # Safety
     /// Image views should not be used. Swapchain should not be used.
@@ -126,7 +167,8 @@ impl SwapchainWrapper {
                            extent: Extent2D, surface: VkSurfaceRef) -> anyhow::Result<()> {
 
         let swapchain = self.swapchain;
-        *self = Self::new(self.device.clone(), physical_device, extent, surface, Some(swapchain))?;
+        *self = Self::new(self.device.clone(), physical_device, extent, surface, Some(swapchain),
+                          self.present_config.clone())?;
         Ok(())
     }
 }
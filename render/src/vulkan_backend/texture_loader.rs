@@ -0,0 +1,193 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+use ash::vk::{self, Extent2D};
+use log::{error, info, warn};
+use render_core::object_handles::UniformResourceId;
+use crate::util::get_resource;
+use crate::util::image::read_image_from_bytes;
+use crate::util::ktx2::{read_ktx2_from_bytes, CompressedFamily, Ktx2Texture};
+use crate::vulkan_backend::object_resource_pool::UniformImage;
+use crate::vulkan_backend::resource_manager::ResourceManager;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+
+/// Image data ready for upload
+pub enum DecodedImage {
+    Rgba8 {
+        data: Vec<u8>,
+        extent: Extent2D,
+    },
+    Ktx2(Ktx2Texture),
+    /// Layers one after another
+    Rgba8Array {
+        data: Vec<u8>,
+        extent: Extent2D,
+        layers: u32,
+    },
+}
+
+enum LoadRequest {
+    Image(String),
+    Array(Vec<String>),
+}
+
+/// Result of a load request. `name` is the file which was actually loaded
+pub struct LoadedImage {
+    pub id: UniformResourceId,
+    pub name: String,
+    pub result: anyhow::Result<DecodedImage>,
+}
+
+impl DecodedImage {
+    /// Upload on the render thread
+    pub fn upload(self, resource_manager: &mut ResourceManager, device: VkDeviceRef, name: &str) -> anyhow::Result<UniformImage> {
+        match self {
+            DecodedImage::Rgba8 { data, extent } => UniformImage::new(data, extent, resource_manager, device, name),
+            DecodedImage::Ktx2(texture) => UniformImage::from_ktx2(texture, resource_manager, device, name),
+            DecodedImage::Rgba8Array { data, extent, layers } => {
+                UniformImage::new_array(data, extent, layers, resource_manager, device, name)
+            }
+        }
+    }
+}
+
+/// Reads and decodes images on a worker thread, so that only GPU upload is done while rendering
+pub struct TextureLoader {
+    requests: Option<Sender<(UniformResourceId, LoadRequest)>>,
+    results: Receiver<LoadedImage>,
+    /// Received, but not taken yet
+    ready: Vec<LoadedImage>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl TextureLoader {
+    /// Compressed formats are chosen among those sampleable by the device, checked once here
+    pub fn new(resource_manager: &ResourceManager) -> Self {
+        let supported_formats: HashSet<vk::Format> = (vk::Format::BC1_RGB_UNORM_BLOCK.as_raw()..=vk::Format::ASTC_12X12_SRGB_BLOCK.as_raw())
+            .map(vk::Format::from_raw)
+            .filter(|format| resource_manager.supports_sampled_format(*format))
+            .collect();
+
+        let (request_sender, request_receiver) = channel::<(UniformResourceId, LoadRequest)>();
+        let (result_sender, result_receiver) = channel();
+        let worker = std::thread::Builder::new()
+            .name("texture loader".to_string())
+            .spawn(move || {
+                for (id, request) in request_receiver {
+                    let (name, result) = match request {
+                        LoadRequest::Image(path) => decode_image(&path, |format| supported_formats.contains(&format)),
+                        LoadRequest::Array(paths) => (paths[0].clone(), decode_image_array(&paths)),
+                    };
+                    if result_sender.send(LoadedImage { id, name, result }).is_err() {
+                        break;
+                    }
+                }
+            })
+            .expect("Failed to spawn texture loader thread");
+
+        Self {
+            requests: Some(request_sender),
+            results: result_receiver,
+            ready: Vec::new(),
+            worker: Some(worker),
+        }
+    }
+
+    /// Path is relative to `resources`, see `ImageCmd::Create`
+    pub fn request(&self, id: UniformResourceId, path: String) {
+        self.send(id, LoadRequest::Image(path));
+    }
+
+    /// Layers of the same size, see `ImageCmd::CreateArray`
+    pub fn request_array(&self, id: UniformResourceId, paths: Vec<String>) {
+        self.send(id, LoadRequest::Array(paths));
+    }
+
+    fn send(&self, id: UniformResourceId, request: LoadRequest) {
+        if let Some(requests) = &self.requests {
+            let _ = requests.send((id, request));
+        }
+    }
+
+    /// Some images finished loading since the last `take_ready`
+    pub fn has_ready(&mut self) -> bool {
+        self.ready.extend(self.results.try_iter());
+        !self.ready.is_empty()
+    }
+
+    pub fn take_ready(&mut self) -> Vec<LoadedImage> {
+        self.ready.extend(self.results.try_iter());
+        std::mem::take(&mut self.ready)
+    }
+}
+
+impl Drop for TextureLoader {
+    fn drop(&mut self) {
+        // worker finishes the current image and exits when the channel is closed
+        self.requests = None;
+        if let Some(worker) = self.worker.take() {
+            if worker.join().is_err() {
+                error!("Texture loader thread panicked");
+            }
+        }
+    }
+}
+
+fn decode_image_array(paths: &[String]) -> anyhow::Result<DecodedImage> {
+    let mut data = Vec::new();
+    let mut array_extent = None;
+    for path in paths {
+        let bytes = get_resource(Path::join("resources".as_ref(), path))?;
+        let (layer, extent) = read_image_from_bytes(bytes)?;
+        if *array_extent.get_or_insert(extent) != extent {
+            anyhow::bail!("Layer {} has extent {:?}, different from the first layer {:?}", path, extent, array_extent);
+        }
+        data.extend_from_slice(&layer);
+    }
+    let extent = array_extent.ok_or_else(|| anyhow::anyhow!("Texture array has no layers"))?;
+    info!("Texture array of {} layers, extent {:?}", paths.len(), extent);
+    Ok(DecodedImage::Rgba8Array { data, extent, layers: paths.len() as u32 })
+}
+
+/// Read and decode image by path relative to `resources`.
+///
+/// For a `.ktx2` path the first existing file with a format accepted by `is_supported` is used: the path
+/// itself, then variants of compressed families in platform preference order (`name.astc.ktx2`,
+/// `name.etc2.ktx2`, `name.bc7.ktx2` on mobile), then `name.png` decoded to RGBA8
+fn decode_image(path: &str, is_supported: impl Fn(vk::Format) -> bool) -> (String, anyhow::Result<DecodedImage>) {
+    let resource_path = |path: &str| Path::join("resources".as_ref(), path);
+    let decode_uncompressed = |path: &str| -> anyhow::Result<DecodedImage> {
+        let data = get_resource(resource_path(path))?;
+        let (data, extent) = read_image_from_bytes(data)?;
+        info!("Image {} extent: {:?}", path, extent);
+        Ok(DecodedImage::Rgba8 { data, extent })
+    };
+    let Some(stem) = path.strip_suffix(".ktx2") else {
+        return (path.to_string(), decode_uncompressed(path));
+    };
+
+    let candidates = std::iter::once(path.to_string())
+        .chain(CompressedFamily::platform_preference().map(|family| format!("{}.{}.ktx2", stem, family.suffix())));
+    for candidate in candidates {
+        let Ok(data) = get_resource(resource_path(&candidate)) else {
+            continue;
+        };
+        let texture = match read_ktx2_from_bytes(&data) {
+            Ok(texture) => texture,
+            Err(e) => return (candidate, Err(e.into())),
+        };
+        if !is_supported(texture.format) {
+            info!("Texture {}: format {:?} is not supported by device", candidate, texture.format);
+            continue;
+        }
+        info!("Texture {}: {:?}, extent {:?}, {} mip levels", candidate, texture.format, texture.extent,
+              texture.levels.len());
+        return (candidate, Ok(DecodedImage::Ktx2(texture)));
+    }
+
+    let fallback = format!("{}.png", stem);
+    warn!("No KTX2 variant of {} is supported, decoding {}", path, fallback);
+    let result = decode_uncompressed(&fallback);
+    (fallback, result)
+}
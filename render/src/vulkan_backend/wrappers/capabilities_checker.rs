@@ -9,6 +9,33 @@ use sparkles_macro::range_event_start;
 use crate::vulkan_backend::wrappers::device::{VkDevice, VkDeviceRef};
 use crate::vulkan_backend::wrappers::instance::{VkInstance, VkInstanceRef};
 
+/// How an optional feature is provided by the device
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureSupport {
+    /// Promoted to core in the API version used with the device
+    Core,
+    /// Device extension must be enabled
+    Extension,
+}
+
+/// Core features for drawing object batches from an indirect args buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IndirectDraws {
+    /// `drawIndirectFirstInstance`: an indirect draw per object
+    Single,
+    /// Also `multiDrawIndirect`: a single indirect draw per batch
+    Multi,
+}
+
+/// Optional features enabled on the device, selected by `CapabilitiesChecker::check_*` methods
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OptionalFeatures {
+    pub timeline_semaphore: Option<FeatureSupport>,
+    pub dynamic_rendering: Option<FeatureSupport>,
+    pub indirect_draws: Option<IndirectDraws>,
+    pub descriptor_indexing: Option<FeatureSupport>,
+}
+
 /// Helper for creating Instance and Device
 pub struct CapabilitiesChecker {
     activated_layers: BTreeSet<String>,
@@ -16,6 +43,7 @@ pub struct CapabilitiesChecker {
     activated_device_extensions: BTreeSet<String>,
 
     portability_enabled: bool,
+    optional_features: OptionalFeatures,
 }
 
 impl CapabilitiesChecker {
@@ -26,7 +54,127 @@ impl CapabilitiesChecker {
             activated_device_extensions: BTreeSet::new(),
 
             portability_enabled: false,
+            optional_features: OptionalFeatures::default(),
+        }
+    }
+
+    /// Core if both instance and device versions are at least `core_version`, otherwise extension if they are
+    /// at least `extension_min_version` and the device supports it.
+    ///
+    /// Features are queried with Vulkan 1.1 functions, so nothing is supported with a 1.0 instance.
+    fn feature_support(instance: &VkInstance, physical_device: vk::PhysicalDevice, instance_api_version: u32,
+                       core_version: u32, extension_min_version: u32, extension: &CStr) -> Option<FeatureSupport> {
+        if instance_api_version < vk::API_VERSION_1_1 {
+            return None;
         }
+        let device_api_version = unsafe { instance.get_physical_device_properties(physical_device) }.api_version;
+        let api_version = device_api_version.min(instance_api_version);
+        if api_version >= core_version {
+            return Some(FeatureSupport::Core);
+        }
+        if api_version < extension_min_version {
+            return None;
+        }
+        let supported_extensions = unsafe { instance.enumerate_device_extension_properties(physical_device) }.ok()?;
+        supported_extensions.iter()
+            .any(|ext| unsafe { CStr::from_ptr(ext.extension_name.as_ptr()) } == extension)
+            .then_some(FeatureSupport::Extension)
+    }
+
+    /// Check whether timeline semaphores can be used, and remember it for `create_device`.
+    ///
+    /// Core in Vulkan 1.2, `VK_KHR_timeline_semaphore` on 1.1. With `FeatureSupport::Extension`, the extension
+    /// must be requested on device creation. In both cases `vk::PhysicalDeviceTimelineSemaphoreFeatures` with
+    /// the feature enabled must be chained to the device create info.
+    pub fn check_timeline_semaphore(&mut self, instance: &VkInstance, physical_device: vk::PhysicalDevice,
+                                    instance_api_version: u32) -> Option<FeatureSupport> {
+        self.optional_features.timeline_semaphore = None;
+        let support = Self::feature_support(instance, physical_device, instance_api_version, vk::API_VERSION_1_2,
+                                            vk::API_VERSION_1_1, ash::khr::timeline_semaphore::NAME)?;
+
+        let mut timeline_features = vk::PhysicalDeviceTimelineSemaphoreFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut timeline_features);
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+        if timeline_features.timeline_semaphore != vk::TRUE {
+            return None;
+        }
+        self.optional_features.timeline_semaphore = Some(support);
+        Some(support)
+    }
+
+    /// Check whether dynamic rendering can be used, and remember it for `create_device`.
+    ///
+    /// Core in Vulkan 1.3, `VK_KHR_dynamic_rendering` on 1.2 (its dependencies are core there). With
+    /// `FeatureSupport::Extension`, the extension must be requested on device creation. In both cases
+    /// `vk::PhysicalDeviceDynamicRenderingFeatures` with the feature enabled must be chained to the device create info.
+    pub fn check_dynamic_rendering(&mut self, instance: &VkInstance, physical_device: vk::PhysicalDevice,
+                                   instance_api_version: u32) -> Option<FeatureSupport> {
+        self.optional_features.dynamic_rendering = None;
+        let support = Self::feature_support(instance, physical_device, instance_api_version, vk::API_VERSION_1_3,
+                                            vk::API_VERSION_1_2, ash::khr::dynamic_rendering::NAME)?;
+
+        let mut dynamic_rendering_features = vk::PhysicalDeviceDynamicRenderingFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut dynamic_rendering_features);
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+        if dynamic_rendering_features.dynamic_rendering != vk::TRUE {
+            return None;
+        }
+        self.optional_features.dynamic_rendering = Some(support);
+        Some(support)
+    }
+
+    /// Check whether a bindless array of `texture_count` combined image samplers can be used, and remember
+    /// it for `create_device`.
+    ///
+    /// Core in Vulkan 1.2, `VK_EXT_descriptor_indexing` on 1.1. Requires partially bound and update-after-bind
+    /// sampled image descriptors, with update-after-bind limits allowing `texture_count` of them in a stage.
+    /// `vk::PhysicalDeviceDescriptorIndexingFeatures` with both features enabled must be chained to the device
+    /// create info.
+    pub fn check_descriptor_indexing(&mut self, instance: &VkInstance, physical_device: vk::PhysicalDevice,
+                                     instance_api_version: u32, texture_count: u32) -> Option<FeatureSupport> {
+        self.optional_features.descriptor_indexing = None;
+        let support = Self::feature_support(instance, physical_device, instance_api_version, vk::API_VERSION_1_2,
+                                            vk::API_VERSION_1_1, ash::ext::descriptor_indexing::NAME)?;
+
+        let mut indexing_features = vk::PhysicalDeviceDescriptorIndexingFeatures::default();
+        let mut features = vk::PhysicalDeviceFeatures2::default().push_next(&mut indexing_features);
+        unsafe { instance.get_physical_device_features2(physical_device, &mut features) };
+        if indexing_features.descriptor_binding_partially_bound != vk::TRUE
+            || indexing_features.descriptor_binding_sampled_image_update_after_bind != vk::TRUE {
+            return None;
+        }
+
+        let mut indexing_properties = vk::PhysicalDeviceDescriptorIndexingProperties::default();
+        let mut properties = vk::PhysicalDeviceProperties2::default().push_next(&mut indexing_properties);
+        unsafe { instance.get_physical_device_properties2(physical_device, &mut properties) };
+        let limit = [
+            indexing_properties.max_per_stage_descriptor_update_after_bind_samplers,
+            indexing_properties.max_per_stage_descriptor_update_after_bind_sampled_images,
+            indexing_properties.max_descriptor_set_update_after_bind_samplers,
+            indexing_properties.max_descriptor_set_update_after_bind_sampled_images,
+            indexing_properties.max_per_stage_update_after_bind_resources,
+        ].into_iter().min().unwrap();
+        if limit < texture_count {
+            info!("Descriptor indexing allows only {} update-after-bind textures", limit);
+            return None;
+        }
+        self.optional_features.descriptor_indexing = Some(support);
+        Some(support)
+    }
+
+    /// Check core features for indirect batch draws, and remember them for `create_device`. They must be
+    /// enabled in `vk::DeviceCreateInfo::enabled_features`
+    pub fn check_indirect_draws(&mut self, instance: &VkInstance, physical_device: vk::PhysicalDevice) -> Option<IndirectDraws> {
+        let features = unsafe { instance.get_physical_device_features(physical_device) };
+        let indirect_draws = if features.draw_indirect_first_instance != vk::TRUE {
+            None
+        } else if features.multi_draw_indirect == vk::TRUE {
+            Some(IndirectDraws::Multi)
+        } else {
+            Some(IndirectDraws::Single)
+        };
+        self.optional_features.indirect_draws = indirect_draws;
+        indirect_draws
     }
 
     pub fn create_instance(&mut self, app_info: &ApplicationInfo,
@@ -64,7 +212,12 @@ impl CapabilitiesChecker {
             .map(|ext| unsafe { CStr::from_ptr(*ext) })
             .collect();
 
-        let supported_extensions = unsafe { entry.enumerate_instance_extension_properties(None) }?;
+        let mut supported_extensions = unsafe { entry.enumerate_instance_extension_properties(None) }?;
+        // some extensions are provided only by layers
+        for layer in &filtered_layers {
+            let layer = unsafe { CStr::from_ptr(*layer) };
+            supported_extensions.extend(unsafe { entry.enumerate_instance_extension_properties(Some(layer)) }?);
+        }
 
         let mut filtered_extensions: Vec<*const c_char> = requested_extensions.iter().filter(|e| {
             let name: &str = e.to_str().unwrap();
@@ -83,10 +236,27 @@ impl CapabilitiesChecker {
         }).map(|layer| layer.as_ptr()).collect();
 
 
+        let enabled_validation_features = [
+            vk::ValidationFeatureEnableEXT::GPU_ASSISTED,
+            vk::ValidationFeatureEnableEXT::SYNCHRONIZATION_VALIDATION,
+        ];
+        let mut validation_features = vk::ValidationFeaturesEXT::default()
+            .enabled_validation_features(&enabled_validation_features);
+
         let mut create_info = InstanceCreateInfo::default()
             .application_info(app_info)
             .push_next(debug_utils_info);
 
+        if cfg!(feature = "validation_gpu_assisted") {
+            if self.activated_instance_extensions.contains(ash::ext::validation_features::NAME.to_str().unwrap()) {
+                info!("GPU-assisted and synchronization validation enabled");
+                create_info = create_info.push_next(&mut validation_features);
+            }
+            else {
+                warn!("GPU-assisted validation is requested, but VK_EXT_validation_features is not supported!");
+            }
+        }
+
         // check if KHR_portability_enumeration supported
         if cfg!(feature="portability_subset") {
             if supported_extensions.iter().any(|ext| unsafe {CStr::from_ptr(ext.extension_name.as_ptr())} == ash::khr::portability_enumeration::NAME) ||
@@ -163,7 +333,31 @@ impl CapabilitiesChecker {
             info!("Activated device extension: {}", e);
         }
 
-        Ok(VkDevice::new(device, instance).into())
+        let debug_utils_enabled = self.activated_instance_extensions.contains(ash::ext::debug_utils::NAME.to_str().unwrap());
+        // extension may have been filtered out above
+        let enabled = |support: Option<FeatureSupport>, extension: &CStr| support.filter(|support| match support {
+            FeatureSupport::Core => true,
+            FeatureSupport::Extension => self.activated_device_extensions.contains(extension.to_str().unwrap()),
+        });
+        let features = OptionalFeatures {
+            timeline_semaphore: enabled(self.optional_features.timeline_semaphore, ash::khr::timeline_semaphore::NAME),
+            dynamic_rendering: enabled(self.optional_features.dynamic_rendering, ash::khr::dynamic_rendering::NAME),
+            indirect_draws: self.optional_features.indirect_draws,
+            descriptor_indexing: enabled(self.optional_features.descriptor_indexing, ash::ext::descriptor_indexing::NAME),
+        };
+        if let Some(support) = features.timeline_semaphore {
+            info!("Timeline semaphores enabled ({:?})", support);
+        }
+        if let Some(support) = features.dynamic_rendering {
+            info!("Dynamic rendering enabled ({:?})", support);
+        }
+        if let Some(indirect_draws) = features.indirect_draws {
+            info!("Indirect batch draws enabled ({:?})", indirect_draws);
+        }
+        if let Some(support) = features.descriptor_indexing {
+            info!("Bindless textures enabled ({:?})", support);
+        }
+        Ok(VkDevice::new(device, instance, debug_utils_enabled, features).into())
     }
 }
 
@@ -26,6 +26,10 @@ impl VkCommandPool {
             .command_buffer_count(n);
         unsafe { self.device.allocate_command_buffers(&info).unwrap() }
     }
+
+    pub fn free_command_buffers(&self, command_buffers: &[CommandBuffer]) {
+        unsafe { self.device.free_command_buffers(self.command_pool, command_buffers) }
+    }
 }
 
 impl Drop for VkCommandPool {
@@ -1,3 +1,4 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
 use ash::{vk, Entry};
 use ash::vk::{DebugUtilsMessageSeverityFlagsEXT, DebugUtilsMessageTypeFlagsEXT, DebugUtilsMessengerCreateInfoEXT};
 use log::{debug, error, info, warn};
@@ -6,6 +7,8 @@ use crate::vulkan_backend::wrappers::instance::VkInstanceRef;
 pub struct VkDebugUtils {
     debug_utils_h: ash::ext::debug_utils::Instance,
     debug_utils_messenger_h: vk::DebugUtilsMessengerEXT,
+    /// ERROR messages of the messenger, boxed so the callback can keep a pointer to it
+    error_count: Box<AtomicUsize>,
     instance: VkInstanceRef
 }
 
@@ -13,13 +16,21 @@ unsafe extern "system" fn vulkan_debug_callback(
     message_severity: DebugUtilsMessageSeverityFlagsEXT,
     message_type: DebugUtilsMessageTypeFlagsEXT,
     p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-    _user_data: *mut std::ffi::c_void,
+    user_data: *mut std::ffi::c_void,
 ) -> vk::Bool32 {
     let callback_data = unsafe { &*p_callback_data };
     let msg = unsafe { std::ffi::CStr::from_ptr(callback_data.p_message) };
     match message_severity {
         DebugUtilsMessageSeverityFlagsEXT::ERROR => {
             error!("{:?}: {}", message_type, msg.to_str().unwrap());
+            // null for the messenger of instance creation
+            if let Some(error_count) = unsafe { user_data.cast::<AtomicUsize>().as_ref() } {
+                error_count.fetch_add(1, Ordering::Relaxed);
+            }
+            // panic can't unwind through the driver, process is aborted after the message is printed
+            if cfg!(feature = "validation_strict") && message_type.contains(DebugUtilsMessageTypeFlagsEXT::VALIDATION) {
+                panic!("Vulkan validation error: {}", msg.to_string_lossy());
+            }
         },
         DebugUtilsMessageSeverityFlagsEXT::INFO => {
             info!("{:?}: {}", message_type, msg.to_str().unwrap());
@@ -42,18 +53,27 @@ impl VkDebugUtils {
 
         let debug_utils_h = ash::ext::debug_utils::Instance::new(&entry, &instance);
 
+        let error_count = Box::new(AtomicUsize::new(0));
+        let create_info = Self::get_messenger_create_info()
+            .user_data(&*error_count as *const AtomicUsize as *mut std::ffi::c_void);
         let debug_utils_messenger_h = unsafe {
-            debug_utils_h.create_debug_utils_messenger(
-                &Self::get_messenger_create_info(), None) }?;
+            debug_utils_h.create_debug_utils_messenger(&create_info, None) }?;
 
 
         Ok(VkDebugUtils {
             debug_utils_messenger_h,
             debug_utils_h,
+            error_count,
             instance
         })
     }
 
+    /// Number of ERROR messages reported since creation, e.g. for tests to check that there were no
+    /// validation errors
+    pub fn error_count(&self) -> usize {
+        self.error_count.load(Ordering::Relaxed)
+    }
+
     /// Can be used during instance creation
     pub fn get_messenger_create_info() -> DebugUtilsMessengerCreateInfoEXT<'static> {
         let debug_messenger_create_info = vk::DebugUtilsMessengerCreateInfoEXT::default()
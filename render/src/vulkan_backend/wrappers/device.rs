@@ -1,28 +1,175 @@
+use std::ffi::CString;
+use std::fmt::Display;
 use std::ops::Deref;
 use std::sync::Arc;
-use ash::{Device, Instance};
+use ash::{vk, Device, Instance};
+use ash::prelude::VkResult;
+use ash::vk::{DebugUtilsObjectNameInfoEXT, Handle};
+use log::warn;
 use sparkles_macro::range_event_start;
+use crate::vulkan_backend::wrappers::capabilities_checker::{FeatureSupport, IndirectDraws, OptionalFeatures};
 use crate::vulkan_backend::wrappers::instance::VkInstanceRef;
 
 pub type VkDeviceRef = Arc<VkDevice>;
 
+/// Timeline semaphore functions are loaded with the KHR suffix from the extension
+#[derive(Clone)]
+enum TimelineSemaphoreFns {
+    Core,
+    Khr(ash::khr::timeline_semaphore::Device),
+}
+
+/// Dynamic rendering functions are loaded with the KHR suffix from the extension
+#[derive(Clone)]
+enum DynamicRenderingFns {
+    Core,
+    Khr(ash::khr::dynamic_rendering::Device),
+}
+
 /// Reference to the vulkan Device.
 /// When last reference is destroyed, device is destroyed as well
 #[derive(Clone)]
 pub struct VkDevice {
     device: Device,
-    instance: VkInstanceRef
+    instance: VkInstanceRef,
+    /// Used for object names, present only with validation layers feature
+    debug_utils: Option<ash::ext::debug_utils::Device>,
+    /// Present when timeline semaphore feature is enabled
+    timeline_semaphore: Option<TimelineSemaphoreFns>,
+    /// Present when dynamic rendering feature is enabled
+    dynamic_rendering: Option<DynamicRenderingFns>,
+    indirect_draws: Option<IndirectDraws>,
+    descriptor_indexing: bool,
+    /// External devices are not destroyed
+    owned: bool,
 }
 impl VkDevice {
-    pub fn new(device: Device, instance: VkInstanceRef) -> VkDevice {
+    pub fn new(device: Device, instance: VkInstanceRef, debug_utils_enabled: bool, features: OptionalFeatures) -> VkDevice {
+        let debug_utils = (cfg!(feature = "validation_layers") && debug_utils_enabled)
+            .then(|| ash::ext::debug_utils::Device::new(&instance, &device));
+        let timeline_semaphore = features.timeline_semaphore.map(|support| match support {
+            FeatureSupport::Core => TimelineSemaphoreFns::Core,
+            FeatureSupport::Extension =>
+                TimelineSemaphoreFns::Khr(ash::khr::timeline_semaphore::Device::new(&instance, &device)),
+        });
+        let dynamic_rendering = features.dynamic_rendering.map(|support| match support {
+            FeatureSupport::Core => DynamicRenderingFns::Core,
+            FeatureSupport::Extension =>
+                DynamicRenderingFns::Khr(ash::khr::dynamic_rendering::Device::new(&instance, &device)),
+        });
         VkDevice {
             device,
-            instance
+            instance,
+            debug_utils,
+            timeline_semaphore,
+            dynamic_rendering,
+            indirect_draws: features.indirect_draws,
+            descriptor_indexing: features.descriptor_indexing.is_some(),
+            owned: true,
+        }
+    }
+
+    /// Wrap device owned by the application, it is not destroyed on drop
+    pub fn new_external(device: Device, instance: VkInstanceRef) -> VkDevice {
+        VkDevice {
+            device,
+            instance,
+            debug_utils: None,
+            timeline_semaphore: None,
+            dynamic_rendering: None,
+            indirect_draws: None,
+            descriptor_indexing: false,
+            owned: false,
         }
     }
     pub(crate) fn instance(&self) -> &Instance {
         &self.instance
     }
+
+    pub fn timeline_semaphores_enabled(&self) -> bool {
+        self.timeline_semaphore.is_some()
+    }
+
+    /// Block until timeline semaphore reaches `value`. Panics if timeline semaphores are not enabled
+    pub fn wait_semaphore_value(&self, semaphore: vk::Semaphore, value: u64, timeout: u64) -> VkResult<()> {
+        let semaphores = [semaphore];
+        let values = [value];
+        let wait_info = vk::SemaphoreWaitInfo::default()
+            .semaphores(&semaphores)
+            .values(&values);
+        match self.timeline_semaphore.as_ref().expect("Timeline semaphores are not enabled") {
+            TimelineSemaphoreFns::Core => unsafe { self.device.wait_semaphores(&wait_info, timeout) },
+            TimelineSemaphoreFns::Khr(fns) => unsafe { fns.wait_semaphores(&wait_info, timeout) },
+        }
+    }
+
+    /// Features enabled for `VulkanRenderConfig::indirect_batches`
+    pub fn indirect_draws(&self) -> Option<IndirectDraws> {
+        self.indirect_draws
+    }
+
+    /// Features enabled for `VulkanRenderConfig::bindless_textures`
+    pub fn descriptor_indexing_enabled(&self) -> bool {
+        self.descriptor_indexing
+    }
+
+    pub fn dynamic_rendering_enabled(&self) -> bool {
+        self.dynamic_rendering.is_some()
+    }
+
+    /// `vkCmdBeginRendering`. Panics if dynamic rendering is not enabled
+    pub fn begin_dynamic_rendering(&self, command_buffer: vk::CommandBuffer, rendering_info: &vk::RenderingInfo) {
+        match self.dynamic_rendering.as_ref().expect("Dynamic rendering is not enabled") {
+            DynamicRenderingFns::Core => unsafe { self.device.cmd_begin_rendering(command_buffer, rendering_info) },
+            DynamicRenderingFns::Khr(fns) => unsafe { fns.cmd_begin_rendering(command_buffer, rendering_info) },
+        }
+    }
+
+    /// `vkCmdEndRendering`. Panics if dynamic rendering is not enabled
+    pub fn end_dynamic_rendering(&self, command_buffer: vk::CommandBuffer) {
+        match self.dynamic_rendering.as_ref().expect("Dynamic rendering is not enabled") {
+            DynamicRenderingFns::Core => unsafe { self.device.cmd_end_rendering(command_buffer) },
+            DynamicRenderingFns::Khr(fns) => unsafe { fns.cmd_end_rendering(command_buffer) },
+        }
+    }
+
+    /// Set timeline semaphore to `value` from host. Panics if timeline semaphores are not enabled
+    pub fn signal_semaphore_value(&self, semaphore: vk::Semaphore, value: u64) -> VkResult<()> {
+        let signal_info = vk::SemaphoreSignalInfo::default()
+            .semaphore(semaphore)
+            .value(value);
+        match self.timeline_semaphore.as_ref().expect("Timeline semaphores are not enabled") {
+            TimelineSemaphoreFns::Core => unsafe { self.device.signal_semaphore(&signal_info) },
+            TimelineSemaphoreFns::Khr(fns) => unsafe { fns.signal_semaphore(&signal_info) },
+        }
+    }
+
+    /// Current value of timeline semaphore. Panics if timeline semaphores are not enabled
+    pub fn semaphore_value(&self, semaphore: vk::Semaphore) -> VkResult<u64> {
+        match self.timeline_semaphore.as_ref().expect("Timeline semaphores are not enabled") {
+            TimelineSemaphoreFns::Core => unsafe { self.device.get_semaphore_counter_value(semaphore) },
+            TimelineSemaphoreFns::Khr(fns) => unsafe { fns.get_semaphore_counter_value(semaphore) },
+        }
+    }
+
+    /// Name object for validation messages and debugging tools.
+    ///
+    /// Does nothing (and doesn't format the name) without validation layers feature.
+    pub fn set_object_name<H: Handle>(&self, handle: H, name: impl Display) {
+        let Some(debug_utils) = &self.debug_utils else {
+            return;
+        };
+        let Ok(name) = CString::new(name.to_string()) else {
+            warn!("Object name contains nul byte and is ignored");
+            return;
+        };
+        let name_info = DebugUtilsObjectNameInfoEXT::default()
+            .object_handle(handle)
+            .object_name(&name);
+        if let Err(e) = unsafe { debug_utils.set_debug_utils_object_name(&name_info) } {
+            warn!("Failed to set object name: {}", e);
+        }
+    }
 }
 
 impl Deref for VkDevice {
@@ -34,6 +181,9 @@ impl Deref for VkDevice {
 
 impl Drop for VkDevice {
     fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
         let g = range_event_start!("[Vulkan] Destroy device");
         // Safety: We use raii and ensure that everyon who use device
         unsafe { self.device.destroy_device(None); }
@@ -36,7 +36,7 @@ pub fn image_2d_info<'a>(
 
 /// Generate imageview create info for a simple 2d image
 /// - 1 layer from layer 0
-/// - 1 mip level from layer 0
+/// - all mip levels of the image
 /// - empty flags
 /// - type same as input image
 /// - format same as input image
@@ -61,12 +61,25 @@ pub fn imageview_info_for_image(
             ImageSubresourceRange::default()
                 .aspect_mask(aspect)
                 .layer_count(1)
-                .level_count(1),
+                .level_count(info.mip_levels),
         );
 
     imageview_info
 }
 
+/// Same as `imageview_info_for_image`, but with array view type and all layers of the image.
+/// Shaders sample it as `sampler2DArray` even with a single layer
+pub fn imageview_info_for_array(
+    image: Image,
+    info: ImageCreateInfo,
+    aspect: ImageAspectFlags,
+) -> ImageViewCreateInfo {
+    let view_info = imageview_info_for_image(image, info, aspect);
+    view_info
+        .view_type(vk::ImageViewType::TYPE_2D_ARRAY)
+        .subresource_range(view_info.subresource_range.layer_count(info.array_layers))
+}
+
 pub fn swapchain_info(
     image_info: ImageCreateInfo,
     color_space: ColorSpaceKHR,
@@ -7,7 +7,9 @@ pub type VkInstanceRef = Arc<VkInstance>;
 
 /// RAII vulakn instance
 pub struct VkInstance {
-    instance: Instance
+    instance: Instance,
+    /// External instances are not destroyed
+    owned: bool,
 }
 
 impl Deref for VkInstance {
@@ -21,12 +23,24 @@ impl VkInstance {
     pub fn new(instance: Instance) -> VkInstance {
         VkInstance {
             instance,
+            owned: true,
+        }
+    }
+
+    /// Wrap instance owned by the application, it is not destroyed on drop
+    pub fn new_external(instance: Instance) -> VkInstance {
+        VkInstance {
+            instance,
+            owned: false,
         }
     }
 }
 
 impl Drop for VkInstance {
     fn drop(&mut self) {
+        if !self.owned {
+            return;
+        }
         let g = range_event_start!("[Vulkan] Destroy instance");
         unsafe { self.instance.destroy_instance(None); }
     }
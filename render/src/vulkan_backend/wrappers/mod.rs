@@ -4,4 +4,6 @@ pub mod device;
 pub mod debug_utils;
 pub mod capabilities_checker;
 pub mod surface;
-pub mod instance;
\ No newline at end of file
+pub mod instance;
+pub mod pipeline_cache;
+pub mod timestamp_pool;pub mod queue;
@@ -0,0 +1,86 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use ash::vk::{PipelineCache, PipelineCacheCreateInfo};
+use log::{info, warn};
+use sparkles_macro::range_event_start;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+
+pub type VkPipelineCacheRef = Arc<VkPipelineCache>;
+
+/// RAII pipeline cache, shared by all pipelines.
+///
+/// When created with a path, cache is seeded from the file contents and written back on drop.
+pub struct VkPipelineCache {
+    device: VkDeviceRef,
+    pipeline_cache: PipelineCache,
+    path: Option<PathBuf>,
+}
+
+impl VkPipelineCache {
+    pub fn new(device: VkDeviceRef, path: Option<PathBuf>) -> VkPipelineCache {
+        let g = range_event_start!("[Vulkan] Create pipeline cache");
+        let initial_data = path.as_ref().and_then(|path| match fs::read(path) {
+            Ok(data) => {
+                info!("Loaded pipeline cache from {:?} ({} bytes)", path, data.len());
+                Some(data)
+            }
+            Err(e) => {
+                info!("Pipeline cache {:?} was not loaded: {}", path, e);
+                None
+            }
+        });
+
+        let pipeline_cache = initial_data.and_then(|data| {
+            let create_info = PipelineCacheCreateInfo::default().initial_data(&data);
+            // driver is allowed to reject incompatible data, but corrupt data can still fail creation
+            match unsafe { device.create_pipeline_cache(&create_info, None) } {
+                Ok(cache) => Some(cache),
+                Err(e) => {
+                    warn!("Pipeline cache data is rejected: {}. Starting with empty cache", e);
+                    None
+                }
+            }
+        }).unwrap_or_else(|| unsafe {
+            device.create_pipeline_cache(&PipelineCacheCreateInfo::default(), None).unwrap()
+        });
+
+        Self {
+            device,
+            pipeline_cache,
+            path,
+        }
+    }
+
+    pub fn get_pipeline_cache(&self) -> PipelineCache {
+        self.pipeline_cache
+    }
+
+    fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+        let data = match unsafe { self.device.get_pipeline_cache_data(self.pipeline_cache) } {
+            Ok(data) => data,
+            Err(e) => {
+                warn!("Failed to get pipeline cache data: {}", e);
+                return;
+            }
+        };
+        if let Some(parent) = path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+        match fs::write(path, &data) {
+            Ok(_) => info!("Saved pipeline cache to {:?} ({} bytes)", path, data.len()),
+            Err(e) => warn!("Failed to save pipeline cache to {:?}: {}", path, e),
+        }
+    }
+}
+
+impl Drop for VkPipelineCache {
+    fn drop(&mut self) {
+        let g = range_event_start!("[Vulkan] Destroy pipeline cache");
+        self.save();
+        unsafe { self.device.destroy_pipeline_cache(self.pipeline_cache, None) };
+    }
+}
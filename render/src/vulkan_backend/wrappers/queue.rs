@@ -0,0 +1,87 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+use ash::prelude::VkResult;
+use ash::vk;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+
+/// Shared handle to the renderer queue, which serializes host access to it.
+///
+/// Vulkan requires external synchronization of a queue for `vkQueueSubmit`, `vkQueuePresentKHR`,
+/// `vkQueueWaitIdle` and `vkDeviceWaitIdle`. Renderer performs all of these through this handle, so other
+/// threads can submit their own work to the same queue with `submit` or `with_queue` at any time, e.g. a
+/// compute prepass recorded on another thread.
+///
+/// Contract for external code:
+/// - never use the raw queue outside of `with_queue`, the lock is the only synchronization
+/// - keep the lock short, renderer blocks on it while presenting
+/// - work submitted here is ordered with renderer submissions only by the queue submission order,
+///   use semaphores or fences to wait for it, renderer does not know about it
+/// - resources used by the submitted work must outlive it, the renderer waits for the whole queue to be
+///   idle only before destroying its own resources
+#[derive(Clone)]
+pub struct QueueSubmitter {
+    inner: Arc<QueueInner>,
+}
+
+struct QueueInner {
+    device: VkDeviceRef,
+    queue: Mutex<vk::Queue>,
+    queue_family_index: u32,
+}
+
+impl QueueSubmitter {
+    pub fn new(device: VkDeviceRef, queue: vk::Queue, queue_family_index: u32) -> QueueSubmitter {
+        QueueSubmitter {
+            inner: Arc::new(QueueInner {
+                device,
+                queue: Mutex::new(queue),
+                queue_family_index,
+            }),
+        }
+    }
+
+    pub fn queue_family_index(&self) -> u32 {
+        self.inner.queue_family_index
+    }
+
+    // a panic while the lock was held does not leave the queue in a broken state
+    fn lock(&self) -> MutexGuard<'_, vk::Queue> {
+        self.inner.queue.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Run `f` with exclusive access to the raw queue
+    pub fn with_queue<R>(&self, f: impl FnOnce(vk::Queue) -> R) -> R {
+        let queue = self.lock();
+        f(*queue)
+    }
+
+    /// `vkQueueSubmit`
+    ///
+    /// # Safety
+    /// Same as of `ash::Device::queue_submit`
+    pub unsafe fn submit(&self, submits: &[vk::SubmitInfo], fence: vk::Fence) -> VkResult<()> {
+        let queue = self.lock();
+        unsafe { self.inner.device.queue_submit(*queue, submits, fence) }
+    }
+
+    /// `vkQueuePresentKHR`, returns whether swapchain is suboptimal
+    ///
+    /// # Safety
+    /// Same as of `ash::khr::swapchain::Device::queue_present`
+    pub unsafe fn present(&self, swapchain_loader: &ash::khr::swapchain::Device,
+                          present_info: &vk::PresentInfoKHR) -> VkResult<bool> {
+        let queue = self.lock();
+        unsafe { swapchain_loader.queue_present(*queue, present_info) }
+    }
+
+    /// `vkQueueWaitIdle`
+    pub fn wait_idle(&self) -> VkResult<()> {
+        let queue = self.lock();
+        unsafe { self.inner.device.queue_wait_idle(*queue) }
+    }
+
+    /// `vkDeviceWaitIdle`, which requires all queues of the device to be synchronized
+    pub fn wait_device_idle(&self) -> VkResult<()> {
+        let _queue = self.lock();
+        unsafe { self.inner.device.device_wait_idle() }
+    }
+}
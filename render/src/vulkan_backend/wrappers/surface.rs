@@ -30,6 +30,10 @@ impl VkSurface {
         // TODO: check all queue families, not just first one
         unsafe { self.surface_loader.get_physical_device_surface_support(physical_device, 0, self.surface) }.unwrap()
     }
+    pub fn supports_queue_family(&self, physical_device: PhysicalDevice, queue_family_index: u32) -> bool {
+        unsafe { self.surface_loader.get_physical_device_surface_support(physical_device, queue_family_index, self.surface) }
+            .unwrap_or(false)
+    }
     pub fn surface(&self) -> &SurfaceKHR {
         &self.surface
     }
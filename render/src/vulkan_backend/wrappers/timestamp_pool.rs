@@ -0,0 +1,140 @@
+use ash::vk;
+use ash::vk::{CommandBuffer, PipelineStageFlags, QueryPool, QueryResultFlags};
+use log::warn;
+use crate::vulkan_backend::wrappers::device::VkDeviceRef;
+
+/// Named pair of timestamp queries
+struct TimestampScope {
+    name: String,
+    /// Start query index, end query is the next one
+    start: u32,
+    ended: bool,
+}
+
+/// RAII timestamp query pool
+///
+/// Queries are reset from the command buffer, so the same recorded command buffer can be
+/// submitted multiple times. Scopes are allocated while recording and live until next reset.
+pub struct TimestampPool {
+    device: VkDeviceRef,
+    query_pool: QueryPool,
+    capacity: u32,
+    /// Nanoseconds per timestamp tick
+    timestamp_period: f32,
+
+    scopes: Vec<TimestampScope>,
+    next_query: u32,
+    /// Command buffer with the last reset was submitted, so queries can be read once it is finished
+    submitted: bool,
+}
+
+impl TimestampPool {
+    pub fn new(device: VkDeviceRef, capacity: u32, timestamp_period: f32) -> TimestampPool {
+        let create_info = vk::QueryPoolCreateInfo::default()
+            .query_type(vk::QueryType::TIMESTAMP)
+            .query_count(capacity);
+        let query_pool = unsafe { device.create_query_pool(&create_info, None) }.unwrap();
+
+        Self {
+            device,
+            query_pool,
+            capacity,
+            timestamp_period,
+
+            scopes: Vec::new(),
+            next_query: 0,
+            submitted: false,
+        }
+    }
+
+    pub fn capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    /// Must be recorded outside of render pass. Releases all scopes.
+    pub fn cmd_reset(&mut self, command_buffer: CommandBuffer) {
+        unsafe {
+            self.device.cmd_reset_query_pool(command_buffer, self.query_pool, 0, self.capacity);
+        }
+        self.scopes.clear();
+        self.next_query = 0;
+        self.submitted = false;
+    }
+
+    /// Command buffer recorded since the last `cmd_reset` was submitted. Until then results are not read,
+    /// queries may have never been reset
+    pub fn mark_submitted(&mut self) {
+        self.submitted = true;
+    }
+
+    /// Allocate a pair of queries and write the start timestamp.
+    ///
+    /// If pool is exhausted, scope is skipped and will not be reported.
+    pub fn begin_scope(&mut self, command_buffer: CommandBuffer, name: &str) {
+        if self.next_query + 2 > self.capacity {
+            warn!("Timestamp pool is exhausted, scope \"{}\" is skipped", name);
+            return;
+        }
+        // end query is reserved right away, so ending a scope never fails
+        let start = self.next_query;
+        self.next_query += 2;
+
+        self.cmd_write_timestamp(command_buffer, PipelineStageFlags::TOP_OF_PIPE, start);
+        self.scopes.push(TimestampScope {
+            name: name.to_string(),
+            start,
+            ended: false,
+        });
+    }
+
+    /// Write the end timestamp for the last open scope with given name
+    pub fn end_scope(&mut self, command_buffer: CommandBuffer, name: &str) {
+        let Some(scope) = self.scopes.iter_mut().rev().find(|s| !s.ended && s.name == name) else {
+            // skipped in begin_scope
+            return;
+        };
+        scope.ended = true;
+        let end = scope.start + 1;
+        self.cmd_write_timestamp(command_buffer, PipelineStageFlags::BOTTOM_OF_PIPE, end);
+    }
+
+    /// Durations of all finished scopes in milliseconds.
+    ///
+    /// Scopes with results not available yet are omitted.
+    pub fn read_scopes(&self) -> Vec<(String, f32)> {
+        self.scopes.iter()
+            .filter(|s| s.ended)
+            .filter_map(|s| self.read_delta(s.start, s.start + 1).map(|d| (s.name.clone(), d)))
+            .collect()
+    }
+
+    pub fn cmd_write_timestamp(&self, command_buffer: CommandBuffer, stage: PipelineStageFlags, index: u32) {
+        assert!(index < self.capacity, "Timestamp query index {} is out of range", index);
+        unsafe {
+            self.device.cmd_write_timestamp(command_buffer, stage, self.query_pool, index);
+        }
+    }
+
+    /// Time between two written timestamps in milliseconds.
+    ///
+    /// Returns `None` if results are not available yet or nothing was submitted since the last reset.
+    pub fn read_delta(&self, start: u32, end: u32) -> Option<f32> {
+        if !self.submitted {
+            return None;
+        }
+        let mut start_ts = [0u64; 1];
+        let mut end_ts = [0u64; 1];
+        unsafe {
+            self.device.get_query_pool_results(self.query_pool, start, &mut start_ts, QueryResultFlags::TYPE_64).ok()?;
+            self.device.get_query_pool_results(self.query_pool, end, &mut end_ts, QueryResultFlags::TYPE_64).ok()?;
+        }
+        let ticks = end_ts[0].wrapping_sub(start_ts[0]);
+        Some(ticks as f32 * self.timestamp_period / 1_000_000.0)
+    }
+}
+
+impl Drop for TimestampPool {
+    fn drop(&mut self) {
+        unsafe { self.device.destroy_query_pool(self.query_pool, None) };
+    }
+}